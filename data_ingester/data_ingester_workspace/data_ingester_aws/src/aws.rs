@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::iter;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
@@ -12,341 +13,576 @@ use hickory_resolver::Name;
 use hickory_resolver::TokioResolver;
 
 use anyhow::{bail, Context, Result};
+use aws_config::identity::IdentityCache;
 use aws_config::meta::region::RegionProviderChain;
+use aws_config::retry::RetryConfig;
+use aws_config::sts::AssumeRoleProvider;
 use aws_config::{BehaviorVersion, SdkConfig};
 use aws_credential_types::provider::SharedCredentialsProvider;
-use aws_sdk_account::config::{Credentials, ProvideCredentials};
 use aws_sdk_account::types::ContactInformation;
+use aws_sdk_guardduty::types::{Condition, FindingCriteria};
 use aws_sdk_iam::operation::get_account_summary::GetAccountSummaryOutput;
 use aws_sdk_iam::types::{AccessKeyMetadata, PasswordPolicy};
 use aws_sdk_kms::types::KeyListEntry;
+use aws_sdk_securityhub::types::{
+    AwsSecurityFindingFilters, DateFilter, StringFilter, StringFilterComparison,
+};
+use aws_sdk_sts::config::SharedIdentityCache;
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell};
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+use crate::aws_accounts::{
+    AccountAssumption, AccountAssumptions, AccountSink, AwsAccount, AwsOrganizationConfig,
+    CallerIdentity,
+};
 use crate::aws_alternate_contact_information::AlternateContact;
+pub use crate::aws_checks::AwsCheckFilter;
+use crate::aws_checks::{aws_checks, AwsCheck};
+use crate::aws_cloudwatch::{
+    log_group_from_arn, MetricAlarm, MetricFilterAlarm, MetricFilterAlarms,
+};
 use crate::aws_config::DescribeConfigurationRecordersOutput;
-use crate::aws_ec2::{DescribeFlowLogs, DescribeVpcs, FlowLog, Vpc};
+use crate::aws_credentials::AwsCredentialsConfig;
+use crate::aws_ec2::{
+    DescribeFlowLogs, DescribeInstances, DescribeNetworkAcls, DescribeSecurityGroups, DescribeVpcs,
+    EbsEncryptionByDefault, FlowLog, Instance, NetworkAcl, SecurityGroup, Vpc,
+};
+use crate::aws_effective_permissions::{
+    AuthorizationDetails, PrincipalPermissions, PrincipalPermissionsList,
+};
+use crate::aws_efs::{DescribeFileSystems, FileSystem};
 use crate::aws_entities_for_policy::EntitiesForPolicyOutput;
+use crate::aws_findings::{
+    AwsFinding, AwsFindings, AwsFindingsConfig, FindingsWatermark, PendingWatermark,
+};
 use crate::aws_iam::Groups;
 use crate::aws_iam::Users;
 use crate::aws_iam::{MfaDevices, VirtualMfaDevices};
+use crate::aws_inventory::{AwsInventory, Inventory, PendingInventory};
 use crate::aws_kms::{KeyMetadata, KeyMetadatas};
-use crate::aws_policy::Policies;
+use crate::aws_policy::{AwsPolicy, Policies};
+use crate::aws_rds::{DbInstance, DescribeDbInstances};
+use crate::aws_regions::{region_is_enabled, AwsRegionConfig, RegionalEvents, FALLBACK_REGIONS};
 use crate::aws_route53::{HostedZone, HostedZones};
+use crate::aws_route53_takeover::{find_takeovers, HickoryTakeoverProbe, TakeoverFindings};
 use crate::aws_s3::{
     GetBucketAclOutput, GetBucketAclOutputs, GetBucketLoggingOutput, GetBucketLoggingOutputs,
     GetBucketPolicyOutput, GetBucketPolicyOutputs, GetBucketVersioningOutput,
     GetBucketVersioningOutputs, GetPublicAccessBlockOutput, GetPublicAccessBlocks,
 };
+use crate::aws_s3_exposure::{
+    optional_configuration, parse_resource_policy, AccessPointConfiguration, BlockPublicAccess,
+    BucketConfiguration, BucketExposures,
+};
 use crate::aws_securityhub::DescribeHubOutput;
 use crate::aws_trail::{TrailWrapper, TrailWrappers};
+use data_ingester_splunk::sink::HecSink;
 use data_ingester_splunk::splunk::try_collect_send;
-use data_ingester_splunk::splunk::{set_ssphp_run, Splunk, ToHecEvents};
+use data_ingester_splunk::splunk::{set_ssphp_run, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
+use data_ingester_supporting::state_store::{state_store_from_env, StateStore};
+
+/// Does the AWS collector need the `aws-access-key-id` and
+/// `aws-secret-access-key` secrets, i.e. SSPHP_AWS_CREDENTIALS is unset
+/// or `secrets`.
+///
+/// An invalid SSPHP_AWS_CREDENTIALS is reported when the collector runs,
+/// until then the keys are treated as required.
+pub fn aws_access_key_secrets_required() -> bool {
+    AwsCredentialsConfig::from_env()
+        .map(|config| config.uses_access_key_secrets())
+        .unwrap_or(true)
+}
+
+/// Run the checks selected by [AwsCheckFilter::from_env]
+pub async fn aws(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
+    aws_with_checks(secrets, splunk, AwsCheckFilter::from_env()).await
+}
 
-pub async fn aws(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+/// Run the checks from [aws_checks] selected by `filter`
+pub async fn aws_with_checks(
+    secrets: Arc<Secrets>,
+    splunk: Arc<dyn HecSink>,
+    filter: AwsCheckFilter,
+) -> Result<()> {
     set_ssphp_run("aws")?;
 
     info!("Starting AWS collection");
     info!("GIT_HASH: {}", env!("GIT_HASH"));
 
-    let aws_client = AwsClient { secrets };
-
-    let _ = try_collect_send(
-        "aws_1_1_maintain_current_contact_details",
-        aws_client.aws_1_1_maintain_current_contact_details(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_2_ensure_security_contact_information_is_registered",
-        aws_client.aws_1_2_ensure_security_contact_information_is_registered(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_4_ensure_no_root_user_account_access_key_exists()",
-        aws_client.aws_1_4_ensure_no_root_user_account_access_key_exists(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_8_ensure_iam_password_policy_requires_minimum_length_of_14",
-        aws_client.aws_1_8_ensure_iam_password_policy_requires_minimum_length_of_14(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_6_ensure_hardware_mfa_is_enabled_for_the_root_user_account",
-        aws_client.aws_1_6_ensure_hardware_mfa_is_enabled_for_the_root_user_account(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_10_ensure_mfa_is_enabled_for_all_iam_users_that_have_a_console_password",
-        aws_client.aws_1_10_ensure_mfa_is_enabled_for_all_iam_users_that_have_a_console_password(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_13_ensure_there_is_only_one_active_access_key_available_for_any_single_iam_user",
-        aws_client
-            .aws_1_13_ensure_there_is_only_one_active_access_key_available_for_any_single_iam_user(
-            ),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_15_ensure_iam_users_receive_permissions_only_through_groups",
-        aws_client.aws_1_15_ensure_iam_users_receive_permissions_only_through_groups(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_16_ensure_iam_policies_that_allow_full_administrative_privileges_are_not_attached",
-        aws_client.aws_1_16_ensure_iam_policies_that_allow_full_administrative_privileges_are_not_attached(),
-        &splunk,
-    )
-        .await;
+    let checks = aws_checks();
+    let checks = filter.select(&checks).context("Selecting AWS checks")?;
+    info!(
+        name = "AWS",
+        operation = "Selecting checks",
+        checks = ?checks.iter().map(|check| check.name).collect::<Vec<_>>()
+    );
 
-    let _ = try_collect_send(
-        "aws_1_17_ensure_a_support_role_has_been_created_to_manage_incidents_with_aws_support",
-        aws_client
-            .aws_1_17_ensure_a_support_role_has_been_created_to_manage_incidents_with_aws_support(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_19_ensure_that_all_the_expired_tls_certificates_stored_in_aws_iam_are_removed",
-        aws_client
-            .aws_1_19_ensure_that_all_the_expired_tls_certificates_stored_in_aws_iam_are_removed(),
-        &splunk,
-    )
-    .await;
+    let aws_client = AwsClient::new(&secrets).await?;
 
-    let _ = try_collect_send(
-        "aws_1_20_ensure_that_iam_access_analyzer_is_enabled_for_all_regions",
-        aws_client.aws_1_20_ensure_that_iam_access_analyzer_is_enabled_for_all_regions(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_1_22_ensure_access_to_awscloudshellfullaccess_is_restricted",
-        aws_client.aws_1_22_ensure_access_to_awscloudshellfullaccess_is_restricted(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_2_1_1_ensure_s3_bucket_policy_is_set_to_deny_http_requests",
-        aws_client.aws_2_1_1_ensure_s3_bucket_policy_is_set_to_deny_http_requests(),
-        &splunk,
-    )
-    .await;
+    match AwsOrganizationConfig::from_env()? {
+        Some(organization_config) => {
+            aws_organization(&aws_client, &organization_config, &checks, splunk).await?
+        }
+        None => {
+            let account = aws_client
+                .account()
+                .await
+                .context("Getting AWS account details")?;
+            aws_account(
+                &aws_client,
+                &checks,
+                Arc::new(AccountSink::new(account, splunk)),
+            )
+            .await?
+        }
+    }
 
-    let _ = try_collect_send(
-        "aws_2_1_2_ensure_mfa_delete_is_enabled_on_s3_buckets",
-        aws_client.aws_2_1_2_ensure_mfa_delete_is_enabled_on_s3_buckets(),
-        &splunk,
-    )
-    .await;
+    info!("AWS Collection Complete");
 
-    let _ = try_collect_send(
-        "aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access",
-        aws_client.aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access(),
-        &splunk,
-    )
-    .await;
+    Ok(())
+}
 
-    let _ = try_collect_send(
-        "aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access_accounts",
-        aws_client
-            .aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access_accounts(),
-        &splunk,
-    )
-    .await;
+/// Run `checks` against each account in the Organization, assuming
+/// [AwsOrganizationConfig::role_name] in member accounts.
+///
+/// Accounts where the role can't be assumed are reported in an
+/// [AccountAssumptions] event.
+async fn aws_organization(
+    aws_client: &AwsClient,
+    organization_config: &AwsOrganizationConfig,
+    checks: &[&AwsCheck],
+    splunk: Arc<dyn HecSink>,
+) -> Result<()> {
+    let caller = aws_client
+        .caller_identity()
+        .await
+        .context("Getting management account identity")?;
+    let accounts = aws_client
+        .organization_accounts()
+        .await
+        .context("Listing Organization accounts")?;
+    info!(
+        name = "AWS",
+        operation = "Organization collection",
+        accounts = accounts.len()
+    );
+
+    let scps = ServiceControlPolicies::new(&aws_client.config().await?)
+        .await
+        .context("Reading Organization")?
+        .context("The management account is not in an Organization")?;
+    let scps = Arc::new(scps);
+
+    let mut assumptions = vec![];
+    for account in accounts {
+        let mut assumption = AccountAssumption::new(&account, &caller, organization_config);
+        let client = if account.account_id == caller.account_id {
+            Ok(aws_client.clone())
+        } else {
+            aws_client
+                .assume_role(&assumption.role_arn, organization_config)
+                .await
+        };
+        let client = match client {
+            Ok(client) => client.with_scps(scps.clone()),
+            Err(err) => {
+                error!(name="AWS", operation="AssumeRole", account_id=account.account_id, role_arn=assumption.role_arn, error=?err);
+                assumption.failed(format!("{:?}", err));
+                assumptions.push(assumption);
+                continue;
+            }
+        };
+        let account = match client.account_alias().await {
+            Ok(account_alias) => AwsAccount {
+                account_alias,
+                ..account
+            },
+            Err(err) => {
+                warn!(name="AWS", operation="ListAccountAliases", account_id=account.account_id, error=?err);
+                account
+            }
+        };
+        assumption.account_alias = account.account_alias.clone();
+        assumptions.push(assumption);
+
+        info!(
+            name = "AWS",
+            operation = "Account collection",
+            account_id = account.account_id
+        );
+        let account_id = account.account_id.clone();
+        if let Err(err) = aws_account(
+            &client,
+            checks,
+            Arc::new(AccountSink::new(account, splunk.clone())),
+        )
+        .await
+        {
+            error!(name="AWS", operation="Account collection", account_id=account_id, error=?err);
+        }
+    }
 
     let _ = try_collect_send(
-        "aws_3_1_ensure_cloudtrail_is_enabled_in_all_regions",
-        aws_client.aws_3_1_ensure_cloudtrail_is_enabled_in_all_regions(),
+        "aws_organization_account_assumptions",
+        async { Ok(AccountAssumptions::new(assumptions)) },
         &splunk,
     )
     .await;
 
-    let _ = try_collect_send(
-        "aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_acl",
-        aws_client
-            .aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_acl(
-            ),
-        &splunk,
-    )
-    .await;
+    Ok(())
+}
 
-    let _ = try_collect_send(
-        "aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_bucket_policy",
+/// Run `checks` against a single account
+async fn aws_account(
+    aws_client: &AwsClient,
+    checks: &[&AwsCheck],
+    splunk: Arc<dyn HecSink>,
+) -> Result<()> {
+    let regions = if checks.iter().any(|check| check.regional) {
         aws_client
-            .aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_bucket_policy(
-            ),
-        &splunk,
-    )
-        .await;
-
-    let _ = try_collect_send(
-        "aws_3_5_ensure_aws_config_is_enabled_in_all_regions",
-        aws_client.aws_3_5_ensure_aws_config_is_enabled_in_all_regions(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_3_6_ensure_s3_bucket_access_logging_is_enabled_on_the_cloudtrail_s3_bucket",
-        aws_client.aws_3_6_ensure_s3_bucket_access_logging_is_enabled_on_the_cloudtrail_s3_bucket(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_3_8_ensure_rotation_for_customer_created_symmetric_cmks_is_enabled",
-        aws_client.aws_3_8_ensure_rotation_for_customer_created_symmetric_cmks_is_enabled(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_vpc",
-        aws_client.aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_vpc(),
-        &splunk,
-    )
-    .await;
-
-    let _ = try_collect_send(
-        "aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_flow_logs",
-        aws_client.aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_flow_logs(),
-        &splunk,
-    )
-    .await;
+            .list_of_regions()
+            .await
+            .context("Listing AWS regions")?
+    } else {
+        vec![]
+    };
+    info!(name = "AWS", operation = "Regional checks", regions = ?regions);
+
+    for check in checks {
+        info!(
+            name = "AWS",
+            operation = "Run check",
+            check = check.name,
+            benchmark = check.benchmark,
+            cis_ids = ?check.cis_ids
+        );
+        (check.run)(check.name, aws_client, &regions, splunk.as_ref()).await;
+    }
 
-    let _ = try_collect_send(
-        "aws_4_16_ensure_aws_security_hub_is_enabled",
-        aws_client.aws_4_16_ensure_aws_security_hub_is_enabled(),
-        &splunk,
-    )
-    .await;
+    Ok(())
+}
 
-    let _ = try_collect_send("aws_dfe_1x", aws_client.aws_dfe_1x(), &splunk).await;
+#[derive(Clone)]
+pub(crate) struct AwsClient {
+    credentials: SharedCredentialsProvider,
+    /// Shared by every config so credentials are only fetched again
+    /// when they expire
+    identity_cache: SharedIdentityCache,
+    region_config: Arc<AwsRegionConfig>,
+    /// Region used by [AwsClient::config] in place of the default
+    /// region, set by [AwsClient::in_region]
+    region: Option<String>,
+    /// Results shared by the checks for the account
+    collected: Arc<Collected>,
+    /// SCPs read by the management account, set by
+    /// [AwsClient::with_scps] in an Organization collection
+    scps: Option<Arc<ServiceControlPolicies>>,
+}
 
-    let _ = try_collect_send("aws_dfe_2x", aws_client.aws_dfe_2x(), &splunk).await;
+/// Results used by more than one check, collected once per account
+#[derive(Default)]
+struct Collected {
+    hosted_zones: OnceCell<HostedZones>,
+    bucket_exposures: OnceCell<BucketExposures>,
+    principal_permissions: OnceCell<PrincipalPermissionsList>,
+}
 
-    let _ = try_collect_send("aws_dfe_3x", aws_client.aws_dfe_3x(), &splunk).await;
+/// The SCPs of an Organization.
+///
+/// The policies attached to each target and the parent of each target
+/// are read once and shared by every account
+pub(crate) struct ServiceControlPolicies {
+    client: aws_sdk_organizations::Client,
+    management_account_id: Option<String>,
+    policies: Mutex<HashMap<String, Vec<AwsPolicy>>>,
+    /// Parent of each account or OU and whether it is the root
+    parents: Mutex<HashMap<String, (String, bool)>>,
+}
 
-    let zones = try_collect_send("aws_dfe_4x", aws_client.aws_dfe_4x(), &splunk)
-        .await
-        .context("AWS 4x: Getting zones")?;
+impl ServiceControlPolicies {
+    /// Organizations: organizations:DescribeOrganization
+    ///
+    /// `None` when the account is not in an Organization
+    pub(crate) async fn new(config: &SdkConfig) -> Result<Option<Self>> {
+        let client = aws_sdk_organizations::Client::new(config);
+        let organization = match client.describe_organization().send().await {
+            Ok(output) => output.organization,
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_aws_organizations_not_in_use_exception()) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(Self {
+            client,
+            management_account_id: organization
+                .and_then(|organization| organization.master_account_id),
+            policies: Mutex::default(),
+            parents: Mutex::default(),
+        }))
+    }
+
+    /// Organizations: organizations:ListParents,
+    /// organizations:ListPoliciesForTarget, organizations:DescribePolicy
+    ///
+    /// The SCPs attached to the account and each OU above it, one
+    /// entry per level. Empty for the management account, which SCPs
+    /// do not apply to
+    pub(crate) async fn for_account(&self, account_id: &str) -> Result<Vec<Vec<AwsPolicy>>> {
+        if self.management_account_id.as_deref() == Some(account_id) {
+            return Ok(vec![]);
+        }
 
-    let _ = try_collect_send("aws_dfe_5x", aws_client.aws_dfe_5x(zones), &splunk).await;
+        let mut scps = vec![];
+        let mut target_id = account_id.to_string();
+        loop {
+            scps.push(self.policies(&target_id).await?);
+            let (parent_id, is_root) = self.parent(&target_id).await?;
+            if is_root {
+                scps.push(self.policies(&parent_id).await?);
+                return Ok(scps);
+            }
+            target_id = parent_id;
+        }
+    }
 
-    info!("AWS Collection Complete");
+    async fn parent(&self, target_id: &str) -> Result<(String, bool)> {
+        let mut parents = self.parents.lock().await;
+        if let Some(parent) = parents.get(target_id) {
+            return Ok(parent.clone());
+        }
+        let parent = self
+            .client
+            .list_parents()
+            .child_id(target_id)
+            .send()
+            .await?
+            .parents
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .context("ListParents returned no parent")?;
+        let parent = (
+            parent.id.context("ListParents returned no parent id")?,
+            parent.r#type == Some(aws_sdk_organizations::types::ParentType::Root),
+        );
+        let _ = parents.insert(target_id.to_string(), parent.clone());
+        Ok(parent)
+    }
+
+    /// The SCPs attached directly to `target_id`
+    async fn policies(&self, target_id: &str) -> Result<Vec<AwsPolicy>> {
+        let mut cache = self.policies.lock().await;
+        if let Some(policies) = cache.get(target_id) {
+            return Ok(policies.clone());
+        }
+        let summaries = self
+            .client
+            .list_policies_for_target()
+            .target_id(target_id)
+            .filter(aws_sdk_organizations::types::PolicyType::ServiceControlPolicy)
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?
+            .into_iter()
+            .flat_map(|page| page.policies.unwrap_or_default());
 
-    Ok(())
+        let mut policies = vec![];
+        for summary in summaries {
+            let content = self
+                .client
+                .describe_policy()
+                .set_policy_id(summary.id)
+                .send()
+                .await?
+                .policy
+                .and_then(|policy| policy.content)
+                .context("DescribePolicy returned no content")?;
+            policies.push(serde_json::from_str(&content)?);
+        }
+        let _ = cache.insert(target_id.to_string(), policies.clone());
+        Ok(policies)
+    }
 }
 
-impl ProvideCredentials for AwsSecrets {
-    fn provide_credentials<'a>(
-        &'a self,
-    ) -> aws_credential_types::provider::future::ProvideCredentials<'a>
-    where
-        Self: 'a,
-    {
-        aws_credential_types::provider::future::ProvideCredentials::new(self.load_credentials())
+impl AwsClient {
+    /// Client using the credentials configured by
+    /// [AwsCredentialsConfig::from_env]
+    pub(crate) async fn new(secrets: &Secrets) -> Result<Self> {
+        let credentials = AwsCredentialsConfig::from_env()?
+            .provider(secrets)
+            .await
+            .context("Building AWS credentials provider")?;
+        let region_config =
+            AwsRegionConfig::from_env().context("Reading AWS region configuration")?;
+        Ok(Self::with_credentials(credentials, Arc::new(region_config)))
     }
-}
 
-#[derive(Debug)]
-struct AwsSecrets {
-    aws_access_key_id: String,
-    aws_secret_access_key: String,
-}
+    pub(crate) fn with_credentials(
+        credentials: SharedCredentialsProvider,
+        region_config: Arc<AwsRegionConfig>,
+    ) -> Self {
+        Self {
+            credentials,
+            identity_cache: IdentityCache::lazy().build(),
+            region_config,
+            region: None,
+            collected: Arc::default(),
+            scps: None,
+        }
+    }
 
-impl AwsSecrets {
-    async fn load_credentials(&self) -> aws_credential_types::provider::Result {
-        Ok(Credentials::new(
-            self.aws_access_key_id.clone(),
-            self.aws_secret_access_key.clone(),
-            None,
-            None,
-            "StaticCredentials",
-        ))
+    /// A client that evaluates permissions against `scps`
+    pub(crate) fn with_scps(self, scps: Arc<ServiceControlPolicies>) -> Self {
+        Self {
+            scps: Some(scps),
+            ..self
+        }
     }
-}
 
-struct AwsClient {
-    secrets: Arc<Secrets>,
-}
+    /// A client whose [AwsClient::config] is for `region`
+    pub(crate) fn in_region(&self, region: &str) -> Self {
+        Self {
+            region: Some(region.to_string()),
+            ..self.clone()
+        }
+    }
 
-impl AwsClient {
-    async fn config(&self) -> Result<SdkConfig> {
+    pub(crate) async fn config(&self) -> Result<SdkConfig> {
+        if let Some(region) = &self.region {
+            return self.config_for_region(region).await;
+        }
         let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .credentials_provider(SharedCredentialsProvider::new(AwsSecrets {
-                aws_access_key_id: self
-                    .secrets
-                    .aws_access_key_id
-                    .as_ref()
-                    .context("Expect aws_access_key_id secret")?
-                    .clone(),
-                aws_secret_access_key: self
-                    .secrets
-                    .aws_secret_access_key
-                    .as_ref()
-                    .context("Expect aws_secret_access_key secret")?
-                    .clone(),
-            }))
-            .region(region_provider)
-            .load()
-            .await;
-        Ok(config)
+        Ok(self.load_config(region_provider).await)
     }
 
     /// Generate an AWS config for a specific region
     async fn config_for_region(&self, region: &str) -> Result<SdkConfig> {
         let region_provider = RegionProviderChain::first_try(Region::new(region.to_string()));
-
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .credentials_provider(SharedCredentialsProvider::new(AwsSecrets {
-                aws_access_key_id: self
-                    .secrets
-                    .aws_access_key_id
-                    .as_ref()
-                    .context("Expect aws_access_key_id secret")?
-                    .clone(),
-                aws_secret_access_key: self
-                    .secrets
-                    .aws_secret_access_key
-                    .as_ref()
-                    .context("Expect aws_secret_access_key secret")?
-                    .clone(),
-            }))
+        Ok(self.load_config(region_provider).await)
+    }
+
+    /// Adaptive retries back off and rate limit each service client
+    /// when it is throttled
+    async fn load_config(&self, region_provider: RegionProviderChain) -> SdkConfig {
+        aws_config::defaults(BehaviorVersion::latest())
+            .credentials_provider(self.credentials.clone())
+            .identity_cache(self.identity_cache.clone())
+            .retry_config(
+                RetryConfig::adaptive().with_max_attempts(self.region_config.max_attempts),
+            )
             .region(region_provider)
             .load()
-            .await;
-        Ok(config)
+            .await
+    }
+
+    /// STS: sts:GetCallerIdentity
+    pub(crate) async fn caller_identity(&self) -> Result<CallerIdentity> {
+        let config = self.config().await?;
+        let identity = aws_sdk_sts::Client::new(&config)
+            .get_caller_identity()
+            .send()
+            .await?;
+        Ok(CallerIdentity {
+            account_id: identity
+                .account
+                .context("GetCallerIdentity returned no account")?,
+            arn: identity.arn.context("GetCallerIdentity returned no ARN")?,
+        })
     }
 
-    /// Get an up to date list of regions from EC2, or use a static list of regions.
-    #[allow(dead_code)]
-    async fn list_of_regions(&self) -> Result<Vec<String>> {
+    /// IAM: iam:ListAccountAliases
+    pub(crate) async fn account_alias(&self) -> Result<Option<String>> {
+        let config = self.config().await?;
+        let aliases = aws_sdk_iam::Client::new(&config)
+            .list_account_aliases()
+            .send()
+            .await?;
+        Ok(aliases.account_aliases.into_iter().next())
+    }
+
+    /// The account these credentials belong to
+    pub(crate) async fn account(&self) -> Result<AwsAccount> {
+        let caller = self.caller_identity().await?;
+        let account_alias = match self.account_alias().await {
+            Ok(account_alias) => account_alias,
+            Err(err) => {
+                warn!(name="AWS", operation="ListAccountAliases", account_id=caller.account_id, error=?err);
+                None
+            }
+        };
+        Ok(AwsAccount {
+            account_id: caller.account_id,
+            account_name: None,
+            account_alias,
+        })
+    }
+
+    /// Organizations: organizations:ListAccounts
+    pub(crate) async fn organization_accounts(&self) -> Result<Vec<AwsAccount>> {
+        let config = self.config().await?;
+        let accounts = aws_sdk_organizations::Client::new(&config)
+            .list_accounts()
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?
+            .into_iter()
+            .flat_map(|page| page.accounts.unwrap_or_default())
+            .filter_map(|account| {
+                Some(AwsAccount {
+                    account_id: account.id?,
+                    account_name: account.name,
+                    account_alias: None,
+                })
+            })
+            .collect();
+        Ok(accounts)
+    }
+
+    /// A client for `role_arn`, assumed with these credentials.
+    ///
+    /// The role is assumed once up front so failures are reported
+    /// before any checks run.
+    /// STS: sts:AssumeRole
+    pub(crate) async fn assume_role(
+        &self,
+        role_arn: &str,
+        organization_config: &AwsOrganizationConfig,
+    ) -> Result<AwsClient> {
+        let config = self.config().await?;
+        let mut builder = AssumeRoleProvider::builder(role_arn)
+            .session_name(&organization_config.session_name)
+            .configure(&config);
+        if let Some(external_id) = &organization_config.external_id {
+            builder = builder.external_id(external_id);
+        }
+        let client = AwsClient::with_credentials(
+            SharedCredentialsProvider::new(builder.build().await),
+            self.region_config.clone(),
+        );
+        let _ = client
+            .caller_identity()
+            .await
+            .with_context(|| format!("Assuming role {}", role_arn))?;
+        Ok(client)
+    }
+
+    /// Get an up to date list of regions enabled for the account from
+    /// EC2, or use a static list of regions.
+    ///
+    /// Regions are filtered by [AwsRegionConfig::filter]
+    /// EC2: ec2:DescribeRegions
+    pub(crate) async fn list_of_regions(&self) -> Result<Vec<String>> {
         let config = self
             .config()
             .await
@@ -357,61 +593,80 @@ impl AwsClient {
                 .regions
                 .unwrap_or_default()
                 .into_iter()
+                .filter(|region| region_is_enabled(region.opt_in_status.as_deref()))
                 .flat_map(|region| region.region_name)
                 .collect(),
             Err(err) => {
                 error!("Unable to get list of regions from EC2 endpoint: {:?}", err);
                 error!("Using static list of regions");
-                vec![
-                    "af-south-1".to_string(),
-                    "ap-east-1".to_string(),
-                    "ap-northeast-1".to_string(),
-                    "ap-northeast-2".to_string(),
-                    "ap-northeast-3".to_string(),
-                    "ap-south-1".to_string(),
-                    "ap-southeast-1".to_string(),
-                    "ap-southeast-2".to_string(),
-                    "ap-southeast-3".to_string(),
-                    "ca-central-1".to_string(),
-                    "cn-north-1".to_string(),
-                    "cn-northwest-1".to_string(),
-                    "eu-central-1".to_string(),
-                    "eu-north-1".to_string(),
-                    "eu-south-1".to_string(),
-                    "eu-west-1".to_string(),
-                    "eu-west-2".to_string(),
-                    "eu-west-3".to_string(),
-                    "me-south-1".to_string(),
-                    "sa-east-1".to_string(),
-                    "us-east-1".to_string(),
-                    "us-east-2".to_string(),
-                    "us-gov-east-1".to_string(),
-                    "us-gov-west-1".to_string(),
-                    "us-west-1".to_string(),
-                    "us-west-2".to_string(),
-                ]
+                FALLBACK_REGIONS
+                    .iter()
+                    .map(|region| region.to_string())
+                    .collect()
             }
         };
 
-        Ok(regions)
+        Ok(self.region_config.filter(regions))
     }
 
-    /// configs for all regions
-    #[allow(dead_code)]
-    async fn config_all_regions(&self) -> Result<Vec<SdkConfig>> {
-        let regions = self
-            .list_of_regions()
+    /// Run `check` in each of `regions`, `concurrency` regions at a time.
+    ///
+    /// A region that fails is logged, recorded on the run manifest step
+    /// and skipped, the check only fails when every region fails.
+    pub(crate) async fn regional<T, F, Fut>(
+        &self,
+        regions: &[String],
+        check: F,
+    ) -> Result<RegionalEvents>
+    where
+        F: Fn(AwsClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+        for<'a> &'a T: ToHecEvents,
+    {
+        self.regional_results(regions, check)
             .await
-            .context("Unable to get regions")?;
-        let mut configs = Vec::with_capacity(regions.len());
-        for region in regions {
-            let config = self
-                .config_for_region(&region)
-                .await
-                .context(format!("Failed to get config for: {}", &region))?;
-            configs.push(config);
+            .map(|(events, _)| events)
+    }
+
+    /// [AwsClient::regional], also returning the result from each
+    /// region the check succeeded in
+    pub(crate) async fn regional_results<T, F, Fut>(
+        &self,
+        regions: &[String],
+        check: F,
+    ) -> Result<(RegionalEvents, Vec<T>)>
+    where
+        F: Fn(AwsClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+        for<'a> &'a T: ToHecEvents,
+    {
+        let mut results: Vec<(String, Result<T>)> =
+            stream::iter(regions.iter().cloned().map(|region| {
+                let future = check(self.in_region(&region));
+                async move { (region, future.await) }
+            }))
+            .buffer_unordered(self.region_config.concurrency)
+            .collect()
+            .await;
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut events = RegionalEvents::default();
+        let mut succeeded = vec![];
+        let mut failed = 0;
+        for (region, result) in results {
+            match result.and_then(|result| events.push(&region, &result).map(|()| result)) {
+                Ok(result) => succeeded.push(result),
+                Err(err) => {
+                    failed += 1;
+                    warn!(name="AWS", operation="Regional check", region=region, error=?err);
+                    events.failed(&region, &err);
+                }
+            }
         }
-        Ok(configs)
+        if !regions.is_empty() && failed == regions.len() {
+            bail!("Check failed in every region");
+        }
+        Ok((events, succeeded))
     }
 
     async fn client_for_bucket(
@@ -419,6 +674,22 @@ impl AwsClient {
         s3_client: &aws_sdk_s3::Client,
         bucket_name: &str,
     ) -> Result<aws_sdk_s3::Client> {
+        let bucket_location = self.bucket_region(s3_client, bucket_name).await?;
+
+        let bucket_region = aws_config::Region::new(bucket_location);
+
+        let region_provider = RegionProviderChain::first_try(bucket_region);
+
+        let bucket_client_config = self.load_config(region_provider).await;
+
+        Ok(aws_sdk_s3::Client::new(&bucket_client_config))
+    }
+
+    async fn bucket_region(
+        &self,
+        s3_client: &aws_sdk_s3::Client,
+        bucket_name: &str,
+    ) -> Result<String> {
         let bucket_location = s3_client
             .get_bucket_location()
             .set_bucket(Some(bucket_name.to_string()))
@@ -431,31 +702,7 @@ impl AwsClient {
             .location_constraint()
             .map(|lc| lc.as_str().to_owned())
             .unwrap_or_else(|| "us-east-1".to_owned());
-
-        let bucket_region = aws_config::Region::new(bucket_location);
-
-        let region_provider = RegionProviderChain::first_try(bucket_region);
-
-        let bucket_client_config = aws_config::defaults(BehaviorVersion::latest())
-            .credentials_provider(SharedCredentialsProvider::new(AwsSecrets {
-                aws_access_key_id: self
-                    .secrets
-                    .aws_access_key_id
-                    .as_ref()
-                    .context("Expect aws_access_key_id secret")?
-                    .clone(),
-                aws_secret_access_key: self
-                    .secrets
-                    .aws_secret_access_key
-                    .as_ref()
-                    .context("Expect aws_secret_access_key secret")?
-                    .clone(),
-            }))
-            .region(region_provider)
-            .load()
-            .await;
-
-        Ok(aws_sdk_s3::Client::new(&bucket_client_config))
+        Ok(bucket_location)
     }
 
     /// IAM: account:GetContactInformation
@@ -510,6 +757,8 @@ impl AwsClient {
         Ok(VirtualMfaDevices { inner: virtual_mfa })
     }
 
+    /// 1.4
+    /// 1.5 `RootAccountMfaEnabled`
     /// https://docs.aws.amazon.com/IAM/latest/APIReference/API_GetAccountSummary.html
     /// https://docs.rs/aws-sdk-iam/latest/aws_sdk_iam/client/struct.Client.html#method.get_account_summary
     pub(crate) async fn aws_1_4_ensure_no_root_user_account_access_key_exists(
@@ -523,7 +772,7 @@ impl AwsClient {
     }
 
     /// 1.8
-    /// 1.9
+    /// 1.9 `PreventsPasswordReuse`
     pub(crate) async fn aws_1_8_ensure_iam_password_policy_requires_minimum_length_of_14(
         &self,
     ) -> Result<AccountPasswordPolicy> {
@@ -538,9 +787,30 @@ impl AwsClient {
         Ok(out)
     }
 
+    /// 1.10
+    /// 1.11 `unused_access_key_with_console_password`
+    /// 1.12 `credentials_unused_45_days`
+    /// 1.14 `access_key_not_rotated_90_days`
     pub(crate) async fn aws_1_10_ensure_mfa_is_enabled_for_all_iam_users_that_have_a_console_password(
         &self,
     ) -> Result<CredentialReport> {
+        let content = self.credential_report().await?;
+        Ok(CredentialReport::from(content.as_ref()))
+    }
+
+    /// 1.7
+    pub(crate) async fn aws_1_7_eliminate_use_of_the_root_user_for_administrative_and_daily_tasks(
+        &self,
+    ) -> Result<RootUserUsage> {
+        let content = self.credential_report().await?;
+        RootUserUsage::from_credential_report(&content)
+            .context("No <root_account> in credential report")
+    }
+
+    /// IAM: iam:GenerateCredentialReport and iam:GetCredentialReport
+    ///
+    /// The CSV content of the report
+    async fn credential_report(&self) -> Result<Vec<u8>> {
         let config = self.config().await?;
         let client = aws_sdk_iam::Client::new(&config);
 
@@ -559,10 +829,7 @@ impl AwsClient {
 
         let report = client.get_credential_report().send().await?;
 
-        let credential_report =
-            CredentialReport::from(report.content.context("No credential report")?.as_ref());
-
-        Ok(credential_report)
+        Ok(report.content.context("No credential report")?.into_inner())
     }
 
     pub(crate) async fn aws_1_13_ensure_there_is_only_one_active_access_key_available_for_any_single_iam_user(
@@ -660,15 +927,12 @@ impl AwsClient {
                 let decoded = urlencoding::decode(document).expect("UTF-8");
                 let json_document: crate::aws_policy::AwsPolicy = serde_json::from_str(&decoded)?;
 
-                match &json_document.statement {
-                    crate::aws_policy::Statement::StatementVec(vec) => Some(
-                        vec.iter()
-                            .any(|statement| statement.contains_full_permissions()),
-                    ),
-                    crate::aws_policy::Statement::StatementElement(statement) => {
-                        Some(statement.contains_full_permissions())
-                    }
-                }
+                Some(
+                    json_document
+                        .statements()
+                        .iter()
+                        .any(|statement| statement.contains_full_permissions()),
+                )
             } else {
                 Some(false)
             };
@@ -677,6 +941,53 @@ impl AwsClient {
         Ok(policies)
     }
 
+    /// Effective permissions of every user and role, shared by
+    /// [AwsClient::aws_inventory]
+    pub(crate) async fn aws_iam_effective_permissions(&self) -> Result<PrincipalPermissionsList> {
+        self.collected
+            .principal_permissions
+            .get_or_try_init(|| self.iam_effective_permissions())
+            .await
+            .cloned()
+    }
+
+    /// IAM: iam:GetAccountAuthorizationDetails
+    /// Organizations: SCPs applied to the account, see [ServiceControlPolicies]
+    ///
+    /// In an Organization collection the SCPs are read with the
+    /// management account's client, otherwise with this client
+    ///
+    /// Evaluate the identity policies, group policies, permissions
+    /// boundary and SCPs of every user and role
+    async fn iam_effective_permissions(&self) -> Result<PrincipalPermissionsList> {
+        let config = self.config().await?;
+        let client = aws_sdk_iam::Client::new(&config);
+        let pages = client
+            .get_account_authorization_details()
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+        let details = AuthorizationDetails::new(pages);
+
+        let account_id = self.caller_identity().await?.account_id;
+        let scps = match &self.scps {
+            Some(scps) => scps.for_account(&account_id).await,
+            None => match ServiceControlPolicies::new(&config).await? {
+                Some(scps) => scps.for_account(&account_id).await,
+                None => Ok(vec![]),
+            },
+        }
+        .context("Reading service control policies")?;
+
+        Ok(PrincipalPermissionsList {
+            inner: details
+                .effective_policies(&scps)
+                .map(|(principal, policies)| PrincipalPermissions::new(principal, &policies))
+                .collect(),
+        })
+    }
+
     pub(crate) async fn aws_1_17_ensure_a_support_role_has_been_created_to_manage_incidents_with_aws_support(
         &self,
     ) -> Result<EntitiesForPolicyOutput> {
@@ -708,7 +1019,6 @@ impl AwsClient {
         Ok(server_certificates)
     }
 
-    /// TODO Might need to check other regions
     pub(crate) async fn aws_1_20_ensure_that_iam_access_analyzer_is_enabled_for_all_regions(
         &self,
     ) -> Result<AnalyzerSummaries> {
@@ -899,26 +1209,266 @@ impl AwsClient {
                     public_access_block_configuration: None,
                     account_id,
                 }
-            }
-        };
-        Ok(public_access_block)
+            }
+        };
+        Ok(public_access_block)
+    }
+
+    /// Exposure of every bucket, shared by [AwsClient::aws_inventory]
+    pub(crate) async fn aws_s3_bucket_exposure(&self) -> Result<BucketExposures> {
+        self.collected
+            .bucket_exposures
+            .get_or_try_init(|| self.s3_bucket_exposure())
+            .await
+            .cloned()
+    }
+
+    /// S3: s3:ListAllMyBuckets, s3:GetBucketPolicy, s3:GetBucketAcl,
+    /// s3:GetBucketPublicAccessBlock, s3:GetBucketOwnershipControls,
+    /// s3:GetEncryptionConfiguration
+    /// S3 Control: s3:GetAccountPublicAccessBlock, s3:ListAccessPoints,
+    /// s3:GetAccessPoint, s3:GetAccessPointPolicy
+    ///
+    /// One consolidated exposure record for each bucket
+    async fn s3_bucket_exposure(&self) -> Result<BucketExposures> {
+        let config = self.config().await?;
+        let s3_client = aws_sdk_s3::Client::new(&config);
+        let account_id = self.caller_identity().await?.account_id;
+
+        let account_block_public_access = self
+            .aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access_accounts()
+            .await?
+            .public_access_block_configuration
+            .as_ref()
+            .map(BlockPublicAccess::from)
+            .unwrap_or_default();
+
+        let buckets = s3_client.list_buckets().send().await?;
+
+        let mut exposures = vec![];
+
+        for bucket in buckets.buckets.unwrap_or_default().into_iter() {
+            let bucket_name = bucket.name.context("Bucket should have name")?;
+            let mut errors = vec![];
+            let region = match self.bucket_region(&s3_client, &bucket_name).await {
+                Ok(region) => region,
+                Err(err) => {
+                    warn!(name="AWS", operation="GetBucketLocation", bucket=?bucket_name, error=?err);
+                    errors.push(format!("GetBucketLocation {}: {:#}", bucket_name, err));
+                    let configuration = BucketConfiguration {
+                        bucket_name,
+                        account_id: account_id.clone(),
+                        account_block_public_access,
+                        errors,
+                        ..Default::default()
+                    };
+                    exposures.push(configuration.exposure());
+                    continue;
+                }
+            };
+            let regional_client = self.in_region(&region);
+            let bucket_client = aws_sdk_s3::Client::new(&regional_client.config().await?);
+
+            let policy = optional_configuration(
+                &bucket_name,
+                "GetBucketPolicy",
+                bucket_client
+                    .get_bucket_policy()
+                    .bucket(&bucket_name)
+                    .send()
+                    .await,
+                &mut errors,
+            )
+            .and_then(|output| output.policy)
+            .and_then(|policy| parse_resource_policy(&bucket_name, &policy, &mut errors));
+
+            let acl_grants = optional_configuration(
+                &bucket_name,
+                "GetBucketAcl",
+                bucket_client
+                    .get_bucket_acl()
+                    .bucket(&bucket_name)
+                    .send()
+                    .await,
+                &mut errors,
+            )
+            .and_then(|output| output.grants)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|grant| grant.into())
+            .collect();
+
+            let bucket_block_public_access = optional_configuration(
+                &bucket_name,
+                "GetPublicAccessBlock",
+                bucket_client
+                    .get_public_access_block()
+                    .bucket(&bucket_name)
+                    .send()
+                    .await,
+                &mut errors,
+            )
+            .and_then(|output| output.public_access_block_configuration)
+            .map(|pabc| {
+                BlockPublicAccess::from(&crate::aws_s3::PublicAccessBlockConfiguration::from(pabc))
+            })
+            .unwrap_or_default();
+
+            let object_ownership = optional_configuration(
+                &bucket_name,
+                "GetBucketOwnershipControls",
+                bucket_client
+                    .get_bucket_ownership_controls()
+                    .bucket(&bucket_name)
+                    .send()
+                    .await,
+                &mut errors,
+            )
+            .and_then(|output| output.ownership_controls)
+            .and_then(|controls| controls.rules.into_iter().next())
+            .map(|rule| rule.object_ownership.as_str().to_owned());
+
+            let encryption = optional_configuration(
+                &bucket_name,
+                "GetBucketEncryption",
+                bucket_client
+                    .get_bucket_encryption()
+                    .bucket(&bucket_name)
+                    .send()
+                    .await,
+                &mut errors,
+            )
+            .and_then(|output| output.server_side_encryption_configuration)
+            .and_then(|configuration| configuration.rules.into_iter().next())
+            .and_then(|rule| rule.apply_server_side_encryption_by_default);
+
+            let access_points = match regional_client
+                .s3_access_points(&account_id, &bucket_name, &mut errors)
+                .await
+            {
+                Ok(access_points) => access_points,
+                Err(err) => {
+                    warn!(name="AWS", operation="ListAccessPoints", bucket=?bucket_name, error=?err);
+                    errors.push(format!("ListAccessPoints {}: {:#}", bucket_name, err));
+                    vec![]
+                }
+            };
+
+            let configuration = BucketConfiguration {
+                bucket_name,
+                region,
+                account_id: account_id.clone(),
+                account_block_public_access,
+                bucket_block_public_access,
+                policy,
+                acl_grants,
+                access_points,
+                encryption: encryption
+                    .as_ref()
+                    .map(|encryption| encryption.sse_algorithm.as_str().to_owned()),
+                kms_master_key_id: encryption.and_then(|encryption| encryption.kms_master_key_id),
+                object_ownership,
+                errors,
+            };
+            exposures.push(configuration.exposure());
+        }
+        Ok(BucketExposures { inner: exposures })
+    }
+
+    /// S3 Control: s3:ListAccessPoints, s3:GetAccessPoint,
+    /// s3:GetAccessPointPolicy
+    ///
+    /// Access points are regional, call on a client in the bucket's region.
+    /// Access point settings that can't be read are added to `errors`
+    async fn s3_access_points(
+        &self,
+        account_id: &str,
+        bucket_name: &str,
+        errors: &mut Vec<String>,
+    ) -> Result<Vec<AccessPointConfiguration>> {
+        let config = self.config().await?;
+        let client = aws_sdk_s3control::Client::new(&config);
+        let pages = client
+            .list_access_points()
+            .account_id(account_id)
+            .bucket(bucket_name)
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+
+        let mut access_points = vec![];
+        for access_point in pages
+            .into_iter()
+            .flat_map(|page| page.access_point_list.unwrap_or_default())
+        {
+            let name = access_point.name;
+            let block_public_access = optional_configuration(
+                &name,
+                "GetAccessPoint",
+                client
+                    .get_access_point()
+                    .account_id(account_id)
+                    .name(&name)
+                    .send()
+                    .await,
+                errors,
+            )
+            .and_then(|output| output.public_access_block_configuration)
+            .map(|pabc| {
+                BlockPublicAccess::from(
+                    &crate::aws_s3control::PublicAccessBlockConfiguration::from(pabc),
+                )
+            })
+            .unwrap_or_default();
+
+            let policy = optional_configuration(
+                &name,
+                "GetAccessPointPolicy",
+                client
+                    .get_access_point_policy()
+                    .account_id(account_id)
+                    .name(&name)
+                    .send()
+                    .await,
+                errors,
+            )
+            .and_then(|output| output.policy)
+            .and_then(|policy| parse_resource_policy(&name, &policy, errors));
+
+            access_points.push(AccessPointConfiguration {
+                name,
+                arn: access_point.access_point_arn,
+                network_origin: Some(access_point.network_origin.as_str().to_owned()),
+                block_public_access,
+                policy,
+            });
+        }
+        Ok(access_points)
     }
 
+    /// 3.1
+    /// 3.2 `ValidatesLogFiles`
+    /// 3.4 `CloudWatchLogsIntegrated`
+    /// 3.7 `EncryptedWithKms`
+    ///
+    /// Run in every region, so shadow copies of multi-region trails are
+    /// left out and each trail is only reported in its home region
     pub(crate) async fn aws_3_1_ensure_cloudtrail_is_enabled_in_all_regions(
         &self,
     ) -> Result<TrailWrappers> {
         let config = self.config().await?;
         let client = aws_sdk_cloudtrail::Client::new(&config);
-        let trails = client.describe_trails().send().await?;
+        let trails = client
+            .describe_trails()
+            .include_shadow_trails(false)
+            .send()
+            .await?;
 
         let mut trail_wrappers = vec![];
 
         for trail in trails.trail_list.unwrap_or_default().into_iter() {
-            trail_wrappers.push(TrailWrapper {
-                trail,
-                trail_status: None,
-                event_selectors: None,
-            });
+            trail_wrappers.push(TrailWrapper::new(trail));
 
             let trail_wrapper = trail_wrappers.last_mut().expect("Just pushed onto vec");
 
@@ -953,6 +1503,11 @@ impl AwsClient {
             trail_wrapper.event_selectors = event_selectors.map(|es| es.into());
         }
 
+        let now = aws_smithy_types::DateTime::from(std::time::SystemTime::now());
+        trail_wrappers
+            .iter_mut()
+            .for_each(|trail_wrapper| trail_wrapper.evaluate(&now));
+
         Ok(TrailWrappers {
             inner: trail_wrappers,
         })
@@ -1267,6 +1822,422 @@ impl AwsClient {
         Ok(hubs)
     }
 
+    /// Security Hub: securityhub:GetFindings
+    ///
+    /// Findings with the workflow statuses from
+    /// [AwsFindingsConfig::from_env] updated since the last run. The
+    /// first run only collects active findings, later runs also collect
+    /// findings that have been archived since
+    pub(crate) async fn aws_securityhub_findings(&self) -> Result<AwsFindings> {
+        let config = self.config().await?;
+        let client = aws_sdk_securityhub::Client::new(&config);
+        let findings_config = AwsFindingsConfig::from_env();
+        let (watermark, state) = self.findings_watermark(&config, "securityhub").await?;
+
+        let equals = |value: &str| {
+            StringFilter::builder()
+                .comparison(StringFilterComparison::Equals)
+                .value(value)
+                .build()
+        };
+        let mut filters = AwsSecurityFindingFilters::builder().set_workflow_status(Some(
+            findings_config
+                .workflow_statuses
+                .iter()
+                .map(|status| equals(status))
+                .collect(),
+        ));
+        filters = match &watermark.updated_at {
+            Some(updated_at) => filters.updated_at(
+                DateFilter::builder()
+                    .start(updated_at)
+                    .end(
+                        aws_smithy_types::DateTime::from(std::time::SystemTime::now())
+                            .fmt(aws_smithy_types::date_time::Format::DateTime)?,
+                    )
+                    .build(),
+            ),
+            None => filters.record_state(equals("ACTIVE")),
+        };
+
+        let findings: Vec<AwsFinding> = client
+            .get_findings()
+            .filters(filters.build())
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_securityhub::types::AwsSecurityFinding>, _>>()
+            .await?
+            .into_iter()
+            .map(AwsFinding::from)
+            .collect();
+
+        let watermark = Self::pending_findings_watermark(watermark, state, &findings)?;
+        Ok(AwsFindings {
+            source: "securityhub_GetFindings",
+            inner: findings,
+            watermark,
+        })
+    }
+
+    /// GuardDuty: guardduty:ListDetectors, guardduty:ListFindings,
+    /// guardduty:GetFindings
+    ///
+    /// Findings updated since the last run. The first run only collects
+    /// unarchived findings, later runs also collect findings that have
+    /// been archived since
+    pub(crate) async fn aws_guardduty_findings(&self) -> Result<AwsFindings> {
+        let config = self.config().await?;
+        let client = aws_sdk_guardduty::Client::new(&config);
+        let (watermark, state) = self.findings_watermark(&config, "guardduty").await?;
+
+        let criteria = match watermark.epoch_millis()? {
+            Some(updated_at) => FindingCriteria::builder().criterion(
+                "updatedAt",
+                Condition::builder()
+                    .greater_than_or_equal(updated_at)
+                    .build(),
+            ),
+            None => FindingCriteria::builder().criterion(
+                "service.archived",
+                Condition::builder().equals("false").build(),
+            ),
+        }
+        .build();
+
+        let detector_ids: Vec<String> = client
+            .list_detectors()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+
+        let mut findings = vec![];
+        for detector_id in detector_ids {
+            let finding_ids: Vec<String> = client
+                .list_findings()
+                .detector_id(&detector_id)
+                .finding_criteria(criteria.clone())
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<_, _>>()
+                .await?;
+
+            // GetFindings accepts at most 50 ids
+            for chunk in finding_ids.chunks(50) {
+                findings.extend(
+                    client
+                        .get_findings()
+                        .detector_id(&detector_id)
+                        .set_finding_ids(Some(chunk.to_vec()))
+                        .send()
+                        .await?
+                        .findings
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(AwsFinding::from),
+                );
+            }
+        }
+
+        let watermark = Self::pending_findings_watermark(watermark, state, &findings)?;
+        Ok(AwsFindings {
+            source: "guardduty_GetFindings",
+            inner: findings,
+            watermark,
+        })
+    }
+
+    /// The saved findings watermark of `product` for the account and
+    /// region of `config`, with the store and key to save the next one.
+    ///
+    /// Without a state store every active finding is collected on
+    /// each run.
+    async fn findings_watermark(
+        &self,
+        config: &SdkConfig,
+        product: &str,
+    ) -> Result<(FindingsWatermark, Option<(Box<dyn StateStore>, String)>)> {
+        let Some(store) = state_store_from_env()? else {
+            warn!(
+                name = "AWS",
+                operation = "Findings",
+                product = product,
+                "No state store configured, collecting all active findings"
+            );
+            return Ok((FindingsWatermark::default(), None));
+        };
+        let account_id = self.caller_identity().await?.account_id;
+        let region = config
+            .region()
+            .map(|region| region.to_string())
+            .unwrap_or_default();
+        let key = FindingsWatermark::key(product, &account_id, &region);
+        let watermark = FindingsWatermark::load(store.as_ref(), &key)
+            .await
+            .with_context(|| format!("Loading findings watermark from {} store", store.name()))?;
+        Ok((watermark, Some((store, key))))
+    }
+
+    /// The watermark advanced past `findings`, saved by
+    /// [PendingWatermark::save] once the findings have been sent
+    fn pending_findings_watermark(
+        mut watermark: FindingsWatermark,
+        state: Option<(Box<dyn StateStore>, String)>,
+        findings: &[AwsFinding],
+    ) -> Result<Option<PendingWatermark>> {
+        let Some((store, key)) = state else {
+            return Ok(None);
+        };
+        watermark.advance(findings)?;
+        Ok(Some(PendingWatermark::new(store, key, watermark)))
+    }
+
+    /// EC2: ec2:GetEbsEncryptionByDefault and ec2:GetEbsDefaultKmsKeyId
+    pub(crate) async fn aws_2_2_1_ensure_ebs_volume_encryption_is_enabled_in_all_regions(
+        &self,
+    ) -> Result<EbsEncryptionByDefault> {
+        let config = self.config().await?;
+        let client = aws_sdk_ec2::Client::new(&config);
+        let encryption = client.get_ebs_encryption_by_default().send().await?;
+        let kms_key_id = client.get_ebs_default_kms_key_id().send().await?.kms_key_id;
+        Ok(EbsEncryptionByDefault {
+            region: config.region().map(|region| region.to_string()),
+            ebs_encryption_by_default: encryption.ebs_encryption_by_default,
+            kms_key_id,
+        })
+    }
+
+    /// 2.3.1
+    /// 2.3.2 `autoMinorVersionUpgradeEnabled`
+    /// 2.3.3 `publicAccessDisabled`
+    pub(crate) async fn aws_2_3_1_ensure_that_encryption_at_rest_is_enabled_for_rds_instances(
+        &self,
+    ) -> Result<DescribeDbInstances> {
+        let config = self.config().await?;
+        let client = aws_sdk_rds::Client::new(&config);
+        let db_instances: Vec<DbInstance> = client
+            .describe_db_instances()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_rds::types::DbInstance>, _>>()
+            .await?
+            .into_iter()
+            .map(|db_instance| db_instance.into())
+            .collect();
+        Ok(DescribeDbInstances {
+            inner: db_instances,
+        })
+    }
+
+    pub(crate) async fn aws_2_4_1_ensure_that_encryption_is_enabled_for_efs_file_systems(
+        &self,
+    ) -> Result<DescribeFileSystems> {
+        let config = self.config().await?;
+        let client = aws_sdk_efs::Client::new(&config);
+        let file_systems: Vec<FileSystem> = client
+            .describe_file_systems()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_efs::types::FileSystemDescription>, _>>()
+            .await?
+            .into_iter()
+            .map(|file_system| file_system.into())
+            .collect();
+        Ok(DescribeFileSystems {
+            inner: file_systems,
+        })
+    }
+
+    /// 4.1 - 4.15
+    ///
+    /// Metric filters on the CloudWatch Logs log group of each trail,
+    /// the alarms on the filter's metric and the subscribers to the
+    /// alarm's SNS topics
+    pub(crate) async fn aws_4_x_ensure_metric_filters_and_alarms_exist_for_cis_events(
+        &self,
+    ) -> Result<MetricFilterAlarms> {
+        let config = self.config().await?;
+        let trails = aws_sdk_cloudtrail::Client::new(&config)
+            .describe_trails()
+            .send()
+            .await?;
+
+        let mut metric_filter_alarms = vec![];
+        for trail in trails.trail_list.unwrap_or_default() {
+            let Some(log_group_arn) = trail.cloud_watch_logs_log_group_arn.as_deref() else {
+                metric_filter_alarms.push(MetricFilterAlarm::failed(
+                    &trail,
+                    None,
+                    "Trail does not deliver to a CloudWatch Logs log group",
+                ));
+                continue;
+            };
+            let Some((region, log_group_name)) = log_group_from_arn(log_group_arn) else {
+                warn!(
+                    name = "AWS",
+                    operation = "Parse log group ARN",
+                    log_group_arn = log_group_arn
+                );
+                metric_filter_alarms.push(MetricFilterAlarm::failed(
+                    &trail,
+                    None,
+                    "Trail log group ARN could not be parsed",
+                ));
+                continue;
+            };
+            let config = self.config_for_region(region).await?;
+            let logs_client = aws_sdk_cloudwatchlogs::Client::new(&config);
+            let cloudwatch_client = aws_sdk_cloudwatch::Client::new(&config);
+            let sns_client = aws_sdk_sns::Client::new(&config);
+
+            let metric_filters = logs_client
+                .describe_metric_filters()
+                .log_group_name(log_group_name)
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<Vec<aws_sdk_cloudwatchlogs::types::MetricFilter>, _>>()
+                .await?;
+
+            if metric_filters.is_empty() {
+                metric_filter_alarms.push(MetricFilterAlarm::failed(
+                    &trail,
+                    Some(log_group_name),
+                    "Log group has no metric filters",
+                ));
+            }
+
+            for metric_filter in metric_filters.iter() {
+                for transformation in metric_filter.metric_transformations() {
+                    let metric_alarms = cloudwatch_client
+                        .describe_alarms_for_metric()
+                        .metric_name(&transformation.metric_name)
+                        .namespace(&transformation.metric_namespace)
+                        .send()
+                        .await?
+                        .metric_alarms
+                        .unwrap_or_default();
+
+                    let mut alarms: Vec<MetricAlarm> = vec![];
+                    for metric_alarm in metric_alarms {
+                        let mut alarm = MetricAlarm::from(metric_alarm);
+                        for topic_arn in alarm
+                            .alarm_actions
+                            .iter()
+                            .filter(|action| action.starts_with("arn:") && action.contains(":sns:"))
+                        {
+                            match sns_client
+                                .list_subscriptions_by_topic()
+                                .topic_arn(topic_arn)
+                                .into_paginator()
+                                .items()
+                                .send()
+                                .collect::<Result<Vec<aws_sdk_sns::types::Subscription>, _>>()
+                                .await
+                            {
+                                Ok(subscriptions) => {
+                                    // Unconfirmed subscriptions have an ARN of `PendingConfirmation`
+                                    alarm.sns_subscriptions += subscriptions
+                                        .iter()
+                                        .filter(|subscription| {
+                                            subscription
+                                                .subscription_arn
+                                                .as_deref()
+                                                .is_some_and(|arn| arn.starts_with("arn:"))
+                                        })
+                                        .count();
+                                }
+                                Err(err) => {
+                                    warn!(name="AWS", operation="ListSubscriptionsByTopic", topic_arn=topic_arn, error=?err);
+                                }
+                            }
+                        }
+                        alarms.push(alarm);
+                    }
+
+                    metric_filter_alarms.push(MetricFilterAlarm::new(
+                        &trail,
+                        log_group_name,
+                        metric_filter,
+                        transformation,
+                        alarms,
+                    ));
+                }
+            }
+        }
+
+        Ok(MetricFilterAlarms {
+            inner: metric_filter_alarms,
+        })
+    }
+
+    pub(crate) async fn aws_5_1_ensure_no_network_acls_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports(
+        &self,
+    ) -> Result<DescribeNetworkAcls> {
+        let config = self.config().await?;
+        let ec2_client = aws_sdk_ec2::Client::new(&config);
+        let network_acls: Vec<NetworkAcl> = ec2_client
+            .describe_network_acls()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_ec2::types::NetworkAcl>, _>>()
+            .await?
+            .into_iter()
+            .map(|network_acl| network_acl.into())
+            .collect();
+        Ok(DescribeNetworkAcls {
+            inner: network_acls,
+        })
+    }
+
+    /// 5.2 `adminPortsOpenToInternetIpv4`
+    /// 5.3 `adminPortsOpenToInternetIpv6`
+    /// 5.4 `isDefault` and `restrictsAllTraffic`
+    pub(crate) async fn aws_5_2_ensure_no_security_groups_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports(
+        &self,
+    ) -> Result<DescribeSecurityGroups> {
+        let config = self.config().await?;
+        let ec2_client = aws_sdk_ec2::Client::new(&config);
+        let security_groups: Vec<SecurityGroup> = ec2_client
+            .describe_security_groups()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_ec2::types::SecurityGroup>, _>>()
+            .await?
+            .into_iter()
+            .map(|security_group| security_group.into())
+            .collect();
+        Ok(DescribeSecurityGroups {
+            inner: security_groups,
+        })
+    }
+
+    pub(crate) async fn aws_5_6_ensure_that_ec2_metadata_service_only_allows_imdsv2(
+        &self,
+    ) -> Result<DescribeInstances> {
+        let config = self.config().await?;
+        let ec2_client = aws_sdk_ec2::Client::new(&config);
+        let instances: Vec<Instance> = ec2_client
+            .describe_instances()
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<aws_sdk_ec2::types::Reservation>, _>>()
+            .await?
+            .into_iter()
+            .flat_map(|reservation| reservation.instances.unwrap_or_default())
+            .map(|instance| instance.into())
+            .collect();
+        Ok(DescribeInstances { inner: instances })
+    }
+
     // TODO: correct usecase ID
     pub(crate) async fn aws_dfe_1x(&self) -> Result<MfaDevices> {
         let config = self.config().await?;
@@ -1369,7 +2340,18 @@ impl AwsClient {
     }
 
     // TODO: correct usecase ID
-    pub(crate) async fn aws_dfe_4x(&self) -> Result<crate::aws_route53::HostedZones> {
+    /// Route53 hosted zones and their record sets, shared by
+    /// [AwsClient::aws_dfe_5x] and [AwsClient::aws_route53_takeover]
+    pub(crate) async fn aws_dfe_4x(&self) -> Result<HostedZones> {
+        self.collected
+            .hosted_zones
+            .get_or_try_init(|| self.list_hosted_zones())
+            .await
+            .cloned()
+    }
+
+    /// Route53: route53:ListHostedZones, route53:ListResourceRecordSets
+    async fn list_hosted_zones(&self) -> Result<HostedZones> {
         let config = self.config().await?;
         let client = aws_sdk_route53::Client::new(&config);
         let mut hosted_zones = client
@@ -1385,15 +2367,29 @@ impl AwsClient {
 
         for zone in hosted_zones.iter_mut() {
             // TODO: Might want to break zone.resource_record_sets into individual Splunk events
-            zone.resource_record_sets = client
+            let mut resource_record_sets = vec![];
+            // ListResourceRecordSets returns at most 300 records and has
+            // no paginator, the next page starts at the record it names
+            let mut request = client
                 .list_resource_record_sets()
-                .set_hosted_zone_id(Some(zone.id.clone()))
-                .send()
-                .await?
-                .resource_record_sets
-                .into_iter()
-                .map(|rrs| Some(rrs.into()))
-                .collect();
+                .set_hosted_zone_id(Some(zone.id.clone()));
+            loop {
+                let output = request.clone().send().await?;
+                resource_record_sets.extend(
+                    output
+                        .resource_record_sets
+                        .into_iter()
+                        .map(|rrs| rrs.into()),
+                );
+                if !output.is_truncated {
+                    break;
+                }
+                request = request
+                    .set_start_record_name(output.next_record_name)
+                    .set_start_record_type(output.next_record_type)
+                    .set_start_record_identifier(output.next_record_identifier);
+            }
+            zone.resource_record_sets = Some(resource_record_sets);
         }
 
         Ok(HostedZones {
@@ -1401,10 +2397,9 @@ impl AwsClient {
         })
     }
 
-    pub(crate) async fn aws_dfe_5x(
-        &self,
-        zones: crate::aws_route53::HostedZones,
-    ) -> Result<RecordSets> {
+    pub(crate) async fn aws_dfe_5x(&self) -> Result<RecordSets> {
+        let zones = self.aws_dfe_4x().await?;
+
         // Build resolver
         let resolver = TokioResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
 
@@ -1562,6 +2557,95 @@ impl AwsClient {
 
         Ok(RecordSets { inner: sets })
     }
+
+    /// Inventory of S3 buckets, IAM users and roles and Route53 zones
+    /// keyed by ARN, built from the results of
+    /// [AwsClient::aws_s3_bucket_exposure],
+    /// [AwsClient::aws_iam_effective_permissions] and
+    /// [AwsClient::aws_dfe_4x] so each is only collected once per run
+    ///
+    /// When a state store is configured the inventory is compared
+    /// with the last run to add `created`, `modified` and `deleted`
+    /// events. The new inventory is saved for the next run once the
+    /// events have been sent
+    pub(crate) async fn aws_inventory(&self) -> Result<AwsInventory> {
+        let account_id = self.caller_identity().await?.account_id;
+        let mut inventory = AwsInventory::default();
+
+        for bucket in self.aws_s3_bucket_exposure().await?.inner {
+            inventory.add(
+                &format!("arn:aws:s3:::{}", bucket.bucket_name),
+                "s3_bucket",
+                &bucket,
+            )?;
+        }
+
+        for principal in self.aws_iam_effective_permissions().await?.inner {
+            let Some(arn) = &principal.principal_arn else {
+                continue;
+            };
+            inventory.add(
+                arn,
+                &format!("iam_{}", principal.principal_type),
+                &principal,
+            )?;
+        }
+
+        for zone in self.aws_dfe_4x().await?.inner {
+            inventory.add(
+                &format!(
+                    "arn:aws:route53:::hostedzone/{}",
+                    zone.id.trim_start_matches("/hostedzone/")
+                ),
+                "route53_hosted_zone",
+                &zone,
+            )?;
+        }
+
+        let Some(store) = state_store_from_env()? else {
+            warn!(
+                name = "AWS",
+                operation = "Inventory",
+                "No state store configured, only sending the snapshot"
+            );
+            inventory.events(None);
+            return Ok(inventory);
+        };
+
+        let previous = Inventory::load(store.as_ref(), &account_id)
+            .await
+            .with_context(|| format!("Loading inventory from {} store", store.name()))?;
+        inventory.events(Some(&previous));
+        inventory.pending = Some(PendingInventory::new(
+            store,
+            account_id,
+            inventory.inventory(Some(&previous)),
+        ));
+        Ok(inventory)
+    }
+
+    /// Route53: route53:ListHostedZones, route53:ListResourceRecordSets
+    /// S3: s3:ListAllMyBuckets
+    ///
+    /// CNAME and alias records pointing at deleted resources on
+    /// takeover prone services
+    pub(crate) async fn aws_route53_takeover(&self) -> Result<TakeoverFindings> {
+        let zones = self.aws_dfe_4x().await?;
+
+        let config = self.config().await?;
+        let buckets = aws_sdk_s3::Client::new(&config)
+            .list_buckets()
+            .send()
+            .await?
+            .buckets
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|bucket| bucket.name)
+            .collect();
+
+        let probe = HickoryTakeoverProbe::new()?;
+        Ok(find_takeovers(&probe, &zones, &buckets).await)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -1863,6 +2947,9 @@ impl ToHecEvents for &ContactInformationSerde {
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct AccountSummary {
     pub summary_map: Option<::std::collections::HashMap<String, i32>>,
+    /// `AccountMFAEnabled` is 1, CIS AWS 1.5
+    #[serde(default)]
+    pub root_account_mfa_enabled: bool,
 }
 
 impl From<GetAccountSummaryOutput> for AccountSummary {
@@ -1873,7 +2960,15 @@ impl From<GetAccountSummaryOutput> for AccountSummary {
                 .collect::<HashMap<String, i32>>()
         });
 
-        Self { summary_map }
+        let root_account_mfa_enabled = summary_map
+            .as_ref()
+            .and_then(|summary_map| summary_map.get("AccountMFAEnabled"))
+            == Some(&1);
+
+        Self {
+            summary_map,
+            root_account_mfa_enabled,
+        }
     }
 }
 
@@ -1909,6 +3004,9 @@ pub(crate) struct AccountPasswordPolicy {
     max_password_age: Option<i32>,
     password_reuse_prevention: Option<i32>,
     hard_expiry: Option<bool>,
+    /// At least the last 24 passwords cannot be reused, CIS AWS 1.9
+    #[serde(default)]
+    prevents_password_reuse: bool,
 }
 
 impl From<aws_sdk_iam::types::PasswordPolicy> for AccountPasswordPolicy {
@@ -1922,6 +3020,9 @@ impl From<aws_sdk_iam::types::PasswordPolicy> for AccountPasswordPolicy {
             allow_users_to_change_password: value.allow_users_to_change_password,
             expire_passwords: value.expire_passwords,
             max_password_age: value.max_password_age,
+            prevents_password_reuse: value
+                .password_reuse_prevention
+                .is_some_and(|remembered| remembered >= 24),
             password_reuse_prevention: value.password_reuse_prevention,
             hard_expiry: value.hard_expiry,
         }
@@ -1978,13 +3079,79 @@ pub(crate) struct CredentialReportUser {
     cert_1_last_rotated: String,
     cert_2_active: bool,
     cert_2_last_rotated: String,
+    /// A console user has an active access key that has never been
+    /// used, i.e. it was set up with the user, CIS AWS 1.11
+    #[serde(skip_deserializing)]
+    unused_access_key_with_console_password: bool,
+    /// The password or an active access key has not been used for 45
+    /// days or more, CIS AWS 1.12
+    #[serde(skip_deserializing)]
+    credentials_unused_45_days: bool,
+    /// An active access key was last rotated more than 90 days ago, CIS AWS 1.14
+    #[serde(skip_deserializing)]
+    access_key_not_rotated_90_days: bool,
+}
+
+impl CredentialReportUser {
+    /// Set the fields evaluating CIS AWS 1.11, 1.12 and 1.14
+    ///
+    /// Credentials that have never been used are aged from when they
+    /// were last changed or rotated.
+    fn evaluate(mut self, now: &aws_smithy_types::DateTime) -> Self {
+        let access_keys = [
+            (
+                self.access_key_1_active,
+                &self.access_key_1_last_used_date,
+                &self.access_key_1_last_rotated,
+            ),
+            (
+                self.access_key_2_active,
+                &self.access_key_2_last_used_date,
+                &self.access_key_2_last_rotated,
+            ),
+        ];
+        let active_keys = || access_keys.iter().filter(|(active, _, _)| *active);
+
+        self.unused_access_key_with_console_password = self.password_enabled
+            && active_keys().any(|(_, last_used, _)| last_used.as_str() == "N/A");
+
+        let password_unused = self.password_enabled
+            && days_since(&self.password_last_used, now)
+                .or_else(|| days_since(&self.password_last_changed, now))
+                .is_some_and(|days| days >= 45);
+        let access_key_unused = active_keys().any(|(_, last_used, last_rotated)| {
+            days_since(last_used, now)
+                .or_else(|| days_since(last_rotated, now))
+                .is_some_and(|days| days >= 45)
+        });
+        self.credentials_unused_45_days = password_unused || access_key_unused;
+
+        self.access_key_not_rotated_90_days = active_keys().any(|(_, _, last_rotated)| {
+            days_since(last_rotated, now).is_some_and(|days| days > 90)
+        });
+        self
+    }
+}
+
+/// Whole days from a credential report date to `now`
+///
+/// `N/A`, `no_information` and `not_supported` do not parse and are `None`
+fn days_since(value: &str, now: &aws_smithy_types::DateTime) -> Option<i64> {
+    aws_smithy_types::DateTime::from_str(
+        value,
+        aws_smithy_types::date_time::Format::DateTimeWithOffset,
+    )
+    .ok()
+    .map(|date| (now.secs() - date.secs()) / 86_400)
 }
 
 impl From<&[u8]> for CredentialReport {
     fn from(value: &[u8]) -> Self {
+        let now = aws_smithy_types::DateTime::from(std::time::SystemTime::now());
         let users = csv::Reader::from_reader(value)
             .deserialize::<CredentialReportUser>()
             .filter_map(|r| r.ok())
+            .map(|user| user.evaluate(&now))
             .collect();
         Self { inner: users }
     }
@@ -2009,6 +3176,114 @@ impl ToHecEvents for &CredentialReport {
     }
 }
 
+/// The `<root_account>` row of the credential report.
+///
+/// Kept apart from [CredentialReportUser] as the root user has
+/// `not_supported` in place of several booleans
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RootUserUsage {
+    arn: String,
+    mfa_active: bool,
+    password_last_used: String,
+    access_key_1_active: bool,
+    access_key_1_last_used_date: String,
+    access_key_2_active: bool,
+    access_key_2_last_used_date: String,
+    /// Days since the password or either access key was last used
+    days_since_last_used: Option<i64>,
+}
+
+impl RootUserUsage {
+    const ROOT_ACCOUNT: &'static str = "<root_account>";
+
+    pub(crate) fn from_credential_report(content: &[u8]) -> Option<Self> {
+        let root = csv::Reader::from_reader(content)
+            .deserialize::<HashMap<String, String>>()
+            .filter_map(|r| r.ok())
+            .find(|row| row.get("user").map(String::as_str) == Some(Self::ROOT_ACCOUNT))?;
+        let get = |field: &str| root.get(field).cloned().unwrap_or_default();
+        let now = aws_smithy_types::DateTime::from(std::time::SystemTime::now());
+        let password_last_used = get("password_last_used");
+        let access_key_1_last_used_date = get("access_key_1_last_used_date");
+        let access_key_2_last_used_date = get("access_key_2_last_used_date");
+        let days_since_last_used = [
+            &password_last_used,
+            &access_key_1_last_used_date,
+            &access_key_2_last_used_date,
+        ]
+        .into_iter()
+        .filter_map(|value| days_since(value, &now))
+        .min();
+        Some(Self {
+            arn: get("arn"),
+            mfa_active: get("mfa_active") == "true",
+            password_last_used,
+            access_key_1_active: get("access_key_1_active") == "true",
+            access_key_1_last_used_date,
+            access_key_2_active: get("access_key_2_active") == "true",
+            access_key_2_last_used_date,
+            days_since_last_used,
+        })
+    }
+}
+
+impl ToHecEvents for &RootUserUsage {
+    type Item = Self;
+
+    fn source(&self) -> &str {
+        "iam_GetCredentialReport_root_account"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(iter::once(self))
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CredentialReport;
+
+    #[test]
+    fn test_credential_report_evaluates_cis_1_11_1_12_1_14() {
+        let now = aws_smithy_types::DateTime::from(std::time::SystemTime::now());
+        let days_ago = |days: i64| {
+            aws_smithy_types::DateTime::from_secs(now.secs() - days * 86_400)
+                .fmt(aws_smithy_types::date_time::Format::DateTime)
+                .unwrap()
+        };
+        let header = "user,arn,user_creation_time,password_enabled,password_last_used,password_last_changed,password_next_rotation,mfa_active,access_key_1_active,access_key_1_last_rotated,access_key_1_last_used_date,access_key_1_last_used_region,access_key_1_last_used_service,access_key_2_active,access_key_2_last_rotated,access_key_2_last_used_date,access_key_2_last_used_region,access_key_2_last_used_service,cert_1_active,cert_1_last_rotated,cert_2_active,cert_2_last_rotated";
+        let compliant = format!(
+            "compliant,arn:aws:iam::1:user/compliant,{0},true,{1},{0},N/A,true,true,{1},{1},eu-west-2,s3,false,N/A,N/A,N/A,N/A,false,N/A,false,N/A",
+            days_ago(10),
+            days_ago(1)
+        );
+        let stale = format!(
+            "stale,arn:aws:iam::1:user/stale,{0},true,{0},{0},N/A,true,true,{0},N/A,N/A,N/A,false,N/A,N/A,N/A,N/A,false,N/A,false,N/A",
+            days_ago(100)
+        );
+        let csv = format!("{}\n{}\n{}\n", header, compliant, stale);
+        let report = CredentialReport::from(csv.as_bytes());
+        assert_eq!(report.inner.len(), 2);
+
+        let compliant = &report.inner[0];
+        assert!(!compliant.unused_access_key_with_console_password);
+        assert!(!compliant.credentials_unused_45_days);
+        assert!(!compliant.access_key_not_rotated_90_days);
+
+        let stale = &report.inner[1];
+        assert!(stale.unused_access_key_with_console_password);
+        assert!(stale.credentials_unused_45_days);
+        assert!(stale.access_key_not_rotated_90_days);
+    }
+}
+
 #[cfg(feature = "live_tests")]
 #[cfg(test)]
 mod live_tests {
@@ -2037,9 +3312,7 @@ mod live_tests {
             secrets.splunk_token.as_ref().context("No value")?,
             true,
         )?;
-        let aws = AwsClient {
-            secrets: Arc::new(secrets),
-        };
+        let aws = AwsClient::new(&secrets).await?;
         Ok((splunk, aws))
     }
 
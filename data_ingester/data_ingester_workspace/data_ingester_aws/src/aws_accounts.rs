@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use data_ingester_splunk::manifest::ManifestStep;
+use data_ingester_splunk::sink::{HecSink, SinkFuture};
+use data_ingester_splunk::splunk::{HecEvent, ToHecEvents};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Configuration for collecting from every account in an AWS Organization
+///
+/// Read from the environment by [AwsOrganizationConfig::from_env]:
+/// SSPHP_AWS_ORGANIZATION_ROLE: name of the audit role to assume in each member account
+/// SSPHP_AWS_ORGANIZATION_EXTERNAL_ID: external ID required by the role's trust policy
+/// SSPHP_AWS_ORGANIZATION_SESSION_NAME: role session name, defaults to `ssphp-aws-collector`
+#[derive(Debug, Clone)]
+pub(crate) struct AwsOrganizationConfig {
+    pub(crate) role_name: String,
+    pub(crate) external_id: Option<String>,
+    pub(crate) session_name: String,
+}
+
+impl AwsOrganizationConfig {
+    /// Returns `None` when SSPHP_AWS_ORGANIZATION_ROLE is not set and
+    /// only the account of the credentials should be collected
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let Ok(role_name) = std::env::var("SSPHP_AWS_ORGANIZATION_ROLE") else {
+            return Ok(None);
+        };
+        if role_name.is_empty() {
+            anyhow::bail!("SSPHP_AWS_ORGANIZATION_ROLE must not be empty");
+        }
+        let session_name = std::env::var("SSPHP_AWS_ORGANIZATION_SESSION_NAME")
+            .unwrap_or_else(|_| "ssphp-aws-collector".to_string());
+        Ok(Some(Self {
+            role_name,
+            external_id: std::env::var("SSPHP_AWS_ORGANIZATION_EXTERNAL_ID").ok(),
+            session_name,
+        }))
+    }
+
+    /// ARN of the audit role in `account_id`
+    pub(crate) fn role_arn(&self, partition: &str, account_id: &str) -> String {
+        format!(
+            "arn:{}:iam::{}:role/{}",
+            partition, account_id, self.role_name
+        )
+    }
+}
+
+/// Result of sts:GetCallerIdentity
+#[derive(Debug, Clone)]
+pub(crate) struct CallerIdentity {
+    pub(crate) account_id: String,
+    pub(crate) arn: String,
+}
+
+impl CallerIdentity {
+    /// Partition from the caller ARN, e.g. `aws` or `aws-us-gov`
+    pub(crate) fn partition(&self) -> Result<&str> {
+        self.arn
+            .split(':')
+            .nth(1)
+            .filter(|partition| !partition.is_empty())
+            .with_context(|| format!("No partition in caller ARN: {}", self.arn))
+    }
+}
+
+/// An AWS account being collected
+#[derive(Debug, Clone)]
+pub(crate) struct AwsAccount {
+    pub(crate) account_id: String,
+    /// Name of the account in the Organization
+    pub(crate) account_name: Option<String>,
+    /// IAM account alias
+    pub(crate) account_alias: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AssumptionStatus {
+    Ok,
+    Failed,
+}
+
+/// Whether the audit role could be assumed in an Organization account
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AccountAssumption {
+    pub(crate) account_id: String,
+    pub(crate) account_name: Option<String>,
+    pub(crate) account_alias: Option<String>,
+    /// The role assumed, or the caller ARN for the management account
+    pub(crate) role_arn: String,
+    pub(crate) session_name: String,
+    pub(crate) external_id_used: bool,
+    pub(crate) status: AssumptionStatus,
+    pub(crate) error: Option<String>,
+}
+
+impl AccountAssumption {
+    pub(crate) fn new(
+        account: &AwsAccount,
+        caller: &CallerIdentity,
+        organization_config: &AwsOrganizationConfig,
+    ) -> Self {
+        let role_arn = if account.account_id == caller.account_id {
+            caller.arn.to_string()
+        } else {
+            organization_config.role_arn(caller.partition().unwrap_or("aws"), &account.account_id)
+        };
+        Self {
+            account_id: account.account_id.to_string(),
+            account_name: account.account_name.clone(),
+            account_alias: account.account_alias.clone(),
+            role_arn,
+            session_name: organization_config.session_name.to_string(),
+            external_id_used: organization_config.external_id.is_some(),
+            status: AssumptionStatus::Ok,
+            error: None,
+        }
+    }
+
+    pub(crate) fn failed(&mut self, error: String) {
+        self.status = AssumptionStatus::Failed;
+        self.error = Some(error);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AccountAssumptions {
+    inner: Vec<AccountAssumption>,
+}
+
+impl AccountAssumptions {
+    pub(crate) fn new(inner: Vec<AccountAssumption>) -> Self {
+        Self { inner }
+    }
+}
+
+impl ToHecEvents for &AccountAssumptions {
+    type Item = AccountAssumption;
+
+    fn source(&self) -> &str {
+        "sts_AssumeRole"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+/// A [HecSink] adding `account_id` and `account_alias` to every event
+/// from one AWS account.
+///
+/// Manifest step names are prefixed with the account ID so steps from
+/// different accounts can be told apart.
+pub(crate) struct AccountSink {
+    account: AwsAccount,
+    inner: Arc<dyn HecSink>,
+}
+
+impl AccountSink {
+    pub(crate) fn new(account: AwsAccount, inner: Arc<dyn HecSink>) -> Self {
+        Self { account, inner }
+    }
+
+    fn tag(&self, mut event: HecEvent) -> HecEvent {
+        let mut value: Value = match serde_json::from_str(&event.event) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!(name="AWS", operation="Tag event with account", error=?err);
+                return event;
+            }
+        };
+        let Some(object) = value.as_object_mut() else {
+            return event;
+        };
+        let _ = object.insert(
+            "account_id".to_string(),
+            Value::String(self.account.account_id.to_string()),
+        );
+        let _ = object.insert(
+            "account_alias".to_string(),
+            self.account
+                .account_alias
+                .as_ref()
+                .map(|alias| Value::String(alias.to_string()))
+                .unwrap_or(Value::Null),
+        );
+        match serde_json::to_string(&value) {
+            Ok(json) => event.event = json,
+            Err(err) => {
+                warn!(name="AWS", operation="Tag event with account", error=?err);
+            }
+        }
+        event
+    }
+}
+
+impl HecSink for AccountSink {
+    fn name(&self) -> &str {
+        "aws_account"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        self.inner
+            .send_batch(events.into_iter().map(|event| self.tag(event)).collect())
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        self.inner.send_collector_batch(
+            collector,
+            events.into_iter().map(|event| self.tag(event)).collect(),
+        )
+    }
+
+    fn record_step(&self, mut step: ManifestStep) {
+        step.name = format!("{}:{}", self.account.account_id, step.name);
+        self.inner.record_step(step)
+    }
+}
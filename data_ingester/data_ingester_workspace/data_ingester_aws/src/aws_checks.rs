@@ -0,0 +1,699 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Result};
+use data_ingester_splunk::manifest::{ManifestStep, StepStatus};
+use data_ingester_splunk::sink::{HecSink, SinkFuture};
+use data_ingester_splunk::splunk::{try_collect_send, HecEvent, ToHecEvents};
+use futures::future::{BoxFuture, FutureExt};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::aws::AwsClient;
+use crate::aws_findings::AwsFindings;
+use crate::aws_inventory::AwsInventory;
+use crate::aws_regions::RegionalEvents;
+
+const CIS_V3: &str = "CIS AWS Foundations Benchmark v3.0.0";
+const DFE: &str = "DfE";
+
+/// Runs a check and sends its events.
+///
+/// Takes the check name, the client for the account, the regions
+/// enabled for the account and the sink
+pub(crate) type CheckFn =
+    for<'a> fn(&'static str, &'a AwsClient, &'a [String], &'a dyn HecSink) -> BoxFuture<'a, ()>;
+
+/// A named check in the [aws_checks] registry
+pub(crate) struct AwsCheck {
+    /// Name of the check, used as the manifest step name
+    pub(crate) name: &'static str,
+    /// CIS controls the events are used to score
+    pub(crate) cis_ids: &'static [&'static str],
+    pub(crate) benchmark: &'static str,
+    /// AWS service the check calls
+    pub(crate) service: &'static str,
+    /// Run once in every region from [AwsClient::list_of_regions]
+    pub(crate) regional: bool,
+    pub(crate) run: CheckFn,
+}
+
+impl AwsCheck {
+    /// Does `pattern` name this check by its name, a CIS id or its service
+    fn matches(&self, pattern: &str) -> bool {
+        self.name == pattern || self.service == pattern || self.cis_ids.contains(&pattern)
+    }
+}
+
+/// Which checks from [aws_checks] to run
+///
+/// Each entry is a check name, a CIS id such as `3.1` or a service
+/// such as `cloudtrail`. An empty `include` runs every check, and
+/// `exclude` is applied after `include`.
+///
+/// Read from the environment by [AwsCheckFilter::from_env]:
+/// SSPHP_AWS_CHECKS: comma separated checks to run
+/// SSPHP_AWS_CHECKS_EXCLUDE: comma separated checks to skip
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AwsCheckFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl AwsCheckFilter {
+    pub fn from_env() -> Self {
+        let list = |key: &str| {
+            std::env::var(key)
+                .unwrap_or_default()
+                .split(',')
+                .map(|check| check.trim())
+                .filter(|check| !check.is_empty())
+                .map(|check| check.to_string())
+                .collect()
+        };
+        Self {
+            include: list("SSPHP_AWS_CHECKS"),
+            exclude: list("SSPHP_AWS_CHECKS_EXCLUDE"),
+        }
+    }
+
+    /// Fails if an entry in `include` or `exclude` matches no check in
+    /// [aws_checks]
+    pub fn validate(&self) -> Result<()> {
+        let _ = self.select(&aws_checks())?;
+        Ok(())
+    }
+
+    /// The checks to run, in registry order.
+    ///
+    /// Fails if an entry in `include` or `exclude` matches no check so
+    /// a mistyped control isn't silently skipped.
+    pub(crate) fn select<'c>(&self, checks: &'c [AwsCheck]) -> Result<Vec<&'c AwsCheck>> {
+        for pattern in self.include.iter().chain(self.exclude.iter()) {
+            if !checks.iter().any(|check| check.matches(pattern)) {
+                bail!("No AWS check matches: {}", pattern);
+            }
+        }
+        Ok(checks
+            .iter()
+            .filter(|check| {
+                self.include.is_empty() || self.include.iter().any(|pattern| check.matches(pattern))
+            })
+            .filter(|check| !self.exclude.iter().any(|pattern| check.matches(pattern)))
+            .collect())
+    }
+}
+
+/// Collect a check and send it, failures are recorded in the manifest
+/// by [try_collect_send]
+async fn send<T>(name: &str, future: impl Future<Output = Result<T>>, splunk: &dyn HecSink)
+where
+    for<'a> &'a T: ToHecEvents + Debug,
+{
+    let _ = try_collect_send(name, future, splunk).await;
+}
+
+/// Passes everything to `inner`, noting whether the step recorded by
+/// [try_collect_send] failed
+struct StepOutcome<'s> {
+    inner: &'s dyn HecSink,
+    failed: AtomicBool,
+}
+
+impl<'s> StepOutcome<'s> {
+    fn new(inner: &'s dyn HecSink) -> Self {
+        Self {
+            inner,
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl HecSink for StepOutcome<'_> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        self.inner.send_batch(events)
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        self.inner.send_collector_batch(collector, events)
+    }
+
+    fn record_step(&self, step: ManifestStep) {
+        if step.status == StepStatus::Failed {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+        self.inner.record_step(step)
+    }
+}
+
+/// Collect findings in every region and send them, then save the
+/// watermark of each region.
+///
+/// Watermarks are only saved once the findings have been sent, so
+/// findings from a failed send are collected again on the next run
+async fn send_findings(
+    name: &str,
+    future: impl Future<Output = Result<(RegionalEvents, Vec<AwsFindings>)>>,
+    splunk: &dyn HecSink,
+) {
+    let outcome = StepOutcome::new(splunk);
+    let (events, findings) = match future.await {
+        Ok((events, findings)) => (Ok(events), findings),
+        Err(err) => (Err(err), vec![]),
+    };
+    let _ = try_collect_send(name, async { events }, &outcome).await;
+    if outcome.failed() {
+        warn!(
+            name = "AWS",
+            operation = "Findings",
+            check = name,
+            "Findings not sent, keeping the last watermark"
+        );
+        return;
+    }
+    for watermark in findings
+        .iter()
+        .filter_map(|findings| findings.watermark.as_ref())
+    {
+        if let Err(err) = watermark.save().await {
+            warn!(name="AWS", operation="Findings", check=name, error=?err);
+        }
+    }
+}
+
+/// Collect the inventory and send it, then save it for the next run.
+///
+/// The inventory is only saved once its events have been sent, so
+/// changes from a failed send are found again on the next run
+async fn send_inventory(
+    name: &str,
+    future: impl Future<Output = Result<AwsInventory>>,
+    splunk: &dyn HecSink,
+) {
+    let outcome = StepOutcome::new(splunk);
+    let result = try_collect_send(name, future, &outcome).await;
+    if outcome.failed() {
+        warn!(
+            name = "AWS",
+            operation = "Inventory",
+            check = name,
+            "Inventory not sent, keeping the last inventory"
+        );
+        return;
+    }
+    if let Some(pending) = result.ok().and_then(|inventory| inventory.pending) {
+        if let Err(err) = pending.save().await {
+            warn!(name="AWS", operation="Inventory", check=name, error=?err);
+        }
+    }
+}
+
+/// A check run once for the account, named after the [AwsClient]
+/// method that collects it, sent with [send] unless another sender
+/// is given
+macro_rules! global {
+    ($check:ident, [$($cis_id:literal),* $(,)?], $benchmark:expr, $service:literal) => {
+        global!($check, [$($cis_id),*], $benchmark, $service, send)
+    };
+    ($check:ident, [$($cis_id:literal),* $(,)?], $benchmark:expr, $service:literal, $send:ident) => {
+        AwsCheck {
+            name: stringify!($check),
+            cis_ids: &[$($cis_id),*],
+            benchmark: $benchmark,
+            service: $service,
+            regional: false,
+            run: |name, client, _, splunk| $send(name, client.$check(), splunk).boxed(),
+        }
+    };
+}
+
+/// A check run in every region with [AwsClient::regional], named after
+/// the [AwsClient] method that collects it
+macro_rules! regional {
+    ($check:ident, [$($cis_id:literal),* $(,)?], $benchmark:expr, $service:literal) => {
+        AwsCheck {
+            name: stringify!($check),
+            cis_ids: &[$($cis_id),*],
+            benchmark: $benchmark,
+            service: $service,
+            regional: true,
+            run: |name, client, regions, splunk| {
+                send(
+                    name,
+                    client.regional(regions, |client| async move { client.$check().await }),
+                    splunk,
+                )
+                .boxed()
+            },
+        }
+    };
+}
+
+/// A findings check run in every region with
+/// [AwsClient::regional_results] and sent with [send_findings]
+macro_rules! findings {
+    ($check:ident, $service:literal) => {
+        AwsCheck {
+            name: stringify!($check),
+            cis_ids: &[],
+            benchmark: DFE,
+            service: $service,
+            regional: true,
+            run: |name, client, regions, splunk| {
+                send_findings(
+                    name,
+                    client.regional_results(regions, |client| async move { client.$check().await }),
+                    splunk,
+                )
+                .boxed()
+            },
+        }
+    };
+}
+
+/// Every check run by the AWS collector, in the order they run
+pub(crate) fn aws_checks() -> Vec<AwsCheck> {
+    vec![
+        global!(aws_1_1_maintain_current_contact_details, ["1.1"], CIS_V3, "account"),
+        global!(
+            aws_1_2_ensure_security_contact_information_is_registered,
+            ["1.2"],
+            CIS_V3,
+            "account"
+        ),
+        global!(
+            aws_1_4_ensure_no_root_user_account_access_key_exists,
+            ["1.4", "1.5"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_8_ensure_iam_password_policy_requires_minimum_length_of_14,
+            ["1.8", "1.9"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_6_ensure_hardware_mfa_is_enabled_for_the_root_user_account,
+            ["1.6"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_7_eliminate_use_of_the_root_user_for_administrative_and_daily_tasks,
+            ["1.5", "1.7"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_10_ensure_mfa_is_enabled_for_all_iam_users_that_have_a_console_password,
+            ["1.10", "1.11", "1.12", "1.14"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_13_ensure_there_is_only_one_active_access_key_available_for_any_single_iam_user,
+            ["1.13"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_15_ensure_iam_users_receive_permissions_only_through_groups,
+            ["1.15"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_16_ensure_iam_policies_that_allow_full_administrative_privileges_are_not_attached,
+            ["1.16"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_17_ensure_a_support_role_has_been_created_to_manage_incidents_with_aws_support,
+            ["1.17"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_1_19_ensure_that_all_the_expired_tls_certificates_stored_in_aws_iam_are_removed,
+            ["1.19"],
+            CIS_V3,
+            "iam"
+        ),
+        regional!(
+            aws_1_20_ensure_that_iam_access_analyzer_is_enabled_for_all_regions,
+            ["1.20"],
+            CIS_V3,
+            "accessanalyzer"
+        ),
+        global!(
+            aws_1_22_ensure_access_to_awscloudshellfullaccess_is_restricted,
+            ["1.22"],
+            CIS_V3,
+            "iam"
+        ),
+        global!(
+            aws_2_1_1_ensure_s3_bucket_policy_is_set_to_deny_http_requests,
+            ["2.1.1"],
+            CIS_V3,
+            "s3"
+        ),
+        global!(aws_2_1_2_ensure_mfa_delete_is_enabled_on_s3_buckets, ["2.1.2"], CIS_V3, "s3"),
+        global!(
+            aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access,
+            ["2.1.4"],
+            CIS_V3,
+            "s3"
+        ),
+        global!(
+            aws_2_1_4_ensure_that_s3_buckets_are_configured_with_block_public_access_accounts,
+            ["2.1.4"],
+            CIS_V3,
+            "s3control"
+        ),
+        regional!(
+            aws_2_2_1_ensure_ebs_volume_encryption_is_enabled_in_all_regions,
+            ["2.2.1"],
+            CIS_V3,
+            "ec2"
+        ),
+        regional!(
+            aws_2_3_1_ensure_that_encryption_at_rest_is_enabled_for_rds_instances,
+            ["2.3.1", "2.3.2", "2.3.3"],
+            CIS_V3,
+            "rds"
+        ),
+        regional!(
+            aws_2_4_1_ensure_that_encryption_is_enabled_for_efs_file_systems,
+            ["2.4.1"],
+            CIS_V3,
+            "efs"
+        ),
+        regional!(
+            aws_3_1_ensure_cloudtrail_is_enabled_in_all_regions,
+            ["3.1", "3.2", "3.4", "3.7"],
+            CIS_V3,
+            "cloudtrail"
+        ),
+        global!(
+            aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_acl,
+            ["3.3"],
+            CIS_V3,
+            "s3"
+        ),
+        global!(
+            aws_3_3_ensure_the_s3_bucket_used_to_store_cloudtrail_logs_is_not_publicly_accessible_bucket_policy,
+            ["3.3"],
+            CIS_V3,
+            "s3"
+        ),
+        regional!(aws_3_5_ensure_aws_config_is_enabled_in_all_regions, ["3.5"], CIS_V3, "config"),
+        global!(
+            aws_3_6_ensure_s3_bucket_access_logging_is_enabled_on_the_cloudtrail_s3_bucket,
+            ["3.6"],
+            CIS_V3,
+            "s3"
+        ),
+        regional!(
+            aws_3_8_ensure_rotation_for_customer_created_symmetric_cmks_is_enabled,
+            ["3.8"],
+            CIS_V3,
+            "kms"
+        ),
+        regional!(
+            aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_vpc,
+            ["3.9"],
+            CIS_V3,
+            "ec2"
+        ),
+        regional!(
+            aws_3_9_ensure_vpc_flow_logging_is_enabled_in_all_vpcs_flow_logs,
+            ["3.9"],
+            CIS_V3,
+            "ec2"
+        ),
+        global!(
+            aws_4_x_ensure_metric_filters_and_alarms_exist_for_cis_events,
+            [
+                "4.1",
+                "4.2",
+                "4.3",
+                "4.4",
+                "4.5",
+                "4.6",
+                "4.7",
+                "4.8",
+                "4.9",
+                "4.10",
+                "4.11",
+                "4.12",
+                "4.13",
+                "4.14",
+                "4.15",
+            ],
+            CIS_V3,
+            "cloudwatch"
+        ),
+        regional!(aws_4_16_ensure_aws_security_hub_is_enabled, ["4.16"], CIS_V3, "securityhub"),
+        regional!(
+            aws_5_1_ensure_no_network_acls_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports,
+            ["5.1"],
+            CIS_V3,
+            "ec2"
+        ),
+        regional!(
+            aws_5_2_ensure_no_security_groups_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports,
+            ["5.2", "5.3", "5.4"],
+            CIS_V3,
+            "ec2"
+        ),
+        regional!(
+            aws_5_6_ensure_that_ec2_metadata_service_only_allows_imdsv2,
+            ["5.6"],
+            CIS_V3,
+            "ec2"
+        ),
+        global!(aws_dfe_1x, [], DFE, "iam"),
+        global!(aws_dfe_2x, [], DFE, "iam"),
+        global!(aws_dfe_3x, [], DFE, "organizations"),
+        global!(aws_dfe_4x, [], DFE, "route53"),
+        global!(aws_dfe_5x, [], DFE, "route53"),
+        global!(aws_iam_effective_permissions, [], DFE, "iam"),
+        global!(aws_s3_bucket_exposure, [], DFE, "s3"),
+        global!(aws_route53_takeover, [], DFE, "route53"),
+        global!(aws_inventory, [], DFE, "inventory", send_inventory),
+        findings!(aws_securityhub_findings, "securityhub"),
+        findings!(aws_guardduty_findings, "guardduty"),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aws_checks, send_findings, send_inventory, AwsCheckFilter};
+    use crate::aws_findings::{AwsFindings, FindingsWatermark, PendingWatermark};
+    use crate::aws_inventory::{AwsInventory, Inventory, PendingInventory};
+    use crate::aws_regions::RegionalEvents;
+    use anyhow::{anyhow, Result};
+    use data_ingester_splunk::sink::{HecSink, SinkFuture};
+    use data_ingester_splunk::splunk::HecEvent;
+    use data_ingester_supporting::state_store::FileStateStore;
+
+    struct TestSink {
+        fails: bool,
+    }
+
+    impl HecSink for TestSink {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn send_batch(&self, _events: Vec<HecEvent>) -> SinkFuture<'_> {
+            let fails = self.fails;
+            Box::pin(async move {
+                if fails {
+                    return Err(anyhow!("always fails"));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn filter(include: &[&str], exclude: &[&str]) -> AwsCheckFilter {
+        AwsCheckFilter {
+            include: include.iter().map(|check| check.to_string()).collect(),
+            exclude: exclude.iter().map(|check| check.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_check_names_are_unique() {
+        let checks = aws_checks();
+        let mut names: Vec<&str> = checks.iter().map(|check| check.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), checks.len());
+    }
+
+    #[test]
+    fn test_empty_filter_selects_every_check() -> Result<()> {
+        let checks = aws_checks();
+        assert_eq!(
+            AwsCheckFilter::default().select(&checks)?.len(),
+            checks.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_by_cis_id_name_and_service() -> Result<()> {
+        let checks = aws_checks();
+        let names = |filter: AwsCheckFilter| -> Result<Vec<&str>> {
+            Ok(filter
+                .select(&checks)?
+                .into_iter()
+                .map(|check| check.name)
+                .collect())
+        };
+
+        assert_eq!(
+            names(filter(&["1.12"], &[]))?,
+            vec!["aws_1_10_ensure_mfa_is_enabled_for_all_iam_users_that_have_a_console_password"]
+        );
+        assert_eq!(names(filter(&["aws_dfe_3x"], &[]))?, vec!["aws_dfe_3x"]);
+        assert_eq!(
+            names(filter(&["ec2"], &["3.9"]))?,
+            vec![
+                "aws_2_2_1_ensure_ebs_volume_encryption_is_enabled_in_all_regions",
+                "aws_5_1_ensure_no_network_acls_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports",
+                "aws_5_2_ensure_no_security_groups_allow_ingress_from_0_0_0_0_0_to_remote_server_administration_ports",
+                "aws_5_6_ensure_that_ec2_metadata_service_only_allows_imdsv2",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_check_is_an_error() {
+        let checks = aws_checks();
+        assert!(filter(&["9.99"], &[]).select(&checks).is_err());
+        assert!(filter(&[], &["not_a_check"]).select(&checks).is_err());
+        assert!(filter(&["9.99"], &[]).validate().is_err());
+        assert!(filter(&["3.1"], &[]).validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_findings_watermark_saved_after_send() -> Result<()> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let directory = std::env::temp_dir().join(format!(
+            "ssphp_aws_findings_{}_{}",
+            std::process::id(),
+            nanos
+        ));
+        let key = FindingsWatermark::key("guardduty", "111111111111", "eu-west-2");
+        let findings = || AwsFindings {
+            source: "guardduty_GetFindings",
+            inner: vec![],
+            watermark: Some(PendingWatermark::new(
+                Box::new(FileStateStore::new(&directory)),
+                key.clone(),
+                FindingsWatermark {
+                    updated_at: Some("2024-01-01T00:00:00Z".to_string()),
+                },
+            )),
+        };
+        let store = FileStateStore::new(&directory);
+
+        send_findings(
+            "aws_guardduty_findings",
+            async { Ok((RegionalEvents::default(), vec![findings()])) },
+            &TestSink { fails: true },
+        )
+        .await;
+        assert_eq!(
+            FindingsWatermark::load(&store, &key).await?,
+            FindingsWatermark::default()
+        );
+
+        send_findings(
+            "aws_guardduty_findings",
+            async { Ok((RegionalEvents::default(), vec![findings()])) },
+            &TestSink { fails: false },
+        )
+        .await;
+        assert_eq!(
+            FindingsWatermark::load(&store, &key).await?.updated_at,
+            Some("2024-01-01T00:00:00Z".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inventory_saved_after_send() -> Result<()> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let directory = std::env::temp_dir().join(format!(
+            "ssphp_aws_inventory_send_{}_{}",
+            std::process::id(),
+            nanos
+        ));
+        let inventory = || -> Result<AwsInventory> {
+            let mut inventory = AwsInventory::default();
+            inventory.add("arn:aws:s3:::one", "s3_bucket", &serde_json::json!({}))?;
+            inventory.events(None);
+            inventory.pending = Some(PendingInventory::new(
+                Box::new(FileStateStore::new(&directory)),
+                "111111111111".to_string(),
+                inventory.inventory(None),
+            ));
+            Ok(inventory)
+        };
+        let store = FileStateStore::new(&directory);
+
+        send_inventory(
+            "aws_inventory",
+            async { inventory() },
+            &TestSink { fails: true },
+        )
+        .await;
+        assert_eq!(
+            Inventory::load(&store, "111111111111").await?,
+            Inventory::default()
+        );
+
+        send_inventory(
+            "aws_inventory",
+            async { inventory() },
+            &TestSink { fails: false },
+        )
+        .await;
+        assert_eq!(
+            Inventory::load(&store, "111111111111")
+                .await?
+                .resources
+                .len(),
+            1
+        );
+        Ok(())
+    }
+}
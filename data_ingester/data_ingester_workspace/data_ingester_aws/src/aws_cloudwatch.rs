@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+/// Words each CIS AWS 4.x metric filter pattern must contain.
+///
+/// Patterns are compared by their words rather than exactly, so
+/// whitespace, quoting and the order of terms do not matter.
+const CIS_METRIC_FILTERS: &[(&str, &[&str])] = &[
+    // Unauthorized API calls
+    (
+        "4.1",
+        &["errorCode", "UnauthorizedOperation", "AccessDenied"],
+    ),
+    // Management Console sign-in without MFA
+    ("4.2", &["ConsoleLogin", "MFAUsed"]),
+    // Usage of the root account
+    (
+        "4.3",
+        &["userIdentity", "Root", "invokedBy", "AwsServiceEvent"],
+    ),
+    // IAM policy changes
+    (
+        "4.4",
+        &[
+            "DeleteGroupPolicy",
+            "DeleteRolePolicy",
+            "DeleteUserPolicy",
+            "PutGroupPolicy",
+            "PutRolePolicy",
+            "PutUserPolicy",
+            "CreatePolicy",
+            "DeletePolicy",
+            "CreatePolicyVersion",
+            "DeletePolicyVersion",
+            "AttachRolePolicy",
+            "DetachRolePolicy",
+            "AttachUserPolicy",
+            "DetachUserPolicy",
+            "AttachGroupPolicy",
+            "DetachGroupPolicy",
+        ],
+    ),
+    // CloudTrail configuration changes
+    (
+        "4.5",
+        &[
+            "CreateTrail",
+            "UpdateTrail",
+            "DeleteTrail",
+            "StartLogging",
+            "StopLogging",
+        ],
+    ),
+    // Management Console authentication failures
+    (
+        "4.6",
+        &["ConsoleLogin", "errorMessage", "Failed", "authentication"],
+    ),
+    // Disabling or scheduled deletion of customer created CMKs
+    ("4.7", &["kms", "DisableKey", "ScheduleKeyDeletion"]),
+    // S3 bucket policy changes
+    (
+        "4.8",
+        &[
+            "s3",
+            "PutBucketAcl",
+            "PutBucketPolicy",
+            "PutBucketCors",
+            "PutBucketLifecycle",
+            "PutBucketReplication",
+            "DeleteBucketPolicy",
+            "DeleteBucketCors",
+            "DeleteBucketLifecycle",
+            "DeleteBucketReplication",
+        ],
+    ),
+    // AWS Config configuration changes
+    (
+        "4.9",
+        &[
+            "config",
+            "StopConfigurationRecorder",
+            "DeleteDeliveryChannel",
+            "PutDeliveryChannel",
+            "PutConfigurationRecorder",
+        ],
+    ),
+    // Security group changes
+    (
+        "4.10",
+        &[
+            "AuthorizeSecurityGroupIngress",
+            "AuthorizeSecurityGroupEgress",
+            "RevokeSecurityGroupIngress",
+            "RevokeSecurityGroupEgress",
+            "CreateSecurityGroup",
+            "DeleteSecurityGroup",
+        ],
+    ),
+    // Network Access Control List changes
+    (
+        "4.11",
+        &[
+            "CreateNetworkAcl",
+            "CreateNetworkAclEntry",
+            "DeleteNetworkAcl",
+            "DeleteNetworkAclEntry",
+            "ReplaceNetworkAclEntry",
+            "ReplaceNetworkAclAssociation",
+        ],
+    ),
+    // Network gateway changes
+    (
+        "4.12",
+        &[
+            "CreateCustomerGateway",
+            "DeleteCustomerGateway",
+            "AttachInternetGateway",
+            "CreateInternetGateway",
+            "DeleteInternetGateway",
+            "DetachInternetGateway",
+        ],
+    ),
+    // Route table changes
+    (
+        "4.13",
+        &[
+            "ec2",
+            "CreateRoute",
+            "CreateRouteTable",
+            "ReplaceRoute",
+            "ReplaceRouteTableAssociation",
+            "DeleteRouteTable",
+            "DeleteRoute",
+            "DisassociateRouteTable",
+        ],
+    ),
+    // VPC changes
+    (
+        "4.14",
+        &[
+            "CreateVpc",
+            "DeleteVpc",
+            "ModifyVpcAttribute",
+            "AcceptVpcPeeringConnection",
+            "CreateVpcPeeringConnection",
+            "DeleteVpcPeeringConnection",
+            "RejectVpcPeeringConnection",
+        ],
+    ),
+    // AWS Organizations changes
+    (
+        "4.15",
+        &[
+            "organizations",
+            "AcceptHandshake",
+            "AttachPolicy",
+            "CreateAccount",
+            "CreateOrganizationalUnit",
+            "CreatePolicy",
+            "DeclineHandshake",
+            "DeleteOrganization",
+            "DeleteOrganizationalUnit",
+            "DeletePolicy",
+            "DetachPolicy",
+            "DisablePolicyType",
+            "EnablePolicyType",
+            "InviteAccountToOrganization",
+            "LeaveOrganization",
+            "MoveAccount",
+            "RemoveAccountFromOrganization",
+            "UpdatePolicy",
+            "UpdateOrganizationalUnit",
+        ],
+    ),
+];
+
+/// CIS AWS 4.x controls a metric filter pattern monitors
+pub(crate) fn cis_controls(filter_pattern: &str) -> Vec<String> {
+    let words: HashSet<&str> = filter_pattern
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect();
+    CIS_METRIC_FILTERS
+        .iter()
+        .filter(|(_, required)| required.iter().all(|word| words.contains(word)))
+        .map(|(control, _)| control.to_string())
+        .collect()
+}
+
+/// Region and name of the log group in a CloudWatch Logs log group ARN
+///
+/// `arn:aws:logs:eu-west-2:111111111111:log-group:CloudTrail/Logs:*`
+pub(crate) fn log_group_from_arn(arn: &str) -> Option<(&str, &str)> {
+    let mut parts = arn.splitn(7, ':');
+    let region = parts.nth(3)?;
+    if parts.nth(1)? != "log-group" {
+        return None;
+    }
+    let name = parts.next()?;
+    let name = name.strip_suffix(":*").unwrap_or(name);
+    Some((region, name))
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct MetricFilterAlarms {
+    pub inner: Vec<MetricFilterAlarm>,
+}
+
+impl ToHecEvents for &MetricFilterAlarms {
+    type Item = MetricFilterAlarm;
+
+    fn source(&self) -> &str {
+        "logs_DescribeMetricFilters"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+/// A metric filter on a CloudTrail log group and the alarms on its metric
+///
+/// A trail that cannot meet any 4.x control, e.g. it has no log group,
+/// is recorded without a filter and with `failure` set
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricFilterAlarm {
+    /// Trail delivering to the log group
+    pub trail_arn: ::std::option::Option<::std::string::String>,
+    pub trail_is_multi_region: ::std::option::Option<bool>,
+    pub log_group_name: ::std::option::Option<::std::string::String>,
+    /// <p>The name of the metric filter.</p>
+    pub filter_name: ::std::option::Option<::std::string::String>,
+    /// <p>A symbolic description of how CloudWatch Logs should interpret the data in each log event.</p>
+    pub filter_pattern: ::std::option::Option<::std::string::String>,
+    /// <p>The name of the CloudWatch metric.</p>
+    pub metric_name: ::std::option::Option<::std::string::String>,
+    /// <p>A custom namespace to contain your metric in CloudWatch.</p>
+    pub metric_namespace: ::std::option::Option<::std::string::String>,
+    /// CIS AWS 4.x controls the filter pattern monitors
+    pub cis_controls: ::std::vec::Vec<::std::string::String>,
+    /// <p>The information for each alarm with the specified metric.</p>
+    pub alarms: ::std::vec::Vec<MetricAlarm>,
+    /// An enabled alarm notifies an SNS topic with a confirmed subscription
+    pub alarm_has_subscribers: bool,
+    /// Why the trail fails every 4.x control
+    pub failure: ::std::option::Option<::std::string::String>,
+}
+
+impl MetricFilterAlarm {
+    pub(crate) fn new(
+        trail: &aws_sdk_cloudtrail::types::Trail,
+        log_group_name: &str,
+        filter: &aws_sdk_cloudwatchlogs::types::MetricFilter,
+        transformation: &aws_sdk_cloudwatchlogs::types::MetricTransformation,
+        alarms: Vec<MetricAlarm>,
+    ) -> Self {
+        Self {
+            trail_arn: trail.trail_arn.clone(),
+            trail_is_multi_region: trail.is_multi_region_trail,
+            log_group_name: Some(log_group_name.to_string()),
+            filter_name: filter.filter_name.clone(),
+            filter_pattern: filter.filter_pattern.clone(),
+            metric_name: Some(transformation.metric_name.clone()),
+            metric_namespace: Some(transformation.metric_namespace.clone()),
+            cis_controls: filter
+                .filter_pattern
+                .as_deref()
+                .map(cis_controls)
+                .unwrap_or_default(),
+            alarm_has_subscribers: alarms.iter().any(|alarm| {
+                alarm.actions_enabled.unwrap_or_default() && alarm.sns_subscriptions > 0
+            }),
+            alarms,
+            failure: None,
+        }
+    }
+
+    /// A failing record for a trail with no usable metric filters
+    pub(crate) fn failed(
+        trail: &aws_sdk_cloudtrail::types::Trail,
+        log_group_name: Option<&str>,
+        failure: &str,
+    ) -> Self {
+        Self {
+            trail_arn: trail.trail_arn.clone(),
+            trail_is_multi_region: trail.is_multi_region_trail,
+            log_group_name: log_group_name.map(str::to_string),
+            filter_name: None,
+            filter_pattern: None,
+            metric_name: None,
+            metric_namespace: None,
+            cis_controls: vec![],
+            alarms: vec![],
+            alarm_has_subscribers: false,
+            failure: Some(failure.to_string()),
+        }
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricAlarm {
+    /// <p>The name of the alarm.</p>
+    pub alarm_name: ::std::option::Option<::std::string::String>,
+    /// <p>The Amazon Resource Name (ARN) of the alarm.</p>
+    pub alarm_arn: ::std::option::Option<::std::string::String>,
+    /// <p>Indicates whether actions should be executed during any changes to the alarm state.</p>
+    pub actions_enabled: ::std::option::Option<bool>,
+    /// <p>The actions to execute when this alarm transitions to the <code>ALARM</code> state from any other state.</p>
+    pub alarm_actions: ::std::vec::Vec<::std::string::String>,
+    /// Confirmed subscriptions to the SNS topics in `alarm_actions`
+    pub sns_subscriptions: usize,
+}
+
+impl From<aws_sdk_cloudwatch::types::MetricAlarm> for MetricAlarm {
+    fn from(value: aws_sdk_cloudwatch::types::MetricAlarm) -> Self {
+        Self {
+            alarm_name: value.alarm_name,
+            alarm_arn: value.alarm_arn,
+            actions_enabled: value.actions_enabled,
+            alarm_actions: value.alarm_actions.unwrap_or_default(),
+            sns_subscriptions: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cis_controls, log_group_from_arn, MetricFilterAlarm};
+
+    #[test]
+    fn test_cis_controls_unauthorized_api_calls() {
+        let pattern = r#"{ ($.errorCode ="*UnauthorizedOperation") || ($.errorCode ="AccessDenied*") && ($.sourceIPAddress!="delivery.logs.amazonaws.com") && ($.eventName!="HeadBucket") }"#;
+        assert_eq!(cis_controls(pattern), vec!["4.1"]);
+    }
+
+    #[test]
+    fn test_cis_controls_cloudtrail_changes() {
+        let pattern = "{ ($.eventName = CreateTrail) || ($.eventName = UpdateTrail) || ($.eventName = DeleteTrail) || ($.eventName = StartLogging) || ($.eventName = StopLogging) }";
+        assert_eq!(cis_controls(pattern), vec!["4.5"]);
+        let partial = "{ ($.eventName = CreateTrail) || ($.eventName = DeleteTrail) }";
+        assert!(cis_controls(partial).is_empty());
+    }
+
+    #[test]
+    fn test_failed_metric_filter_alarm() {
+        let trail = aws_sdk_cloudtrail::types::Trail::builder()
+            .trail_arn("arn:aws:cloudtrail:eu-west-2:111111111111:trail/main")
+            .build();
+        let failed = MetricFilterAlarm::failed(&trail, None, "No log group");
+        assert_eq!(failed.failure.as_deref(), Some("No log group"));
+        assert!(failed.cis_controls.is_empty());
+        assert!(!failed.alarm_has_subscribers);
+        let json = serde_json::to_value(&failed).unwrap();
+        assert_eq!(
+            json["trailArn"],
+            "arn:aws:cloudtrail:eu-west-2:111111111111:trail/main"
+        );
+    }
+
+    #[test]
+    fn test_log_group_from_arn() {
+        assert_eq!(
+            log_group_from_arn("arn:aws:logs:eu-west-2:111111111111:log-group:CloudTrail/Logs:*"),
+            Some(("eu-west-2", "CloudTrail/Logs"))
+        );
+        assert_eq!(
+            log_group_from_arn("arn:aws:logs:eu-west-2:111111111111:log-group:aws-cloudtrail"),
+            Some(("eu-west-2", "aws-cloudtrail"))
+        );
+        assert_eq!(log_group_from_arn("arn:aws:s3:::bucket"), None);
+    }
+}
@@ -0,0 +1,480 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_credential_types::provider::error::CredentialsError;
+use aws_credential_types::provider::future;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use azure_core::auth::TokenCredential;
+use azure_identity::{DefaultAzureCredential, TokenCredentialOptions};
+use data_ingester_supporting::keyvault::Secrets;
+use tracing::info;
+
+/// Default session name for roles assumed with web identity or role chaining
+const DEFAULT_SESSION_NAME: &str = "ssphp-aws-collector";
+
+/// Scope requested for the Azure token used as the OIDC web identity.
+///
+/// The audience of the token is `api://AzureADTokenExchange`, which is
+/// what the IAM OIDC provider for Entra ID should expect
+const DEFAULT_AZURE_SCOPE: &str = "api://AzureADTokenExchange/.default";
+
+/// How the AWS collector gets its credentials
+///
+/// Read from the environment by [AwsCredentialsConfig::from_env]:
+/// SSPHP_AWS_CREDENTIALS: `secrets` (default), `chain` or `web_identity`
+/// SSPHP_AWS_PROFILE: profile used by the `chain` source
+/// SSPHP_AWS_WEB_IDENTITY_ROLE_ARN: role assumed by the `web_identity` source
+/// SSPHP_AWS_WEB_IDENTITY_TOKEN_FILE: OIDC token file for `web_identity`.
+///     When unset, a token from the default Azure credentials is used
+/// SSPHP_AWS_WEB_IDENTITY_AZURE_SCOPE: scope of that Azure token,
+///     defaults to `api://AzureADTokenExchange/.default`
+/// SSPHP_AWS_ROLE_CHAIN: comma separated role ARNs assumed in order after
+///     the source credentials
+/// SSPHP_AWS_SESSION_NAME: role session name, defaults to `ssphp-aws-collector`
+/// SSPHP_AWS_STS_ENDPOINT: STS endpoint URL, e.g. a local mock
+#[derive(Debug, Clone)]
+pub(crate) struct AwsCredentialsConfig {
+    pub(crate) source: CredentialSource,
+    pub(crate) role_chain: Vec<String>,
+    pub(crate) session_name: String,
+    pub(crate) sts_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CredentialSource {
+    /// Static access key pair from the `aws-access-key-id` and
+    /// `aws-secret-access-key` secrets
+    Secrets,
+    /// The standard AWS chain: environment, profile, web identity token
+    /// file, ECS and IMDS
+    Chain { profile: Option<String> },
+    /// sts:AssumeRoleWithWebIdentity with an OIDC token
+    WebIdentity {
+        role_arn: String,
+        token: WebIdentityToken,
+    },
+}
+
+/// Where the OIDC token for sts:AssumeRoleWithWebIdentity comes from
+#[derive(Debug, Clone)]
+pub(crate) enum WebIdentityToken {
+    /// Read from a file each time credentials are fetched
+    File(PathBuf),
+    /// An access token for `scope` from the default Azure credentials,
+    /// e.g. the Function App's managed identity
+    Azure { scope: String },
+}
+
+impl Default for AwsCredentialsConfig {
+    fn default() -> Self {
+        Self {
+            source: CredentialSource::Secrets,
+            role_chain: vec![],
+            session_name: DEFAULT_SESSION_NAME.to_string(),
+            sts_endpoint: None,
+        }
+    }
+}
+
+impl AwsCredentialsConfig {
+    pub(crate) fn from_env() -> Result<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        let source = match env("SSPHP_AWS_CREDENTIALS").as_deref() {
+            None | Some("secrets") => CredentialSource::Secrets,
+            Some("chain") => CredentialSource::Chain {
+                profile: env("SSPHP_AWS_PROFILE"),
+            },
+            Some("web_identity") => {
+                let role_arn = env("SSPHP_AWS_WEB_IDENTITY_ROLE_ARN")
+                    .context("SSPHP_AWS_WEB_IDENTITY_ROLE_ARN is required for web_identity")?;
+                let token = match env("SSPHP_AWS_WEB_IDENTITY_TOKEN_FILE") {
+                    Some(path) => WebIdentityToken::File(PathBuf::from(path)),
+                    None => WebIdentityToken::Azure {
+                        scope: env("SSPHP_AWS_WEB_IDENTITY_AZURE_SCOPE")
+                            .unwrap_or_else(|| DEFAULT_AZURE_SCOPE.to_string()),
+                    },
+                };
+                CredentialSource::WebIdentity { role_arn, token }
+            }
+            Some(other) => bail!(
+                "Unknown SSPHP_AWS_CREDENTIALS: {}. Expected secrets, chain or web_identity",
+                other
+            ),
+        };
+
+        let role_chain = env("SSPHP_AWS_ROLE_CHAIN")
+            .map(|chain| {
+                chain
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|role_arn| !role_arn.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            source,
+            role_chain,
+            session_name: env("SSPHP_AWS_SESSION_NAME")
+                .unwrap_or_else(|| DEFAULT_SESSION_NAME.to_string()),
+            sts_endpoint: env("SSPHP_AWS_STS_ENDPOINT"),
+        })
+    }
+
+    /// Are the credentials read from the `aws-access-key-id` and
+    /// `aws-secret-access-key` secrets
+    pub(crate) fn uses_access_key_secrets(&self) -> bool {
+        matches!(self.source, CredentialSource::Secrets)
+    }
+
+    /// Build the credentials provider for the configured source, wrapped
+    /// in one [AssumeRoleProvider] per role in the chain
+    pub(crate) async fn provider(&self, secrets: &Secrets) -> Result<SharedCredentialsProvider> {
+        let region = RegionProviderChain::default_provider()
+            .or_else("us-east-1")
+            .region()
+            .await
+            .context("No AWS region")?;
+
+        let mut provider = match &self.source {
+            CredentialSource::Secrets => {
+                SharedCredentialsProvider::new(AwsSecrets::from_secrets(secrets)?)
+            }
+            CredentialSource::Chain { profile } => {
+                let mut builder = DefaultCredentialsChain::builder().region(region.clone());
+                if let Some(profile) = profile {
+                    builder = builder.profile_name(profile);
+                }
+                SharedCredentialsProvider::new(builder.build().await)
+            }
+            CredentialSource::WebIdentity { role_arn, token } => {
+                let sts_config = self.sts_config(region.clone(), None).await;
+                SharedCredentialsProvider::new(WebIdentityProvider::new(
+                    aws_sdk_sts::Client::new(&sts_config),
+                    role_arn,
+                    &self.session_name,
+                    token.clone(),
+                )?)
+            }
+        };
+
+        for role_arn in &self.role_chain {
+            info!(name = "AWS", operation = "Role chain", role_arn = role_arn);
+            let sts_config = self.sts_config(region.clone(), Some(provider)).await;
+            provider = SharedCredentialsProvider::new(
+                AssumeRoleProvider::builder(role_arn)
+                    .session_name(&self.session_name)
+                    .configure(&sts_config)
+                    .build()
+                    .await,
+            );
+        }
+
+        Ok(provider)
+    }
+
+    /// Config for calling STS, without credentials when `credentials` is
+    /// `None`
+    async fn sts_config(
+        &self,
+        region: Region,
+        credentials: Option<SharedCredentialsProvider>,
+    ) -> SdkConfig {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(region);
+        loader = match credentials {
+            Some(credentials) => loader.credentials_provider(credentials),
+            None => loader.no_credentials(),
+        };
+        if let Some(endpoint) = &self.sts_endpoint {
+            loader = loader.endpoint_url(endpoint);
+        }
+        loader.load().await
+    }
+}
+
+/// Static access key pair from [Secrets]
+#[derive(Debug)]
+pub(crate) struct AwsSecrets {
+    aws_access_key_id: String,
+    aws_secret_access_key: String,
+}
+
+impl AwsSecrets {
+    pub(crate) fn from_secrets(secrets: &Secrets) -> Result<Self> {
+        Ok(Self {
+            aws_access_key_id: secrets
+                .aws_access_key_id
+                .as_ref()
+                .context("Expect aws_access_key_id secret")?
+                .clone(),
+            aws_secret_access_key: secrets
+                .aws_secret_access_key
+                .as_ref()
+                .context("Expect aws_secret_access_key secret")?
+                .clone(),
+        })
+    }
+
+    async fn load_credentials(&self) -> aws_credential_types::provider::Result {
+        Ok(Credentials::new(
+            self.aws_access_key_id.clone(),
+            self.aws_secret_access_key.clone(),
+            None,
+            None,
+            "StaticCredentials",
+        ))
+    }
+}
+
+impl ProvideCredentials for AwsSecrets {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load_credentials())
+    }
+}
+
+/// Credentials from sts:AssumeRoleWithWebIdentity.
+///
+/// A fresh token is fetched for every call so expired OIDC tokens are
+/// never reused. Caching of the AWS credentials is left to the identity
+/// cache of the clients using this provider.
+#[derive(Clone)]
+pub(crate) struct WebIdentityProvider {
+    sts: aws_sdk_sts::Client,
+    role_arn: String,
+    session_name: String,
+    token: WebIdentityToken,
+    azure_credential: Option<Arc<DefaultAzureCredential>>,
+}
+
+impl std::fmt::Debug for WebIdentityProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebIdentityProvider")
+            .field("role_arn", &self.role_arn)
+            .field("session_name", &self.session_name)
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+impl WebIdentityProvider {
+    pub(crate) fn new(
+        sts: aws_sdk_sts::Client,
+        role_arn: &str,
+        session_name: &str,
+        token: WebIdentityToken,
+    ) -> Result<Self> {
+        let azure_credential = match &token {
+            WebIdentityToken::File(_) => None,
+            WebIdentityToken::Azure { .. } => Some(Arc::new(
+                DefaultAzureCredential::create(TokenCredentialOptions::default())
+                    .context("Unable to build default Azure Credentials")?,
+            )),
+        };
+        Ok(Self {
+            sts,
+            role_arn: role_arn.to_string(),
+            session_name: session_name.to_string(),
+            token,
+            azure_credential,
+        })
+    }
+
+    async fn web_identity_token(&self) -> Result<String> {
+        match &self.token {
+            WebIdentityToken::File(path) => {
+                let token = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Reading web identity token: {}", path.display()))?;
+                Ok(token.trim().to_string())
+            }
+            WebIdentityToken::Azure { scope } => {
+                let token = self
+                    .azure_credential
+                    .as_ref()
+                    .context("No Azure credential for web identity")?
+                    .get_token(&[scope])
+                    .await
+                    .context("Getting Azure token for web identity")?;
+                Ok(token.token.secret().to_string())
+            }
+        }
+    }
+
+    async fn load_credentials(&self) -> Result<Credentials> {
+        let token = self.web_identity_token().await?;
+        let response = self
+            .sts
+            .assume_role_with_web_identity()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name)
+            .web_identity_token(token)
+            .send()
+            .await
+            .with_context(|| format!("AssumeRoleWithWebIdentity: {}", self.role_arn))?;
+        let credentials = response
+            .credentials
+            .context("AssumeRoleWithWebIdentity returned no credentials")?;
+        let expiry = SystemTime::try_from(credentials.expiration)
+            .context("Invalid credential expiration")?;
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.session_token),
+            Some(expiry),
+            "WebIdentity",
+        ))
+    }
+}
+
+impl ProvideCredentials for WebIdentityProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.load_credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AwsCredentialsConfig, CredentialSource, WebIdentityToken};
+    use anyhow::Result;
+    use aws_credential_types::provider::ProvideCredentials;
+    use data_ingester_supporting::keyvault::Secrets;
+    use data_ingester_supporting::secret_provider::{get_secrets, FileSecretProvider};
+    use mockito::Matcher;
+    use std::collections::HashMap;
+
+    const ASSUME_ROLE_WITH_WEB_IDENTITY_RESPONSE: &str = r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <Credentials>
+      <AccessKeyId>ASIAWEBIDENTITY</AccessKeyId>
+      <SecretAccessKey>web-identity-secret</SecretAccessKey>
+      <SessionToken>web-identity-session</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#;
+
+    const ASSUME_ROLE_RESPONSE: &str = r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIACHAINED</AccessKeyId>
+      <SecretAccessKey>chained-secret</SecretAccessKey>
+      <SessionToken>chained-session</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+    <AssumedRoleUser>
+      <AssumedRoleId>AROACHAINED:ssphp-aws-collector</AssumedRoleId>
+      <Arn>arn:aws:sts::222222222222:assumed-role/audit/ssphp-aws-collector</Arn>
+    </AssumedRoleUser>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#;
+
+    fn web_identity_config(
+        server: &mockito::Server,
+        token_file: &std::path::Path,
+    ) -> AwsCredentialsConfig {
+        AwsCredentialsConfig {
+            source: CredentialSource::WebIdentity {
+                role_arn: "arn:aws:iam::111111111111:role/ssphp".to_string(),
+                token: WebIdentityToken::File(token_file.to_path_buf()),
+            },
+            sts_endpoint: Some(server.url()),
+            ..Default::default()
+        }
+    }
+
+    async fn no_secrets() -> Result<Secrets> {
+        get_secrets(&FileSecretProvider::new(HashMap::new())).await
+    }
+
+    fn token_file(name: &str) -> Result<std::path::PathBuf> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, "oidc-token\n")?;
+        Ok(path)
+    }
+
+    #[tokio::test]
+    async fn test_web_identity_against_mock_sts() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex("Action=AssumeRoleWithWebIdentity".to_string()),
+                Matcher::Regex("WebIdentityToken=oidc-token".to_string()),
+                Matcher::Regex("RoleSessionName=ssphp-aws-collector".to_string()),
+            ]))
+            .with_header("content-type", "text/xml")
+            .with_body(ASSUME_ROLE_WITH_WEB_IDENTITY_RESPONSE)
+            .create_async()
+            .await;
+
+        let token_file = token_file("ssphp_test_web_identity_token")?;
+        let provider = web_identity_config(&server, &token_file)
+            .provider(&no_secrets().await?)
+            .await?;
+        let credentials = provider.provide_credentials().await?;
+
+        assert_eq!(credentials.access_key_id(), "ASIAWEBIDENTITY");
+        assert_eq!(credentials.session_token(), Some("web-identity-session"));
+        assert!(credentials.expiry().is_some());
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_role_chain_against_mock_sts() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let web_identity = server
+            .mock("POST", "/")
+            .match_body(Matcher::Regex(
+                "Action=AssumeRoleWithWebIdentity".to_string(),
+            ))
+            .with_header("content-type", "text/xml")
+            .with_body(ASSUME_ROLE_WITH_WEB_IDENTITY_RESPONSE)
+            .create_async()
+            .await;
+        let assume_role = server
+            .mock("POST", "/")
+            .match_body(Matcher::Regex("Action=AssumeRole&".to_string()))
+            .match_header(
+                "authorization",
+                Matcher::Regex("Credential=ASIAWEBIDENTITY/".to_string()),
+            )
+            .with_header("content-type", "text/xml")
+            .with_body(ASSUME_ROLE_RESPONSE)
+            .create_async()
+            .await;
+
+        let token_file = token_file("ssphp_test_role_chain_token")?;
+        let mut config = web_identity_config(&server, &token_file);
+        config.role_chain = vec!["arn:aws:iam::222222222222:role/audit".to_string()];
+        let credentials = config
+            .provider(&no_secrets().await?)
+            .await?
+            .provide_credentials()
+            .await?;
+
+        assert_eq!(credentials.access_key_id(), "ASIACHAINED");
+        web_identity.assert_async().await;
+        assume_role.assert_async().await;
+        Ok(())
+    }
+}
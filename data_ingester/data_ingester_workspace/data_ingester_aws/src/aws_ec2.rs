@@ -249,3 +249,383 @@ impl From<aws_sdk_ec2::types::DestinationOptionsResponse> for DestinationOptions
         }
     }
 }
+
+/// Remote server administration ports, SSH and RDP, from CIS AWS 5.1 - 5.3
+const ADMIN_PORTS: [i32; 2] = [22, 3389];
+
+/// Does the port range include an administration port.
+///
+/// A missing range or `-1` means all ports.
+fn includes_admin_port(from: Option<i32>, to: Option<i32>) -> bool {
+    match (from, to) {
+        (Some(from), Some(to)) if from >= 0 && to >= 0 => {
+            ADMIN_PORTS.iter().any(|port| (from..=to).contains(port))
+        }
+        _ => true,
+    }
+}
+
+/// Does the protocol carry ports that could be an administration port.
+///
+/// `-1` is all protocols, TCP and UDP are given by name for security
+/// groups and by number for network ACLs
+fn is_admin_protocol(protocol: Option<&str>) -> bool {
+    matches!(
+        protocol,
+        None | Some("-1") | Some("tcp") | Some("udp") | Some("6") | Some("17")
+    )
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EbsEncryptionByDefault {
+    /// The region the setting applies to
+    pub region: ::std::option::Option<::std::string::String>,
+    /// <p>Indicates whether encryption by default is enabled.</p>
+    pub ebs_encryption_by_default: ::std::option::Option<bool>,
+    /// <p>The Amazon Resource Name (ARN) of the default KMS key for encryption by default.</p>
+    pub kms_key_id: ::std::option::Option<::std::string::String>,
+}
+
+impl ToHecEvents for &EbsEncryptionByDefault {
+    type Item = Self;
+
+    fn source(&self) -> &str {
+        "ec2_GetEbsEncryptionByDefault"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(std::iter::once(self))
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct DescribeNetworkAcls {
+    pub inner: Vec<NetworkAcl>,
+}
+
+impl ToHecEvents for &DescribeNetworkAcls {
+    type Item = NetworkAcl;
+
+    fn source(&self) -> &str {
+        "ec2_DescribeNetworkAcls"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkAcl {
+    /// <p>The ID of the network ACL.</p>
+    pub network_acl_id: ::std::option::Option<::std::string::String>,
+    /// <p>The ID of the VPC for the network ACL.</p>
+    pub vpc_id: ::std::option::Option<::std::string::String>,
+    /// <p>Indicates whether this is the default network ACL for the VPC.</p>
+    pub is_default: ::std::option::Option<bool>,
+    /// <p>The ID of the Amazon Web Services account that owns the network ACL.</p>
+    pub owner_id: ::std::option::Option<::std::string::String>,
+    /// <p>The entries (rules) in the network ACL.</p>
+    pub entries: ::std::vec::Vec<NetworkAclEntry>,
+    /// An ingress entry allows SSH or RDP from 0.0.0.0/0 or ::/0
+    pub admin_ports_open_to_internet: bool,
+}
+
+impl From<aws_sdk_ec2::types::NetworkAcl> for NetworkAcl {
+    fn from(value: aws_sdk_ec2::types::NetworkAcl) -> Self {
+        let entries: Vec<NetworkAclEntry> = value
+            .entries
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.into())
+            .collect();
+        let admin_ports_open_to_internet = entries
+            .iter()
+            .any(|entry| entry.allows_admin_ports_from_internet());
+        Self {
+            network_acl_id: value.network_acl_id,
+            vpc_id: value.vpc_id,
+            is_default: value.is_default,
+            owner_id: value.owner_id,
+            entries,
+            admin_ports_open_to_internet,
+        }
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkAclEntry {
+    /// <p>The rule number for the entry. ACL entries are processed in ascending order by rule number.</p>
+    pub rule_number: ::std::option::Option<i32>,
+    /// <p>Indicates whether the rule is an egress rule (applied to traffic leaving the subnet).</p>
+    pub egress: ::std::option::Option<bool>,
+    /// <p>Indicates whether to allow or deny the traffic that matches the rule.</p>
+    pub rule_action: ::std::option::Option<String>,
+    /// <p>The protocol number. A value of "-1" means all protocols.</p>
+    pub protocol: ::std::option::Option<::std::string::String>,
+    /// <p>The IPv4 network range to allow or deny, in CIDR notation.</p>
+    pub cidr_block: ::std::option::Option<::std::string::String>,
+    /// <p>The IPv6 network range to allow or deny, in CIDR notation.</p>
+    pub ipv6_cidr_block: ::std::option::Option<::std::string::String>,
+    /// <p>The first port in the range.</p>
+    pub port_range_from: ::std::option::Option<i32>,
+    /// <p>The last port in the range.</p>
+    pub port_range_to: ::std::option::Option<i32>,
+}
+
+impl NetworkAclEntry {
+    fn allows_admin_ports_from_internet(&self) -> bool {
+        self.egress == Some(false)
+            && self.rule_action.as_deref() == Some("allow")
+            && (self.cidr_block.as_deref() == Some("0.0.0.0/0")
+                || self.ipv6_cidr_block.as_deref() == Some("::/0"))
+            && is_admin_protocol(self.protocol.as_deref())
+            && includes_admin_port(self.port_range_from, self.port_range_to)
+    }
+}
+
+impl From<aws_sdk_ec2::types::NetworkAclEntry> for NetworkAclEntry {
+    fn from(value: aws_sdk_ec2::types::NetworkAclEntry) -> Self {
+        Self {
+            rule_number: value.rule_number,
+            egress: value.egress,
+            rule_action: value.rule_action.map(|ra| ra.as_str().to_owned()),
+            protocol: value.protocol,
+            cidr_block: value.cidr_block,
+            ipv6_cidr_block: value.ipv6_cidr_block,
+            port_range_from: value.port_range.as_ref().and_then(|pr| pr.from),
+            port_range_to: value.port_range.as_ref().and_then(|pr| pr.to),
+        }
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct DescribeSecurityGroups {
+    pub inner: Vec<SecurityGroup>,
+}
+
+impl ToHecEvents for &DescribeSecurityGroups {
+    type Item = SecurityGroup;
+
+    fn source(&self) -> &str {
+        "ec2_DescribeSecurityGroups"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityGroup {
+    /// <p>The ID of the security group.</p>
+    pub group_id: ::std::option::Option<::std::string::String>,
+    /// <p>The name of the security group.</p>
+    pub group_name: ::std::option::Option<::std::string::String>,
+    /// <p>The ID of the VPC for the security group.</p>
+    pub vpc_id: ::std::option::Option<::std::string::String>,
+    /// <p>The Amazon Web Services account ID of the owner of the security group.</p>
+    pub owner_id: ::std::option::Option<::std::string::String>,
+    /// <p>The inbound rules associated with the security group.</p>
+    pub ip_permissions: ::std::vec::Vec<IpPermission>,
+    /// <p>The outbound rules associated with the security group.</p>
+    pub ip_permissions_egress: ::std::vec::Vec<IpPermission>,
+    /// The VPC's default security group
+    pub is_default: bool,
+    /// The group has no inbound or outbound rules, CIS AWS 5.4
+    pub restricts_all_traffic: bool,
+    /// An inbound rule allows SSH or RDP from 0.0.0.0/0, CIS AWS 5.2
+    pub admin_ports_open_to_internet_ipv4: bool,
+    /// An inbound rule allows SSH or RDP from ::/0, CIS AWS 5.3
+    pub admin_ports_open_to_internet_ipv6: bool,
+}
+
+impl From<aws_sdk_ec2::types::SecurityGroup> for SecurityGroup {
+    fn from(value: aws_sdk_ec2::types::SecurityGroup) -> Self {
+        let ip_permissions: Vec<IpPermission> = value
+            .ip_permissions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ip| ip.into())
+            .collect();
+        let ip_permissions_egress: Vec<IpPermission> = value
+            .ip_permissions_egress
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ip| ip.into())
+            .collect();
+        let admin_ports_open_to_internet_ipv4 = ip_permissions.iter().any(|ip| {
+            ip.includes_admin_ports() && ip.cidr_ips.iter().any(|cidr| cidr == "0.0.0.0/0")
+        });
+        let admin_ports_open_to_internet_ipv6 = ip_permissions
+            .iter()
+            .any(|ip| ip.includes_admin_ports() && ip.cidr_ipv6s.iter().any(|cidr| cidr == "::/0"));
+        Self {
+            is_default: value.group_name.as_deref() == Some("default"),
+            restricts_all_traffic: ip_permissions.is_empty() && ip_permissions_egress.is_empty(),
+            group_id: value.group_id,
+            group_name: value.group_name,
+            vpc_id: value.vpc_id,
+            owner_id: value.owner_id,
+            ip_permissions,
+            ip_permissions_egress,
+            admin_ports_open_to_internet_ipv4,
+            admin_ports_open_to_internet_ipv6,
+        }
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpPermission {
+    /// <p>The IP protocol name (<code>tcp</code>, <code>udp</code>, <code>icmp</code>, <code>icmpv6</code>) or number. Use <code>-1</code> to specify all protocols.</p>
+    pub ip_protocol: ::std::option::Option<::std::string::String>,
+    /// <p>The start of port range for the TCP and UDP protocols.</p>
+    pub from_port: ::std::option::Option<i32>,
+    /// <p>The end of port range for the TCP and UDP protocols.</p>
+    pub to_port: ::std::option::Option<i32>,
+    /// <p>The IPv4 address ranges.</p>
+    pub cidr_ips: ::std::vec::Vec<::std::string::String>,
+    /// <p>The IPv6 address ranges.</p>
+    pub cidr_ipv6s: ::std::vec::Vec<::std::string::String>,
+    /// <p>The security groups.</p>
+    pub group_ids: ::std::vec::Vec<::std::string::String>,
+    /// <p>The prefix list IDs.</p>
+    pub prefix_list_ids: ::std::vec::Vec<::std::string::String>,
+}
+
+impl IpPermission {
+    fn includes_admin_ports(&self) -> bool {
+        is_admin_protocol(self.ip_protocol.as_deref())
+            && includes_admin_port(self.from_port, self.to_port)
+    }
+}
+
+impl From<aws_sdk_ec2::types::IpPermission> for IpPermission {
+    fn from(value: aws_sdk_ec2::types::IpPermission) -> Self {
+        Self {
+            ip_protocol: value.ip_protocol,
+            from_port: value.from_port,
+            to_port: value.to_port,
+            cidr_ips: value
+                .ip_ranges
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|range| range.cidr_ip)
+                .collect(),
+            cidr_ipv6s: value
+                .ipv6_ranges
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|range| range.cidr_ipv6)
+                .collect(),
+            group_ids: value
+                .user_id_group_pairs
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|pair| pair.group_id)
+                .collect(),
+            prefix_list_ids: value
+                .prefix_list_ids
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|prefix_list| prefix_list.prefix_list_id)
+                .collect(),
+        }
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct DescribeInstances {
+    pub inner: Vec<Instance>,
+}
+
+impl ToHecEvents for &DescribeInstances {
+    type Item = Instance;
+
+    fn source(&self) -> &str {
+        "ec2_DescribeInstances"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    /// <p>The ID of the instance.</p>
+    pub instance_id: ::std::option::Option<::std::string::String>,
+    /// <p>The ID of the VPC in which the instance is running.</p>
+    pub vpc_id: ::std::option::Option<::std::string::String>,
+    /// <p>The current state of the instance.</p>
+    pub state: ::std::option::Option<String>,
+    /// <p>Indicates whether IMDSv2 is required.</p>
+    pub http_tokens: ::std::option::Option<String>,
+    /// <p>Indicates whether the HTTP metadata endpoint on your instances is enabled or disabled.</p>
+    pub http_endpoint: ::std::option::Option<String>,
+    /// <p>The maximum number of hops that the metadata token can travel.</p>
+    pub http_put_response_hop_limit: ::std::option::Option<i32>,
+    /// IMDSv1 can not be used, either tokens are required or the
+    /// metadata endpoint is disabled. CIS AWS 5.6
+    pub imdsv2_required: bool,
+}
+
+impl From<aws_sdk_ec2::types::Instance> for Instance {
+    fn from(value: aws_sdk_ec2::types::Instance) -> Self {
+        let metadata_options = value.metadata_options.as_ref();
+        let http_tokens = metadata_options
+            .and_then(|mo| mo.http_tokens.as_ref())
+            .map(|ht| ht.as_str().to_owned());
+        let http_endpoint = metadata_options
+            .and_then(|mo| mo.http_endpoint.as_ref())
+            .map(|he| he.as_str().to_owned());
+        Self {
+            imdsv2_required: http_tokens.as_deref() == Some("required")
+                || http_endpoint.as_deref() == Some("disabled"),
+            instance_id: value.instance_id,
+            vpc_id: value.vpc_id,
+            state: value
+                .state
+                .and_then(|state| state.name)
+                .map(|name| name.as_str().to_owned()),
+            http_tokens,
+            http_endpoint,
+            http_put_response_hop_limit: metadata_options
+                .and_then(|mo| mo.http_put_response_hop_limit),
+        }
+    }
+}
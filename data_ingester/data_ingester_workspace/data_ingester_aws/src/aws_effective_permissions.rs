@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use aws_sdk_iam::operation::get_account_authorization_details::GetAccountAuthorizationDetailsOutput;
+use serde::Serialize;
+use tracing::warn;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+use crate::aws_policy::AwsPolicy;
+use crate::aws_policy_evaluation::EffectivePolicies;
+
+/// A principal allowed every one of these actions on every resource
+/// is treated as an administrator.
+///
+/// Only `*` or `NotAction` grants with few exclusions allow all of
+/// them.
+const ADMIN_PROBE_ACTIONS: &[&str] = &[
+    "iam:CreateUser",
+    "iam:AttachRolePolicy",
+    "iam:PassRole",
+    "ec2:RunInstances",
+    "s3:DeleteBucket",
+    "kms:ScheduleKeyDeletion",
+    "lambda:CreateFunction",
+];
+
+/// Combinations of actions that let a principal gain more permissions
+/// than it was granted.
+///
+/// https://rhinosecuritylabs.com/aws/aws-privilege-escalation-methods-mitigation/
+const ESCALATION_PATHS: &[(&str, &[&str])] = &[
+    ("create_policy_version", &["iam:CreatePolicyVersion"]),
+    (
+        "set_default_policy_version",
+        &["iam:SetDefaultPolicyVersion"],
+    ),
+    ("create_access_key", &["iam:CreateAccessKey"]),
+    ("create_login_profile", &["iam:CreateLoginProfile"]),
+    ("update_login_profile", &["iam:UpdateLoginProfile"]),
+    ("attach_user_policy", &["iam:AttachUserPolicy"]),
+    ("attach_group_policy", &["iam:AttachGroupPolicy"]),
+    (
+        "attach_role_policy",
+        &["iam:AttachRolePolicy", "sts:AssumeRole"],
+    ),
+    ("put_user_policy", &["iam:PutUserPolicy"]),
+    ("put_group_policy", &["iam:PutGroupPolicy"]),
+    ("put_role_policy", &["iam:PutRolePolicy", "sts:AssumeRole"]),
+    ("add_user_to_group", &["iam:AddUserToGroup"]),
+    (
+        "update_assume_role_policy",
+        &["iam:UpdateAssumeRolePolicy", "sts:AssumeRole"],
+    ),
+    (
+        "pass_role_lambda_invoke",
+        &[
+            "iam:PassRole",
+            "lambda:CreateFunction",
+            "lambda:InvokeFunction",
+        ],
+    ),
+    (
+        "pass_role_lambda_event_source",
+        &[
+            "iam:PassRole",
+            "lambda:CreateFunction",
+            "lambda:CreateEventSourceMapping",
+        ],
+    ),
+    ("update_lambda_code", &["lambda:UpdateFunctionCode"]),
+    ("pass_role_ec2", &["iam:PassRole", "ec2:RunInstances"]),
+    (
+        "pass_role_cloudformation",
+        &["iam:PassRole", "cloudformation:CreateStack"],
+    ),
+    (
+        "pass_role_data_pipeline",
+        &[
+            "iam:PassRole",
+            "datapipeline:CreatePipeline",
+            "datapipeline:PutPipelineDefinition",
+        ],
+    ),
+    (
+        "pass_role_glue_dev_endpoint",
+        &["iam:PassRole", "glue:CreateDevEndpoint"],
+    ),
+    ("update_glue_dev_endpoint", &["glue:UpdateDevEndpoint"]),
+];
+
+/// Parse a policy document, which IAM returns URL encoded
+pub(crate) fn parse_policy_document(document: &str) -> Result<AwsPolicy> {
+    let decoded = urlencoding::decode(document)?;
+    Ok(serde_json::from_str(&decoded)?)
+}
+
+/// Findings derived from evaluating every policy that applies to a
+/// user or role
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct PrincipalPermissions {
+    pub(crate) principal_arn: Option<String>,
+    pub(crate) principal_name: Option<String>,
+    /// `user` or `role`
+    pub(crate) principal_type: String,
+    pub(crate) groups: Vec<String>,
+    pub(crate) permissions_boundary_arn: Option<String>,
+    /// SCPs apply to the account and were part of the evaluation
+    pub(crate) scps_evaluated: bool,
+    pub(crate) admin_equivalent: bool,
+    /// Names from [ESCALATION_PATHS] the principal can use
+    pub(crate) privilege_escalation_paths: Vec<String>,
+    /// Actions granted on every resource
+    pub(crate) wildcard_resource_actions: Vec<String>,
+}
+
+impl PrincipalPermissions {
+    pub(crate) fn new(principal: &Principal, policies: &EffectivePolicies) -> Self {
+        let admin_equivalent = ADMIN_PROBE_ACTIONS
+            .iter()
+            .all(|action| policies.is_allowed(action, "*"));
+
+        let privilege_escalation_paths = ESCALATION_PATHS
+            .iter()
+            .filter(|(_, actions)| {
+                actions
+                    .iter()
+                    .all(|action| policies.is_allowed(action, "*"))
+            })
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let mut wildcard_resource_actions: Vec<String> = policies
+            .wildcard_resource_statements()
+            .filter_map(|statement| statement.action.as_ref())
+            .flat_map(|action| action.values())
+            .filter(|action| policies.is_allowed(action, "*"))
+            .cloned()
+            .collect();
+        wildcard_resource_actions.sort();
+        wildcard_resource_actions.dedup();
+
+        Self {
+            principal_arn: principal.arn.clone(),
+            principal_name: principal.name.clone(),
+            principal_type: principal.principal_type.to_string(),
+            groups: principal.groups.clone(),
+            permissions_boundary_arn: principal.permissions_boundary_arn.clone(),
+            scps_evaluated: !policies.scps.is_empty(),
+            admin_equivalent,
+            privilege_escalation_paths,
+            wildcard_resource_actions,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub(crate) struct PrincipalPermissionsList {
+    pub(crate) inner: Vec<PrincipalPermissions>,
+}
+
+impl ToHecEvents for &PrincipalPermissionsList {
+    type Item = PrincipalPermissions;
+
+    fn source(&self) -> &str {
+        "iam_GetAccountAuthorizationDetails_effective_permissions"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+/// A user or role and the names of the policies that apply to it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Principal {
+    pub(crate) arn: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) principal_type: &'static str,
+    pub(crate) groups: Vec<String>,
+    pub(crate) permissions_boundary_arn: Option<String>,
+    /// Policies embedded in the principal and its groups
+    inline_policies: Vec<AwsPolicy>,
+    /// ARNs of the managed policies attached to the principal and
+    /// its groups
+    managed_policy_arns: Vec<String>,
+}
+
+/// Users, roles, groups and managed policies from
+/// iam:GetAccountAuthorizationDetails
+#[derive(Debug, Default)]
+pub(crate) struct AuthorizationDetails {
+    principals: Vec<Principal>,
+    /// Default version of each managed policy by ARN
+    managed_policies: HashMap<String, AwsPolicy>,
+}
+
+impl AuthorizationDetails {
+    pub(crate) fn new(pages: Vec<GetAccountAuthorizationDetailsOutput>) -> Self {
+        let mut details = Self::default();
+        let mut groups: HashMap<String, (Vec<AwsPolicy>, Vec<String>)> = HashMap::new();
+        let mut users = vec![];
+        let mut roles = vec![];
+
+        for page in pages {
+            for policy in page.policies.unwrap_or_default() {
+                let Some(arn) = policy.arn else {
+                    continue;
+                };
+                let document = policy
+                    .policy_version_list
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|version| version.is_default_version)
+                    .and_then(|version| version.document);
+                if let Some(policy) = document.and_then(|document| parse(&arn, &document)) {
+                    let _ = details.managed_policies.insert(arn, policy);
+                }
+            }
+            for group in page.group_detail_list.unwrap_or_default() {
+                let Some(group_name) = group.group_name else {
+                    continue;
+                };
+                let policies = (
+                    inline_policies(&group_name, group.group_policy_list),
+                    attached_policy_arns(group.attached_managed_policies),
+                );
+                let _ = groups.insert(group_name, policies);
+            }
+            users.extend(page.user_detail_list.unwrap_or_default());
+            roles.extend(page.role_detail_list.unwrap_or_default());
+        }
+
+        for user in users {
+            let name = user.user_name.unwrap_or_default();
+            let mut principal = Principal {
+                inline_policies: inline_policies(&name, user.user_policy_list),
+                managed_policy_arns: attached_policy_arns(user.attached_managed_policies),
+                arn: user.arn,
+                name: Some(name),
+                principal_type: "user",
+                groups: user.group_list.unwrap_or_default(),
+                permissions_boundary_arn: user
+                    .permissions_boundary
+                    .and_then(|boundary| boundary.permissions_boundary_arn),
+            };
+            for group in principal.groups.iter() {
+                if let Some((inline, managed)) = groups.get(group) {
+                    principal.inline_policies.extend(inline.iter().cloned());
+                    principal
+                        .managed_policy_arns
+                        .extend(managed.iter().cloned());
+                }
+            }
+            details.principals.push(principal);
+        }
+
+        for role in roles {
+            let name = role.role_name.unwrap_or_default();
+            details.principals.push(Principal {
+                inline_policies: inline_policies(&name, role.role_policy_list),
+                managed_policy_arns: attached_policy_arns(role.attached_managed_policies),
+                arn: role.arn,
+                name: Some(name),
+                principal_type: "role",
+                groups: vec![],
+                permissions_boundary_arn: role
+                    .permissions_boundary
+                    .and_then(|boundary| boundary.permissions_boundary_arn),
+            });
+        }
+
+        details
+    }
+
+    /// Each principal with its identity policies, boundary and `scps`
+    pub(crate) fn effective_policies<'d>(
+        &'d self,
+        scps: &'d [Vec<AwsPolicy>],
+    ) -> impl Iterator<Item = (&'d Principal, EffectivePolicies)> + 'd {
+        self.principals.iter().map(move |principal| {
+            let mut identity = principal.inline_policies.clone();
+            identity.extend(
+                principal
+                    .managed_policy_arns
+                    .iter()
+                    .filter_map(|arn| self.managed_policy(arn)),
+            );
+            let boundary = principal
+                .permissions_boundary_arn
+                .as_ref()
+                .and_then(|arn| self.managed_policy(arn));
+            (
+                principal,
+                EffectivePolicies {
+                    identity,
+                    boundary,
+                    scps: scps.to_vec(),
+                },
+            )
+        })
+    }
+
+    fn managed_policy(&self, arn: &str) -> Option<AwsPolicy> {
+        let policy = self.managed_policies.get(arn).cloned();
+        if policy.is_none() {
+            warn!(
+                name = "AWS",
+                operation = "Effective permissions",
+                missing_policy = arn
+            );
+        }
+        policy
+    }
+}
+
+fn parse(name: &str, document: &str) -> Option<AwsPolicy> {
+    match parse_policy_document(document) {
+        Ok(policy) => Some(policy),
+        Err(err) => {
+            warn!(name="AWS", operation="Parse policy document", policy=name, error=?err);
+            None
+        }
+    }
+}
+
+fn inline_policies(
+    principal: &str,
+    policies: Option<Vec<aws_sdk_iam::types::PolicyDetail>>,
+) -> Vec<AwsPolicy> {
+    policies
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|policy| {
+            let name = format!(
+                "{}/{}",
+                principal,
+                policy.policy_name.as_deref().unwrap_or_default()
+            );
+            parse(&name, policy.policy_document.as_deref()?)
+        })
+        .collect()
+}
+
+fn attached_policy_arns(policies: Option<Vec<aws_sdk_iam::types::AttachedPolicy>>) -> Vec<String> {
+    policies
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|policy| policy.policy_arn)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_policy_document, Principal, PrincipalPermissions};
+    use crate::aws_policy_evaluation::EffectivePolicies;
+    use anyhow::Result;
+
+    fn principal() -> Principal {
+        Principal {
+            arn: Some("arn:aws:iam::111111111111:user/alice".into()),
+            name: Some("alice".into()),
+            principal_type: "user",
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_admin_equivalent() -> Result<()> {
+        let policies = EffectivePolicies {
+            identity: vec![parse_policy_document(
+                "%7B%22Version%22%3A%222012-10-17%22%2C%22Statement%22%3A%5B%7B%22Effect%22%3A%22Allow%22%2C%22Action%22%3A%22%2A%22%2C%22Resource%22%3A%22%2A%22%7D%5D%7D",
+            )?],
+            ..Default::default()
+        };
+        let permissions = PrincipalPermissions::new(&principal(), &policies);
+        assert!(permissions.admin_equivalent);
+        assert!(!permissions.scps_evaluated);
+        assert_eq!(permissions.wildcard_resource_actions, vec!["*"]);
+        assert!(permissions
+            .privilege_escalation_paths
+            .contains(&"pass_role_lambda_invoke".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pass_role_escalation() -> Result<()> {
+        let policies = EffectivePolicies {
+            identity: vec![parse_policy_document(
+                r#"{"Version": "2012-10-17", "Statement": [
+                    {"Effect": "Allow", "Action": ["iam:PassRole", "lambda:CreateFunction", "lambda:InvokeFunction"], "Resource": "*"},
+                    {"Effect": "Allow", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::bucket/*"}
+                ]}"#,
+            )?],
+            ..Default::default()
+        };
+        let permissions = PrincipalPermissions::new(&principal(), &policies);
+        assert!(!permissions.admin_equivalent);
+        assert_eq!(
+            permissions.privilege_escalation_paths,
+            vec!["pass_role_lambda_invoke"]
+        );
+        assert_eq!(
+            permissions.wildcard_resource_actions,
+            vec![
+                "iam:PassRole",
+                "lambda:CreateFunction",
+                "lambda:InvokeFunction"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_boundary_removes_findings() -> Result<()> {
+        let policies = EffectivePolicies {
+            identity: vec![parse_policy_document(
+                r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": "*", "Resource": "*"}}"#,
+            )?],
+            boundary: Some(parse_policy_document(
+                r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": "s3:*", "Resource": "*"}}"#,
+            )?),
+            ..Default::default()
+        };
+        let permissions = PrincipalPermissions::new(&principal(), &policies);
+        assert!(!permissions.admin_equivalent);
+        assert!(permissions.privilege_escalation_paths.is_empty());
+        assert!(permissions.wildcard_resource_actions.is_empty());
+        Ok(())
+    }
+}
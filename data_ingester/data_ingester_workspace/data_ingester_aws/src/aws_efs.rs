@@ -0,0 +1,60 @@
+use serde::Serialize;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct DescribeFileSystems {
+    pub inner: Vec<FileSystem>,
+}
+
+impl ToHecEvents for &DescribeFileSystems {
+    type Item = FileSystem;
+
+    fn source(&self) -> &str {
+        "efs_DescribeFileSystems"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSystem {
+    /// <p>The ID of the file system, assigned by Amazon EFS.</p>
+    pub file_system_id: ::std::string::String,
+    /// <p>The Amazon Resource Name (ARN) for the EFS file system.</p>
+    pub file_system_arn: ::std::option::Option<::std::string::String>,
+    /// <p>You can add tags to a file system, including a <code>Name</code> tag.</p>
+    pub name: ::std::option::Option<::std::string::String>,
+    /// <p>The Amazon Web Services account that created the file system.</p>
+    pub owner_id: ::std::string::String,
+    /// <p>The lifecycle phase of the file system.</p>
+    pub life_cycle_state: String,
+    /// <p>A Boolean value that, if true, indicates that the file system is encrypted.</p>
+    pub encrypted: ::std::option::Option<bool>,
+    /// <p>The ID of an KMS key used to protect the encrypted file system.</p>
+    pub kms_key_id: ::std::option::Option<::std::string::String>,
+}
+
+impl From<aws_sdk_efs::types::FileSystemDescription> for FileSystem {
+    fn from(value: aws_sdk_efs::types::FileSystemDescription) -> Self {
+        Self {
+            file_system_id: value.file_system_id,
+            file_system_arn: value.file_system_arn,
+            name: value.name,
+            owner_id: value.owner_id,
+            life_cycle_state: value.life_cycle_state.as_str().to_owned(),
+            encrypted: value.encrypted,
+            kms_key_id: value.kms_key_id,
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use anyhow::{Context, Result};
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use serde::{Deserialize, Serialize};
+
+use data_ingester_splunk::splunk::ToHecEvents;
+use data_ingester_supporting::state_store::StateStore;
+
+/// Workflow statuses collected when SSPHP_AWS_FINDINGS_WORKFLOW_STATUS
+/// is not set, every status so resolved and suppressed updates arrive
+const DEFAULT_WORKFLOW_STATUSES: &[&str] = &["NEW", "NOTIFIED", "RESOLVED", "SUPPRESSED"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Severity {
+    Informational,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// Security Hub `Severity.Label`
+    pub(crate) fn from_label(label: &str) -> Self {
+        match label.to_ascii_uppercase().as_str() {
+            "CRITICAL" => Self::Critical,
+            "HIGH" => Self::High,
+            "MEDIUM" => Self::Medium,
+            "LOW" => Self::Low,
+            _ => Self::Informational,
+        }
+    }
+
+    /// GuardDuty severity score, from 1.0 to 10.0
+    pub(crate) fn from_score(score: f64) -> Self {
+        if score >= 9.0 {
+            Self::Critical
+        } else if score >= 7.0 {
+            Self::High
+        } else if score >= 4.0 {
+            Self::Medium
+        } else if score >= 1.0 {
+            Self::Low
+        } else {
+            Self::Informational
+        }
+    }
+}
+
+/// Which Security Hub findings to collect
+///
+/// Read from the environment by [AwsFindingsConfig::from_env]:
+/// SSPHP_AWS_FINDINGS_WORKFLOW_STATUS: comma separated Security Hub
+/// workflow statuses to collect, defaults to every status
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AwsFindingsConfig {
+    pub(crate) workflow_statuses: Vec<String>,
+}
+
+impl Default for AwsFindingsConfig {
+    fn default() -> Self {
+        Self {
+            workflow_statuses: DEFAULT_WORKFLOW_STATUSES
+                .iter()
+                .map(|status| status.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl AwsFindingsConfig {
+    pub(crate) fn from_env() -> Self {
+        Self::from_workflow_statuses(
+            &std::env::var("SSPHP_AWS_FINDINGS_WORKFLOW_STATUS").unwrap_or_default(),
+        )
+    }
+
+    fn from_workflow_statuses(statuses: &str) -> Self {
+        let workflow_statuses: Vec<String> = statuses
+            .split(',')
+            .map(|status| status.trim().to_ascii_uppercase())
+            .filter(|status| !status.is_empty())
+            .collect();
+        if workflow_statuses.is_empty() {
+            return Self::default();
+        }
+        Self { workflow_statuses }
+    }
+}
+
+/// The newest `UpdatedAt` seen for one product in one account and
+/// region.
+///
+/// The next run asks for findings updated at or after the watermark,
+/// so findings updated at exactly the watermark are sent again.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FindingsWatermark {
+    pub(crate) updated_at: Option<String>,
+}
+
+impl FindingsWatermark {
+    /// State store key for the watermark of a product in an account and region
+    pub(crate) fn key(product: &str, account_id: &str, region: &str) -> String {
+        format!("aws/findings/{}/{}/{}.json", product, account_id, region)
+    }
+
+    /// The watermark saved under `key` by the last run, empty for the
+    /// first run
+    pub(crate) async fn load(store: &dyn StateStore, key: &str) -> Result<Self> {
+        match store.get(key).await? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) async fn save(&self, store: &dyn StateStore, key: &str) -> Result<()> {
+        store.put(key, serde_json::to_vec(self)?).await
+    }
+
+    /// The watermark as epoch milliseconds, for GuardDuty finding criteria
+    pub(crate) fn epoch_millis(&self) -> Result<Option<i64>> {
+        self.updated_at
+            .as_deref()
+            .map(|updated_at| {
+                DateTime::from_str(updated_at, Format::DateTime)
+                    .with_context(|| format!("Parsing findings watermark: {}", updated_at))?
+                    .to_millis()
+                    .context("Findings watermark out of range")
+            })
+            .transpose()
+    }
+
+    /// Move the watermark to the newest `updated_at` in `findings`
+    pub(crate) fn advance(&mut self, findings: &[AwsFinding]) -> Result<()> {
+        let mut newest = self.epoch_millis()?;
+        for finding in findings {
+            let Some(updated_at) = &finding.updated_at else {
+                continue;
+            };
+            let millis = DateTime::from_str(updated_at, Format::DateTime)
+                .with_context(|| format!("Parsing finding UpdatedAt: {}", updated_at))?
+                .to_millis()?;
+            if newest.is_none_or(|newest| millis > newest) {
+                newest = Some(millis);
+                self.updated_at = Some(updated_at.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A Security Hub or GuardDuty finding, normalised to the fields used
+/// by our own results
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct AwsFinding {
+    /// `securityhub` or `guardduty`
+    pub(crate) product: &'static str,
+    pub(crate) id: String,
+    pub(crate) account_id: Option<String>,
+    pub(crate) region: Option<String>,
+    pub(crate) title: Option<String>,
+    pub(crate) resource_type: Option<String>,
+    /// ARN or ID of the first affected resource
+    pub(crate) resource_id: Option<String>,
+    pub(crate) severity: Severity,
+    /// Security Hub control id such as `S3.8`, otherwise the generator
+    /// or GuardDuty finding type
+    pub(crate) control_id: Option<String>,
+    /// `passed`, `failed`, `warning` or `not_available` for control
+    /// findings, `active` for detections, `archived` once archived
+    pub(crate) status: String,
+    /// Security Hub workflow status such as `new`
+    pub(crate) workflow_status: Option<String>,
+    pub(crate) created_at: Option<String>,
+    pub(crate) updated_at: Option<String>,
+}
+
+impl From<aws_sdk_securityhub::types::AwsSecurityFinding> for AwsFinding {
+    fn from(value: aws_sdk_securityhub::types::AwsSecurityFinding) -> Self {
+        let resource = value.resources.unwrap_or_default().into_iter().next();
+        let (security_control_id, compliance_status) = match value.compliance {
+            Some(compliance) => (compliance.security_control_id, compliance.status),
+            None => (None, None),
+        };
+        let archived =
+            value.record_state == Some(aws_sdk_securityhub::types::RecordState::Archived);
+        Self {
+            product: "securityhub",
+            id: value.id.unwrap_or_default(),
+            account_id: value.aws_account_id,
+            region: value.region,
+            title: value.title,
+            resource_type: resource
+                .as_ref()
+                .and_then(|resource| resource.r#type.clone()),
+            resource_id: resource.and_then(|resource| resource.id),
+            severity: value
+                .severity
+                .and_then(|severity| severity.label)
+                .map(|label| Severity::from_label(label.as_str()))
+                .unwrap_or(Severity::Informational),
+            control_id: security_control_id.or(value.generator_id),
+            status: match compliance_status {
+                _ if archived => "archived".to_string(),
+                Some(status) => status.as_str().to_ascii_lowercase(),
+                None => "active".to_string(),
+            },
+            workflow_status: value
+                .workflow
+                .and_then(|workflow| workflow.status)
+                .map(|status| status.as_str().to_ascii_lowercase()),
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+impl From<aws_sdk_guardduty::types::Finding> for AwsFinding {
+    fn from(value: aws_sdk_guardduty::types::Finding) -> Self {
+        let resource_type = value
+            .resource
+            .as_ref()
+            .and_then(|resource| resource.resource_type.clone());
+        let resource_id = value.resource.and_then(|resource| {
+            resource
+                .instance_details
+                .and_then(|instance| instance.instance_id)
+                .or_else(|| {
+                    resource
+                        .access_key_details
+                        .and_then(|access_key| access_key.access_key_id)
+                })
+                .or_else(|| {
+                    resource
+                        .s3_bucket_details
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find_map(|bucket| bucket.arn)
+                })
+                .or_else(|| resource.eks_cluster_details.and_then(|cluster| cluster.arn))
+        });
+        let archived = value
+            .service
+            .and_then(|service| service.archived)
+            .unwrap_or(false);
+        Self {
+            product: "guardduty",
+            id: value.id.unwrap_or_default(),
+            account_id: value.account_id,
+            region: value.region,
+            title: value.title,
+            resource_type,
+            resource_id,
+            severity: value
+                .severity
+                .map(Severity::from_score)
+                .unwrap_or(Severity::Informational),
+            control_id: value.r#type,
+            status: if archived { "archived" } else { "active" }.to_string(),
+            workflow_status: None,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+/// A [FindingsWatermark] advanced past collected findings, saved only
+/// once those findings have been sent so a failed send collects them
+/// again on the next run
+pub(crate) struct PendingWatermark {
+    store: Box<dyn StateStore>,
+    key: String,
+    watermark: FindingsWatermark,
+}
+
+impl PendingWatermark {
+    pub(crate) fn new(
+        store: Box<dyn StateStore>,
+        key: String,
+        watermark: FindingsWatermark,
+    ) -> Self {
+        Self {
+            store,
+            key,
+            watermark,
+        }
+    }
+
+    pub(crate) async fn save(&self) -> Result<()> {
+        self.watermark
+            .save(self.store.as_ref(), &self.key)
+            .await
+            .with_context(|| format!("Saving findings watermark to {} store", self.store.name()))
+    }
+}
+
+impl std::fmt::Debug for PendingWatermark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingWatermark")
+            .field("store", &self.store.name())
+            .field("key", &self.key)
+            .field("watermark", &self.watermark)
+            .finish()
+    }
+}
+
+/// Findings from one product in one account and region
+#[derive(Debug, Default)]
+pub(crate) struct AwsFindings {
+    pub(crate) source: &'static str,
+    pub(crate) inner: Vec<AwsFinding>,
+    /// Saved once the findings are sent, `None` without a state store
+    pub(crate) watermark: Option<PendingWatermark>,
+}
+
+impl ToHecEvents for &AwsFindings {
+    type Item = AwsFinding;
+
+    fn source(&self) -> &str {
+        self.source
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AwsFinding, AwsFindingsConfig, FindingsWatermark, Severity};
+    use anyhow::Result;
+    use aws_sdk_guardduty::types as guardduty;
+    use aws_sdk_securityhub::types as securityhub;
+
+    #[test]
+    fn test_workflow_statuses() {
+        assert_eq!(
+            AwsFindingsConfig::from_workflow_statuses("").workflow_statuses,
+            vec!["NEW", "NOTIFIED", "RESOLVED", "SUPPRESSED"]
+        );
+        assert_eq!(
+            AwsFindingsConfig::from_workflow_statuses(" new, resolved ,").workflow_statuses,
+            vec!["NEW", "RESOLVED"]
+        );
+    }
+
+    #[test]
+    fn test_guardduty_severity() {
+        assert_eq!(Severity::from_score(9.5), Severity::Critical);
+        assert_eq!(Severity::from_score(8.0), Severity::High);
+        assert_eq!(Severity::from_score(5.0), Severity::Medium);
+        assert_eq!(Severity::from_score(2.0), Severity::Low);
+        assert_eq!(Severity::from_score(0.0), Severity::Informational);
+    }
+
+    #[test]
+    fn test_security_hub_control_finding() {
+        let finding = securityhub::AwsSecurityFinding::builder()
+            .id("arn:aws:securityhub:eu-west-2:111111111111:finding/1")
+            .aws_account_id("111111111111")
+            .region("eu-west-2")
+            .generator_id("security-control/S3.8")
+            .title("S3 general purpose buckets should block public access")
+            .resources(
+                securityhub::Resource::builder()
+                    .r#type("AwsS3Bucket")
+                    .id("arn:aws:s3:::example")
+                    .build(),
+            )
+            .severity(
+                securityhub::Severity::builder()
+                    .label(securityhub::SeverityLabel::High)
+                    .build(),
+            )
+            .compliance(
+                securityhub::Compliance::builder()
+                    .status(securityhub::ComplianceStatus::Failed)
+                    .security_control_id("S3.8")
+                    .build(),
+            )
+            .workflow(
+                securityhub::Workflow::builder()
+                    .status(securityhub::WorkflowStatus::New)
+                    .build(),
+            )
+            .updated_at("2024-05-01T10:00:00.000Z")
+            .build();
+        let mut archived = finding.clone();
+        archived.record_state = Some(securityhub::RecordState::Archived);
+        assert_eq!(AwsFinding::from(archived).status, "archived");
+
+        let finding = AwsFinding::from(finding);
+        assert_eq!(finding.product, "securityhub");
+        assert_eq!(finding.resource_type.as_deref(), Some("AwsS3Bucket"));
+        assert_eq!(finding.resource_id.as_deref(), Some("arn:aws:s3:::example"));
+        assert_eq!(finding.severity, Severity::High);
+        assert_eq!(finding.control_id.as_deref(), Some("S3.8"));
+        assert_eq!(finding.status, "failed");
+        assert_eq!(finding.workflow_status.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn test_guardduty_finding() {
+        let finding = guardduty::Finding::builder()
+            .id("abc")
+            .account_id("111111111111")
+            .region("eu-west-2")
+            .r#type("UnauthorizedAccess:EC2/SSHBruteForce")
+            .severity(8.0)
+            .resource(
+                guardduty::Resource::builder()
+                    .resource_type("Instance")
+                    .instance_details(
+                        guardduty::InstanceDetails::builder()
+                            .instance_id("i-0123456789abcdef0")
+                            .build(),
+                    )
+                    .build(),
+            )
+            .service(guardduty::Service::builder().archived(false).build())
+            .updated_at("2024-05-01T10:00:00.000Z")
+            .build();
+        let finding = AwsFinding::from(finding);
+        assert_eq!(finding.product, "guardduty");
+        assert_eq!(finding.resource_type.as_deref(), Some("Instance"));
+        assert_eq!(finding.resource_id.as_deref(), Some("i-0123456789abcdef0"));
+        assert_eq!(finding.severity, Severity::High);
+        assert_eq!(
+            finding.control_id.as_deref(),
+            Some("UnauthorizedAccess:EC2/SSHBruteForce")
+        );
+        assert_eq!(finding.status, "active");
+    }
+
+    #[test]
+    fn test_watermark_advances_to_newest() -> Result<()> {
+        let finding = |updated_at: &str| {
+            AwsFinding::from(
+                guardduty::Finding::builder()
+                    .id(updated_at)
+                    .updated_at(updated_at)
+                    .build(),
+            )
+        };
+        let mut watermark = FindingsWatermark {
+            updated_at: Some("2024-05-01T10:00:00.000Z".to_string()),
+        };
+        watermark.advance(&[
+            finding("2024-05-02T09:00:00.000Z"),
+            finding("2024-05-03T08:00:00Z"),
+            finding("2024-04-30T00:00:00.000Z"),
+        ])?;
+        assert_eq!(
+            watermark.updated_at.as_deref(),
+            Some("2024-05-03T08:00:00Z")
+        );
+        assert_eq!(watermark.epoch_millis()?, Some(1_714_723_200_000));
+
+        watermark.advance(&[])?;
+        assert_eq!(
+            watermark.updated_at.as_deref(),
+            Some("2024-05-03T08:00:00Z")
+        );
+        Ok(())
+    }
+}
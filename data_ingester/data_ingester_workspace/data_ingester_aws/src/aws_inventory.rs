@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use data_ingester_splunk::splunk::ToHecEvents;
+use data_ingester_supporting::state_store::StateStore;
+
+/// The inventory saved between runs, resource type and content hash
+/// by ARN
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Inventory {
+    pub(crate) resources: BTreeMap<String, InventoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct InventoryEntry {
+    pub(crate) resource_type: String,
+    pub(crate) hash: String,
+}
+
+impl Inventory {
+    /// State store key for the inventory of an account
+    pub(crate) fn key(account_id: &str) -> String {
+        format!("aws/inventory/{}.json", account_id)
+    }
+
+    /// The inventory saved by the last run, empty for the first run
+    pub(crate) async fn load(store: &dyn StateStore, account_id: &str) -> Result<Self> {
+        match store.get(&Self::key(account_id)).await? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) async fn save(&self, store: &dyn StateStore, account_id: &str) -> Result<()> {
+        store
+            .put(&Self::key(account_id), serde_json::to_vec(self)?)
+            .await
+    }
+}
+
+/// An inventory to save once its events have been sent, so a failed
+/// send is diffed again on the next run
+pub(crate) struct PendingInventory {
+    store: Box<dyn StateStore>,
+    account_id: String,
+    inventory: Inventory,
+}
+
+impl PendingInventory {
+    pub(crate) fn new(
+        store: Box<dyn StateStore>,
+        account_id: String,
+        inventory: Inventory,
+    ) -> Self {
+        Self {
+            store,
+            account_id,
+            inventory,
+        }
+    }
+
+    pub(crate) async fn save(&self) -> Result<()> {
+        self.inventory
+            .save(self.store.as_ref(), &self.account_id)
+            .await
+            .with_context(|| format!("Saving inventory to {} store", self.store.name()))
+    }
+}
+
+impl std::fmt::Debug for PendingInventory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingInventory")
+            .field("store", &self.store.name())
+            .field("account_id", &self.account_id)
+            .field("resources", &self.inventory.resources.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InventoryEventKind {
+    /// The resource as it is in this run
+    Snapshot,
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A resource in the snapshot, or a change to it since the last run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct InventoryEvent {
+    pub(crate) kind: InventoryEventKind,
+    pub(crate) arn: String,
+    pub(crate) resource_type: String,
+    /// Hash of the resource in this run, `None` once deleted
+    pub(crate) hash: Option<String>,
+    /// Hash of the resource in the last run
+    pub(crate) previous_hash: Option<String>,
+    /// The resource in this run, `None` once deleted
+    pub(crate) resource: Option<Value>,
+}
+
+/// Fields describing how a resource was collected rather than the
+/// resource itself, left out of its hash
+const DIAGNOSTIC_FIELDS: [&str; 2] = ["errors", "severity"];
+
+#[derive(Debug)]
+struct InventoryResource {
+    resource_type: String,
+    hash: String,
+    value: Value,
+    /// Part of the resource could not be read, so it is not compared
+    /// with the last run
+    incomplete: bool,
+}
+
+/// Snapshot and change events for every resource in an account
+#[derive(Debug, Default)]
+pub(crate) struct AwsInventory {
+    resources: BTreeMap<String, InventoryResource>,
+    pub(crate) inner: Vec<InventoryEvent>,
+    /// Saved once the events are sent, `None` without a state store
+    pub(crate) pending: Option<PendingInventory>,
+}
+
+impl AwsInventory {
+    /// Add a resource to the snapshot
+    pub(crate) fn add<T: Serialize>(
+        &mut self,
+        arn: &str,
+        resource_type: &str,
+        resource: &T,
+    ) -> Result<()> {
+        let value = serde_json::to_value(resource)?;
+        let mut hashed = value.clone();
+        let mut incomplete = false;
+        if let Some(object) = hashed.as_object_mut() {
+            incomplete = object
+                .get("errors")
+                .and_then(Value::as_array)
+                .is_some_and(|errors| !errors.is_empty());
+            for field in DIAGNOSTIC_FIELDS {
+                let _ = object.remove(field);
+            }
+        }
+        let hash = hex::encode(Sha256::digest(serde_json::to_vec(&hashed)?));
+        let _ = self.resources.insert(
+            arn.to_string(),
+            InventoryResource {
+                resource_type: resource_type.to_string(),
+                hash,
+                value,
+                incomplete,
+            },
+        );
+        Ok(())
+    }
+
+    /// The compact inventory to save for the next run.
+    ///
+    /// Incomplete resources keep their entry from the `previous`
+    /// inventory so a failed read doesn't show up as a change on the
+    /// next run
+    pub(crate) fn inventory(&self, previous: Option<&Inventory>) -> Inventory {
+        Inventory {
+            resources: self
+                .resources
+                .iter()
+                .map(|(arn, resource)| {
+                    let entry = previous
+                        .filter(|_| resource.incomplete)
+                        .and_then(|previous| previous.resources.get(arn))
+                        .cloned()
+                        .unwrap_or_else(|| InventoryEntry {
+                            resource_type: resource.resource_type.clone(),
+                            hash: resource.hash.clone(),
+                        });
+                    (arn.clone(), entry)
+                })
+                .collect(),
+        }
+    }
+
+    /// Build the snapshot events, and change events against the
+    /// `previous` inventory if there is one
+    pub(crate) fn events(&mut self, previous: Option<&Inventory>) {
+        let mut events = vec![];
+        for (arn, resource) in self.resources.iter() {
+            let event = |kind, previous_hash: Option<&String>| InventoryEvent {
+                kind,
+                arn: arn.clone(),
+                resource_type: resource.resource_type.clone(),
+                hash: Some(resource.hash.clone()),
+                previous_hash: previous_hash.cloned(),
+                resource: Some(resource.value.clone()),
+            };
+            events.push(event(InventoryEventKind::Snapshot, None));
+
+            let Some(previous) = previous else {
+                continue;
+            };
+            match previous.resources.get(arn) {
+                None => events.push(event(InventoryEventKind::Created, None)),
+                Some(entry) if entry.hash != resource.hash && !resource.incomplete => {
+                    events.push(event(InventoryEventKind::Modified, Some(&entry.hash)))
+                }
+                Some(_) => {}
+            }
+        }
+
+        if let Some(previous) = previous {
+            events.extend(
+                previous
+                    .resources
+                    .iter()
+                    .filter(|(arn, _)| !self.resources.contains_key(*arn))
+                    .map(|(arn, entry)| InventoryEvent {
+                        kind: InventoryEventKind::Deleted,
+                        arn: arn.clone(),
+                        resource_type: entry.resource_type.clone(),
+                        hash: None,
+                        previous_hash: Some(entry.hash.clone()),
+                        resource: None,
+                    }),
+            );
+        }
+        self.inner = events;
+    }
+}
+
+impl ToHecEvents for &AwsInventory {
+    type Item = InventoryEvent;
+
+    fn source(&self) -> &str {
+        "aws_inventory"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AwsInventory, Inventory, InventoryEventKind};
+    use anyhow::Result;
+    use data_ingester_supporting::state_store::FileStateStore;
+    use serde_json::json;
+
+    fn kinds(inventory: &AwsInventory) -> Vec<(InventoryEventKind, &str)> {
+        inventory
+            .inner
+            .iter()
+            .filter(|event| event.kind != InventoryEventKind::Snapshot)
+            .map(|event| (event.kind, event.arn.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_first_run_is_snapshot_only() -> Result<()> {
+        let mut inventory = AwsInventory::default();
+        inventory.add(
+            "arn:aws:s3:::one",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        inventory.events(None);
+        assert_eq!(inventory.inner.len(), 1);
+        assert_eq!(inventory.inner[0].kind, InventoryEventKind::Snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes_between_runs() -> Result<()> {
+        let mut previous = AwsInventory::default();
+        previous.add(
+            "arn:aws:s3:::kept",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        previous.add(
+            "arn:aws:s3:::changed",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        previous.add(
+            "arn:aws:s3:::removed",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        let previous = previous.inventory(None);
+
+        let mut current = AwsInventory::default();
+        current.add(
+            "arn:aws:s3:::kept",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        current.add(
+            "arn:aws:s3:::changed",
+            "s3_bucket",
+            &json!({"public_read": true}),
+        )?;
+        current.add(
+            "arn:aws:s3:::added",
+            "s3_bucket",
+            &json!({"public_read": true}),
+        )?;
+        current.events(Some(&previous));
+
+        assert_eq!(
+            kinds(&current),
+            vec![
+                (InventoryEventKind::Created, "arn:aws:s3:::added"),
+                (InventoryEventKind::Modified, "arn:aws:s3:::changed"),
+                (InventoryEventKind::Deleted, "arn:aws:s3:::removed"),
+            ]
+        );
+        let modified = current
+            .inner
+            .iter()
+            .find(|event| event.kind == InventoryEventKind::Modified)
+            .expect("modified event");
+        assert_eq!(
+            modified.previous_hash.as_ref(),
+            previous
+                .resources
+                .get("arn:aws:s3:::changed")
+                .map(|entry| &entry.hash)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_collection_errors_are_not_changes() -> Result<()> {
+        let mut previous = AwsInventory::default();
+        previous.add(
+            "arn:aws:s3:::one",
+            "s3_bucket",
+            &json!({"public_read": false, "severity": null, "errors": []}),
+        )?;
+        let previous = previous.inventory(None);
+
+        let mut unreadable = AwsInventory::default();
+        unreadable.add(
+            "arn:aws:s3:::one",
+            "s3_bucket",
+            &json!({"public_read": true, "severity": "high", "errors": ["AccessDenied"]}),
+        )?;
+        unreadable.events(Some(&previous));
+        assert!(kinds(&unreadable).is_empty());
+        assert_eq!(unreadable.inventory(Some(&previous)), previous);
+
+        let mut rated = AwsInventory::default();
+        rated.add(
+            "arn:aws:s3:::one",
+            "s3_bucket",
+            &json!({"public_read": false, "severity": "low", "errors": []}),
+        )?;
+        rated.events(Some(&previous));
+        assert!(kinds(&rated).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inventory_round_trip() -> Result<()> {
+        let store = FileStateStore::new(
+            std::env::temp_dir().join(format!("ssphp_aws_inventory_{}", std::process::id())),
+        );
+        let mut inventory = AwsInventory::default();
+        inventory.add(
+            "arn:aws:s3:::one",
+            "s3_bucket",
+            &json!({"public_read": false}),
+        )?;
+        inventory
+            .inventory(None)
+            .save(&store, "111111111111")
+            .await?;
+
+        let loaded = Inventory::load(&store, "111111111111").await?;
+        assert_eq!(loaded, inventory.inventory(None));
+        assert_eq!(
+            Inventory::load(&store, "222222222222").await?,
+            Inventory::default()
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use data_ingester_splunk::splunk::ToHecEvents;
 
@@ -16,22 +19,33 @@ pub struct AwsPolicy {
 #[serde(untagged)]
 pub enum Statement {
     StatementVec(Vec<StatementElement>),
-    StatementElement(StatementElement),
+    StatementElement(Box<StatementElement>),
 }
 
+/// Condition block of a statement, operator to condition key to values
+///
+/// `{"StringEquals": {"aws:RequestedRegion": ["eu-west-2"]}}`
+pub type Condition = BTreeMap<String, BTreeMap<String, Value>>;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StatementElement {
     #[serde(rename = "Effect")]
     pub effect: String,
     #[serde(rename = "Action")]
-    pub action: Action,
+    pub action: Option<Action>,
+    #[serde(rename = "NotAction")]
+    pub not_action: Option<Action>,
     #[serde(rename = "Resource")]
-    pub resource: Resource,
+    pub resource: Option<Resource>,
+    #[serde(rename = "NotResource")]
+    pub not_resource: Option<Resource>,
+    #[serde(rename = "Principal")]
+    pub principal: Option<Principal>,
     #[serde(rename = "Sid")]
     pub sid: Option<String>,
-    // #[serde(rename = "Condition")]
-    // pub condition: Option<Condition>,
+    #[serde(rename = "Condition")]
+    pub condition: Option<Condition>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -42,6 +56,15 @@ pub enum Action {
     ActionSingle(String),
 }
 
+impl Action {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Action::ActionVec(vec) => vec,
+            Action::ActionSingle(action) => std::slice::from_ref(action),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
@@ -50,21 +73,81 @@ pub enum Resource {
     ResourceSingle(String),
 }
 
+impl Resource {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Resource::ResourceVec(vec) => vec,
+            Resource::ResourceSingle(resource) => std::slice::from_ref(resource),
+        }
+    }
+}
+
+/// Principal of a resource based policy statement, either `"*"` or a
+/// map of principal type to principals
+///
+/// `{"AWS": ["arn:aws:iam::111111111111:root"], "Service": "cloudtrail.amazonaws.com"}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Principal {
+    PrincipalSingle(String),
+    PrincipalMap(BTreeMap<String, PrincipalValues>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PrincipalValues {
+    PrincipalVec(Vec<String>),
+    PrincipalSingle(String),
+}
+
+impl Principal {
+    /// Values for one principal type such as `AWS` or `Service`
+    pub fn values(&self, principal_type: &str) -> &[String] {
+        match self {
+            Principal::PrincipalSingle(_) => &[],
+            Principal::PrincipalMap(map) => match map.get(principal_type) {
+                Some(PrincipalValues::PrincipalVec(vec)) => vec,
+                Some(PrincipalValues::PrincipalSingle(value)) => std::slice::from_ref(value),
+                None => &[],
+            },
+        }
+    }
+
+    /// Everyone, including anonymous users
+    pub fn is_public(&self) -> bool {
+        match self {
+            Principal::PrincipalSingle(principal) => principal == "*",
+            Principal::PrincipalMap(_) => self.values("AWS").iter().any(|aws| aws == "*"),
+        }
+    }
+}
+
+impl AwsPolicy {
+    pub fn statements(&self) -> &[StatementElement] {
+        match &self.statement {
+            Statement::StatementVec(vec) => vec,
+            Statement::StatementElement(statement) => std::slice::from_ref(&**statement),
+        }
+    }
+}
+
 impl StatementElement {
     pub fn contains_full_permissions(&self) -> bool {
         if self.effect != "Allow" {
             return false;
         }
         // This needs to understand ARNs better
-        let all_resources = match &self.resource {
-            Resource::ResourceVec(vec) => vec.iter().any(|r| r == "*"),
-            Resource::ResourceSingle(resource) => resource == "*",
-        };
-
-        let all_actions = match &self.action {
-            Action::ActionVec(vec) => vec.iter().any(|action| action == "*" || action == "*:*"),
-            Action::ActionSingle(action) => action == "*" || action == "*:*",
-        };
+        let all_resources = self
+            .resource
+            .as_ref()
+            .is_some_and(|resource| resource.values().iter().any(|r| r == "*"));
+
+        let all_actions = self.action.as_ref().is_some_and(|action| {
+            action
+                .values()
+                .iter()
+                .any(|action| action == "*" || action == "*:*")
+        });
 
         matches!((all_actions, all_resources), (true, true))
     }
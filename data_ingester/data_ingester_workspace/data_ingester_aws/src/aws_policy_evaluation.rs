@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use data_ingester_supporting::cidr::cidr_contains;
+
+use crate::aws_policy::{AwsPolicy, Condition, StatementElement};
+
+/// Condition keys of a request, keys are lower case as condition keys
+/// are case insensitive
+pub(crate) type RequestContext = HashMap<String, Vec<String>>;
+
+/// How the conditions of a statement are evaluated
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Conditions<'r> {
+    /// Against the condition keys of a request
+    Context(&'r RequestContext),
+    /// For the worst case request: a conditional Allow can be
+    /// satisfied and a conditional Deny is not relied on
+    WorstCase,
+}
+
+/// A request to evaluate against a principal's policies
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Request<'r> {
+    pub(crate) action: &'r str,
+    pub(crate) resource: &'r str,
+    pub(crate) conditions: Conditions<'r>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Decision {
+    Allow,
+    ExplicitDeny,
+    /// No statement allowed the request
+    ImplicitDeny,
+}
+
+/// Every policy that applies to an IAM user or role.
+///
+/// https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_policies_evaluation-logic.html
+///
+/// Resource policies, session policies and the SCP exemption of
+/// service-linked roles are not modelled.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EffectivePolicies {
+    /// Inline and attached policies of the principal and, for users,
+    /// its groups
+    pub(crate) identity: Vec<AwsPolicy>,
+    pub(crate) boundary: Option<AwsPolicy>,
+    /// SCPs attached to each level of the Organization, from the root
+    /// to the account. Every level has to allow a request.
+    pub(crate) scps: Vec<Vec<AwsPolicy>>,
+}
+
+impl EffectivePolicies {
+    /// Can the principal make `request`
+    pub(crate) fn evaluate(&self, request: &Request) -> Decision {
+        let boundary = self
+            .boundary
+            .as_ref()
+            .map(|boundary| evaluate_policies(std::slice::from_ref(boundary), request));
+        let scps: Vec<Decision> = self
+            .scps
+            .iter()
+            .map(|level| evaluate_policies(level, request))
+            .collect();
+        let identity = evaluate_policies(&self.identity, request);
+
+        if boundary
+            .iter()
+            .chain(scps.iter())
+            .chain(std::iter::once(&identity))
+            .any(|decision| *decision == Decision::ExplicitDeny)
+        {
+            return Decision::ExplicitDeny;
+        }
+        if scps.iter().any(|decision| *decision != Decision::Allow)
+            || boundary.is_some_and(|decision| decision != Decision::Allow)
+        {
+            return Decision::ImplicitDeny;
+        }
+        identity
+    }
+
+    /// Can any request for `action` on `resource` be allowed.
+    ///
+    /// Evaluated for [Conditions::WorstCase], a principal is assumed to
+    /// meet the conditions of an Allow and to avoid those of a Deny.
+    pub(crate) fn is_allowed(&self, action: &str, resource: &str) -> bool {
+        self.evaluate(&Request {
+            action,
+            resource,
+            conditions: Conditions::WorstCase,
+        }) == Decision::Allow
+    }
+
+    /// Allow statements in the identity policies granting actions on
+    /// every resource
+    pub(crate) fn wildcard_resource_statements(&self) -> impl Iterator<Item = &StatementElement> {
+        self.identity
+            .iter()
+            .flat_map(|policy| policy.statements())
+            .filter(|statement| statement.effect == "Allow" && statement.condition.is_none())
+            .filter(|statement| {
+                statement
+                    .resource
+                    .as_ref()
+                    .is_some_and(|resource| resource.values().iter().any(|r| r == "*"))
+            })
+    }
+}
+
+/// Decision of a single set of policies, ignoring every other type of
+/// policy
+pub(crate) fn evaluate_policies(policies: &[AwsPolicy], request: &Request) -> Decision {
+    let mut decision = Decision::ImplicitDeny;
+    for statement in policies.iter().flat_map(|policy| policy.statements()) {
+        if !statement_applies(statement, request) {
+            continue;
+        }
+        if statement.effect == "Deny" {
+            return Decision::ExplicitDeny;
+        }
+        if statement.effect == "Allow" {
+            decision = Decision::Allow;
+        }
+    }
+    decision
+}
+
+fn statement_applies(statement: &StatementElement, request: &Request) -> bool {
+    let action = match (&statement.action, &statement.not_action) {
+        (Some(action), _) => action
+            .values()
+            .iter()
+            .any(|pattern| action_matches(pattern, request.action)),
+        (None, Some(not_action)) => !not_action
+            .values()
+            .iter()
+            .any(|pattern| action_matches(pattern, request.action)),
+        (None, None) => false,
+    };
+    let resource = match (&statement.resource, &statement.not_resource) {
+        (Some(resource), _) => resource
+            .values()
+            .iter()
+            .any(|pattern| wildcard_match(pattern, request.resource)),
+        (None, Some(not_resource)) => !not_resource
+            .values()
+            .iter()
+            .any(|pattern| wildcard_match(pattern, request.resource)),
+        // SCPs have no resource element
+        (None, None) => true,
+    };
+    action
+        && resource
+        && statement
+            .condition
+            .as_ref()
+            .is_none_or(|condition| match request.conditions {
+                Conditions::Context(context) => condition_matches(condition, context),
+                Conditions::WorstCase => statement.effect == "Allow",
+            })
+}
+
+/// Actions are case insensitive, `*` on its own is every action
+pub(crate) fn action_matches(pattern: &str, action: &str) -> bool {
+    wildcard_match(&pattern.to_lowercase(), &action.to_lowercase())
+}
+
+/// Match `value` against a pattern where `*` is any run of characters
+/// and `?` is any single character
+pub(crate) fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `*` in the pattern and the value position it
+    // was tried against, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            p = star_p + 1;
+            v = star_v + 1;
+            star = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Every operator and key in a condition block has to match.
+///
+/// Operators that are not supported never match, so a conditional
+/// Allow is not granted and a conditional Deny is not applied.
+fn condition_matches(condition: &Condition, context: &RequestContext) -> bool {
+    condition.iter().all(|(operator, keys)| {
+        keys.iter().all(|(key, values)| {
+            operator_matches(
+                operator,
+                &condition_values(values),
+                context.get(&key.to_lowercase()),
+            )
+        })
+    })
+}
+
+/// The values of a condition key, flattening arrays
+pub(crate) fn condition_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(condition_values).collect(),
+        Value::String(value) => vec![value.to_string()],
+        Value::Null => vec![],
+        value => vec![value.to_string()],
+    }
+}
+
+fn operator_matches(
+    operator: &str,
+    policy_values: &[String],
+    context: Option<&Vec<String>>,
+) -> bool {
+    let (for_all_values, operator) = match operator.split_once(':') {
+        Some(("ForAllValues", operator)) => (true, operator),
+        Some(("ForAnyValue", operator)) => (false, operator),
+        Some(_) => return false,
+        None => (false, operator),
+    };
+    let (if_exists, operator) = match operator.strip_suffix("IfExists") {
+        Some(operator) => (true, operator),
+        None => (false, operator),
+    };
+
+    let context_values = context.filter(|values| !values.is_empty());
+    if operator == "Null" {
+        let key_is_null = context_values.is_none();
+        return policy_values
+            .iter()
+            .any(|value| value.eq_ignore_ascii_case(&key_is_null.to_string()));
+    }
+
+    let (negated, positive) = match operator {
+        "StringNotEquals" => (true, "StringEquals"),
+        "StringNotEqualsIgnoreCase" => (true, "StringEqualsIgnoreCase"),
+        "StringNotLike" => (true, "StringLike"),
+        "ArnNotEquals" => (true, "ArnEquals"),
+        "ArnNotLike" => (true, "ArnLike"),
+        "NumericNotEquals" => (true, "NumericEquals"),
+        "NotIpAddress" => (true, "IpAddress"),
+        operator => (false, operator),
+    };
+
+    let Some(context_values) = context_values else {
+        // A missing key only matches the negated and `IfExists`
+        // operators and `ForAllValues`
+        return if_exists || for_all_values || negated;
+    };
+
+    let mut supported = true;
+    let predicate = |context_value: &String| {
+        let any = policy_values.iter().any(|policy_value| {
+            match value_matches(positive, policy_value, context_value) {
+                Some(matches) => matches,
+                None => {
+                    supported = false;
+                    false
+                }
+            }
+        });
+        any != negated
+    };
+    let matches = if for_all_values {
+        context_values.iter().all(predicate)
+    } else {
+        context_values.iter().any(predicate)
+    };
+    supported && matches
+}
+
+/// Does a single context value match a single policy value for a
+/// non-negated operator, `None` for unsupported operators
+fn value_matches(operator: &str, policy_value: &str, context_value: &str) -> Option<bool> {
+    let numbers = || {
+        Some((
+            policy_value.parse::<f64>().ok()?,
+            context_value.parse::<f64>().ok()?,
+        ))
+    };
+    let matches = match operator {
+        "StringEquals" | "ArnEquals" => policy_value == context_value,
+        "StringEqualsIgnoreCase" => policy_value.eq_ignore_ascii_case(context_value),
+        "StringLike" | "ArnLike" => wildcard_match(policy_value, context_value),
+        "Bool" => policy_value.eq_ignore_ascii_case(context_value),
+        "NumericEquals" => numbers().is_some_and(|(p, c)| c == p),
+        "NumericLessThan" => numbers().is_some_and(|(p, c)| c < p),
+        "NumericLessThanEquals" => numbers().is_some_and(|(p, c)| c <= p),
+        "NumericGreaterThan" => numbers().is_some_and(|(p, c)| c > p),
+        "NumericGreaterThanEquals" => numbers().is_some_and(|(p, c)| c >= p),
+        "IpAddress" => context_value
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| cidr_contains(policy_value, ip)),
+        _ => return None,
+    };
+    Some(matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        evaluate_policies, wildcard_match, Conditions, Decision, EffectivePolicies, Request,
+        RequestContext,
+    };
+    use crate::aws_policy::AwsPolicy;
+    use anyhow::Result;
+
+    fn policy(json: &str) -> Result<AwsPolicy> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    fn request<'r>(action: &'r str, resource: &'r str, context: &'r RequestContext) -> Request<'r> {
+        Request {
+            action,
+            resource,
+            conditions: Conditions::Context(context),
+        }
+    }
+
+    const ADMIN: &str = r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": "*", "Resource": "*"}}"#;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match(
+            "arn:aws:s3:::bucket/*",
+            "arn:aws:s3:::bucket/a/b"
+        ));
+        assert!(wildcard_match("iam:*User*", "iam:CreateUserPolicy"));
+        assert!(wildcard_match("ec2:Describe?pcs", "ec2:DescribeVpcs"));
+        assert!(!wildcard_match(
+            "arn:aws:s3:::bucket/*",
+            "arn:aws:s3:::other/a"
+        ));
+        assert!(!wildcard_match("s3:Get*", "s3:PutObject"));
+    }
+
+    #[test]
+    fn test_not_action_and_case_insensitive_actions() -> Result<()> {
+        let power_user = policy(
+            r#"{"Version": "2012-10-17", "Statement": [{"Effect": "Allow", "NotAction": ["iam:*", "organizations:*"], "Resource": "*"}]}"#,
+        )?;
+        let context = RequestContext::new();
+        let policies = [power_user];
+        assert_eq!(
+            evaluate_policies(&policies, &request("EC2:RunInstances", "*", &context)),
+            Decision::Allow
+        );
+        assert_eq!(
+            evaluate_policies(&policies, &request("iam:CreateUser", "*", &context)),
+            Decision::ImplicitDeny
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_explicit_deny_wins() -> Result<()> {
+        let policies = EffectivePolicies {
+            identity: vec![
+                policy(ADMIN)?,
+                policy(
+                    r#"{"Version": "2012-10-17", "Statement": {"Effect": "Deny", "Action": "s3:*", "NotResource": "arn:aws:s3:::logs/*"}}"#,
+                )?,
+            ],
+            ..Default::default()
+        };
+        assert!(policies.is_allowed("s3:GetObject", "arn:aws:s3:::logs/2024"));
+        assert!(!policies.is_allowed("s3:GetObject", "arn:aws:s3:::secrets/key"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_boundary_and_scps_limit_identity_policies() -> Result<()> {
+        let boundary = policy(
+            r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": ["s3:*", "ec2:*"], "Resource": "*"}}"#,
+        )?;
+        let full_access = policy(
+            r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": "*"}}"#,
+        )?;
+        let deny_ec2 = policy(
+            r#"{"Version": "2012-10-17", "Statement": {"Effect": "Deny", "Action": "ec2:RunInstances"}}"#,
+        )?;
+        let policies = EffectivePolicies {
+            identity: vec![policy(ADMIN)?],
+            boundary: Some(boundary),
+            scps: vec![vec![full_access.clone()], vec![full_access, deny_ec2]],
+        };
+        assert!(policies.is_allowed("s3:PutObject", "arn:aws:s3:::bucket/key"));
+        assert!(!policies.is_allowed("iam:CreateUser", "*"));
+        assert!(!policies.is_allowed("ec2:RunInstances", "*"));
+
+        let no_scp_allow = EffectivePolicies {
+            identity: vec![policy(ADMIN)?],
+            scps: vec![vec![]],
+            ..Default::default()
+        };
+        assert!(!no_scp_allow.is_allowed("s3:PutObject", "*"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditions() -> Result<()> {
+        let policies = [policy(
+            r#"{"Version": "2012-10-17", "Statement": [
+                {"Effect": "Allow", "Action": "*", "Resource": "*", "Condition": {"Bool": {"aws:MultiFactorAuthPresent": "true"}}},
+                {"Effect": "Deny", "Action": "*", "Resource": "*", "Condition": {"NotIpAddress": {"aws:SourceIp": ["10.0.0.0/8"]}, "StringNotEqualsIfExists": {"aws:RequestedRegion": "eu-west-2"}}}
+            ]}"#,
+        )?];
+
+        // Negated operators match a missing key
+        let mut context = RequestContext::new();
+        assert_eq!(
+            evaluate_policies(&policies, &request("s3:GetObject", "*", &context)),
+            Decision::ExplicitDeny
+        );
+
+        let _ = context.insert("aws:multifactorauthpresent".into(), vec!["true".into()]);
+        let _ = context.insert("aws:sourceip".into(), vec!["10.1.2.3".into()]);
+        assert_eq!(
+            evaluate_policies(&policies, &request("s3:GetObject", "*", &context)),
+            Decision::Allow
+        );
+
+        let _ = context.insert("aws:sourceip".into(), vec!["192.0.2.1".into()]);
+        assert_eq!(
+            evaluate_policies(&policies, &request("s3:GetObject", "*", &context)),
+            Decision::ExplicitDeny
+        );
+
+        let _ = context.insert("aws:requestedregion".into(), vec!["eu-west-2".into()]);
+        assert_eq!(
+            evaluate_policies(&policies, &request("s3:GetObject", "*", &context)),
+            Decision::Allow
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_allowed_assumes_worst_case_conditions() -> Result<()> {
+        let policies = EffectivePolicies {
+            identity: vec![policy(
+                r#"{"Version": "2012-10-17", "Statement": [
+                    {"Effect": "Allow", "Action": "iam:*", "Resource": "*", "Condition": {"Bool": {"aws:MultiFactorAuthPresent": "true"}}},
+                    {"Effect": "Deny", "Action": "iam:DeleteUser", "Resource": "*", "Condition": {"NotIpAddress": {"aws:SourceIp": ["10.0.0.0/8"]}}},
+                    {"Effect": "Deny", "Action": "iam:CreateAccessKey", "Resource": "*"}
+                ]}"#,
+            )?],
+            ..Default::default()
+        };
+        // A conditional Allow can be met
+        assert!(policies.is_allowed("iam:CreateUser", "*"));
+        // A conditional Deny can be avoided
+        assert!(policies.is_allowed("iam:DeleteUser", "*"));
+        assert!(!policies.is_allowed("iam:CreateAccessKey", "*"));
+        assert!(!policies.is_allowed("s3:GetObject", "*"));
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+pub struct DescribeDbInstances {
+    pub inner: Vec<DbInstance>,
+}
+
+impl ToHecEvents for &DescribeDbInstances {
+    type Item = DbInstance;
+
+    fn source(&self) -> &str {
+        "rds_DescribeDBInstances"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[derive(::std::clone::Clone, ::std::cmp::PartialEq, ::std::fmt::Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbInstance {
+    /// <p>The user-supplied database identifier. This identifier is the unique key that identifies a DB instance.</p>
+    pub db_instance_identifier: ::std::option::Option<::std::string::String>,
+    /// <p>The Amazon Resource Name (ARN) for the DB instance.</p>
+    pub db_instance_arn: ::std::option::Option<::std::string::String>,
+    /// <p>The database engine used for this DB instance.</p>
+    pub engine: ::std::option::Option<::std::string::String>,
+    /// <p>The version of the database engine.</p>
+    pub engine_version: ::std::option::Option<::std::string::String>,
+    /// <p>The current state of this database.</p>
+    pub db_instance_status: ::std::option::Option<::std::string::String>,
+    /// <p>Indicates whether the DB instance is encrypted.</p>
+    pub storage_encrypted: ::std::option::Option<bool>,
+    /// <p>If <code>StorageEncrypted</code> is enabled, the Amazon Web Services KMS key identifier for the encrypted DB instance.</p>
+    pub kms_key_id: ::std::option::Option<::std::string::String>,
+    /// <p>Indicates whether minor version patches are applied automatically.</p>
+    pub auto_minor_version_upgrade: ::std::option::Option<bool>,
+    /// <p>Indicates whether the DB instance is publicly accessible.</p>
+    pub publicly_accessible: ::std::option::Option<bool>,
+    /// Minor version patches are applied automatically, CIS AWS 2.3.2
+    pub auto_minor_version_upgrade_enabled: bool,
+    /// The instance is not publicly accessible, CIS AWS 2.3.3
+    pub public_access_disabled: bool,
+}
+
+impl From<aws_sdk_rds::types::DbInstance> for DbInstance {
+    fn from(value: aws_sdk_rds::types::DbInstance) -> Self {
+        Self {
+            db_instance_identifier: value.db_instance_identifier,
+            db_instance_arn: value.db_instance_arn,
+            engine: value.engine,
+            engine_version: value.engine_version,
+            db_instance_status: value.db_instance_status,
+            storage_encrypted: value.storage_encrypted,
+            kms_key_id: value.kms_key_id,
+            auto_minor_version_upgrade: value.auto_minor_version_upgrade,
+            publicly_accessible: value.publicly_accessible,
+            auto_minor_version_upgrade_enabled: value.auto_minor_version_upgrade == Some(true),
+            public_access_disabled: value.publicly_accessible == Some(false),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::{bail, Result};
+use data_ingester_splunk::splunk::ToHecEvents;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Default number of regions checked at the same time
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Default attempts for each AWS request, including throttled requests
+pub(crate) const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Which regions the regional checks run in
+///
+/// Read from the environment by [AwsRegionConfig::from_env]:
+/// SSPHP_AWS_REGIONS: comma separated regions to check. When unset every
+///     region enabled for the account is checked
+/// SSPHP_AWS_REGIONS_DENY: comma separated regions to skip
+/// SSPHP_AWS_REGION_CONCURRENCY: regions checked at the same time, defaults to 4
+/// SSPHP_AWS_MAX_ATTEMPTS: attempts for each request before giving up,
+///     defaults to 5
+#[derive(Debug, Clone)]
+pub(crate) struct AwsRegionConfig {
+    pub(crate) allow: Option<Vec<String>>,
+    pub(crate) deny: Vec<String>,
+    pub(crate) concurrency: usize,
+    pub(crate) max_attempts: u32,
+}
+
+impl Default for AwsRegionConfig {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            deny: vec![],
+            concurrency: DEFAULT_CONCURRENCY,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+}
+
+impl AwsRegionConfig {
+    pub(crate) fn from_env() -> Result<Self> {
+        let mut config = Self {
+            allow: std::env::var("SSPHP_AWS_REGIONS")
+                .ok()
+                .map(|regions| split_regions(&regions))
+                .filter(|regions| !regions.is_empty()),
+            deny: std::env::var("SSPHP_AWS_REGIONS_DENY")
+                .map(|regions| split_regions(&regions))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        if let Ok(concurrency) = std::env::var("SSPHP_AWS_REGION_CONCURRENCY") {
+            config.concurrency = concurrency.parse()?;
+            if config.concurrency == 0 {
+                bail!("SSPHP_AWS_REGION_CONCURRENCY must be greater than 0");
+            }
+        }
+        if let Ok(max_attempts) = std::env::var("SSPHP_AWS_MAX_ATTEMPTS") {
+            config.max_attempts = max_attempts.parse()?;
+            if config.max_attempts == 0 {
+                bail!("SSPHP_AWS_MAX_ATTEMPTS must be greater than 0");
+            }
+        }
+        Ok(config)
+    }
+
+    /// Apply the allow and deny lists to the regions enabled for an account
+    pub(crate) fn filter(&self, regions: Vec<String>) -> Vec<String> {
+        let mut regions: Vec<String> = regions
+            .into_iter()
+            .filter(|region| {
+                self.allow
+                    .as_ref()
+                    .is_none_or(|allow| allow.contains(region))
+            })
+            .filter(|region| !self.deny.contains(region))
+            .collect();
+        regions.sort();
+        regions.dedup();
+        regions
+    }
+}
+
+fn split_regions(regions: &str) -> Vec<String> {
+    regions
+        .split(',')
+        .map(|region| region.trim())
+        .filter(|region| !region.is_empty())
+        .map(|region| region.to_string())
+        .collect()
+}
+
+/// Regions used when ec2:DescribeRegions fails.
+///
+/// Only regions enabled by default in the `aws` partition, opt-in
+/// regions such as af-south-1 or me-south-1 reject every request unless
+/// the account has enabled them.
+pub(crate) const FALLBACK_REGIONS: [&str; 17] = [
+    "ap-northeast-1",
+    "ap-northeast-2",
+    "ap-northeast-3",
+    "ap-south-1",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "ca-central-1",
+    "eu-central-1",
+    "eu-north-1",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "sa-east-1",
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+];
+
+/// Is a region from ec2:DescribeRegions usable by the account.
+///
+/// Opt-in regions that have not been enabled reject every request
+pub(crate) fn region_is_enabled(opt_in_status: Option<&str>) -> bool {
+    !matches!(opt_in_status, Some("not-opted-in"))
+}
+
+/// Events from one check run in several regions, each tagged with
+/// the `region` it came from
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RegionalEvents {
+    source: String,
+    sourcetype: String,
+    inner: Vec<Value>,
+    /// Regions the check failed in, recorded on the run manifest
+    #[serde(skip)]
+    errors: Vec<String>,
+}
+
+impl RegionalEvents {
+    /// Add the events of a check run in `region`
+    pub(crate) fn push<T>(&mut self, region: &str, result: &T) -> Result<()>
+    where
+        for<'a> &'a T: ToHecEvents,
+    {
+        if self.source.is_empty() {
+            self.source = result.source().to_string();
+            self.sourcetype = result.sourcetype().to_string();
+        }
+        for item in result.collection() {
+            let mut value = serde_json::to_value(item)?;
+            if let Some(object) = value.as_object_mut() {
+                let _ = object.insert("region".to_string(), Value::String(region.to_string()));
+            }
+            self.inner.push(value);
+        }
+        Ok(())
+    }
+
+    /// Record that the check failed in `region`
+    pub(crate) fn failed(&mut self, region: &str, err: &anyhow::Error) {
+        self.errors.push(format!("{}: {:?}", region, err));
+    }
+}
+
+impl ToHecEvents for &RegionalEvents {
+    type Item = Value;
+
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn sourcetype(&self) -> &str {
+        &self.sourcetype
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+
+    fn errors(&self) -> Vec<String> {
+        self.errors.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{region_is_enabled, AwsRegionConfig, RegionalEvents, FALLBACK_REGIONS};
+    use anyhow::Result;
+    use data_ingester_splunk::splunk::ToHecEvents;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize)]
+    struct Hub {
+        hub_arn: String,
+    }
+
+    #[derive(Debug)]
+    struct Hubs {
+        inner: Vec<Hub>,
+    }
+
+    impl ToHecEvents for &Hubs {
+        type Item = Hub;
+
+        fn source(&self) -> &str {
+            "securityhub_DescribeHub"
+        }
+
+        fn sourcetype(&self) -> &str {
+            "ssphp:aws:json"
+        }
+
+        fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+            Box::new(self.inner.iter())
+        }
+
+        fn ssphp_run_key(&self) -> &str {
+            "aws"
+        }
+    }
+
+    fn regions(regions: &[&str]) -> Vec<String> {
+        regions.iter().map(|region| region.to_string()).collect()
+    }
+
+    #[test]
+    fn test_filter_allow_and_deny() {
+        let config = AwsRegionConfig {
+            allow: Some(regions(&["eu-west-1", "eu-west-2", "us-east-1"])),
+            deny: regions(&["us-east-1"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.filter(regions(&[
+                "us-east-1",
+                "eu-west-2",
+                "ap-south-1",
+                "eu-west-1"
+            ])),
+            regions(&["eu-west-1", "eu-west-2"])
+        );
+    }
+
+    #[test]
+    fn test_filter_without_allow_list() {
+        let config = AwsRegionConfig {
+            deny: regions(&["ap-south-1"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.filter(regions(&["us-east-1", "ap-south-1", "eu-west-2"])),
+            regions(&["eu-west-2", "us-east-1"])
+        );
+    }
+
+    #[test]
+    fn test_region_is_enabled() {
+        assert!(region_is_enabled(Some("opt-in-not-required")));
+        assert!(region_is_enabled(Some("opted-in")));
+        assert!(region_is_enabled(None));
+        assert!(!region_is_enabled(Some("not-opted-in")));
+    }
+
+    #[test]
+    fn test_fallback_regions_exclude_opt_in_regions() {
+        for opt_in in [
+            "af-south-1",
+            "ap-east-1",
+            "ap-southeast-3",
+            "eu-south-1",
+            "me-south-1",
+        ] {
+            assert!(!FALLBACK_REGIONS.contains(&opt_in));
+        }
+    }
+
+    #[test]
+    fn test_regional_events_record_failed_regions() {
+        let mut events = RegionalEvents::default();
+        events.failed("eu-west-2", &anyhow::anyhow!("AccessDenied"));
+        let errors = (&events).errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("eu-west-2: "));
+        assert!((&events).collection().next().is_none());
+    }
+
+    #[test]
+    fn test_regional_events_are_tagged_with_region() -> Result<()> {
+        let mut events = RegionalEvents::default();
+        events.push(
+            "eu-west-2",
+            &Hubs {
+                inner: vec![Hub {
+                    hub_arn: "arn:aws:securityhub:eu-west-2:111111111111:hub/default".into(),
+                }],
+            },
+        )?;
+        events.push(
+            "us-east-1",
+            &Hubs {
+                inner: vec![Hub {
+                    hub_arn: "arn:aws:securityhub:us-east-1:111111111111:hub/default".into(),
+                }],
+            },
+        )?;
+
+        let events = &events;
+        assert_eq!(events.source(), "securityhub_DescribeHub");
+        let regions: Vec<&str> = events
+            .collection()
+            .filter_map(|event| event["region"].as_str())
+            .collect();
+        assert_eq!(regions, vec!["eu-west-2", "us-east-1"]);
+        assert_eq!(events.to_hec_events()?.len(), 2);
+        Ok(())
+    }
+}
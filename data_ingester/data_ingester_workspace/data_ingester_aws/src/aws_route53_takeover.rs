@@ -0,0 +1,641 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioResolver;
+use serde::Serialize;
+use tracing::warn;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+use crate::aws_policy_evaluation::wildcard_match;
+use crate::aws_route53::HostedZones;
+
+/// Records probed at once
+const PROBE_CONCURRENCY: usize = 16;
+
+/// A service whose hostnames can be claimed by anyone once the
+/// resource behind them is deleted
+///
+/// https://github.com/EdOverflow/can-i-take-over-xyz
+#[derive(Debug)]
+struct TakeoverProvider {
+    name: &'static str,
+    /// Hostname patterns, `*` is any run of characters
+    patterns: &'static [&'static str],
+    /// Text in the response served for a name with no resource behind
+    /// it, compared with runs of whitespace collapsed to one space
+    fingerprints: &'static [&'static str],
+    /// The provider removes the name from DNS when the resource is
+    /// deleted, so NXDOMAIN means the name can be claimed
+    nxdomain_vulnerable: bool,
+}
+
+const PROVIDERS: &[TakeoverProvider] = &[
+    TakeoverProvider {
+        name: "s3_website",
+        patterns: &["*s3-website-*.amazonaws.com", "*s3-website.*.amazonaws.com"],
+        fingerprints: &["NoSuchBucket", "The specified bucket does not exist"],
+        nxdomain_vulnerable: false,
+    },
+    TakeoverProvider {
+        name: "elastic_beanstalk",
+        patterns: &["*.elasticbeanstalk.com"],
+        fingerprints: &[],
+        nxdomain_vulnerable: true,
+    },
+    TakeoverProvider {
+        name: "cloudfront",
+        patterns: &["*.cloudfront.net"],
+        // The 403 served when no distribution has the name as an
+        // alternate domain, not the generic CloudFront error page
+        fingerprints: &[
+            "Bad request. We can't connect to the server for this app or website at this time.",
+        ],
+        nxdomain_vulnerable: true,
+    },
+    TakeoverProvider {
+        name: "azure_app_service",
+        patterns: &["*.azurewebsites.net"],
+        fingerprints: &["404 Web Site not found"],
+        nxdomain_vulnerable: true,
+    },
+    TakeoverProvider {
+        name: "azure_cloud_service",
+        patterns: &["*.cloudapp.net", "*.cloudapp.azure.com"],
+        fingerprints: &[],
+        nxdomain_vulnerable: true,
+    },
+    TakeoverProvider {
+        name: "github_pages",
+        patterns: &["*.github.io"],
+        fingerprints: &["There isn't a GitHub Pages site here"],
+        nxdomain_vulnerable: false,
+    },
+    TakeoverProvider {
+        name: "heroku",
+        patterns: &["*.herokuapp.com", "*.herokudns.com"],
+        fingerprints: &["No such app", "no-such-app.html"],
+        nxdomain_vulnerable: true,
+    },
+];
+
+/// DNS and HTTP lookups used to decide if a record is dangling
+pub(crate) trait TakeoverProbe {
+    /// Does `name` resolve. `Ok(false)` for NXDOMAIN
+    async fn resolves(&self, name: &str) -> Result<bool>;
+
+    /// Body served for `http://{host}/`
+    async fn fetch(&self, host: &str) -> Result<String>;
+}
+
+/// [TakeoverProbe] using the system resolver and plain HTTP
+pub(crate) struct HickoryTakeoverProbe {
+    resolver: TokioResolver,
+    http: reqwest::Client,
+}
+
+impl HickoryTakeoverProbe {
+    pub(crate) fn new() -> Result<Self> {
+        Self::with_resolver(ResolverConfig::default(), ResolverOpts::default())
+    }
+
+    pub(crate) fn with_resolver(config: ResolverConfig, options: ResolverOpts) -> Result<Self> {
+        Ok(Self {
+            resolver: TokioResolver::tokio(config, options),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+}
+
+impl TakeoverProbe for HickoryTakeoverProbe {
+    async fn resolves(&self, name: &str) -> Result<bool> {
+        match self.resolver.lookup_ip(name).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.is_nx_domain() => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn fetch(&self, host: &str) -> Result<String> {
+        Ok(self
+            .http
+            .get(format!("http://{}/", host))
+            .send()
+            .await?
+            .text()
+            .await?)
+    }
+}
+
+/// A Route53 record pointing at a resource that no longer exists
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct TakeoverFinding {
+    pub(crate) hosted_zone_name: String,
+    pub(crate) record_name: String,
+    /// `CNAME` or `A`/`AAAA` for aliases
+    pub(crate) record_type: String,
+    pub(crate) target: String,
+    pub(crate) alias: bool,
+    pub(crate) provider: String,
+    /// Why the record is dangling: `nxdomain`, `fingerprint` or
+    /// `bucket_not_in_inventory`, or `unverified` when every lookup
+    /// failed so the record may be dangling
+    pub(crate) evidence: Vec<String>,
+    pub(crate) fingerprint: Option<String>,
+    /// Bucket an S3 website record serves
+    pub(crate) bucket_name: Option<String>,
+    /// Errors from lookups that could not be completed
+    pub(crate) errors: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct TakeoverFindings {
+    pub(crate) inner: Vec<TakeoverFinding>,
+}
+
+impl ToHecEvents for &TakeoverFindings {
+    type Item = TakeoverFinding;
+
+    fn source(&self) -> &str {
+        "route53_takeover_detector"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+/// Lowercase without the trailing dot of a fully qualified name
+fn normalise(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+fn provider_for(target: &str) -> Option<&'static TakeoverProvider> {
+    PROVIDERS.iter().find(|provider| {
+        provider
+            .patterns
+            .iter()
+            .any(|pattern| wildcard_match(pattern, target))
+    })
+}
+
+/// `body` with each run of whitespace replaced by one space
+fn collapse_whitespace(body: &str) -> String {
+    body.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Bucket behind an S3 website endpoint. A CNAME names the bucket in
+/// the target, an alias must use the bucket name as the record name
+fn s3_bucket_name(record_name: &str, target: &str, alias: bool) -> String {
+    if alias {
+        return record_name.to_string();
+    }
+    target
+        .split_once(".s3-website")
+        .map(|(bucket, _)| bucket.to_string())
+        .unwrap_or_else(|| record_name.to_string())
+}
+
+/// A record target served by a takeover prone provider
+struct Candidate {
+    hosted_zone_name: String,
+    record_name: String,
+    record_type: String,
+    target: String,
+    alias: bool,
+    provider: &'static TakeoverProvider,
+}
+
+/// Check every CNAME and alias record that points at a takeover prone
+/// provider, [PROBE_CONCURRENCY] records at a time. `buckets` are the
+/// S3 buckets owned by the account
+pub(crate) async fn find_takeovers<P: TakeoverProbe>(
+    probe: &P,
+    zones: &HostedZones,
+    buckets: &HashSet<String>,
+) -> TakeoverFindings {
+    let mut candidates = vec![];
+    for zone in zones.inner.iter() {
+        for record in zone.resource_record_sets.iter().flatten() {
+            let mut targets: Vec<(String, bool)> = vec![];
+            if record.r#type == "CNAME" {
+                targets.extend(
+                    record
+                        .resource_records
+                        .iter()
+                        .flatten()
+                        .map(|resource_record| (normalise(&resource_record.value), false)),
+                );
+            }
+            if let Some(alias_target) = &record.alias_target {
+                targets.push((normalise(&alias_target.dns_name), true));
+            }
+
+            for (target, alias) in targets {
+                let Some(provider) = provider_for(&target) else {
+                    continue;
+                };
+                candidates.push(Candidate {
+                    hosted_zone_name: zone.name.clone(),
+                    record_name: normalise(&record.name),
+                    record_type: record.r#type.clone(),
+                    target,
+                    alias,
+                    provider,
+                });
+            }
+        }
+    }
+
+    let probed: Vec<Option<TakeoverFinding>> = stream::iter(
+        candidates
+            .into_iter()
+            .map(|candidate| probe_candidate(probe, candidate, buckets)),
+    )
+    .buffer_unordered(PROBE_CONCURRENCY)
+    .collect()
+    .await;
+    let mut findings: Vec<TakeoverFinding> = probed.into_iter().flatten().collect();
+    findings.sort_by(|a, b| {
+        (&a.hosted_zone_name, &a.record_name, &a.target).cmp(&(
+            &b.hosted_zone_name,
+            &b.record_name,
+            &b.target,
+        ))
+    });
+    TakeoverFindings { inner: findings }
+}
+
+/// Probe one record target, `None` if nothing suggests it is dangling.
+///
+/// A target whose lookups all fail is kept as `unverified` with the
+/// errors, so it isn't mistaken for a healthy record
+async fn probe_candidate<P: TakeoverProbe>(
+    probe: &P,
+    candidate: Candidate,
+    buckets: &HashSet<String>,
+) -> Option<TakeoverFinding> {
+    let Candidate {
+        hosted_zone_name,
+        record_name,
+        record_type,
+        target,
+        alias,
+        provider,
+    } = candidate;
+    let mut finding = TakeoverFinding {
+        hosted_zone_name,
+        record_name: record_name.clone(),
+        record_type,
+        target: target.clone(),
+        alias,
+        provider: provider.name.to_string(),
+        evidence: vec![],
+        fingerprint: None,
+        bucket_name: None,
+        errors: vec![],
+    };
+
+    let mut probes = 0;
+    // Aliases point at shared endpoints that always resolve
+    if provider.nxdomain_vulnerable && !alias {
+        probes += 1;
+        match probe.resolves(&target).await {
+            Ok(true) => {}
+            Ok(false) => finding.evidence.push("nxdomain".to_string()),
+            Err(err) => {
+                warn!(name="AWS", operation="Route53 takeover", target=target, error=?err);
+                finding
+                    .errors
+                    .push(format!("resolving {}: {}", target, err));
+            }
+        }
+    }
+
+    if !provider.fingerprints.is_empty() {
+        probes += 1;
+        match probe.fetch(&record_name).await {
+            Ok(body) => {
+                let body = collapse_whitespace(&body);
+                finding.fingerprint = provider
+                    .fingerprints
+                    .iter()
+                    .find(|fingerprint| body.contains(*fingerprint))
+                    .map(|fingerprint| fingerprint.to_string());
+                if finding.fingerprint.is_some() {
+                    finding.evidence.push("fingerprint".to_string());
+                }
+            }
+            Err(err) => {
+                warn!(name="AWS", operation="Route53 takeover", host=record_name, error=?err);
+                finding
+                    .errors
+                    .push(format!("fetching http://{}/: {}", record_name, err));
+            }
+        }
+    }
+
+    if provider.name == "s3_website" {
+        let bucket_name = s3_bucket_name(&record_name, &target, alias);
+        if !buckets.contains(&bucket_name) {
+            finding.evidence.push("bucket_not_in_inventory".to_string());
+        }
+        finding.bucket_name = Some(bucket_name);
+    }
+
+    if finding.evidence.is_empty() && probes > 0 && finding.errors.len() == probes {
+        finding.evidence.push("unverified".to_string());
+    }
+
+    (!finding.evidence.is_empty()).then_some(finding)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use super::{find_takeovers, HickoryTakeoverProbe, TakeoverProbe};
+    use crate::aws_route53::{HostedZone, HostedZones, ResourceRecordSet};
+    use anyhow::{anyhow, Result};
+    use aws_sdk_route53::types::{AliasTarget, ResourceRecord, RrType};
+    use hickory_resolver::config::{NameServerConfig, ResolverConfig, ResolverOpts};
+    use hickory_resolver::proto::op::{Message, ResponseCode};
+    use hickory_resolver::proto::rr::rdata::A;
+    use hickory_resolver::proto::rr::{RData, Record, RecordType};
+    use hickory_resolver::proto::xfer::Protocol;
+    use tokio::net::UdpSocket;
+
+    /// Local DNS and HTTP stand-in, names not in `resolving` are
+    /// NXDOMAIN and lookups of names in `failing` fail
+    #[derive(Default)]
+    struct StandIn {
+        resolving: HashSet<String>,
+        failing: HashSet<String>,
+        bodies: HashMap<String, String>,
+    }
+
+    impl TakeoverProbe for StandIn {
+        async fn resolves(&self, name: &str) -> Result<bool> {
+            if self.failing.contains(name) {
+                return Err(anyhow!("SERVFAIL"));
+            }
+            Ok(self.resolving.contains(name))
+        }
+
+        async fn fetch(&self, host: &str) -> Result<String> {
+            if self.failing.contains(host) {
+                return Err(anyhow!("connection refused"));
+            }
+            Ok(self.bodies.get(host).cloned().unwrap_or_default())
+        }
+    }
+
+    fn cname(name: &str, target: &str) -> Result<ResourceRecordSet> {
+        Ok(aws_sdk_route53::types::ResourceRecordSet::builder()
+            .name(name)
+            .r#type(RrType::Cname)
+            .resource_records(ResourceRecord::builder().value(target).build()?)
+            .build()?
+            .into())
+    }
+
+    fn alias(name: &str, target: &str) -> Result<ResourceRecordSet> {
+        Ok(aws_sdk_route53::types::ResourceRecordSet::builder()
+            .name(name)
+            .r#type(RrType::A)
+            .alias_target(
+                AliasTarget::builder()
+                    .hosted_zone_id("Z3GKZC51ZF0DB4")
+                    .dns_name(target)
+                    .evaluate_target_health(false)
+                    .build()?,
+            )
+            .build()?
+            .into())
+    }
+
+    fn zones(records: Vec<ResourceRecordSet>) -> Result<HostedZones> {
+        let mut zone: HostedZone = aws_sdk_route53::types::HostedZone::builder()
+            .id("/hostedzone/Z1")
+            .name("example.gov.uk.")
+            .caller_reference("test")
+            .build()?
+            .into();
+        zone.resource_record_sets = Some(records);
+        Ok(HostedZones { inner: vec![zone] })
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_cname_is_vulnerable() -> Result<()> {
+        let zones = zones(vec![
+            cname("old.example.gov.uk.", "old-app.azurewebsites.net")?,
+            cname("live.example.gov.uk.", "live-app.azurewebsites.net")?,
+            cname("www.example.gov.uk.", "www.example.com")?,
+        ])?;
+        let probe = StandIn {
+            resolving: HashSet::from(["live-app.azurewebsites.net".to_string()]),
+            ..Default::default()
+        };
+
+        let findings = find_takeovers(&probe, &zones, &HashSet::new()).await;
+        assert_eq!(findings.inner.len(), 1);
+        let finding = &findings.inner[0];
+        assert_eq!(finding.record_name, "old.example.gov.uk");
+        assert_eq!(finding.provider, "azure_app_service");
+        assert_eq!(finding.evidence, vec!["nxdomain"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_is_vulnerable() -> Result<()> {
+        let zones = zones(vec![
+            cname("docs.example.gov.uk.", "example.github.io.")?,
+            cname("blog.example.gov.uk.", "example.github.io.")?,
+        ])?;
+        let probe = StandIn {
+            bodies: HashMap::from([
+                (
+                    "docs.example.gov.uk".to_string(),
+                    "<h1>404</h1><p>There isn't a GitHub Pages site here.</p>".to_string(),
+                ),
+                (
+                    "blog.example.gov.uk".to_string(),
+                    "<h1>Blog</h1>".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let findings = find_takeovers(&probe, &zones, &HashSet::new()).await;
+        assert_eq!(findings.inner.len(), 1);
+        let finding = &findings.inner[0];
+        assert_eq!(finding.record_name, "docs.example.gov.uk");
+        assert_eq!(finding.provider, "github_pages");
+        assert_eq!(finding.evidence, vec!["fingerprint"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_s3_website_inventory() -> Result<()> {
+        let zones = zones(vec![
+            alias(
+                "static.example.gov.uk.",
+                "s3-website.eu-west-2.amazonaws.com.",
+            )?,
+            cname(
+                "assets.example.gov.uk.",
+                "assets-bucket.s3-website-eu-west-1.amazonaws.com",
+            )?,
+        ])?;
+        let probe = StandIn {
+            bodies: HashMap::from([(
+                "assets.example.gov.uk".to_string(),
+                "<Code>NoSuchBucket</Code>".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let buckets = HashSet::from(["static.example.gov.uk".to_string()]);
+
+        let findings = find_takeovers(&probe, &zones, &buckets).await;
+        assert_eq!(findings.inner.len(), 1);
+        let finding = &findings.inner[0];
+        assert_eq!(finding.bucket_name.as_deref(), Some("assets-bucket"));
+        assert_eq!(
+            finding.evidence,
+            vec!["fingerprint", "bucket_not_in_inventory"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_lookups_are_unverified() -> Result<()> {
+        let zones = zones(vec![
+            cname("app.example.gov.uk.", "app.herokuapp.com")?,
+            cname("api.example.gov.uk.", "api.herokuapp.com")?,
+        ])?;
+        let probe = StandIn {
+            failing: HashSet::from([
+                "app.herokuapp.com".to_string(),
+                "app.example.gov.uk".to_string(),
+                "api.herokuapp.com".to_string(),
+            ]),
+            ..Default::default()
+        };
+
+        let findings = find_takeovers(&probe, &zones, &HashSet::new()).await;
+        assert_eq!(findings.inner.len(), 1);
+        let finding = &findings.inner[0];
+        assert_eq!(finding.record_name, "app.example.gov.uk");
+        assert_eq!(finding.evidence, vec!["unverified"]);
+        assert_eq!(finding.errors.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cloudfront_fingerprint() -> Result<()> {
+        let zones = zones(vec![
+            cname("cdn.example.gov.uk.", "d111111abcdef8.cloudfront.net")?,
+            cname("app.example.gov.uk.", "d222222abcdef8.cloudfront.net")?,
+        ])?;
+        let probe = StandIn {
+            resolving: HashSet::from([
+                "d111111abcdef8.cloudfront.net".to_string(),
+                "d222222abcdef8.cloudfront.net".to_string(),
+            ]),
+            bodies: HashMap::from([
+                (
+                    "cdn.example.gov.uk".to_string(),
+                    "<H1>403 ERROR</H1>\n<H2>The request could not be satisfied.</H2>\n<HR noshade size=\"1px\">\nBad request.\nWe can't connect to the server for this app or website at this time.".to_string(),
+                ),
+                (
+                    "app.example.gov.uk".to_string(),
+                    "<H1>502 ERROR</H1>\n<H2>The request could not be satisfied.</H2>\n<HR noshade size=\"1px\">\nCloudFront wasn't able to connect to the origin.".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let findings = find_takeovers(&probe, &zones, &HashSet::new()).await;
+        assert_eq!(findings.inner.len(), 1);
+        let finding = &findings.inner[0];
+        assert_eq!(finding.record_name, "cdn.example.gov.uk");
+        assert_eq!(finding.provider, "cloudfront");
+        assert_eq!(finding.evidence, vec!["fingerprint"]);
+        Ok(())
+    }
+
+    /// Answer DNS queries on a local UDP port: `live.example.` resolves,
+    /// `gone.example.` is NXDOMAIN and anything else is SERVFAIL
+    async fn local_resolver() -> Result<SocketAddr> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = socket.local_addr()?;
+        let _responder = tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buffer).await {
+                let Ok(request) = Message::from_vec(&buffer[..len]) else {
+                    continue;
+                };
+                let Some(query) = request.queries().first().cloned() else {
+                    continue;
+                };
+                let response_code = match query.name().to_ascii().as_str() {
+                    "live.example." => ResponseCode::NoError,
+                    "gone.example." => ResponseCode::NXDomain,
+                    _ => ResponseCode::ServFail,
+                };
+                let mut response =
+                    Message::error_msg(request.id(), request.op_code(), response_code);
+                let _ = response.set_recursion_desired(request.recursion_desired());
+                let _ = response.set_recursion_available(true);
+                let _ = response.add_query(query.clone());
+                if response_code == ResponseCode::NoError && query.query_type() == RecordType::A {
+                    let _ = response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A(A::new(127, 0, 0, 1)),
+                    ));
+                }
+                if let Ok(bytes) = response.to_vec() {
+                    let _ = socket.send_to(&bytes, from).await;
+                }
+            }
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_hickory_probe_resolves() -> Result<()> {
+        let addr = local_resolver().await?;
+        let config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            vec![NameServerConfig::new(addr, Protocol::Udp)],
+        );
+        let mut options = ResolverOpts::default();
+        options.attempts = 1;
+        options.timeout = Duration::from_secs(2);
+        options.cache_size = 0;
+        let probe = HickoryTakeoverProbe::with_resolver(config, options)?;
+
+        assert!(probe.resolves("live.example.").await?);
+        assert!(!probe.resolves("gone.example.").await?);
+        assert!(probe.resolves("broken.example.").await.is_err());
+        Ok(())
+    }
+}
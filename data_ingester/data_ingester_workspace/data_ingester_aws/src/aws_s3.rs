@@ -65,7 +65,7 @@ impl From<aws_sdk_s3::types::Owner> for Owner {
     }
 }
 
-#[derive(serde::Serialize, Debug)]
+#[derive(serde::Serialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct Grant {
     /// <p>The person being granted permissions.</p>
@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_smithy_types::error::display::DisplayErrorContext;
+use serde::Serialize;
+use tracing::warn;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+
+use crate::aws_findings::Severity;
+use crate::aws_policy::{AwsPolicy, StatementElement};
+use crate::aws_policy_evaluation::{
+    action_matches, condition_values, evaluate_policies, wildcard_match, Conditions, Decision,
+    Request,
+};
+use crate::aws_s3::Grant;
+
+/// Actions that read the objects or the listing of a bucket
+const READ_ACTIONS: &[&str] = &[
+    "s3:GetObject",
+    "s3:GetObjectVersion",
+    "s3:ListBucket",
+    "s3:ListBucketVersions",
+];
+
+/// Actions that change the objects of a bucket or who can access it
+const WRITE_ACTIONS: &[&str] = &[
+    "s3:PutObject",
+    "s3:DeleteObject",
+    "s3:DeleteObjectVersion",
+    "s3:PutObjectAcl",
+    "s3:PutBucketAcl",
+    "s3:PutBucketPolicy",
+];
+
+/// Condition keys that limit a statement to known callers. A statement
+/// for `*` restricted by one of these, to values without wildcards, is
+/// not public.
+///
+/// https://docs.aws.amazon.com/AmazonS3/latest/userguide/access-control-block-public-access.html#access-control-block-public-access-policy-status
+const RESTRICTING_CONDITION_KEYS: &[&str] = &[
+    "aws:sourceip",
+    "aws:sourcearn",
+    "aws:sourcevpc",
+    "aws:sourcevpce",
+    "aws:sourceowner",
+    "aws:sourceaccount",
+    "aws:principalarn",
+    "aws:principalaccount",
+    "aws:principalorgid",
+    "aws:userid",
+    "s3:dataaccesspointaccount",
+    "s3:dataaccesspointarn",
+];
+
+/// Error codes S3 returns when a bucket or access point has no such
+/// configuration
+const NOT_CONFIGURED_CODES: &[&str] = &[
+    "NoSuchBucketPolicy",
+    "NoSuchPublicAccessBlockConfiguration",
+    "NoSuchAccessPointPolicy",
+    "OwnershipControlsNotFoundError",
+    "ServerSideEncryptionConfigurationNotFoundError",
+];
+
+const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+const AUTHENTICATED_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AuthenticatedUsers";
+
+/// Block Public Access settings, `true` if either the account or the
+/// bucket (or access point) enables them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct BlockPublicAccess {
+    pub(crate) block_public_acls: bool,
+    pub(crate) ignore_public_acls: bool,
+    pub(crate) block_public_policy: bool,
+    pub(crate) restrict_public_buckets: bool,
+}
+
+impl BlockPublicAccess {
+    fn or(self, other: Self) -> Self {
+        Self {
+            block_public_acls: self.block_public_acls || other.block_public_acls,
+            ignore_public_acls: self.ignore_public_acls || other.ignore_public_acls,
+            block_public_policy: self.block_public_policy || other.block_public_policy,
+            restrict_public_buckets: self.restrict_public_buckets || other.restrict_public_buckets,
+        }
+    }
+}
+
+impl From<&crate::aws_s3::PublicAccessBlockConfiguration> for BlockPublicAccess {
+    fn from(value: &crate::aws_s3::PublicAccessBlockConfiguration) -> Self {
+        Self {
+            block_public_acls: value.block_public_acls.unwrap_or_default(),
+            ignore_public_acls: value.ignore_public_acls.unwrap_or_default(),
+            block_public_policy: value.block_public_policy.unwrap_or_default(),
+            restrict_public_buckets: value.restrict_public_buckets.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&crate::aws_s3control::PublicAccessBlockConfiguration> for BlockPublicAccess {
+    fn from(value: &crate::aws_s3control::PublicAccessBlockConfiguration) -> Self {
+        Self {
+            block_public_acls: value.block_public_acls.unwrap_or_default(),
+            ignore_public_acls: value.ignore_public_acls.unwrap_or_default(),
+            block_public_policy: value.block_public_policy.unwrap_or_default(),
+            restrict_public_buckets: value.restrict_public_buckets.unwrap_or_default(),
+        }
+    }
+}
+
+/// An S3 access point attached to a bucket
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AccessPointConfiguration {
+    pub(crate) name: String,
+    pub(crate) arn: Option<String>,
+    /// `Internet` or `VPC`
+    pub(crate) network_origin: Option<String>,
+    pub(crate) block_public_access: BlockPublicAccess,
+    pub(crate) policy: Option<AwsPolicy>,
+}
+
+/// Everything read about a bucket that decides who can access it
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BucketConfiguration {
+    pub(crate) bucket_name: String,
+    pub(crate) region: String,
+    pub(crate) account_id: String,
+    pub(crate) account_block_public_access: BlockPublicAccess,
+    pub(crate) bucket_block_public_access: BlockPublicAccess,
+    pub(crate) policy: Option<AwsPolicy>,
+    pub(crate) acl_grants: Vec<Grant>,
+    pub(crate) access_points: Vec<AccessPointConfiguration>,
+    /// `AES256`, `aws:kms` or `aws:kms:dsse`
+    pub(crate) encryption: Option<String>,
+    pub(crate) kms_master_key_id: Option<String>,
+    /// `BucketOwnerEnforced`, `BucketOwnerPreferred` or `ObjectWriter`
+    pub(crate) object_ownership: Option<String>,
+    /// Parts of the configuration that could not be read
+    pub(crate) errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct AccessPointExposure {
+    pub(crate) name: String,
+    pub(crate) arn: Option<String>,
+    pub(crate) network_origin: Option<String>,
+    pub(crate) block_public_access: BlockPublicAccess,
+    pub(crate) public_read: bool,
+    pub(crate) public_write: bool,
+}
+
+/// Normalised exposure of one bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct BucketExposure {
+    pub(crate) bucket_name: String,
+    pub(crate) region: String,
+    pub(crate) account_id: String,
+    /// Account and bucket settings combined
+    pub(crate) block_public_access: BlockPublicAccess,
+    pub(crate) public_read: bool,
+    pub(crate) public_write: bool,
+    /// What makes the bucket public: `acl`, `policy` or
+    /// `access_point:<name>`
+    pub(crate) public_read_sources: Vec<String>,
+    pub(crate) public_write_sources: Vec<String>,
+    /// Public grants that Block Public Access currently overrides
+    pub(crate) blocked_public_sources: Vec<String>,
+    /// Accounts other than the owner granted access by the bucket policy
+    pub(crate) cross_account_principals: Vec<String>,
+    /// The bucket policy denies requests without TLS
+    pub(crate) secure_transport_enforced: bool,
+    pub(crate) access_points: Vec<AccessPointExposure>,
+    pub(crate) encryption: Option<String>,
+    pub(crate) kms_master_key_id: Option<String>,
+    pub(crate) object_ownership: Option<String>,
+    /// ACLs are disabled and ignored by S3
+    pub(crate) acls_disabled: bool,
+    /// `None` when nothing public was found but part of the
+    /// configuration could not be read, so the bucket may be exposed
+    pub(crate) severity: Option<Severity>,
+    /// Parts of the configuration that could not be read
+    pub(crate) errors: Vec<String>,
+}
+
+impl BucketConfiguration {
+    pub(crate) fn exposure(&self) -> BucketExposure {
+        let block_public_access = self
+            .account_block_public_access
+            .or(self.bucket_block_public_access);
+        let acls_disabled = self.object_ownership.as_deref() == Some("BucketOwnerEnforced");
+
+        let mut read = vec![];
+        let mut write = vec![];
+        let mut blocked = vec![];
+
+        let (acl_read, acl_write) = public_acl_grants(&self.acl_grants);
+        if (acl_read || acl_write) && !acls_disabled {
+            if block_public_access.ignore_public_acls {
+                blocked.push("acl".to_string());
+            } else {
+                push_if(acl_read, &mut read, "acl");
+                push_if(acl_write, &mut write, "acl");
+            }
+        }
+
+        let bucket_arn = format!("arn:aws:s3:::{}", self.bucket_name);
+        let (policy_read, policy_write) = self
+            .policy
+            .as_ref()
+            .map(|policy| public_policy_grants(policy, &[&bucket_arn]))
+            .unwrap_or_default();
+        if policy_read || policy_write {
+            if block_public_access.restrict_public_buckets {
+                blocked.push("policy".to_string());
+            } else {
+                push_if(policy_read, &mut read, "policy");
+                push_if(policy_write, &mut write, "policy");
+            }
+        }
+
+        let access_points: Vec<AccessPointExposure> = self
+            .access_points
+            .iter()
+            .map(|access_point| {
+                let (ap_read, ap_write) = access_point_grants(access_point, &bucket_arn);
+                let source = format!("access_point:{}", access_point.name);
+                let ap_block = block_public_access.or(access_point.block_public_access);
+                let internet = access_point.network_origin.as_deref() != Some("VPC");
+                let restricted = ap_block.restrict_public_buckets || !internet;
+                if (ap_read || ap_write) && restricted {
+                    blocked.push(source.clone());
+                }
+                let public_read = ap_read && !restricted;
+                let public_write = ap_write && !restricted;
+                push_if(public_read, &mut read, &source);
+                push_if(public_write, &mut write, &source);
+                AccessPointExposure {
+                    name: access_point.name.clone(),
+                    arn: access_point.arn.clone(),
+                    network_origin: access_point.network_origin.clone(),
+                    block_public_access: ap_block,
+                    public_read,
+                    public_write,
+                }
+            })
+            .collect();
+
+        let cross_account_principals = self
+            .policy
+            .as_ref()
+            .map(|policy| cross_account_principals(policy, &self.account_id))
+            .unwrap_or_default();
+        let secure_transport_enforced = self
+            .policy
+            .as_ref()
+            .is_some_and(|policy| denies_insecure_transport(policy, &bucket_arn));
+
+        let severity = if !write.is_empty() {
+            Some(Severity::Critical)
+        } else if !read.is_empty() {
+            Some(Severity::High)
+        } else if !self.errors.is_empty() {
+            None
+        } else if !cross_account_principals.is_empty() {
+            Some(Severity::Medium)
+        } else if !secure_transport_enforced || self.encryption.is_none() || !acls_disabled {
+            Some(Severity::Low)
+        } else {
+            Some(Severity::Informational)
+        };
+
+        BucketExposure {
+            bucket_name: self.bucket_name.clone(),
+            region: self.region.clone(),
+            account_id: self.account_id.clone(),
+            block_public_access,
+            public_read: !read.is_empty(),
+            public_write: !write.is_empty(),
+            public_read_sources: read,
+            public_write_sources: write,
+            blocked_public_sources: blocked,
+            cross_account_principals,
+            secure_transport_enforced,
+            access_points,
+            encryption: self.encryption.clone(),
+            kms_master_key_id: self.kms_master_key_id.clone(),
+            object_ownership: self.object_ownership.clone(),
+            acls_disabled,
+            severity,
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+fn push_if(condition: bool, sources: &mut Vec<String>, source: &str) {
+    if condition {
+        sources.push(source.to_string());
+    }
+}
+
+/// Read and write access granted to everyone, or every AWS account,
+/// by an ACL
+fn public_acl_grants(grants: &[Grant]) -> (bool, bool) {
+    let public_permissions: Vec<&str> = grants
+        .iter()
+        .filter(|grant| {
+            grant
+                .grantee
+                .as_ref()
+                .and_then(|grantee| grantee.uri.as_deref())
+                .is_some_and(|uri| uri == ALL_USERS_URI || uri == AUTHENTICATED_USERS_URI)
+        })
+        .filter_map(|grant| grant.permission.as_deref())
+        .collect();
+    let read = public_permissions
+        .iter()
+        .any(|permission| matches!(*permission, "READ" | "FULL_CONTROL"));
+    let write = public_permissions
+        .iter()
+        .any(|permission| matches!(*permission, "WRITE" | "WRITE_ACP" | "FULL_CONTROL"));
+    (read, write)
+}
+
+/// Read and write access granted to everyone by a resource policy on
+/// any of `arns` or the objects below them
+fn public_policy_grants(policy: &AwsPolicy, arns: &[&str]) -> (bool, bool) {
+    let public: Vec<&StatementElement> = policy
+        .statements()
+        .iter()
+        .filter(|statement| statement.effect == "Allow")
+        .filter(|statement| {
+            statement
+                .principal
+                .as_ref()
+                .is_some_and(|principal| principal.is_public())
+        })
+        .filter(|statement| !is_restricted(statement))
+        .filter(|statement| applies_to(statement, arns))
+        .collect();
+    let grants = |actions: &[&str]| {
+        public.iter().any(|statement| {
+            actions
+                .iter()
+                .any(|action| grants_action(statement, action))
+        })
+    };
+    (grants(READ_ACTIONS), grants(WRITE_ACTIONS))
+}
+
+/// Public grants of an access point policy
+fn access_point_grants(access_point: &AccessPointConfiguration, bucket_arn: &str) -> (bool, bool) {
+    let Some(policy) = &access_point.policy else {
+        return (false, false);
+    };
+    let arn = access_point.arn.as_deref().unwrap_or(bucket_arn);
+    public_policy_grants(policy, &[arn])
+}
+
+fn grants_action(statement: &StatementElement, action: &str) -> bool {
+    match (&statement.action, &statement.not_action) {
+        (Some(actions), _) => actions
+            .values()
+            .iter()
+            .any(|pattern| action_matches(pattern, action)),
+        (None, Some(not_actions)) => !not_actions
+            .values()
+            .iter()
+            .any(|pattern| action_matches(pattern, action)),
+        (None, None) => false,
+    }
+}
+
+/// Does the statement cover the resource or anything below it
+fn applies_to(statement: &StatementElement, arns: &[&str]) -> bool {
+    let Some(resource) = &statement.resource else {
+        // NotResource grants everything else
+        return statement.not_resource.is_some();
+    };
+    resource.values().iter().any(|pattern| {
+        arns.iter().any(|arn| {
+            wildcard_match(pattern, arn)
+                || wildcard_match(pattern, &format!("{}/", arn))
+                || pattern.starts_with(&format!("{}/", arn))
+        })
+    })
+}
+
+/// A statement limited by a fixed value of a condition key that
+/// identifies the caller
+fn is_restricted(statement: &StatementElement) -> bool {
+    let Some(condition) = &statement.condition else {
+        return false;
+    };
+    condition.iter().any(|(operator, keys)| {
+        !operator.contains("Not")
+            && !operator.starts_with("ForAllValues")
+            && keys.iter().any(|(key, values)| {
+                let values = condition_values(values);
+                RESTRICTING_CONDITION_KEYS.contains(&key.to_lowercase().as_str())
+                    && !values.is_empty()
+                    && values.iter().all(|value| {
+                        !value.contains('*') && value != "0.0.0.0/0" && value != "::/0"
+                    })
+            })
+    })
+}
+
+/// Accounts other than `account_id` named as AWS principals in Allow
+/// statements
+fn cross_account_principals(policy: &AwsPolicy, account_id: &str) -> Vec<String> {
+    let mut accounts: Vec<String> = policy
+        .statements()
+        .iter()
+        .filter(|statement| statement.effect == "Allow")
+        .filter_map(|statement| statement.principal.as_ref())
+        .flat_map(|principal| principal.values("AWS"))
+        .filter_map(|principal| principal_account(principal))
+        .filter(|account| account != account_id)
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+    accounts
+}
+
+/// Account of an AWS principal, either an account ID or an ARN
+fn principal_account(principal: &str) -> Option<String> {
+    if principal.len() == 12 && principal.chars().all(|c| c.is_ascii_digit()) {
+        return Some(principal.to_string());
+    }
+    principal
+        .split(':')
+        .nth(4)
+        .filter(|account| !account.is_empty())
+        .map(|account| account.to_string())
+}
+
+/// Does the policy deny everyone reading objects over plain HTTP
+fn denies_insecure_transport(policy: &AwsPolicy, bucket_arn: &str) -> bool {
+    let statements = policy
+        .statements()
+        .iter()
+        .filter(|statement| statement.effect == "Deny")
+        .filter(|statement| {
+            statement
+                .principal
+                .as_ref()
+                .is_some_and(|principal| principal.is_public())
+        })
+        .cloned()
+        .collect();
+    let deny = AwsPolicy {
+        version: policy.version.clone(),
+        statement: crate::aws_policy::Statement::StatementVec(statements),
+    };
+    let context = HashMap::from([("aws:securetransport".to_string(), vec!["false".to_string()])]);
+    let object = format!("{}/ssphp-probe", bucket_arn);
+    evaluate_policies(
+        &[deny],
+        &Request {
+            action: "s3:GetObject",
+            resource: &object,
+            conditions: Conditions::Context(&context),
+        },
+    ) == Decision::ExplicitDeny
+}
+
+/// The output of an S3 request, or `None` if the configuration is not
+/// set or could not be read. Read failures are logged and added to
+/// `errors`
+pub(crate) fn optional_configuration<T, E>(
+    resource: &str,
+    operation: &str,
+    result: Result<T, E>,
+    errors: &mut Vec<String>,
+) -> Option<T>
+where
+    E: ProvideErrorMetadata + std::error::Error,
+{
+    match result {
+        Ok(output) => Some(output),
+        Err(err) => {
+            if !err
+                .code()
+                .is_some_and(|code| NOT_CONFIGURED_CODES.contains(&code))
+            {
+                warn!(name="AWS", operation=operation, resource=resource, error=%DisplayErrorContext(&err));
+                errors.push(format!(
+                    "{} {}: {}",
+                    operation,
+                    resource,
+                    DisplayErrorContext(&err)
+                ));
+            }
+            None
+        }
+    }
+}
+
+/// Parse a bucket or access point policy, invalid documents are logged
+/// and added to `errors`
+pub(crate) fn parse_resource_policy(
+    resource: &str,
+    policy: &str,
+    errors: &mut Vec<String>,
+) -> Option<AwsPolicy> {
+    match serde_json::from_str(policy) {
+        Ok(policy) => Some(policy),
+        Err(err) => {
+            warn!(name="AWS", operation="Parse resource policy", resource=resource, error=?err);
+            errors.push(format!("Parse policy {}: {}", resource, err));
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub(crate) struct BucketExposures {
+    pub(crate) inner: Vec<BucketExposure>,
+}
+
+impl ToHecEvents for &BucketExposures {
+    type Item = BucketExposure;
+
+    fn source(&self) -> &str {
+        "s3_bucket_exposure"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "ssphp:aws:json"
+    }
+
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+
+    fn ssphp_run_key(&self) -> &str {
+        "aws"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccessPointConfiguration, BlockPublicAccess, BucketConfiguration};
+    use crate::aws_findings::Severity;
+    use crate::aws_policy::AwsPolicy;
+    use crate::aws_s3::{Grant, Grantee};
+    use anyhow::Result;
+
+    fn bucket() -> BucketConfiguration {
+        BucketConfiguration {
+            bucket_name: "bucket".into(),
+            region: "eu-west-2".into(),
+            account_id: "111111111111".into(),
+            encryption: Some("AES256".into()),
+            object_ownership: Some("BucketOwnerEnforced".into()),
+            ..Default::default()
+        }
+    }
+
+    fn policy(statements: &str) -> Result<AwsPolicy> {
+        Ok(serde_json::from_str(&format!(
+            r#"{{"Version": "2012-10-17", "Statement": [{}]}}"#,
+            statements
+        ))?)
+    }
+
+    const DENY_HTTP: &str = r#"{"Effect": "Deny", "Principal": "*", "Action": "s3:*", "Resource": ["arn:aws:s3:::bucket", "arn:aws:s3:::bucket/*"], "Condition": {"Bool": {"aws:SecureTransport": "false"}}}"#;
+
+    #[test]
+    fn test_private_bucket() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.policy = Some(policy(DENY_HTTP)?);
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_read);
+        assert!(!exposure.public_write);
+        assert!(exposure.secure_transport_enforced);
+        assert_eq!(exposure.severity, Some(Severity::Informational));
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_policy() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.policy = Some(policy(
+            r#"{"Effect": "Allow", "Principal": {"AWS": "*"}, "Action": "s3:GetObject", "Resource": "arn:aws:s3:::bucket/public/*"}"#,
+        )?);
+        let exposure = bucket.exposure();
+        assert!(exposure.public_read);
+        assert!(!exposure.public_write);
+        assert_eq!(exposure.public_read_sources, vec!["policy"]);
+        assert!(!exposure.secure_transport_enforced);
+        assert_eq!(exposure.severity, Some(Severity::High));
+
+        bucket.account_block_public_access = BlockPublicAccess {
+            restrict_public_buckets: true,
+            ..Default::default()
+        };
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_read);
+        assert_eq!(exposure.blocked_public_sources, vec!["policy"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restricted_policy_is_not_public() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.policy = Some(policy(
+            r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:*", "Resource": "arn:aws:s3:::bucket/*", "Condition": {"StringEquals": {"aws:SourceVpce": "vpce-1a2b3c4d"}}},
+               {"Effect": "Allow", "Principal": {"AWS": "arn:aws:iam::222222222222:root"}, "Action": "s3:GetObject", "Resource": "arn:aws:s3:::bucket/*"}"#,
+        )?);
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_read);
+        assert!(!exposure.public_write);
+        assert_eq!(exposure.cross_account_principals, vec!["222222222222"]);
+        assert_eq!(exposure.severity, Some(Severity::Medium));
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_acl() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.object_ownership = Some("ObjectWriter".into());
+        bucket.acl_grants = vec![Grant {
+            grantee: Some(Grantee {
+                display_name: None,
+                email_address: None,
+                id: None,
+                uri: Some("http://acs.amazonaws.com/groups/global/AllUsers".into()),
+                r#type: "Group".into(),
+            }),
+            permission: Some("WRITE".into()),
+        }];
+        let exposure = bucket.exposure();
+        assert!(exposure.public_write);
+        assert_eq!(exposure.severity, Some(Severity::Critical));
+
+        bucket.object_ownership = Some("BucketOwnerEnforced".into());
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_write);
+        assert!(exposure.acls_disabled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_access_point() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.access_points = vec![AccessPointConfiguration {
+            name: "public".into(),
+            arn: Some("arn:aws:s3:eu-west-2:111111111111:accesspoint/public".into()),
+            network_origin: Some("Internet".into()),
+            policy: Some(policy(
+                r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:eu-west-2:111111111111:accesspoint/public/object/*"}"#,
+            )?),
+            ..Default::default()
+        }];
+        let exposure = bucket.exposure();
+        assert!(exposure.public_read);
+        assert_eq!(exposure.public_read_sources, vec!["access_point:public"]);
+
+        bucket.access_points[0].network_origin = Some("VPC".into());
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_read);
+        assert_eq!(exposure.blocked_public_sources, vec!["access_point:public"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_configuration_has_no_severity() -> Result<()> {
+        let mut bucket = bucket();
+        bucket.policy = Some(policy(DENY_HTTP)?);
+        bucket.errors = vec!["GetBucketAcl bucket: AccessDenied".into()];
+        let exposure = bucket.exposure();
+        assert!(!exposure.public_read);
+        assert_eq!(exposure.severity, None);
+        assert_eq!(exposure.errors, bucket.errors);
+
+        bucket.policy = Some(policy(
+            r#"{"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::bucket/*"}"#,
+        )?);
+        assert_eq!(bucket.exposure().severity, Some(Severity::High));
+        Ok(())
+    }
+}
@@ -36,6 +36,39 @@ pub struct TrailWrapper {
     pub trail: Trail,
     pub trail_status: Option<GetTrailStatusOutput>,
     pub event_selectors: Option<GetEventSelectorsOutputSerde>,
+    /// Log file validation is enabled, CIS AWS 3.2
+    pub validates_log_files: bool,
+    /// The trail delivers to a CloudWatch Logs log group and last
+    /// delivered within a day, CIS AWS 3.4
+    pub cloud_watch_logs_integrated: bool,
+    /// Logs are encrypted with a KMS key, CIS AWS 3.7
+    pub encrypted_with_kms: bool,
+}
+
+impl TrailWrapper {
+    pub(crate) fn new(trail: Trail) -> Self {
+        Self {
+            trail,
+            trail_status: None,
+            event_selectors: None,
+            validates_log_files: false,
+            cloud_watch_logs_integrated: false,
+            encrypted_with_kms: false,
+        }
+    }
+
+    /// Set the fields evaluating CIS AWS 3.2, 3.4 and 3.7 once the
+    /// trail status has been fetched
+    pub(crate) fn evaluate(&mut self, now: &aws_smithy_types::DateTime) {
+        self.validates_log_files = self.trail.log_file_validation_enabled == Some(true);
+        self.cloud_watch_logs_integrated = self.trail.cloud_watch_logs_log_group_arn.is_some()
+            && self
+                .trail_status
+                .as_ref()
+                .and_then(|status| status.latest_cloud_watch_logs_delivery_time.as_ref())
+                .is_some_and(|delivered| now.secs() - delivered.secs() <= 86_400);
+        self.encrypted_with_kms = self.trail.kms_key_id.is_some();
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -1,14 +1,27 @@
 pub mod aws;
+mod aws_accounts;
 mod aws_alternate_contact_information;
+mod aws_checks;
+mod aws_cloudwatch;
 mod aws_config;
+mod aws_credentials;
 mod aws_ec2;
+mod aws_effective_permissions;
+mod aws_efs;
 mod aws_entities_for_policy;
+mod aws_findings;
 mod aws_iam;
+mod aws_inventory;
 mod aws_kms;
 mod aws_organizations;
 mod aws_policy;
+mod aws_policy_evaluation;
+mod aws_rds;
+mod aws_regions;
 mod aws_route53;
+mod aws_route53_takeover;
 mod aws_s3;
+mod aws_s3_exposure;
 mod aws_s3control;
 mod aws_securityhub;
 mod aws_trail;
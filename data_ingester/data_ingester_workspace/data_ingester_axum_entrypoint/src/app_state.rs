@@ -2,17 +2,28 @@ use anyhow::Context;
 use anyhow::Result;
 use axum::http::HeaderMap;
 use axum::Json;
+use data_ingester_aws::aws::aws_access_key_secrets_required;
+use data_ingester_splunk::redaction::{set_redactor, Redactor};
+use data_ingester_splunk::routing::{RoutingConfig, RoutingSink};
+use data_ingester_splunk::sink::{sink_from_secrets, sinks_from_env, FanOutSink, HecSink};
 use data_ingester_splunk::splunk::Splunk;
-use data_ingester_supporting::keyvault::get_keyvault_secrets;
+use data_ingester_splunk::{ResendCounts, SpoolDepth};
+use data_ingester_supporting::keyvault::KeyVaultSecretProvider;
 use data_ingester_supporting::keyvault::Secrets;
+use data_ingester_supporting::secret_provider::{
+    get_secrets, ChainedSecretProvider, EnvSecretProvider, FileSecretProvider, SecretProvider,
+    COLLECTORS,
+};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::env;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::SystemTime;
+use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{info, warn};
 use valuable::Valuable;
 
 use crate::azure_request_response::AzureInvokeRequest;
@@ -22,6 +33,8 @@ use crate::azure_request_response::AzureInvokeRequest;
 pub(crate) struct AppState {
     /// Splunk Client
     pub(crate) splunk: Arc<Splunk>,
+    /// Sink collectors send their events to
+    pub(crate) sink: Arc<dyn HecSink>,
     /// Secret
     pub(crate) secrets: Arc<Secrets>,
 
@@ -63,6 +76,9 @@ pub(crate) struct AppState {
     /// Lock for threagile to stop concurrent executions
     pub(crate) threagile_lock: Arc<Mutex<()>>,
     pub(crate) stats: Arc<RwLock<Stats>>,
+
+    /// Senders to cancel in-flight collections keyed by collector route name
+    pub(crate) cancellations: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
 }
 
 /// Records stats for requsets made to this execution
@@ -70,6 +86,7 @@ pub(crate) struct AppState {
 pub(crate) struct Stats {
     start_up_time: u64,
     instance_requests: Vec<Invocation>,
+    scheduled: Vec<ScheduledRun>,
 }
 
 impl Stats {
@@ -81,6 +98,36 @@ impl Stats {
                 .unwrap_or_default()
                 .as_secs(),
             instance_requests: vec![],
+            scheduled: vec![],
+        }
+    }
+
+    /// Record the last and / or next run of a scheduled collector
+    pub(crate) fn scheduled(
+        &mut self,
+        name: &str,
+        cron: &str,
+        last_run: Option<u64>,
+        next_run: Option<u64>,
+    ) {
+        let index = match self.scheduled.iter().position(|run| run.name == name) {
+            Some(index) => index,
+            None => {
+                self.scheduled.push(ScheduledRun {
+                    name: name.to_string(),
+                    cron: cron.to_string(),
+                    last_run: None,
+                    next_run: None,
+                });
+                self.scheduled.len() - 1
+            }
+        };
+        let run = &mut self.scheduled[index];
+        if last_run.is_some() {
+            run.last_run = last_run;
+        }
+        if next_run.is_some() {
+            run.next_run = next_run;
         }
     }
 
@@ -102,6 +149,15 @@ impl Stats {
     }
 }
 
+/// Last and next run times of a collector started by the scheduler
+#[derive(Serialize, Debug, Valuable)]
+pub(crate) struct ScheduledRun {
+    name: String,
+    cron: String,
+    last_run: Option<u64>,
+    next_run: Option<u64>,
+}
+
 /// Represents a single Invocation and it's metadata
 #[derive(Serialize, Debug, Valuable)]
 pub(crate) struct Invocation {
@@ -109,6 +165,9 @@ pub(crate) struct Invocation {
     start: u64,
     finish: Option<u64>,
     got_lock: Option<bool>,
+    timeout_secs: Option<u64>,
+    attempts: u32,
+    outcome: Option<String>,
     errors: Option<String>,
     headers: Headers,
     request: Option<AzureInvokeRequest>,
@@ -132,6 +191,9 @@ impl Invocation {
             finish: None,
             errors: None,
             got_lock: None,
+            timeout_secs: None,
+            attempts: 0,
+            outcome: None,
             headers,
             request,
         }
@@ -153,6 +215,24 @@ impl Invocation {
         self
     }
 
+    /// Timeout applied to each attempt
+    pub(crate) fn timeout(&mut self, timeout_secs: u64) -> &mut Self {
+        self.timeout_secs = Some(timeout_secs);
+        self
+    }
+
+    /// Number of attempts made so far
+    pub(crate) fn attempts(&mut self, attempts: u32) -> &mut Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// How the collection ended: success, failed, timed_out or cancelled
+    pub(crate) fn outcome<T: Into<String>>(&mut self, outcome: T) -> &mut Self {
+        self.outcome = Some(outcome.into());
+        self
+    }
+
     /// Any errors during exectuction
     pub(crate) fn errors<T: Into<String>>(&mut self, errors: T) -> &mut Self {
         self.errors = Some(errors.into());
@@ -183,6 +263,8 @@ impl From<HeaderMap> for Headers {
 #[derive(Serialize, Debug, Valuable)]
 pub(crate) struct AppStateHealthCheck<'a> {
     splunk: ArcState,
+    splunk_spool: Option<SpoolState>,
+    splunk_resends: ResendState,
     secrets: ArcState,
     aws_lock: ArcMutexState,
     azure_lock: ArcMutexState,
@@ -206,6 +288,42 @@ struct ArcState {
     arc_weak_count: usize,
 }
 
+/// Records the depth of the Splunk on-disk spool
+#[derive(Serialize, Debug, Valuable)]
+struct SpoolState {
+    segments: usize,
+    bytes: u64,
+}
+
+impl From<SpoolDepth> for SpoolState {
+    fn from(value: SpoolDepth) -> Self {
+        Self {
+            segments: value.segments,
+            bytes: value.bytes,
+        }
+    }
+}
+
+/// Records the events the Splunk client has resent, dead lettered or dropped
+#[derive(Serialize, Debug, Valuable)]
+struct ResendState {
+    resent: u64,
+    dead_lettered: u64,
+    dropped: u64,
+    unacknowledged: usize,
+}
+
+impl From<ResendCounts> for ResendState {
+    fn from(value: ResendCounts) -> Self {
+        Self {
+            resent: value.resent,
+            dead_lettered: value.dead_lettered,
+            dropped: value.dropped,
+            unacknowledged: value.unacknowledged,
+        }
+    }
+}
+
 /// Records the stats for an Mutex
 #[derive(Serialize, Debug, Valuable)]
 struct MutexState {
@@ -253,6 +371,8 @@ impl<'a, 'b> From<(&'b Arc<AppState>, &'a Stats)> for AppStateHealthCheck<'a> {
     fn from((value, stats): (&'b Arc<AppState>, &'a Stats)) -> Self {
         Self {
             splunk: (&value.splunk).into(),
+            splunk_spool: value.splunk.spool_depth().map(|depth| depth.into()),
+            splunk_resends: value.splunk.resend_counts().into(),
             secrets: (&value.secrets).into(),
             aws_lock: (&value.aws_lock).into(),
             azure_lock: (&value.azure_lock).into(),
@@ -275,10 +395,16 @@ impl AppState {
     /// Create a new AppState
     pub(crate) async fn new() -> Result<Self> {
         let secrets = AppState::get_secrets().await?;
-        let splunk = AppState::create_splunk_client(&secrets)?;
+        set_redactor(
+            Redactor::from_env(secrets.redaction_hmac_key.as_deref())
+                .context("Reading redaction rules from SSPHP_REDACTION_FILE")?,
+        );
+        let splunk = Arc::new(AppState::create_splunk_client(&secrets)?);
+        let sink = AppState::create_sink(splunk.clone(), &secrets)?;
         Ok(Self {
             secrets: Arc::new(secrets),
-            splunk: Arc::new(splunk),
+            splunk,
+            sink,
 
             aws_lock: Arc::new(Mutex::new(())),
             azure_lock: Arc::new(Mutex::new(())),
@@ -294,24 +420,57 @@ impl AppState {
             sonar_cloud: Arc::new(Mutex::new(())),
             stats: Arc::new(RwLock::new(Stats::new())),
             threagile_lock: Arc::new(Mutex::new(())),
+            cancellations: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
-    /// Get secrets from KeyVault for the Application
+    /// Get secrets for the Application
+    ///
+    /// Secrets are read from SSPHP_SECRET_* environment variables,
+    /// then the file named by SSPHP_SECRETS_FILE, then the KeyVault
+    /// named by KEY_VAULT_NAME.
     async fn get_secrets() -> Result<Secrets> {
-        info!("Getting KeyVault secrets");
-        let key_vault_name =
-            env::var("KEY_VAULT_NAME").context("Getting key vault name from env:KEY_VAULT_NAME")?;
+        let mut providers: Vec<Box<dyn SecretProvider>> = vec![Box::new(EnvSecretProvider::new())];
 
-        get_keyvault_secrets(&key_vault_name)
+        if let Ok(secrets_file) = env::var("SSPHP_SECRETS_FILE") {
+            providers.push(Box::new(
+                FileSecretProvider::from_path(Path::new(&secrets_file))
+                    .context("Reading secrets from env:SSPHP_SECRETS_FILE")?,
+            ));
+        }
+
+        if let Ok(key_vault_name) = env::var("KEY_VAULT_NAME") {
+            info!("Getting KeyVault secrets");
+            providers.push(Box::new(
+                KeyVaultSecretProvider::new(&key_vault_name).context("Creating KeyVault client")?,
+            ));
+        }
+
+        let secrets = get_secrets(&ChainedSecretProvider::new(providers))
             .await
-            .context("Getting KeyVault secrets")
+            .context("Getting secrets")?;
+
+        let aws_access_keys = aws_access_key_secrets_required();
+        for collector in COLLECTORS {
+            let missing = secrets.missing_for(collector, aws_access_keys);
+            if !missing.is_empty() {
+                warn!(
+                    name = "Secrets",
+                    collector = collector,
+                    missing_secrets = ?missing,
+                    "Collector is missing required secrets"
+                );
+            }
+        }
+
+        Ok(secrets)
     }
 
     /// Create a splunk Client to send data with
     fn create_splunk_client(secrets: &Secrets) -> Result<Splunk> {
         info!("Creating Splunk client");
-        let hec_acknowledgement = false;
+        let hec_acknowledgement = Splunk::hec_acknowledgment_from_env()
+            .context("Reading Splunk acknowledgment config")?;
         let splunk = Splunk::new(
             secrets
                 .splunk_host
@@ -328,4 +487,41 @@ impl AppState {
         info!("Splunk Client created");
         Ok(splunk)
     }
+
+    /// Create the sink for collectors.
+    ///
+    /// Sends to Splunk, or to the destinations of the routing table in
+    /// SSPHP_ROUTING_FILE, and to any additional sinks listed in
+    /// SSPHP_SINKS
+    fn create_sink(splunk: Arc<Splunk>, secrets: &Secrets) -> Result<Arc<dyn HecSink>> {
+        let sink: Arc<dyn HecSink> = match RoutingConfig::from_env()
+            .context("Reading routing table from SSPHP_ROUTING_FILE")?
+        {
+            Some(routing) => {
+                info!(
+                    "Routing collector events to {} destinations",
+                    routing.destinations.len()
+                );
+                Arc::new(
+                    RoutingSink::new(routing, |spec| match spec {
+                        "splunk" => Ok(splunk.clone() as Arc<dyn HecSink>),
+                        _ => sink_from_secrets(spec, secrets),
+                    })
+                    .context("Building routing sink")?,
+                )
+            }
+            None => splunk,
+        };
+        let additional_sinks = sinks_from_env().context("Building sinks from SSPHP_SINKS")?;
+        if additional_sinks.is_empty() {
+            return Ok(sink);
+        }
+        info!(
+            "Sending collector events to Splunk and {} additional sinks",
+            additional_sinks.len()
+        );
+        let mut sinks: Vec<Arc<dyn HecSink>> = vec![sink];
+        sinks.extend(additional_sinks);
+        Ok(Arc::new(FanOutSink::new(sinks)))
+    }
 }
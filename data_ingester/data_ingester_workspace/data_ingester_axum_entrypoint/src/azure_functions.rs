@@ -2,18 +2,26 @@ use crate::app_state::AppState;
 use crate::app_state::AppStateHealthCheck;
 use crate::azure_request_response::AzureInvokeRequest;
 use crate::azure_request_response::AzureInvokeResponse;
-use crate::runner::function_runner;
+use crate::runner::{cancel_collector, run_collector};
+use crate::scheduler::{start_scheduler, ScheduleConfig};
 use crate::start_local_tracing;
 use anyhow::Context;
 use anyhow::Result;
-use axum::http::HeaderMap;
-use axum::{extract::State, routing::get, routing::post, Json, Router};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{extract::Path, extract::State, routing::get, routing::post, Json, Router};
 use data_ingester_splunk::splunk::set_ssphp_run;
 use data_ingester_splunk::start_splunk_tracing;
+use data_ingester_supporting::metrics::{self, METRICS};
+use data_ingester_supporting::secret_provider::COLLECTORS;
+use memory_stats::memory_stats;
 use std::env;
+use std::future::IntoFuture;
 use std::sync::Arc;
-use tokio::sync::oneshot::Sender;
-use tracing::info;
+use std::time::Duration;
+use tokio::sync::oneshot::{self, Sender};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 use valuable::Valuable;
 
 /// Start the Axum server
@@ -26,13 +34,22 @@ use valuable::Valuable;
 ///
 /// tx: takes a [Sender] - used send a signal indicating the server is ready
 ///
+/// On SIGTERM or Ctrl-C the server stops accepting requests and waits
+/// up to SSPHP_COLLECTOR_SHUTDOWN_DEADLINE_SECS (default 60) for running
+/// collectors to finish, cancelling any still running. It then waits up
+/// to SSPHP_SHUTDOWN_DEADLINE_SECS (default 30) for queued events to be
+/// sent.
+///
 pub(crate) async fn start_server(tx: Sender<()>) -> Result<()> {
     let name = "axum_server";
     let tracing_guard = start_local_tracing().context("Starting Tracing for server pre Splunk")?;
 
     info!(name = name, "Starting");
 
-    let app_state = AppState::new().await.context("Building App State")?;
+    let shutdown_deadline = deadline_from_env("SSPHP_SHUTDOWN_DEADLINE_SECS", 30)?;
+    let collector_deadline = deadline_from_env("SSPHP_COLLECTOR_SHUTDOWN_DEADLINE_SECS", 60)?;
+
+    let app_state = Arc::new(AppState::new().await.context("Building App State")?);
 
     set_ssphp_run("default")?;
     start_splunk_tracing(
@@ -45,27 +62,32 @@ pub(crate) async fn start_server(tx: Sender<()>) -> Result<()> {
     drop(tracing_guard);
     info!(name = name, "Splunk tracing started");
 
+    if let Some(schedule) = ScheduleConfig::from_env().context("Loading collector schedule")? {
+        let _ = start_scheduler(schedule, app_state.clone()).context("Starting scheduler")?;
+        info!(name = name, "Scheduler started");
+    }
+
     let app = Router::new()
         .route("/", get(get_health_check))
-        .route("/aws", post(post_aws))
-        .route("/azure", post(post_azure))
-        .route("/azure_dev_ops", post(post_azure_dev_ops))
-        .route("/azure_resource_graph", post(post_azure_resource_graph))
-        .route("/github", post(post_github))
-        .route(
-            "/github_custom_properties",
-            post(post_github_custom_properties),
-        )
-        .route("/m365", post(post_m365))
-        .route(
-            "/financial_business_partners",
-            post(post_financial_business_partners),
-        )
-        .route("/powershell", post(post_powershell))
-        .route("/qualys_qvs", post(post_qualys_qvs))
-        .route("/sonar_cloud", post(post_sonar_cloud))
-        .route("/threagile", post(post_threagile))
-        .with_state(Arc::new(app_state));
+        .route("/metrics", get(get_metrics))
+        .route("/cancel/{collector}", post(post_cancel));
+    // One route per collector, e.g. `/aws`
+    let app = COLLECTORS
+        .iter()
+        .copied()
+        .fold(app, |app, collector| {
+            app.route(
+                &format!("/{}", collector),
+                post(
+                    move |headers: HeaderMap,
+                          State(state): State<Arc<AppState>>,
+                          payload: CollectorPayload| {
+                        post_collector(collector, headers, state, payload)
+                    },
+                ),
+            )
+        })
+        .with_state(app_state.clone());
 
     let port_key = "FUNCTIONS_CUSTOMHANDLER_PORT";
     let port: u16 = match env::var(port_key) {
@@ -77,13 +99,99 @@ pub(crate) async fn start_server(tx: Sender<()>) -> Result<()> {
     let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
         .await
         .context("Binding to socket")?;
-    let axum_serve = axum::serve(listener, app);
+    let (signalled_tx, signalled_rx) = oneshot::channel::<()>();
+    let mut server = tokio::spawn(
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                shutdown_signal().await;
+                info!(name = "axum_server", "Shutdown signal received");
+                let _ = signalled_tx.send(());
+            })
+            .into_future(),
+    );
     tx.send(())
         .expect("Caller should be listening for Warp start event");
-    axum_serve.await.context("Axum Serv")?;
+    tokio::select! {
+        result = &mut server => result.context("Axum server task")?.context("Axum Serv")?,
+        Ok(()) = signalled_rx => {
+            wait_for_collectors(&app_state, collector_deadline).await;
+            // In-flight requests complete once their collectors have stopped
+            server
+                .await
+                .context("Axum server task")?
+                .context("Axum Serv")?;
+        }
+    }
+
+    let report = app_state.sink.shutdown(shutdown_deadline).await;
+    info!(name = name, report = ?report, undelivered = report.undelivered(), "Shutdown");
     Ok(())
 }
 
+/// Read a shutdown deadline in seconds from `key`, defaults to `default_secs`
+fn deadline_from_env(key: &str, default_secs: u64) -> Result<Duration> {
+    let Ok(secs) = env::var(key) else {
+        return Ok(Duration::from_secs(default_secs));
+    };
+    Ok(Duration::from_secs(
+        secs.parse()
+            .with_context(|| format!("Parsing {} as u64", key))?,
+    ))
+}
+
+/// Wait up to `deadline` for running collectors to finish, then cancel
+/// any that are still running
+async fn wait_for_collectors(state: &AppState, deadline: Duration) {
+    let started = Instant::now();
+    loop {
+        let running: Vec<String> = state.cancellations.lock().await.keys().cloned().collect();
+        if running.is_empty() {
+            return;
+        }
+        if started.elapsed() >= deadline {
+            warn!(name = "axum_server", running = ?running, deadline_secs = deadline.as_secs(), "Cancelling collectors still running at shutdown");
+            for collector in running {
+                let _ = cancel_collector(state, &collector).await;
+            }
+            return;
+        }
+        info!(name = "axum_server", running = ?running, "Waiting for collectors to finish");
+        tokio::time::sleep(Duration::from_secs(5).min(deadline.saturating_sub(started.elapsed())))
+            .await;
+    }
+}
+
+/// Resolves when the process receives SIGTERM or Ctrl-C
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!(name="axum_server", operation="Listen for Ctrl-C", error=?err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                let _ = signal.recv().await;
+            }
+            Err(err) => {
+                error!(name="axum_server", operation="Listen for SIGTERM", error=?err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
 /// Health check
 async fn get_health_check(State(state): State<Arc<AppState>>) -> Json<AzureInvokeResponse> {
     info!("Health check");
@@ -105,287 +213,63 @@ async fn get_health_check(State(state): State<Arc<AppState>>) -> Json<AzureInvok
     })
 }
 
-/// Collect AWS data
-#[axum::debug_handler]
-async fn post_aws(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "AWS",
-            state.aws_lock.clone(),
-            state,
-            data_ingester_aws::aws::aws,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect Azure data
-#[axum::debug_handler]
-async fn post_azure(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Azure",
-            state.azure_lock.clone(),
-            state,
-            data_ingester_azure::azure_users,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect Azure Resource Graph data
-#[axum::debug_handler]
-async fn post_azure_resource_graph(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Azure Resource Graph",
-            state.azure_resource_graph_lock.clone(),
-            state,
-            data_ingester_azure_rest::resource_graph::azure_resource_graph,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect GitHub data
-#[axum::debug_handler]
-async fn post_github(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "GitHub",
-            state.github_lock.clone(),
-            state,
-            data_ingester_github::entrypoint::github_octocrab_entrypoint,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Set GitHub Custom Properties
-#[axum::debug_handler]
-async fn post_github_custom_properties(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "GitHub Custom Properties",
-            state.github_custom_properties_lock.clone(),
-            state,
-            data_ingester_github::entrypoint::github_set_custom_properties_entrypoint,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect M365 data
-#[axum::debug_handler]
-async fn post_m365(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "M365",
-            state.m365_lock.clone(),
-            state,
-            data_ingester_ms_graph::ms_graph::m365,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect Powershell data
-///
-/// Installs powershell on the functions host before collection
-#[axum::debug_handler]
-async fn post_powershell(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    if state.powershell_lock.try_lock().is_ok() && !*state.powershell_installed.lock().await {
-        info!("Powershell: Installing");
-
-        data_ingester_ms_powershell::powershell::install_powershell()
-            .await
-            .expect("Powershell should install cleanly in the Azure Function instance");
-        *state.powershell_installed.lock().await = true;
+/// Prometheus metrics
+async fn get_metrics() -> Response {
+    if let Some(usage) = memory_stats() {
+        METRICS.memory(usage.physical_mem, usage.virtual_mem);
+    }
+    match metrics::gather() {
+        Ok(body) => ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body).into_response(),
+        Err(err) => {
+            error!(name = "Metrics", error = ?err);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", err)).into_response()
+        }
     }
-
-    Json(
-        function_runner(
-            "Powershell",
-            state.powershell_lock.clone(),
-            state,
-            data_ingester_ms_powershell::runner::powershell,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Collect Splunk test data
-#[axum::debug_handler]
-async fn post_qualys_qvs(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Qualys QVS",
-            state.qualys_qvs_lock.clone(),
-            state,
-            data_ingester_qualys::entrypoint::qualys_qvs,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
-
-/// Run Threagile against assets from Splunk
-#[axum::debug_handler]
-async fn post_sonar_cloud(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Sonar Cloud",
-            state.sonar_cloud.clone(),
-            state,
-            data_ingester_sonar_cloud::entrypoint,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
 }
 
-/// Run Threagile against assets from Splunk
+/// Cancel an in-flight collection and release its lock
+///
+/// collector: The collector route name, e.g. `aws`
 #[axum::debug_handler]
-async fn post_threagile(
-    headers: HeaderMap,
+async fn post_cancel(
+    Path(collector): Path<String>,
     State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
 ) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Threagile",
-            state.threagile_lock.clone(),
-            state,
-            data_ingester_threagile::threagile,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
+    let cancelled = cancel_collector(&state, &collector).await;
+    info!(
+        name = "Cancel",
+        collector = collector,
+        cancelled = cancelled
+    );
+    let msg = if cancelled {
+        format!("{} cancelled", collector)
+    } else {
+        format!("{} is not running", collector)
+    };
+    Json(AzureInvokeResponse {
+        outputs: None,
+        logs: vec![msg],
+        return_value: None,
+    })
 }
 
-#[axum::debug_handler]
-async fn post_financial_business_partners(
-    headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
-) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Financial Business Partners",
-            state.financial_business_partners_lock.clone(),
-            state,
-            data_ingester_financial_business_partners::entrypoint,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
-}
+/// Request body for collector routes, a missing or invalid body is treated as no request
+type CollectorPayload =
+    std::result::Result<Option<Json<AzureInvokeRequest>>, axum::extract::rejection::JsonRejection>;
 
-#[axum::debug_handler]
-async fn post_azure_dev_ops(
+/// Run a collector, see [run_collector]
+async fn post_collector(
+    collector: &'static str,
     headers: HeaderMap,
-    State(state): State<Arc<AppState>>,
-    payload: std::result::Result<
-        Option<Json<AzureInvokeRequest>>,
-        axum::extract::rejection::JsonRejection,
-    >,
+    state: Arc<AppState>,
+    payload: CollectorPayload,
 ) -> Json<AzureInvokeResponse> {
-    Json(
-        function_runner(
-            "Azure Dev Ops",
-            state.azure_dev_ops_lock.clone(),
-            state,
-            data_ingester_azure_dev_ops::entrypoint::entrypoint,
-            headers,
-            payload.unwrap_or(None),
-        )
-        .await,
-    )
+    let response = run_collector(collector, state, headers, payload.unwrap_or(None))
+        .await
+        .unwrap_or_else(|| AzureInvokeResponse {
+            outputs: None,
+            logs: vec![format!("Unknown collector: {}", collector)],
+            return_value: None,
+        });
+    Json(response)
 }
@@ -46,6 +46,19 @@ pub struct AzureInvokeRequest {
 #[derive(Debug, Deserialize, Default, Serialize, Valuable)]
 pub(crate) struct Data {
     pub(crate) timer: Option<Timer>,
+    /// Checks to run, used to re-run single controls
+    #[serde(default)]
+    pub(crate) checks: Option<Checks>,
+}
+
+/// Include and exclude lists of checks, e.g.
+/// `{"Data": {"checks": {"include": ["3.1"]}}}`
+#[derive(Debug, Deserialize, Default, Serialize, Valuable, Clone)]
+pub(crate) struct Checks {
+    #[serde(default)]
+    pub(crate) include: Vec<String>,
+    #[serde(default)]
+    pub(crate) exclude: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Default, Serialize, Valuable)]
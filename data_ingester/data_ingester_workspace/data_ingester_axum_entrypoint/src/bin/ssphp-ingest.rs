@@ -0,0 +1,255 @@
+//! Run a single collector locally without the Azure Functions host.
+//!
+//! ssphp-ingest run aws --secrets-file secrets.toml --sink ndjson:/tmp/out
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use data_ingester_aws::aws::aws_access_key_secrets_required;
+use data_ingester_splunk::manifest::ManifestSink;
+use data_ingester_splunk::redaction::{set_redactor, RedactionConfig, Redactor};
+use data_ingester_splunk::routing::{RoutingConfig, RoutingSink};
+use data_ingester_splunk::sink::{sink_from_secrets, FanOutSink, HecSink};
+use data_ingester_supporting::keyvault::{KeyVaultSecretProvider, Secrets};
+use data_ingester_supporting::secret_provider::{
+    get_secrets, ChainedSecretProvider, EnvSecretProvider, FileSecretProvider, SecretProvider,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "ssphp-ingest",
+    version,
+    about = "Run SSPHP data collectors locally"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a collector once
+    Run {
+        /// Collector to run
+        collector: Collector,
+
+        /// TOML or JSON file of KeyVault secret names to values.
+        ///
+        /// SSPHP_SECRET_* environment variables take precedence over
+        /// the file and KeyVault
+        #[arg(long)]
+        secrets_file: Option<PathBuf>,
+
+        /// Name of a KeyVault to read secrets from
+        #[arg(long)]
+        key_vault: Option<String>,
+
+        /// Where to send events. Can be repeated.
+        ///
+        /// splunk (the HEC endpoint from the secrets), ian_splunk,
+        /// stdout, ndjson:<directory>, object_store:<directory>,
+        /// hec:<token>@<host>
+        #[arg(long = "sink", default_value = "splunk")]
+        sinks: Vec<String>,
+
+        /// TOML routing table picking a destination for each event,
+        /// used instead of --sink
+        #[arg(long, conflicts_with = "sinks")]
+        routing_file: Option<PathBuf>,
+
+        /// TOML redaction rules applied to events before they are
+        /// sent. Defaults to SSPHP_REDACTION_FILE
+        #[arg(long)]
+        redaction_file: Option<PathBuf>,
+
+        /// Seconds to wait for queued events to be sent before exiting
+        #[arg(long, default_value_t = 120)]
+        shutdown_deadline_secs: u64,
+    },
+    /// List the available collectors
+    List,
+}
+
+/// Collectors, named after their routes in the Azure Functions app
+#[derive(ValueEnum, Clone, Copy, Debug)]
+#[value(rename_all = "snake_case")]
+enum Collector {
+    Aws,
+    Azure,
+    AzureDevOps,
+    AzureResourceGraph,
+    FinancialBusinessPartners,
+    Github,
+    GithubCustomProperties,
+    M365,
+    Powershell,
+    QualysQvs,
+    SonarCloud,
+    Threagile,
+}
+
+impl Collector {
+    async fn run(&self, secrets: Arc<Secrets>, sink: Arc<dyn HecSink>) -> Result<()> {
+        match self {
+            Collector::Aws => data_ingester_aws::aws::aws(secrets, sink).await,
+            Collector::Azure => data_ingester_azure::azure_users(secrets, sink).await,
+            Collector::AzureDevOps => {
+                data_ingester_azure_dev_ops::entrypoint::entrypoint(secrets, sink).await
+            }
+            Collector::AzureResourceGraph => {
+                data_ingester_azure_rest::resource_graph::azure_resource_graph(secrets, sink).await
+            }
+            Collector::FinancialBusinessPartners => {
+                data_ingester_financial_business_partners::entrypoint(secrets, sink).await
+            }
+            Collector::Github => {
+                data_ingester_github::entrypoint::github_octocrab_entrypoint(secrets, sink).await
+            }
+            Collector::GithubCustomProperties => {
+                data_ingester_github::entrypoint::github_set_custom_properties_entrypoint(
+                    secrets, sink,
+                )
+                .await
+            }
+            Collector::M365 => data_ingester_ms_graph::ms_graph::m365(secrets, sink).await,
+            Collector::Powershell => {
+                data_ingester_ms_powershell::runner::powershell(secrets, sink).await
+            }
+            Collector::QualysQvs => {
+                data_ingester_qualys::entrypoint::qualys_qvs(secrets, sink).await
+            }
+            Collector::SonarCloud => data_ingester_sonar_cloud::entrypoint(secrets, sink).await,
+            Collector::Threagile => data_ingester_threagile::threagile(secrets, sink).await,
+        }
+    }
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_ansi(false)
+        .compact()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            EnvFilter::from_default_env()
+                .add_directive("info".parse().context("Parsing default log level")?),
+        )
+        .init();
+
+    match cli.command {
+        Command::List => {
+            for collector in Collector::value_variants() {
+                if let Some(value) = collector.to_possible_value() {
+                    println!("{}", value.get_name());
+                }
+            }
+            Ok(())
+        }
+        Command::Run {
+            collector,
+            secrets_file,
+            key_vault,
+            sinks,
+            routing_file,
+            redaction_file,
+            shutdown_deadline_secs,
+        } => {
+            let secrets = Arc::new(load_secrets(secrets_file, key_vault).await?);
+            set_redactor(load_redactor(&secrets, redaction_file.as_deref())?);
+            let collector_name = collector
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default();
+            let missing_secrets =
+                secrets.missing_for(&collector_name, aws_access_key_secrets_required());
+            if !missing_secrets.is_empty() {
+                anyhow::bail!(
+                    "{} is missing required secrets: {}",
+                    collector_name,
+                    missing_secrets.join(", ")
+                );
+            }
+            let sink = match &routing_file {
+                Some(path) => build_routing_sink(&secrets, path)?,
+                None => build_sink(&secrets, &sinks)?,
+            };
+            let sink = Arc::new(ManifestSink::new(&collector_name, sink));
+            info!(collector=?collector, sinks=?sinks, routing_file=?routing_file, "Running collector");
+            let result = collector
+                .run(secrets, sink.clone())
+                .await
+                .with_context(|| format!("Running {:?} collector", collector));
+            let _ = sink
+                .finish(result.as_ref().err().map(|err| format!("{:?}", err)))
+                .await
+                .context("Sending run manifest")?;
+            let report = sink
+                .shutdown(Duration::from_secs(shutdown_deadline_secs))
+                .await;
+            result?;
+            if report.undelivered() > 0 {
+                anyhow::bail!(
+                    "{} events were not delivered: {:?}",
+                    report.undelivered(),
+                    report
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn load_secrets(secrets_file: Option<PathBuf>, key_vault: Option<String>) -> Result<Secrets> {
+    let mut providers: Vec<Box<dyn SecretProvider>> = vec![Box::new(EnvSecretProvider::new())];
+    if let Some(path) = secrets_file {
+        providers.push(Box::new(FileSecretProvider::from_path(&path)?));
+    }
+    if let Some(key_vault) = key_vault {
+        providers.push(Box::new(
+            KeyVaultSecretProvider::new(&key_vault).context("Creating KeyVault client")?,
+        ));
+    }
+    get_secrets(&ChainedSecretProvider::new(providers))
+        .await
+        .context("Getting secrets")
+}
+
+fn load_redactor(secrets: &Secrets, redaction_file: Option<&Path>) -> Result<Option<Redactor>> {
+    let hmac_key = secrets.redaction_hmac_key.as_deref();
+    let Some(path) = redaction_file else {
+        return Redactor::from_env(hmac_key)
+            .context("Reading redaction rules from SSPHP_REDACTION_FILE");
+    };
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading redaction file: {}", path.display()))?;
+    Ok(Some(Redactor::new(
+        RedactionConfig::from_toml(&contents)?,
+        hmac_key,
+    )?))
+}
+
+fn build_sink(secrets: &Secrets, specs: &[String]) -> Result<Arc<dyn HecSink>> {
+    let mut sinks = specs
+        .iter()
+        .map(|spec| sink_from_secrets(spec, secrets))
+        .collect::<Result<Vec<_>>>()?;
+    if sinks.len() == 1 {
+        return Ok(sinks.remove(0));
+    }
+    Ok(Arc::new(FanOutSink::new(sinks)))
+}
+
+fn build_routing_sink(secrets: &Secrets, path: &Path) -> Result<Arc<dyn HecSink>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading routing file: {}", path.display()))?;
+    let routing = RoutingConfig::from_toml(&contents)?;
+    Ok(Arc::new(
+        RoutingSink::new(routing, |spec| sink_from_secrets(spec, secrets))
+            .context("Building routing sink")?,
+    ))
+}
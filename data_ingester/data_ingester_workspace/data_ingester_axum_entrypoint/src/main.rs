@@ -3,6 +3,7 @@ mod app_state;
 mod azure_functions;
 mod azure_request_response;
 mod runner;
+mod scheduler;
 use azure_functions::start_server;
 use memory_stats::memory_stats;
 use tokio::sync::oneshot;
@@ -1,10 +1,14 @@
+use anyhow::Context;
 use axum::{http::HeaderMap, Json};
-use data_ingester_splunk::splunk::Splunk;
+use data_ingester_aws::aws::{aws_access_key_secrets_required, AwsCheckFilter};
+use data_ingester_splunk::manifest::ManifestSink;
+use data_ingester_splunk::sink::HecSink;
 use data_ingester_supporting::keyvault::Secrets;
+use data_ingester_supporting::metrics::METRICS;
 use std::{future::Future, sync::Arc};
 use tokio::{
-    sync::Mutex,
-    time::{timeout, Duration},
+    sync::{oneshot, Mutex},
+    time::{timeout, Duration, Instant},
 };
 use tracing::{error, info, instrument};
 
@@ -47,17 +51,83 @@ impl std::fmt::Display for RunnerState {
     }
 }
 
+/// How a collector run ended
+enum RunOutcome {
+    Success,
+    Failed(anyhow::Error),
+    TimedOut,
+    Cancelled,
+}
+
+impl std::fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let outcome = match self {
+            RunOutcome::Success => "success",
+            RunOutcome::Failed(_) => "failed",
+            RunOutcome::TimedOut => "timed_out",
+            RunOutcome::Cancelled => "cancelled",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+/// Timeout and retry settings for a collector.
+///
+/// Read from environment variables using the collector's route name,
+/// e.g. for AWS:
+///
+/// SSPHP_AWS_TIMEOUT_SECS: Maximum run time of a single attempt. Defaults to 8 hours
+/// SSPHP_AWS_RETRIES: Number of times to retry a failed or timed out run. Defaults to 0
+/// SSPHP_AWS_RETRY_BACKOFF_SECS: Delay before the first retry, doubled for each retry. Defaults to 60
+///
+/// An invalid value stops the collector from starting.
+struct RunnerSettings {
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RunnerSettings {
+    /// Read the settings for `name`, failing on values that are not
+    /// whole numbers rather than silently using the default
+    fn for_collector(name: &str) -> Result<Self, anyhow::Error> {
+        let prefix = format!("SSPHP_{}", route_name(name).to_uppercase());
+        let var = |suffix: &str, default: u64| -> Result<u64, anyhow::Error> {
+            let key = format!("{}_{}", prefix, suffix);
+            let Ok(value) = std::env::var(&key) else {
+                return Ok(default);
+            };
+            value
+                .parse::<u64>()
+                .with_context(|| format!("Parsing {}='{}' as u64", key, value))
+        };
+        Ok(Self {
+            timeout: Duration::from_secs(var("TIMEOUT_SECS", 60 * 60 * 8)?),
+            retries: u32::try_from(var("RETRIES", 0)?)
+                .with_context(|| format!("{}_RETRIES is too large", prefix))?,
+            backoff: Duration::from_secs(var("RETRY_BACKOFF_SECS", 60)?),
+        })
+    }
+}
+
+/// Route name for a collector display name, e.g. `Azure Dev Ops` -> `azure_dev_ops`
+pub(crate) fn route_name(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
 /// Run a entrypoint function
 ///
 /// Checks to see if the lock can be held, then runs the async
 /// function for data collection.
 ///
-/// The lock should be freed when the function completes or the
-/// function fails.
+/// The lock should be freed when the function completes, fails, times
+/// out or is cancelled with [cancel_collector].
+///
+/// Failed or timed out runs are retried with backoff, see [RunnerSettings].
 ///
 /// name: The name of this function to use for logging
 /// lock: The lock to prevent concurrent executions
-/// func: an async function taking [Arc<Secrets}] and [Arc<Splunk>]
+/// func: an async function taking [Arc<Secrets}] and [Arc<dyn HecSink>]
 ///
 #[instrument(skip(lock, state, func, headers, request))]
 pub(crate) async fn function_runner<F, R>(
@@ -69,7 +139,7 @@ pub(crate) async fn function_runner<F, R>(
     request: Option<Json<AzureInvokeRequest>>,
 ) -> AzureInvokeResponse
 where
-    F: Fn(Arc<Secrets>, Arc<Splunk>) -> R,
+    F: Fn(Arc<Secrets>, Arc<dyn HecSink>) -> R,
     R: Future<Output = Result<(), anyhow::Error>>,
 {
     let mut stage = RunnerState::new();
@@ -87,6 +157,47 @@ where
         return_value: None,
     };
 
+    let missing_secrets = state
+        .secrets
+        .missing_for(name, aws_access_key_secrets_required());
+    if !missing_secrets.is_empty() {
+        let msg = format!(
+            "{} is missing required secrets: {}. NOT starting.",
+            name,
+            missing_secrets.join(", ")
+        );
+        state
+            .stats
+            .write()
+            .await
+            .get(invocation_index)
+            .errors(msg.to_owned())
+            .finish();
+        error!(name = name, stage=%stage, missing_secrets=?missing_secrets);
+        response.logs.push(msg);
+        return response;
+    }
+
+    let settings = match RunnerSettings::for_collector(name) {
+        Ok(settings) => settings,
+        Err(err) => {
+            let msg = format!(
+                "{} has invalid runner settings: {:#}. NOT starting.",
+                name, err
+            );
+            state
+                .stats
+                .write()
+                .await
+                .get(invocation_index)
+                .errors(msg.to_owned())
+                .finish();
+            error!(name = name, stage=%stage, operation="Read runner settings", error=?err);
+            response.logs.push(msg);
+            return response;
+        }
+    };
+
     stage.next();
 
     let lock = match lock.try_lock() {
@@ -121,55 +232,117 @@ where
     };
     stage.next();
 
-    // TODO set per collector timeouts
-    let result = match timeout(
-        Duration::from_secs(60 * 60 * 8),
-        func(state.secrets.clone(), state.splunk.clone()),
-    )
-    .await
-    {
-        Ok(result) => {
-            info!(name=name, stage=%stage, complete=false, "");
-            Some(result)
+    let _ = state
+        .stats
+        .write()
+        .await
+        .get(invocation_index)
+        .timeout(settings.timeout.as_secs());
+
+    let (cancel_tx, mut cancel_rx) = oneshot::channel::<()>();
+    let _ = state
+        .cancellations
+        .lock()
+        .await
+        .insert(route_name(name), cancel_tx);
+
+    let started = Instant::now();
+    let mut attempt = 0;
+    let outcome = loop {
+        attempt += 1;
+        let _ = state
+            .stats
+            .write()
+            .await
+            .get(invocation_index)
+            .attempts(attempt);
+        info!(name=name, stage=%stage, attempt=attempt, timeout_secs=settings.timeout.as_secs());
+
+        let manifest = Arc::new(ManifestSink::new(&route_name(name), state.sink.clone()));
+        let run = timeout(
+            settings.timeout,
+            func(state.secrets.clone(), manifest.clone()),
+        );
+        let outcome = tokio::select! {
+            result = run => match result {
+                Ok(Ok(())) => RunOutcome::Success,
+                Ok(Err(err)) => RunOutcome::Failed(err),
+                Err(_) => RunOutcome::TimedOut,
+            },
+            _ = &mut cancel_rx => RunOutcome::Cancelled,
+        };
+
+        let manifest_error = match &outcome {
+            RunOutcome::Success => None,
+            RunOutcome::Failed(err) => Some(format!("{:?}", err)),
+            RunOutcome::TimedOut | RunOutcome::Cancelled => Some(outcome.to_string()),
+        };
+        if let Err(err) = manifest.finish(manifest_error).await {
+            error!(name=name, stage=%stage, operation="Send run manifest", error=?err);
         }
-        Err(err) => {
-            state
-                .stats
-                .write()
-                .await
-                .get(invocation_index)
-                .errors(format!("{:#?}", err))
-                .finish();
-            stage.next();
-            error!(name=name, stage=%stage, complete=false, error=?err);
-            None
+
+        match &outcome {
+            RunOutcome::Success | RunOutcome::Cancelled => break outcome,
+            RunOutcome::Failed(err) => {
+                error!(name=name, stage=%stage, attempt=attempt, complete=false, error=?err)
+            }
+            RunOutcome::TimedOut => {
+                error!(name=name, stage=%stage, attempt=attempt, complete=false, timeout_secs=settings.timeout.as_secs())
+            }
+        }
+
+        if attempt > settings.retries {
+            break outcome;
+        }
+
+        let backoff = settings
+            .backoff
+            .saturating_mul(2_u32.saturating_pow(attempt - 1));
+        info!(name=name, stage=%stage, attempt=attempt, backoff_secs=backoff.as_secs(), "Retrying");
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {},
+            _ = &mut cancel_rx => break RunOutcome::Cancelled,
         }
     };
 
-    let (result, complete) = if let Some(result) = result {
-        match result {
-            Ok(_) => {
-                stage.next();
-                state.stats.write().await.get(invocation_index).finish();
-                (format!("{} Success", name), true)
+    let _ = state.cancellations.lock().await.remove(&route_name(name));
+    stage.next();
+    METRICS.collector_run(
+        &route_name(name),
+        &outcome.to_string(),
+        started.elapsed().as_secs_f64(),
+    );
+
+    {
+        let mut stats = state.stats.write().await;
+        let invocation = stats.get(invocation_index).outcome(outcome.to_string());
+        match &outcome {
+            RunOutcome::Success => {}
+            RunOutcome::Failed(err) => {
+                let _ = invocation.errors(format!("{:#?}", err));
             }
-            Err(e) => {
-                state
-                    .stats
-                    .write()
-                    .await
-                    .get(invocation_index)
-                    .errors(format!("{:#?}", e))
-                    .finish();
-                stage.next();
-                error!(name=name, stage=%stage, complete=false, error=?e);
-                let error = format!("{} entrypoint failed with error: {:#?}", &name, &e);
-                (error, false)
+            RunOutcome::TimedOut | RunOutcome::Cancelled => {
+                let _ = invocation.errors(format!("{} {}", name, outcome));
             }
         }
-    } else {
-        let error = format!("{} entrypoint failed after collector timeout", &name);
-        (error, false)
+        invocation.finish();
+    }
+
+    let (result, complete) = match &outcome {
+        RunOutcome::Success => (format!("{} Success", name), true),
+        RunOutcome::Failed(err) => (
+            format!("{} entrypoint failed with error: {:#?}", &name, &err),
+            false,
+        ),
+        RunOutcome::TimedOut => (
+            format!(
+                "{} entrypoint failed after collector timeout of {}s",
+                &name,
+                settings.timeout.as_secs()
+            ),
+            false,
+        ),
+        RunOutcome::Cancelled => (format!("{} entrypoint cancelled", &name), false),
     };
 
     drop(lock);
@@ -178,3 +351,241 @@ where
     error!(name=name, stage=%stage, complete=complete);
     response
 }
+
+/// Run a collector by its route name through [function_runner]
+///
+/// Used by both the HTTP handlers and the scheduler. Returns `None`
+/// for an unknown collector.
+///
+/// For AWS, `checks` in the request selects which checks to run,
+/// otherwise they are read from the environment, see
+/// [AwsCheckFilter::from_env]
+pub(crate) async fn run_collector(
+    collector: &str,
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    request: Option<Json<AzureInvokeRequest>>,
+) -> Option<AzureInvokeResponse> {
+    let response = match collector {
+        "aws" => {
+            let filter = request
+                .as_ref()
+                .and_then(|request| request.data.checks.clone())
+                .map(|checks| AwsCheckFilter {
+                    include: checks.include,
+                    exclude: checks.exclude,
+                })
+                .unwrap_or_else(AwsCheckFilter::from_env);
+            // An unknown check is a bad request, don't retry it
+            if let Err(err) = filter.validate() {
+                error!(name = "AWS", operation = "Validate check filter", error = ?err);
+                return Some(AzureInvokeResponse {
+                    outputs: None,
+                    logs: vec![format!("AWS has invalid checks: {:#}. NOT starting.", err)],
+                    return_value: None,
+                });
+            }
+            function_runner(
+                "AWS",
+                state.aws_lock.clone(),
+                state,
+                move |secrets, sink| {
+                    data_ingester_aws::aws::aws_with_checks(secrets, sink, filter.clone())
+                },
+                headers,
+                request,
+            )
+            .await
+        }
+        "azure" => {
+            function_runner(
+                "Azure",
+                state.azure_lock.clone(),
+                state,
+                data_ingester_azure::azure_users,
+                headers,
+                request,
+            )
+            .await
+        }
+        "azure_dev_ops" => {
+            function_runner(
+                "Azure Dev Ops",
+                state.azure_dev_ops_lock.clone(),
+                state,
+                data_ingester_azure_dev_ops::entrypoint::entrypoint,
+                headers,
+                request,
+            )
+            .await
+        }
+        "azure_resource_graph" => {
+            function_runner(
+                "Azure Resource Graph",
+                state.azure_resource_graph_lock.clone(),
+                state,
+                data_ingester_azure_rest::resource_graph::azure_resource_graph,
+                headers,
+                request,
+            )
+            .await
+        }
+        "financial_business_partners" => {
+            function_runner(
+                "Financial Business Partners",
+                state.financial_business_partners_lock.clone(),
+                state,
+                data_ingester_financial_business_partners::entrypoint,
+                headers,
+                request,
+            )
+            .await
+        }
+        "github" => {
+            function_runner(
+                "GitHub",
+                state.github_lock.clone(),
+                state,
+                data_ingester_github::entrypoint::github_octocrab_entrypoint,
+                headers,
+                request,
+            )
+            .await
+        }
+        "github_custom_properties" => {
+            function_runner(
+                "GitHub Custom Properties",
+                state.github_custom_properties_lock.clone(),
+                state,
+                data_ingester_github::entrypoint::github_set_custom_properties_entrypoint,
+                headers,
+                request,
+            )
+            .await
+        }
+        "m365" => {
+            function_runner(
+                "M365",
+                state.m365_lock.clone(),
+                state,
+                data_ingester_ms_graph::ms_graph::m365,
+                headers,
+                request,
+            )
+            .await
+        }
+        "powershell" => {
+            if let Err(err) = install_powershell(&state).await {
+                error!(name="Powershell", operation="Install powershell", error=?err);
+                return Some(AzureInvokeResponse {
+                    outputs: None,
+                    logs: vec![
+                        "Powershell".to_string(),
+                        format!("Powershell install failed: {:?}. NOT starting.", err),
+                    ],
+                    return_value: None,
+                });
+            }
+            function_runner(
+                "Powershell",
+                state.powershell_lock.clone(),
+                state,
+                data_ingester_ms_powershell::runner::powershell,
+                headers,
+                request,
+            )
+            .await
+        }
+        "qualys_qvs" => {
+            function_runner(
+                "Qualys QVS",
+                state.qualys_qvs_lock.clone(),
+                state,
+                data_ingester_qualys::entrypoint::qualys_qvs,
+                headers,
+                request,
+            )
+            .await
+        }
+        "sonar_cloud" => {
+            function_runner(
+                "Sonar Cloud",
+                state.sonar_cloud.clone(),
+                state,
+                data_ingester_sonar_cloud::entrypoint,
+                headers,
+                request,
+            )
+            .await
+        }
+        "threagile" => {
+            function_runner(
+                "Threagile",
+                state.threagile_lock.clone(),
+                state,
+                data_ingester_threagile::threagile,
+                headers,
+                request,
+            )
+            .await
+        }
+        _ => return None,
+    };
+    Some(response)
+}
+
+/// Install powershell on the functions host, once per process
+///
+/// [AppState::powershell_installed] is held for the whole install, so
+/// concurrent runs wait for the first install instead of racing it.
+async fn install_powershell(state: &AppState) -> Result<(), anyhow::Error> {
+    let mut installed = state.powershell_installed.lock().await;
+    if !*installed {
+        info!(name = "Powershell", "Installing");
+        data_ingester_ms_powershell::powershell::install_powershell().await?;
+        *installed = true;
+    }
+    Ok(())
+}
+
+/// Cancel an in-flight collection.
+///
+/// Aborts the running collector future, which releases its lock.
+/// Returns false if the collector is not running.
+pub(crate) async fn cancel_collector(state: &AppState, collector: &str) -> bool {
+    let Some(cancel) = state
+        .cancellations
+        .lock()
+        .await
+        .remove(&route_name(collector))
+    else {
+        return false;
+    };
+    cancel.send(()).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::RunnerSettings;
+
+    #[test]
+    fn test_runner_settings_defaults() {
+        let settings = RunnerSettings::for_collector("Runner Defaults").unwrap();
+        assert_eq!(settings.timeout.as_secs(), 60 * 60 * 8);
+        assert_eq!(settings.retries, 0);
+        assert_eq!(settings.backoff.as_secs(), 60);
+    }
+
+    #[test]
+    fn test_runner_settings_rejects_invalid_values() {
+        std::env::set_var("SSPHP_RUNNER_INVALID_RETRIES", "three");
+        assert!(RunnerSettings::for_collector("Runner Invalid").is_err());
+        std::env::set_var("SSPHP_RUNNER_INVALID_RETRIES", "3");
+        std::env::set_var("SSPHP_RUNNER_INVALID_TIMEOUT_SECS", "-1");
+        assert!(RunnerSettings::for_collector("Runner Invalid").is_err());
+        std::env::set_var("SSPHP_RUNNER_INVALID_TIMEOUT_SECS", "60");
+        let settings = RunnerSettings::for_collector("Runner Invalid").unwrap();
+        assert_eq!(settings.retries, 3);
+        assert_eq!(settings.timeout.as_secs(), 60);
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, HeaderValue};
+use chrono::Utc;
+use cron::Schedule;
+use data_ingester_supporting::secret_provider::COLLECTORS;
+use rand::Rng;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{app_state::AppState, runner::run_collector};
+
+/// Schedule for a single collector
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct CollectorSchedule {
+    /// Cron expression including a seconds field, e.g. "0 30 2 * * *"
+    cron: String,
+    /// Maximum number of seconds to randomly delay each run by
+    #[serde(default)]
+    jitter_secs: u64,
+}
+
+/// Collector schedules keyed by collector route name
+///
+/// ```toml
+/// [aws]
+/// cron = "0 0 2 * * *"
+/// jitter_secs = 600
+///
+/// [github]
+/// cron = "0 0 */6 * * *"
+/// ```
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct ScheduleConfig(HashMap<String, CollectorSchedule>);
+
+impl ScheduleConfig {
+    /// Load the schedule from the file named by SSPHP_SCHEDULE_FILE,
+    /// returns `None` if it is not set.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("SSPHP_SCHEDULE_FILE") else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading schedule file: {}", path))?;
+        Ok(Some(Self::from_toml(&contents)?))
+    }
+
+    /// Parse and validate a schedule
+    pub(crate) fn from_toml(contents: &str) -> Result<Self> {
+        let config: ScheduleConfig = toml::from_str(contents).context("Parsing schedule TOML")?;
+        for (collector, schedule) in config.0.iter() {
+            if !COLLECTORS.contains(&collector.as_str()) {
+                anyhow::bail!("Unknown collector in schedule: '{}'", collector);
+            }
+            let _ = Schedule::from_str(&schedule.cron)
+                .with_context(|| format!("Parsing cron '{}' for '{}'", schedule.cron, collector))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Start a task for each scheduled collector
+pub(crate) fn start_scheduler(
+    config: ScheduleConfig,
+    state: Arc<AppState>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let mut tasks = vec![];
+    for (collector, collector_schedule) in config.0 {
+        let schedule = Schedule::from_str(&collector_schedule.cron)
+            .with_context(|| format!("Parsing cron for '{}'", collector))?;
+        info!(
+            name = "Scheduler",
+            collector = collector,
+            cron = collector_schedule.cron,
+            jitter_secs = collector_schedule.jitter_secs,
+            "Scheduling collector"
+        );
+        tasks.push(tokio::spawn(scheduled_collector(
+            collector,
+            collector_schedule,
+            schedule,
+            state.clone(),
+        )));
+    }
+    Ok(tasks)
+}
+
+/// Run a collector each time its schedule fires
+async fn scheduled_collector(
+    collector: String,
+    collector_schedule: CollectorSchedule,
+    schedule: Schedule,
+    state: Arc<AppState>,
+) {
+    loop {
+        let Some(next) = schedule.upcoming(Utc).next() else {
+            error!(
+                name = "Scheduler",
+                collector = collector,
+                "Schedule has no upcoming runs"
+            );
+            return;
+        };
+        let jitter = if collector_schedule.jitter_secs > 0 {
+            rand::thread_rng().gen_range(0..=collector_schedule.jitter_secs)
+        } else {
+            0
+        };
+        let next_run = u64::try_from(next.timestamp()).unwrap_or_default() + jitter;
+
+        state.stats.write().await.scheduled(
+            &collector,
+            &collector_schedule.cron,
+            None,
+            Some(next_run),
+        );
+
+        tokio::time::sleep(Duration::from_secs(next_run.saturating_sub(now()))).await;
+
+        state.stats.write().await.scheduled(
+            &collector,
+            &collector_schedule.cron,
+            Some(now()),
+            None,
+        );
+
+        info!(
+            name = "Scheduler",
+            collector = collector,
+            "Starting collector"
+        );
+        match run_collector(&collector, state.clone(), scheduler_headers(), None).await {
+            Some(response) => {
+                info!(name="Scheduler", collector=collector, logs=?response.logs, "Collector finished")
+            }
+            None => {
+                error!(
+                    name = "Scheduler",
+                    collector = collector,
+                    "Unknown collector"
+                );
+                return;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Headers recorded on the [crate::app_state::Invocation] for scheduled runs
+fn scheduler_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let _ = headers.insert("x-ssphp-scheduler", HeaderValue::from_static("cron"));
+    headers
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScheduleConfig;
+
+    #[test]
+    fn test_schedule_config_from_toml() {
+        let config = ScheduleConfig::from_toml(
+            r#"
+[aws]
+cron = "0 0 2 * * *"
+jitter_secs = 600
+
+[github]
+cron = "0 0 */6 * * *"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.0.len(), 2);
+        assert_eq!(config.0["aws"].jitter_secs, 600);
+        assert_eq!(config.0["github"].jitter_secs, 0);
+    }
+
+    #[test]
+    fn test_schedule_config_rejects_unknown_collector() {
+        let config = ScheduleConfig::from_toml(
+            r#"
+[not_a_collector]
+cron = "0 0 2 * * *"
+"#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_schedule_config_rejects_invalid_cron() {
+        let config = ScheduleConfig::from_toml(
+            r#"
+[aws]
+cron = "every day"
+"#,
+        );
+        assert!(config.is_err());
+    }
+}
@@ -1,14 +1,17 @@
 use anyhow::{Context, Result};
 use data_ingester_azure_rest::azure_rest::AzureRest;
-use data_ingester_ms_graph::ms_graph::MsGraph;
+use data_ingester_ms_graph::conditional_access_evaluation::ConditionalAccessEvaluator;
+use data_ingester_ms_graph::membership::MembershipGraph;
+use data_ingester_ms_graph::ms_graph::{MsGraph, NamedLocations};
 use data_ingester_ms_graph::users::UsersMap;
+use data_ingester_splunk::sink::HecSink;
 use data_ingester_splunk::splunk::try_collect_send;
-use data_ingester_splunk::splunk::{set_ssphp_run, Splunk, ToHecEvents};
+use data_ingester_splunk::splunk::{set_ssphp_run, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 
-pub async fn azure_users(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn azure_users(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("azure_users")?;
 
     info!("Starting Azure Users collection");
@@ -84,15 +87,36 @@ pub async fn azure_users(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<(
     let caps = ms_graph.list_conditional_access_policies().await?;
     splunk.send_batch((&caps).to_hec_events()?).await?;
 
+    info!("Getting AAD named locations");
+    let named_locations = match ms_graph.list_named_locations().await {
+        Ok(named_locations) => named_locations,
+        Err(err) => {
+            warn!(name="Azure", operation="Get named locations", error=?err);
+            NamedLocations::default()
+        }
+    };
+    let evaluator = ConditionalAccessEvaluator::new(&caps, &named_locations);
+
     info!("Getting AAD roles definitions");
     let aad_role_definitions = ms_graph.list_role_definitions().await?;
     splunk
         .send_batch((&aad_role_definitions).to_hec_events()?)
         .await?;
 
+    info!("Getting AAD role assignments and eligibilities");
+    let role_membership = match ms_graph.get_role_membership_graph().await {
+        Ok(role_membership) => role_membership,
+        Err(err) => {
+            warn!(name="Azure", operation="Get role membership", error=?err);
+            MembershipGraph::incomplete()
+        }
+    };
+
     let splunk_clone = splunk.clone();
     let process_to_splunk = tokio::spawn(async move {
         while let Some(mut users) = reciever.recv().await {
+            users.add_directory_roles(&role_membership, &aad_role_definitions);
+
             users.set_is_privileged(&aad_role_definitions);
 
             users.process_caps(&caps);
@@ -105,6 +129,11 @@ pub async fn azure_users(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<(
                 .context("Failed to add azure roles")?;
 
             splunk_clone.send_batch((&users).to_hec_events()?).await?;
+
+            let findings = evaluator.user_findings(&users);
+            splunk_clone
+                .send_batch((&findings).to_hec_events()?)
+                .await?;
         }
         anyhow::Ok::<()>(())
     });
@@ -513,14 +513,15 @@ pub(crate) trait AzureDevOpsClientMethods: AzureDevOpsClient {
 mod live_tests {
     use crate::test_utils::TEST_SETUP;
     use anyhow::Result;
-    use data_ingester_splunk::splunk::{Splunk, ToHecEvents};
+    use data_ingester_splunk::sink::HecSink;
+    use data_ingester_splunk::splunk::ToHecEvents;
+    use std::sync::Arc;
 
-    async fn send_to_splunk(splunks: &[Splunk], ado_response: impl ToHecEvents) -> Result<()> {
+    async fn send_to_splunk(sink: &Arc<dyn HecSink>, ado_response: impl ToHecEvents) -> Result<()> {
         let hec_events = (ado_response).to_hec_events()?;
 
-        for splunk in splunks {
-            splunk.send_batch(hec_events.clone()).await?;
-        }
+        sink.send_collector_batch("azure_dev_ops", hec_events)
+            .await?;
 
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
         Ok(())
@@ -531,19 +532,19 @@ mod live_tests {
         let t = &*TEST_SETUP;
         let _: Result<()> = t.runtime.block_on(async {
             let projects = t.ado.projects_list(&t.organization).await?;
-            send_to_splunk(&t.splunks, projects).await?;
+            send_to_splunk(&t.sink, projects).await?;
 
             let policy_configuration = t
                 .ado
                 .git_policy_configuration_get(&t.organization, &t.project)
                 .await?;
-            send_to_splunk(&t.splunks, policy_configuration).await?;
+            send_to_splunk(&t.sink, policy_configuration).await?;
 
             let result = t
                 .ado
                 .git_repository_list(&t.organization, &t.project)
                 .await?;
-            send_to_splunk(&t.splunks, result).await?;
+            send_to_splunk(&t.sink, result).await?;
             Ok(())
         });
     }
@@ -555,7 +556,7 @@ mod live_tests {
             let projects = t.ado.projects_list(&t.organization).await?;
             assert!(!projects.value.is_empty());
             assert_eq!(projects.count, projects.value.len());
-            send_to_splunk(&t.splunks, projects).await?;
+            send_to_splunk(&t.sink, projects).await?;
             Ok(())
         });
     }
@@ -569,7 +570,7 @@ mod live_tests {
             // assert!(false);
             assert!(!audit_streams.value.is_empty());
             assert_eq!(audit_streams.count, audit_streams.value.len());
-            send_to_splunk(&t.splunks, audit_streams).await?;
+            send_to_splunk(&t.sink, audit_streams).await?;
             Ok(())
         });
         result.unwrap();
@@ -583,7 +584,7 @@ mod live_tests {
             let pat_tokens = t.ado.pat_tokens(&t.organization).await?;
             assert!(!pat_tokens.value.is_empty());
             assert_eq!(pat_tokens.count, pat_tokens.value.len());
-            send_to_splunk(&t.splunks, pat_tokens).await?;
+            send_to_splunk(&t.sink, pat_tokens).await?;
             Ok(())
         });
         result.unwrap();
@@ -600,7 +601,7 @@ mod live_tests {
 
             assert!(!policy_configuration.value.is_empty());
             assert_eq!(policy_configuration.count, policy_configuration.value.len());
-            send_to_splunk(&t.splunks, policy_configuration).await?;
+            send_to_splunk(&t.sink, policy_configuration).await?;
             Ok(())
         });
     }
@@ -616,7 +617,7 @@ mod live_tests {
 
             assert!(!policy_configuration.value.is_empty());
             assert_eq!(policy_configuration.count, policy_configuration.value.len());
-            send_to_splunk(&t.splunks, policy_configuration).await?;
+            send_to_splunk(&t.sink, policy_configuration).await?;
             Ok(())
         });
     }
@@ -631,7 +632,7 @@ mod live_tests {
                 .await?;
             assert!(!result.value.is_empty());
             assert_eq!(result.count, result.value.len());
-            send_to_splunk(&t.splunks, result).await?;
+            send_to_splunk(&t.sink, result).await?;
             Ok(())
         });
     }
@@ -642,7 +643,7 @@ mod live_tests {
         let result: Result<()> = t.runtime.block_on(async {
             let result = t.ado.organizations_list().await?;
             assert!(!result.organizations.is_empty());
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             Ok(())
         });
         result.unwrap();
@@ -654,7 +655,7 @@ mod live_tests {
         let result: Result<()> = t.runtime.block_on(async {
             let result = t.ado.graph_users_list(&t.organization).await?;
             assert!(!result.value.is_empty());
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             Ok(())
         });
         result.unwrap();
@@ -666,7 +667,7 @@ mod live_tests {
         let result: Result<()> = t.runtime.block_on(async {
             let result = t.ado.graph_service_principals_list(&t.organization).await?;
             assert!(!result.value.is_empty());
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             Ok(())
         });
         result.unwrap();
@@ -678,7 +679,7 @@ mod live_tests {
         let result: Result<()> = t.runtime.block_on(async {
             let result = t.ado.graph_groups_list(&t.organization).await?;
             assert!(!result.value.is_empty());
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             Ok(())
         });
         result.unwrap();
@@ -689,7 +690,7 @@ mod live_tests {
         let t = &*TEST_SETUP;
         let result: Result<()> = t.runtime.block_on(async {
             let result = t.ado.adv_security_org_enablement(&t.organization).await?;
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             assert!(!result.value.is_empty());
             Ok(())
         });
@@ -704,7 +705,7 @@ mod live_tests {
                 .ado
                 .adv_security_project_enablement(&t.organization, &t.project)
                 .await?;
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             assert!(!result.value.is_empty());
             Ok(())
         });
@@ -719,7 +720,7 @@ mod live_tests {
                 .ado
                 .adv_security_repo_enablement(&t.organization, &t.project, &t.repo)
                 .await?;
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             assert!(!result.value.is_empty());
             Ok(())
         });
@@ -735,7 +736,7 @@ mod live_tests {
                 .ado
                 .adv_security_alerts(&t.organization, &t.project, &t.repo)
                 .await?;
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             assert!(!result.value.is_empty());
             Ok(())
         });
@@ -750,7 +751,7 @@ mod live_tests {
                 .ado
                 .build_general_settings(&t.organization, &t.project)
                 .await?;
-            send_to_splunk(&t.splunks, &result).await?;
+            send_to_splunk(&t.sink, &result).await?;
             assert!(!result.value.is_empty());
             Ok(())
         });
@@ -770,7 +771,7 @@ mod live_tests {
     //         .to_string();
     //     let accounts = t.ado.accounts_list(&t.organization, &owner_id).await?;
     //     assert!(accounts.value.len() > 0);
-    //     send_to_splunk(&t.splunks, accounts).await?;
+    //     send_to_splunk(&t.sink, accounts).await?;
     //     Ok(())
     // }
 
@@ -778,7 +779,7 @@ mod live_tests {
     // async fn test_ado_owner_id() -> Result<()> {
     //     let t = test_setup().await;
     //     let owner_id = t.ado.org_details(&t.organization).await?;
-    //     // send_to_splunk(&t.splunks, accounts).await?;
+    //     // send_to_splunk(&t.sink, accounts).await?;
     //     Ok(())
     // }
 
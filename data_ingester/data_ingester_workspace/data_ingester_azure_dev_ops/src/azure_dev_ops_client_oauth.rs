@@ -3,6 +3,7 @@ use crate::ado_metadata::{AdoMetadata, AdoMetadataBuilder, NoRestDocs, NoType, N
 use crate::ado_response::{AddAdoResponse, AdoPaging, AdoRateLimiting, AdoResponse};
 use crate::data::organization::Organizations;
 use anyhow::{Context, Result};
+use data_ingester_supporting::metrics::METRICS;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use tracing::{debug, error, trace};
@@ -55,6 +56,7 @@ impl AzureDevOpsClientOauth {
             .send()
             .await?;
 
+        METRICS.http_status("ado", response.status().as_u16());
         if !response.status().is_success() {
             error!(name="Azure Dev Ops", operation="organizations_list GET request", error="Non 2xx status code", status=?response.status(), headers=?response.headers());
             anyhow::bail!("failed request");
@@ -129,6 +131,7 @@ impl AzureDevOpsClient for AzureDevOpsClientOauth {
                 .await?;
 
             let status = response.status();
+            METRICS.http_status("ado", status.as_u16());
             let headers = response.headers().clone();
             let text = response.text().await?;
 
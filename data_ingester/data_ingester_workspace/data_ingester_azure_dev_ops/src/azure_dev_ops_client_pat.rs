@@ -3,6 +3,7 @@ use crate::ado_metadata::{AdoMetadata, AdoMetadataBuilder, NoRestDocs, NoType, N
 use crate::ado_response::{AddAdoResponse, AdoPaging, AdoRateLimiting, AdoResponse};
 use crate::data::organization::Organizations;
 use anyhow::Result;
+use data_ingester_supporting::metrics::METRICS;
 use serde::de::DeserializeOwned;
 use tracing::{debug, error, trace};
 
@@ -72,6 +73,7 @@ impl AzureDevOpsClient for AzureDevOpsClientPat {
                 .await?;
 
             let status = response.status();
+            METRICS.http_status("ado", status.as_u16());
             let headers = response.headers().clone();
             let text = response.text().await?;
 
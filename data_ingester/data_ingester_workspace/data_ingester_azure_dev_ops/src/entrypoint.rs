@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
-use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, Splunk};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send};
 use data_ingester_supporting::keyvault::Secrets;
 use std::sync::Arc;
 use tracing::{error, info};
@@ -11,7 +12,7 @@ use crate::{
     data::{projects::Projects, repositories::Repositories},
 };
 
-pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("azure_devops")?;
     info!("Starting Azure DevOps ADO collection");
 
@@ -48,7 +49,7 @@ pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()
 
 async fn collect_organization<A: AzureDevOpsClientMethods>(
     ado: &A,
-    splunk: Arc<Splunk>,
+    splunk: Arc<dyn HecSink>,
     organization: &str,
 ) -> Result<()> {
     let _users = try_collect_send(
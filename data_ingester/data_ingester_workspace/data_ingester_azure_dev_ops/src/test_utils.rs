@@ -2,8 +2,10 @@ use std::sync::LazyLock;
 
 use crate::azure_dev_ops_client_oauth::AzureDevOpsClientOauth;
 use anyhow::Context;
-use data_ingester_splunk::splunk::Splunk;
+use data_ingester_splunk::routing::{RoutingConfig, RoutingSink};
+use data_ingester_splunk::sink::{sink_from_secrets, HecSink};
 use data_ingester_supporting::keyvault::get_keyvault_secrets;
+use std::sync::Arc;
 use tracing_subscriber::EnvFilter;
 
 #[allow(unused)]
@@ -12,12 +14,22 @@ pub(crate) struct TestSetup {
     pub(crate) organization: String,
     pub(crate) project: String,
     pub(crate) repo: String,
-    pub(crate) splunks: Vec<Splunk>,
+    pub(crate) sink: Arc<dyn HecSink>,
     #[allow(unused)]
     // pub(crate) tracing_guard: DefaultGuard,
     pub(crate) runtime: tokio::runtime::Runtime,
 }
 
+/// Send test events to both Splunk HEC endpoints in the KeyVault
+#[allow(unused)]
+const ROUTING: &str = r#"
+default = ["splunk", "ian"]
+
+[destinations]
+splunk = "splunk"
+ian = "ian_splunk"
+"#;
+
 #[allow(unused)]
 pub(crate) static TEST_SETUP: LazyLock<TestSetup> = LazyLock::new(test_setup_setup);
 
@@ -31,7 +43,7 @@ fn test_setup_setup() -> TestSetup {
         .with_env_filter(EnvFilter::from_env("RUST_LOG"))
         .init();
 
-    let (ado, splunks) = runtime.block_on(async {
+    let (ado, sink) = runtime.block_on(async {
         let secrets = get_keyvault_secrets(
             &std::env::var("KEY_VAULT_NAME").expect("Need KEY_VAULT_NAME enviornment variable"),
         )
@@ -57,29 +69,14 @@ fn test_setup_setup() -> TestSetup {
         .await
         .unwrap();
 
-        let splunk = Splunk::new(
-            secrets.splunk_host.as_ref().context("No value").unwrap(),
-            secrets.splunk_token.as_ref().context("No value").unwrap(),
-            false,
-        )
-        .unwrap();
-
-        let splunk_ian = Splunk::new(
-            secrets
-                .ian_splunk_host
-                .as_ref()
-                .context("No value")
-                .unwrap(),
-            secrets
-                .ian_splunk_token
-                .as_ref()
-                .context("No value")
-                .unwrap(),
-            true,
-        )
-        .unwrap();
+        let sink: Arc<dyn HecSink> = Arc::new(
+            RoutingSink::new(RoutingConfig::from_toml(ROUTING).unwrap(), |spec| {
+                sink_from_secrets(spec, &secrets)
+            })
+            .unwrap(),
+        );
 
-        (ado, vec![splunk, splunk_ian])
+        (ado, sink)
     });
 
     let organization = "aktest0831".to_string();
@@ -91,7 +88,7 @@ fn test_setup_setup() -> TestSetup {
         organization,
         project,
         repo,
-        splunks,
+        sink,
         runtime,
     }
 }
@@ -23,6 +23,7 @@ use url::Url;
 
 use data_ingester_splunk::splunk::ToHecEvents;
 use data_ingester_splunk::splunk::{get_ssphp_run, HecEvent};
+use data_ingester_supporting::metrics::METRICS;
 
 pub struct AzureRest {
     pub(crate) credential: Arc<ClientSecretCredential>,
@@ -239,9 +240,9 @@ impl AzureRest {
                 format!("Bearer {}", response.token.secret()),
             )
             .send()
-            .await?
-            .text()
             .await?;
+        METRICS.http_status("arm", response.status().as_u16());
+        let response = response.text().await?;
 
         let rt: T = serde_json::from_str(&response)?;
         Ok(rt)
@@ -314,6 +315,7 @@ impl AzureRest {
             };
 
             let status = response.status();
+            METRICS.http_status("arm", status.as_u16());
 
             let response_body = match response.text().await {
                 Ok(ok) => ok,
@@ -370,9 +372,9 @@ impl AzureRest {
                     format!("Bearer {}", response.token.secret()),
                 )
                 .send()
-                .await?
-                .text()
                 .await?;
+            METRICS.http_status("arm", response.status().as_u16());
+            let response = response.text().await?;
 
             let rt: ReturnType = serde_json::from_str(&response)?;
             collection.push(rt);
@@ -400,9 +402,9 @@ impl AzureRest {
                     format!("Bearer {}", response.token.secret()),
                 )
                 .send()
-                .await?
-                .text()
                 .await?;
+            METRICS.http_status("arm", response.status().as_u16());
+            let response = response.text().await?;
 
             let rt: ReturnType = serde_json::from_str(&response)?;
 
@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
 use async_recursion::async_recursion;
 use azure_core::auth::TokenCredential;
-use data_ingester_splunk::splunk::{set_ssphp_run, Splunk, ToHecEvents};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -11,7 +12,7 @@ use std::{
 };
 use tokio::time::{Duration, Instant};
 use tracing::{error, info};
-pub async fn azure_resource_graph(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn azure_resource_graph(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("azure_resource_graph")?;
 
     info!("Starting Azure Resource Graph collection");
@@ -40,7 +41,7 @@ pub async fn azure_resource_graph(secrets: Arc<Secrets>, splunk: Arc<Splunk>) ->
     Ok(())
 }
 
-async fn resource_graph_all(az_client: AzureRest, splunk: &Splunk) -> Result<()> {
+async fn resource_graph_all(az_client: AzureRest, splunk: &dyn HecSink) -> Result<()> {
     let endpoint = "https://management.azure.com/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01";
     let mut rate_limit = RateLimit::default();
     for sub in az_client.subscriptions().inner.iter() {
@@ -2,7 +2,8 @@ pub mod fbp_results;
 pub mod validator;
 
 use anyhow::{Context, Result};
-use data_ingester_splunk::splunk::{set_ssphp_run, HecEvent, Splunk, ToHecEvents};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, HecEvent, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
 use futures::TryStreamExt;
 use rand::Rng;
@@ -81,7 +82,7 @@ async fn get_contact_details(secrets: Arc<Secrets>) -> Result<Vec<ContactDetails
     Ok(contact_details)
 }
 
-pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("fbp")?;
 
     let contact_details = get_contact_details(secrets)
@@ -2,13 +2,17 @@
 use crate::{custom_properties::CustomPropertySetter, OctocrabGit};
 use anyhow::{Context, Result};
 use data_ingester_financial_business_partners::{fbp_results::FbpResult, validator::Validator};
-use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, Splunk, ToHecEvents};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, ToHecEvents};
 use data_ingester_supporting::keyvault::{GitHubApp, Secrets};
 use std::sync::Arc;
 use tracing::{error, info};
 
 /// Public entry point
-pub async fn github_octocrab_entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn github_octocrab_entrypoint(
+    secrets: Arc<Secrets>,
+    splunk: Arc<dyn HecSink>,
+) -> Result<()> {
     set_ssphp_run("github")?;
 
     info!("Starting GitHub collection");
@@ -39,7 +43,7 @@ pub async fn github_octocrab_entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splun
 /// for it.
 async fn github_app(
     github_app: &GitHubApp,
-    splunk: &Arc<Splunk>,
+    splunk: &Arc<dyn HecSink>,
     custom_property_validator: Option<Arc<Validator>>,
 ) -> Result<()> {
     let client = OctocrabGit::new_from_app(github_app).context("Build OctocrabGit")?;
@@ -87,7 +91,7 @@ async fn github_app(
 async fn github_collect_installation_org(
     github_client: OctocrabGit,
     org_name: String,
-    splunk: Arc<Splunk>,
+    splunk: Arc<dyn HecSink>,
     custom_property_validator: Option<Arc<Validator>>,
 ) -> Result<()> {
     github_client.wait_for_rate_limit().await?;
@@ -326,7 +330,7 @@ async fn github_collect_installation_org(
 
 pub async fn github_set_custom_properties_entrypoint(
     secrets: Arc<Secrets>,
-    splunk: Arc<Splunk>,
+    splunk: Arc<dyn HecSink>,
 ) -> Result<()> {
     set_ssphp_run("github")?;
     info!("Updating GitHub custom properties");
@@ -397,7 +401,7 @@ pub async fn github_set_custom_properties_entrypoint(
 async fn update_custom_properties(
     github_client: OctocrabGit,
     org_name: String,
-    splunk: Arc<Splunk>,
+    splunk: Arc<dyn HecSink>,
     fbp_results: Arc<FbpResult>,
 ) -> Result<()> {
     let portfolio_setter = CustomPropertySetter::from_fbp_portfolio(fbp_results.portfolios());
@@ -25,6 +25,7 @@ use custom_properties::{CustomProperties, CustomPropertySetter, SetOrgRepoCustom
 use data_ingester_financial_business_partners::validator::Validator;
 use data_ingester_sarif::{Sarif, SarifHecs};
 use data_ingester_supporting::keyvault::GitHubApp;
+use data_ingester_supporting::metrics::METRICS;
 use github_response::GithubNextLink;
 use graphql_client::GraphQLQuery;
 use graphql_client::Response;
@@ -190,6 +191,7 @@ impl OctocrabGit {
         let response = self.client._put(&url, Some(custom_property)).await?;
 
         let status = response.status().as_u16();
+        METRICS.http_status("github", status);
 
         let mut body = response
             .collect()
@@ -236,6 +238,7 @@ impl OctocrabGit {
         let response = self.client._patch(&url, Some(&setter)).await?;
 
         let status = response.status().as_u16();
+        METRICS.http_status("github", status);
 
         let mut body = response
             .collect()
@@ -652,6 +655,7 @@ impl OctocrabGit {
             .with_context(|| format!("Using Octocrab to get url: {}", uri))?;
 
         let status = response.status().as_u16();
+        METRICS.http_status("github", status);
         let mut body = response
             .collect()
             .await
@@ -700,6 +704,7 @@ impl OctocrabGit {
                 .context("Failed getting response 'link'")?;
 
             let status = response.status().as_u16();
+            METRICS.http_status("github", status);
 
             let mut body = response
                 .collect()
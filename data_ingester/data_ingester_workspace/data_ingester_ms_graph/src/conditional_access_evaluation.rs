@@ -0,0 +1,1191 @@
+//! What-if evaluation of Conditional Access policies.
+//!
+//! [ConditionalAccessEvaluator] finds the policies that apply to a
+//! simulated [SignInContext] and merges the grant and session controls
+//! they enforce, much like the What If tool in the Entra portal.
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::{serde_as, DefaultOnNull};
+use tracing::warn;
+
+use data_ingester_splunk::splunk::ToHecEvents;
+use data_ingester_supporting::cidr::cidr_contains;
+
+use crate::conditional_access_policies::{
+    ConditionalAccessPolicies, ConditionalAccessPolicy, ConditionalAccessPolicyConditionsUsers,
+};
+use crate::membership::AssignmentState;
+use crate::ms_graph::NamedLocations;
+use crate::users::{GroupOrRole, User, UsersMap};
+
+/// Candidate addresses for sign-ins from an untrusted location, from
+/// the TEST-NET documentation ranges. The first not in a trusted named
+/// location is used, see [ConditionalAccessEvaluator::untrusted_location]
+const UNTRUSTED_IP_ADDRESSES: [IpAddr; 3] = [
+    IpAddr::V4(Ipv4Addr::new(203, 0, 113, 10)),
+    IpAddr::V4(Ipv4Addr::new(198, 51, 100, 10)),
+    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10)),
+];
+
+/// Candidate countries for sign-ins from an untrusted location,
+/// uninhabited territories that are rarely part of a named location
+const UNTRUSTED_COUNTRIES: &[&str] = &["AQ", "BV", "HM", "TF", "GS"];
+
+/// Microsoft Azure Management, part of `MicrosoftAdminPortals`
+const AZURE_MANAGEMENT: &[&str] = &[
+    "797f4846-ba00-4fd7-ba43-dac1f8f63013",
+    "MicrosoftAdminPortals",
+];
+
+/// Office 365 Exchange Online, part of `Office365`
+const EXCHANGE_ONLINE: &[&str] = &["00000002-0000-0ff1-ce00-000000000000", "Office365"];
+
+/// A sign-in simulated for every user by
+/// [ConditionalAccessEvaluator::user_findings].
+///
+/// A finding is raised when the sign-in is neither blocked nor required
+/// to use MFA.
+struct WhatIfScenario {
+    finding: &'static str,
+    description: &'static str,
+    severity: &'static str,
+    /// Only simulate the sign-in for privileged users
+    privileged_only: bool,
+    applications: &'static [&'static str],
+    client_app_type: &'static str,
+    sign_in_risk: &'static str,
+}
+
+const SCENARIOS: &[WhatIfScenario] = &[
+    WhatIfScenario {
+        finding: "privileged_user_no_mfa_from_untrusted_location",
+        description: "Privileged user can sign in to the Azure admin portals from an untrusted location without MFA",
+        severity: "high",
+        privileged_only: true,
+        applications: AZURE_MANAGEMENT,
+        client_app_type: "browser",
+        sign_in_risk: "none",
+    },
+    WhatIfScenario {
+        finding: "user_no_mfa_from_untrusted_location",
+        description: "User can sign in to Office 365 from an untrusted location without MFA",
+        severity: "medium",
+        privileged_only: false,
+        applications: EXCHANGE_ONLINE,
+        client_app_type: "browser",
+        sign_in_risk: "none",
+    },
+    WhatIfScenario {
+        finding: "legacy_authentication_allowed",
+        description: "User can sign in to Exchange Online with legacy authentication",
+        severity: "medium",
+        privileged_only: false,
+        applications: EXCHANGE_ONLINE,
+        client_app_type: "exchangeActiveSync",
+        sign_in_risk: "none",
+    },
+    WhatIfScenario {
+        finding: "high_risk_sign_in_not_mitigated",
+        description: "High risk sign-in to Office 365 is neither blocked nor required to use MFA",
+        severity: "high",
+        privileged_only: false,
+        applications: EXCHANGE_ONLINE,
+        client_app_type: "browser",
+        sign_in_risk: "high",
+    },
+];
+
+/// The user of a simulated sign-in
+#[derive(Debug, Clone, Default)]
+pub struct SignInUser {
+    pub id: String,
+    /// `internalGuest`, `b2bCollaborationGuest`, `b2bCollaborationMember`,
+    /// `b2bDirectConnectUser`, `otherExternalUser` or `serviceProvider`,
+    /// `None` for members
+    pub guest_or_external_user_type: Option<String>,
+    /// Home tenant of a guest or external user
+    pub external_tenant_id: Option<String>,
+    /// Transitive group memberships
+    pub group_ids: HashSet<String>,
+    /// Directory role object ids and role template ids of active roles
+    pub role_ids: HashSet<String>,
+}
+
+impl From<&User<'_>> for SignInUser {
+    fn from(user: &User<'_>) -> Self {
+        let mut group_ids = HashSet::new();
+        let mut role_ids = HashSet::new();
+        for member_of in user.transitive_member_of.iter().flatten() {
+            match member_of {
+                GroupOrRole::Group(group) => {
+                    let _ = group_ids.insert(group.id.clone());
+                }
+                GroupOrRole::Role(role) => {
+                    let _ = role_ids.insert(role.id.clone());
+                    let _ = role_ids.insert(role.role_template_id.clone());
+                }
+            }
+        }
+        // Eligible roles are only targeted by policies once activated
+        for role in user.directory_roles.iter().flatten() {
+            if role.assignment_state == AssignmentState::Active {
+                let _ = role_ids.insert(role.role_definition_id.clone());
+            }
+        }
+        let is_guest = user.user_type.as_deref() == Some("Guest");
+        Self {
+            id: user.id.clone(),
+            guest_or_external_user_type: is_guest.then(|| "b2bCollaborationGuest".to_string()),
+            external_tenant_id: if is_guest { home_tenant(user) } else { None },
+            group_ids,
+            role_ids,
+        }
+    }
+}
+
+/// Home tenant of a guest.
+///
+/// The tenant id from the issuer of a federated identity, e.g.
+/// `https://sts.windows.net/{tenant id}/`, otherwise the home domain from
+/// a `alice_contoso.com#EXT#@fabrikam.onmicrosoft.com` user principal name
+fn home_tenant(user: &User<'_>) -> Option<String> {
+    user.identities
+        .iter()
+        .flatten()
+        .filter_map(|identity| identity.issuer.as_deref())
+        .find_map(|issuer| {
+            issuer
+                .split('/')
+                .find(|part| is_guid(part))
+                .map(|tenant_id| tenant_id.to_string())
+        })
+        .or_else(|| {
+            let upn = user.user_principal_name.as_deref()?;
+            let (external, _) = upn.split_once("#EXT#")?;
+            let (_, domain) = external.rsplit_once('_')?;
+            (!domain.is_empty()).then(|| domain.to_string())
+        })
+}
+
+fn is_guid(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    parts.iter().map(|part| part.len()).eq([8, 4, 4, 4, 12])
+        && parts
+            .iter()
+            .all(|part| part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// A simulated sign-in
+#[derive(Debug, Clone, Default)]
+pub struct SignInContext {
+    pub user: SignInUser,
+    /// Application id of the resource and the groups it belongs to,
+    /// e.g. `Office365`
+    pub applications: Vec<String>,
+    /// User action such as `urn:user:registersecurityinfo`, in place of
+    /// an application
+    pub user_action: Option<String>,
+    /// `browser`, `mobileAppsAndDesktopClients`, `exchangeActiveSync`
+    /// or `other`
+    pub client_app_type: String,
+    /// `android`, `iOS`, `windows`, `windowsPhone`, `macOS` or `linux`
+    pub platform: Option<String>,
+    pub ip_address: Option<IpAddr>,
+    /// Two letter country code, `None` when the country is unknown
+    pub country: Option<String>,
+    /// `none`, `low`, `medium` or `high`
+    pub sign_in_risk: String,
+    /// `none`, `low`, `medium` or `high`
+    pub user_risk: String,
+}
+
+/// Controls enforced by every applicable policy, merged.
+///
+/// A control is only set when every way of satisfying a policy
+/// requires it, so `mfa` is not set by a policy requiring MFA *or* a
+/// compliant device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EffectiveControls {
+    pub block: bool,
+    /// MFA or an authentication strength is required
+    pub mfa: bool,
+    pub compliant_device: bool,
+    pub domain_joined_device: bool,
+    pub approved_application: bool,
+    pub compliant_application: bool,
+    pub password_change: bool,
+    pub authentication_strengths: Vec<String>,
+    pub terms_of_use: Vec<String>,
+    /// Shortest sign-in frequency, `0` when reauthentication is
+    /// required every time
+    pub sign_in_frequency_hours: Option<u32>,
+    /// `never` wins over `always`
+    pub persistent_browser: Option<String>,
+    pub cloud_app_security: bool,
+    pub application_enforced_restrictions: bool,
+}
+
+impl EffectiveControls {
+    fn add_grant(&mut self, grant: &GrantControls) {
+        if grant
+            .built_in_controls
+            .iter()
+            .any(|control| control == "block")
+        {
+            self.block = true;
+            return;
+        }
+
+        let mut alternatives: Vec<&str> =
+            grant.built_in_controls.iter().map(String::as_str).collect();
+        if grant.authentication_strength.is_some() {
+            alternatives.push("authenticationStrength");
+        }
+        if !grant.terms_of_use.is_empty() {
+            alternatives.push("termsOfUse");
+        }
+        if alternatives.is_empty() {
+            return;
+        }
+
+        let all_required = grant.operator.as_deref() != Some("OR") || alternatives.len() == 1;
+        let required = |controls: &[&str]| {
+            if all_required {
+                alternatives
+                    .iter()
+                    .any(|alternative| controls.contains(alternative))
+            } else {
+                alternatives
+                    .iter()
+                    .all(|alternative| controls.contains(alternative))
+            }
+        };
+
+        self.mfa |= required(&["mfa", "authenticationStrength"]);
+        self.compliant_device |= required(&["compliantDevice"]);
+        self.domain_joined_device |= required(&["domainJoinedDevice"]);
+        self.approved_application |= required(&["approvedApplication"]);
+        self.compliant_application |= required(&["compliantApplication"]);
+        self.password_change |= required(&["passwordChange"]);
+        if required(&["authenticationStrength"]) {
+            if let Some(strength) = &grant.authentication_strength {
+                self.authentication_strengths.push(
+                    strength
+                        .display_name
+                        .clone()
+                        .or_else(|| strength.id.clone())
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        if required(&["termsOfUse"]) {
+            self.terms_of_use.extend(grant.terms_of_use.iter().cloned());
+        }
+    }
+
+    fn add_session(&mut self, session: &SessionControls) {
+        if let Some(hours) = session
+            .sign_in_frequency
+            .as_ref()
+            .and_then(SignInFrequency::hours)
+        {
+            self.sign_in_frequency_hours = Some(
+                self.sign_in_frequency_hours
+                    .map_or(hours, |current| current.min(hours)),
+            );
+        }
+        if let Some(persistent_browser) = &session.persistent_browser {
+            if persistent_browser.is_enabled.unwrap_or(false)
+                && self.persistent_browser.as_deref() != Some("never")
+            {
+                self.persistent_browser = persistent_browser.mode.clone();
+            }
+        }
+        self.cloud_app_security |= session.cloud_app_security.as_ref().is_some_and(is_enabled);
+        self.application_enforced_restrictions |= session
+            .application_enforced_restrictions
+            .as_ref()
+            .is_some_and(is_enabled);
+    }
+}
+
+/// A policy that applies to the sign-in
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApplicablePolicy {
+    pub id: String,
+    pub display_name: Option<String>,
+    /// `false` for report-only policies, whose controls are not merged
+    pub enforced: bool,
+    /// Conditions that cannot be simulated and are assumed to match
+    pub unevaluated_conditions: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WhatIfResult {
+    pub applicable_policies: Vec<ApplicablePolicy>,
+    pub controls: EffectiveControls,
+}
+
+/// Evaluates [SignInContext]s against the policies of a tenant
+#[derive(Debug, Default)]
+pub struct ConditionalAccessEvaluator {
+    policies: Vec<CompiledPolicy>,
+    named_locations: Vec<NamedLocation>,
+}
+
+impl ConditionalAccessEvaluator {
+    /// Policies or named locations that cannot be parsed are logged and
+    /// skipped
+    pub fn new(policies: &ConditionalAccessPolicies, named_locations: &NamedLocations) -> Self {
+        let policies = policies
+            .inner
+            .iter()
+            .filter_map(|policy| match CompiledPolicy::new(policy) {
+                Ok(policy) => Some(policy),
+                Err(err) => {
+                    warn!(name="MS Graph", operation="Conditional Access what-if", policy=policy.id.as_str(), error=?err);
+                    None
+                }
+            })
+            .collect();
+        let named_locations = named_locations
+            .inner
+            .iter()
+            .filter_map(|location| match parse::<NamedLocation>(location) {
+                Ok(location) => Some(location),
+                Err(err) => {
+                    warn!(name="MS Graph", operation="Conditional Access what-if", error=?err);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            policies,
+            named_locations,
+        }
+    }
+
+    /// An address and country in no trusted named location, preferring
+    /// a country in no named location at all so a policy blocking some
+    /// countries doesn't hide a finding. `None` if every candidate is
+    /// trusted
+    fn untrusted_location(&self) -> Option<(IpAddr, String)> {
+        let trusted: Vec<&NamedLocation> = self
+            .named_locations
+            .iter()
+            .filter(|location| location.is_trusted)
+            .collect();
+        let ip_address = UNTRUSTED_IP_ADDRESSES.into_iter().find(|ip_address| {
+            !trusted
+                .iter()
+                .any(|location| location.contains_ip_address(*ip_address))
+        })?;
+        let country = UNTRUSTED_COUNTRIES
+            .iter()
+            .find(|country| {
+                !self
+                    .named_locations
+                    .iter()
+                    .any(|location| location.lists_country(country))
+            })
+            .or_else(|| {
+                UNTRUSTED_COUNTRIES.iter().find(|country| {
+                    !trusted
+                        .iter()
+                        .any(|location| location.lists_country(country))
+                })
+            })?;
+        Some((ip_address, country.to_string()))
+    }
+
+    pub fn evaluate(&self, context: &SignInContext) -> WhatIfResult {
+        let locations: Vec<&NamedLocation> = self
+            .named_locations
+            .iter()
+            .filter(|location| location.matches(context))
+            .collect();
+
+        let mut result = WhatIfResult::default();
+        for policy in &self.policies {
+            let enforced = match policy.state.as_deref() {
+                Some("enabled") => true,
+                Some("enabledForReportingButNotEnforced") => false,
+                _ => continue,
+            };
+            if !policy.applies(context, &locations) {
+                continue;
+            }
+            if enforced {
+                if let Some(grant) = &policy.grant_controls {
+                    result.controls.add_grant(grant);
+                }
+                if let Some(session) = &policy.session_controls {
+                    result.controls.add_session(session);
+                }
+            }
+            result.applicable_policies.push(ApplicablePolicy {
+                id: policy.id.clone(),
+                display_name: policy.display_name.clone(),
+                enforced,
+                unevaluated_conditions: policy.unevaluated_conditions(),
+            });
+        }
+        result
+    }
+
+    /// Simulate the [SCENARIOS] for every enabled user
+    pub fn user_findings(&self, users: &UsersMap) -> ConditionalAccessFindings {
+        let mut findings = vec![];
+        let Some((ip_address, country)) = self.untrusted_location() else {
+            warn!(
+                name = "MS Graph",
+                operation = "Conditional Access what-if",
+                "Every candidate untrusted location is a trusted named location"
+            );
+            return ConditionalAccessFindings { inner: findings };
+        };
+        for user in users.inner.values() {
+            if user.account_enabled == Some(false) {
+                continue;
+            }
+            let is_privileged = user.is_privileged.unwrap_or(false);
+            let sign_in_user = SignInUser::from(user);
+            for scenario in SCENARIOS {
+                if scenario.privileged_only && !is_privileged {
+                    continue;
+                }
+                let result = self.evaluate(&SignInContext {
+                    user: sign_in_user.clone(),
+                    applications: scenario
+                        .applications
+                        .iter()
+                        .map(|application| application.to_string())
+                        .collect(),
+                    user_action: None,
+                    client_app_type: scenario.client_app_type.to_string(),
+                    platform: Some("windows".to_string()),
+                    ip_address: Some(ip_address),
+                    country: Some(country.clone()),
+                    sign_in_risk: scenario.sign_in_risk.to_string(),
+                    user_risk: "none".to_string(),
+                });
+                if result.controls.block || result.controls.mfa {
+                    continue;
+                }
+                findings.push(ConditionalAccessFinding {
+                    finding: scenario.finding,
+                    description: scenario.description,
+                    severity: scenario.severity,
+                    user_id: user.id.clone(),
+                    user_principal_name: user.user_principal_name.clone(),
+                    display_name: user.display_name.clone(),
+                    is_privileged,
+                    applicable_policies: result.applicable_policies,
+                    controls: result.controls,
+                });
+            }
+        }
+        ConditionalAccessFindings { inner: findings }
+    }
+}
+
+/// A simulated sign-in that is neither blocked nor required to use MFA
+#[derive(Debug, Clone, Serialize)]
+pub struct ConditionalAccessFinding {
+    pub finding: &'static str,
+    pub description: &'static str,
+    pub severity: &'static str,
+    pub user_id: String,
+    pub user_principal_name: Option<String>,
+    pub display_name: Option<String>,
+    pub is_privileged: bool,
+    pub applicable_policies: Vec<ApplicablePolicy>,
+    pub controls: EffectiveControls,
+}
+
+#[derive(Debug, Default)]
+pub struct ConditionalAccessFindings {
+    pub inner: Vec<ConditionalAccessFinding>,
+}
+
+impl ToHecEvents for &ConditionalAccessFindings {
+    type Item = ConditionalAccessFinding;
+    fn source(&self) -> &str {
+        "msgraph"
+    }
+
+    fn sourcetype(&self) -> &str {
+        "SSPHP.AAD.conditional_access_finding"
+    }
+    fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+        Box::new(self.inner.iter())
+    }
+    fn ssphp_run_key(&self) -> &str {
+        "azure_users"
+    }
+}
+
+/// A policy with its conditions and controls parsed once
+#[derive(Debug)]
+struct CompiledPolicy {
+    id: String,
+    display_name: Option<String>,
+    state: Option<String>,
+    users: ConditionalAccessPolicyConditionsUsers,
+    applications: ApplicationsCondition,
+    client_app_types: Vec<String>,
+    platforms: Option<PlatformsCondition>,
+    locations: Option<LocationsCondition>,
+    sign_in_risk_levels: Vec<String>,
+    user_risk_levels: Vec<String>,
+    has_device_filter: bool,
+    grant_controls: Option<GrantControls>,
+    session_controls: Option<SessionControls>,
+}
+
+impl CompiledPolicy {
+    fn new(policy: &ConditionalAccessPolicy) -> Result<Self> {
+        let conditions = &policy.conditions;
+        Ok(Self {
+            id: policy.id.clone(),
+            display_name: policy.display_name.clone(),
+            state: policy.state.clone(),
+            users: conditions.users.clone(),
+            applications: parse(&conditions.applications).context("applications")?,
+            client_app_types: parse(&conditions.client_app_types).context("clientAppTypes")?,
+            platforms: optional(&conditions.platforms).context("platforms")?,
+            locations: optional(&conditions.locations).context("locations")?,
+            sign_in_risk_levels: parse(&conditions.sign_in_risk_levels)
+                .context("signInRiskLevels")?,
+            user_risk_levels: parse(&conditions.user_risk_levels).context("userRiskLevels")?,
+            has_device_filter: conditions
+                .devices
+                .get("deviceFilter")
+                .is_some_and(|filter| !filter.is_null()),
+            grant_controls: policy
+                .grant_controls
+                .as_ref()
+                .filter(|value| !value.is_null())
+                .map(parse)
+                .transpose()
+                .context("grantControls")?,
+            session_controls: policy
+                .session_controls
+                .as_ref()
+                .filter(|value| !value.is_null())
+                .map(parse)
+                .transpose()
+                .context("sessionControls")?,
+        })
+    }
+
+    fn applies(&self, context: &SignInContext, locations: &[&NamedLocation]) -> bool {
+        self.users.applies_to(&context.user)
+            && self.applications.applies(context)
+            && listed_or_all(&self.client_app_types, &context.client_app_type)
+            && self
+                .platforms
+                .as_ref()
+                .is_none_or(|platforms| platforms.applies(context.platform.as_deref()))
+            && self
+                .locations
+                .as_ref()
+                .is_none_or(|conditions| conditions.applies(locations))
+            && listed_or_all(&self.sign_in_risk_levels, &context.sign_in_risk)
+            && listed_or_all(&self.user_risk_levels, &context.user_risk)
+    }
+
+    fn unevaluated_conditions(&self) -> Vec<&'static str> {
+        if self.has_device_filter {
+            vec!["devices"]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Parse a condition, treating `null` as the default
+fn parse<T: DeserializeOwned + Default>(value: &Value) -> Result<T> {
+    if value.is_null() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_value(value.clone())?)
+}
+
+/// Parse a condition that is `null` when not configured
+fn optional<T: DeserializeOwned>(value: &Value) -> Result<Option<T>> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(value.clone())?))
+}
+
+/// An empty list, or one containing `all`, matches every value
+fn listed_or_all(list: &[String], value: &str) -> bool {
+    list.is_empty()
+        || list
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case("all") || item.eq_ignore_ascii_case(value))
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccessapplications?view=graph-rest-1.0
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ApplicationsCondition {
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    include_applications: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    exclude_applications: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    include_user_actions: Vec<String>,
+}
+
+impl ApplicationsCondition {
+    fn applies(&self, context: &SignInContext) -> bool {
+        if let Some(user_action) = &context.user_action {
+            return self.include_user_actions.contains(user_action);
+        }
+        let listed = |list: &[String]| {
+            context
+                .applications
+                .iter()
+                .any(|application| list.contains(application))
+        };
+        (self.include_applications.iter().any(|app| app == "All")
+            || listed(&self.include_applications))
+            && !listed(&self.exclude_applications)
+    }
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccessplatforms?view=graph-rest-1.0
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PlatformsCondition {
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    include_platforms: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    exclude_platforms: Vec<String>,
+}
+
+impl PlatformsCondition {
+    /// An unknown platform is only included by `all`
+    fn applies(&self, platform: Option<&str>) -> bool {
+        let listed = |list: &[String]| {
+            platform
+                .is_some_and(|platform| list.iter().any(|item| item.eq_ignore_ascii_case(platform)))
+        };
+        (self
+            .include_platforms
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case("all"))
+            || listed(&self.include_platforms))
+            && !listed(&self.exclude_platforms)
+    }
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccesslocations?view=graph-rest-1.0
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct LocationsCondition {
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    include_locations: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    exclude_locations: Vec<String>,
+}
+
+impl LocationsCondition {
+    /// `locations` are the named locations the sign-in is in
+    fn applies(&self, locations: &[&NamedLocation]) -> bool {
+        let trusted = locations.iter().any(|location| location.is_trusted);
+        let listed = |list: &[String]| {
+            list.iter().any(|item| item == "All")
+                || (trusted && list.iter().any(|item| item == "AllTrusted"))
+                || locations.iter().any(|location| list.contains(&location.id))
+        };
+        listed(&self.include_locations) && !listed(&self.exclude_locations)
+    }
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccessgrantcontrols?view=graph-rest-1.0
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GrantControls {
+    /// `AND` or `OR`
+    operator: Option<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    built_in_controls: Vec<String>,
+    authentication_strength: Option<AuthenticationStrength>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    terms_of_use: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticationStrength {
+    id: Option<String>,
+    display_name: Option<String>,
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccesssessioncontrols?view=graph-rest-1.0
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct SessionControls {
+    sign_in_frequency: Option<SignInFrequency>,
+    persistent_browser: Option<PersistentBrowser>,
+    cloud_app_security: Option<Value>,
+    application_enforced_restrictions: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct SignInFrequency {
+    is_enabled: Option<bool>,
+    /// `hours` or `days`
+    r#type: Option<String>,
+    value: Option<u32>,
+    /// `timeBased` or `everyTime`
+    frequency_interval: Option<String>,
+}
+
+impl SignInFrequency {
+    fn hours(&self) -> Option<u32> {
+        if !self.is_enabled.unwrap_or(false) {
+            return None;
+        }
+        if self.frequency_interval.as_deref() == Some("everyTime") {
+            return Some(0);
+        }
+        let value = self.value?;
+        match self.r#type.as_deref() {
+            Some("days") => Some(value.saturating_mul(24)),
+            _ => Some(value),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PersistentBrowser {
+    is_enabled: Option<bool>,
+    /// `always` or `never`
+    mode: Option<String>,
+}
+
+fn is_enabled(value: &Value) -> bool {
+    value
+        .get("isEnabled")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/namedlocation?view=graph-rest-1.0
+// Both ipNamedLocation and countryNamedLocation parse into this
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct NamedLocation {
+    id: String,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    is_trusted: bool,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    ip_ranges: Vec<IpRange>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    countries_and_regions: Vec<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    include_unknown_countries_and_regions: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct IpRange {
+    cidr_address: String,
+}
+
+impl NamedLocation {
+    fn matches(&self, context: &SignInContext) -> bool {
+        if context
+            .ip_address
+            .is_some_and(|ip_address| self.contains_ip_address(ip_address))
+        {
+            return true;
+        }
+        match &context.country {
+            Some(country) => self.lists_country(country),
+            None => self.include_unknown_countries_and_regions,
+        }
+    }
+
+    fn contains_ip_address(&self, ip_address: IpAddr) -> bool {
+        self.ip_ranges
+            .iter()
+            .any(|range| cidr_contains(&range.cidr_address, ip_address))
+    }
+
+    fn lists_country(&self, country: &str) -> bool {
+        self.countries_and_regions
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(country))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        ConditionalAccessEvaluator, SignInContext, SignInUser, UNTRUSTED_COUNTRIES,
+        UNTRUSTED_IP_ADDRESSES,
+    };
+    use crate::conditional_access_policies::ConditionalAccessPolicies;
+    use crate::directory_roles::DirectoryRole;
+    use crate::ms_graph::NamedLocations;
+    use crate::users::{GroupOrRole, ObjectIdentity, User, UsersMap};
+    use anyhow::Result;
+    use serde_json::{json, Value};
+    use std::net::IpAddr;
+
+    const GLOBAL_ADMINISTRATOR: &str = "62e90394-69f5-4237-9190-012177145e10";
+
+    const UNTRUSTED_IP_ADDRESS: IpAddr = UNTRUSTED_IP_ADDRESSES[0];
+
+    const UNTRUSTED_COUNTRY: &str = UNTRUSTED_COUNTRIES[0];
+
+    fn policy(id: &str, conditions: Value, grant_controls: Value) -> Value {
+        let mut full_conditions = json!({
+            "applications": {"includeApplications": ["All"], "excludeApplications": [], "includeUserActions": []},
+            "clientApplications": null,
+            "clientAppTypes": ["all"],
+            "devices": null,
+            "locations": null,
+            "platforms": null,
+            "servicePrincipalRiskLevels": [],
+            "signInRiskLevels": [],
+            "userRiskLevels": [],
+            "users": {
+                "includeUsers": ["All"],
+                "excludeUsers": [],
+                "includeGroups": [],
+                "excludeGroups": [],
+                "includeRoles": [],
+                "excludeRoles": [],
+                "includeGuestsOrExternalUsers": null,
+                "excludeGuestsOrExternalUsers": null
+            }
+        });
+        if let (Some(full), Some(overrides)) =
+            (full_conditions.as_object_mut(), conditions.as_object())
+        {
+            for (key, value) in overrides {
+                let _ = full.insert(key.clone(), value.clone());
+            }
+        }
+        json!({
+            "id": id,
+            "displayName": id,
+            "state": "enabled",
+            "conditions": full_conditions,
+            "grantControls": grant_controls,
+            "sessionControls": null
+        })
+    }
+
+    fn evaluator(policies: Vec<Value>) -> Result<ConditionalAccessEvaluator> {
+        let policies: ConditionalAccessPolicies =
+            serde_json::from_value(json!({ "value": policies }))?;
+        let named_locations: NamedLocations = serde_json::from_value(json!({"value": [
+            {
+                "@odata.type": "#microsoft.graph.ipNamedLocation",
+                "id": "office",
+                "displayName": "Office",
+                "isTrusted": true,
+                "ipRanges": [{"@odata.type": "#microsoft.graph.iPv4CidrRange", "cidrAddress": "192.0.2.0/24"}]
+            },
+            {
+                "@odata.type": "#microsoft.graph.countryNamedLocation",
+                "id": "blocked_countries",
+                "displayName": "Blocked countries",
+                "countriesAndRegions": ["KP"],
+                "includeUnknownCountriesAndRegions": true
+            }
+        ]}))?;
+        Ok(ConditionalAccessEvaluator::new(&policies, &named_locations))
+    }
+
+    fn context(ip_address: &str) -> Result<SignInContext> {
+        Ok(SignInContext {
+            user: SignInUser {
+                id: "user1".into(),
+                ..Default::default()
+            },
+            applications: vec!["00000002-0000-0ff1-ce00-000000000000".into()],
+            client_app_type: "browser".into(),
+            platform: Some("windows".into()),
+            ip_address: Some(ip_address.parse()?),
+            country: Some(UNTRUSTED_COUNTRY.into()),
+            sign_in_risk: "none".into(),
+            user_risk: "none".into(),
+            ..Default::default()
+        })
+    }
+
+    fn mfa() -> Value {
+        json!({"operator": "OR", "builtInControls": ["mfa"]})
+    }
+
+    #[test]
+    fn test_trusted_location_excluded() -> Result<()> {
+        let evaluator = evaluator(vec![policy(
+            "mfa_outside_office",
+            json!({"locations": {"includeLocations": ["All"], "excludeLocations": ["AllTrusted"]}}),
+            mfa(),
+        )])?;
+
+        let office = evaluator.evaluate(&context("192.0.2.10")?);
+        assert!(office.applicable_policies.is_empty());
+        assert!(!office.controls.mfa);
+
+        let untrusted = evaluator.evaluate(&context(&UNTRUSTED_IP_ADDRESS.to_string())?);
+        assert_eq!(untrusted.applicable_policies.len(), 1);
+        assert!(untrusted.controls.mfa);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_country_location() -> Result<()> {
+        let evaluator = evaluator(vec![policy(
+            "block_countries",
+            json!({"locations": {"includeLocations": ["blocked_countries"], "excludeLocations": []}}),
+            json!({"operator": "OR", "builtInControls": ["block"]}),
+        )])?;
+        let mut context = context(&UNTRUSTED_IP_ADDRESS.to_string())?;
+        assert!(!evaluator.evaluate(&context).controls.block);
+        context.country = None;
+        assert!(evaluator.evaluate(&context).controls.block);
+        Ok(())
+    }
+
+    #[test]
+    fn test_or_controls_do_not_require_mfa() -> Result<()> {
+        let evaluator = evaluator(vec![policy(
+            "mfa_or_compliant",
+            json!({}),
+            json!({"operator": "OR", "builtInControls": ["mfa", "compliantDevice"]}),
+        )])?;
+        let result = evaluator.evaluate(&context(&UNTRUSTED_IP_ADDRESS.to_string())?);
+        assert!(!result.controls.mfa);
+        assert!(!result.controls.compliant_device);
+
+        let evaluator = evaluator_with_strength()?;
+        let result = evaluator.evaluate(&context(&UNTRUSTED_IP_ADDRESS.to_string())?);
+        assert!(result.controls.mfa);
+        assert!(result.controls.compliant_device);
+        assert_eq!(
+            result.controls.authentication_strengths,
+            vec!["Phishing-resistant MFA"]
+        );
+        Ok(())
+    }
+
+    fn evaluator_with_strength() -> Result<ConditionalAccessEvaluator> {
+        evaluator(vec![policy(
+            "strength_and_compliant",
+            json!({}),
+            json!({
+                "operator": "AND",
+                "builtInControls": ["compliantDevice"],
+                "authenticationStrength": {"id": "00000000-0000-0000-0000-000000000004", "displayName": "Phishing-resistant MFA"}
+            }),
+        )])
+    }
+
+    #[test]
+    fn test_report_only_not_enforced() -> Result<()> {
+        let mut report_only = policy("report_only", json!({}), mfa());
+        report_only["state"] = json!("enabledForReportingButNotEnforced");
+        let mut disabled = policy("disabled", json!({}), mfa());
+        disabled["state"] = json!("disabled");
+        let evaluator = evaluator(vec![report_only, disabled])?;
+
+        let result = evaluator.evaluate(&context(&UNTRUSTED_IP_ADDRESS.to_string())?);
+        assert_eq!(result.applicable_policies.len(), 1);
+        assert!(!result.applicable_policies[0].enforced);
+        assert!(!result.controls.mfa);
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_app_types_and_applications() -> Result<()> {
+        let evaluator = evaluator(vec![
+            policy(
+                "block_legacy",
+                json!({"clientAppTypes": ["exchangeActiveSync", "other"]}),
+                json!({"operator": "OR", "builtInControls": ["block"]}),
+            ),
+            policy(
+                "admin_portals_mfa",
+                json!({"applications": {"includeApplications": ["MicrosoftAdminPortals"]}}),
+                mfa(),
+            ),
+        ])?;
+
+        let mut context = context(&UNTRUSTED_IP_ADDRESS.to_string())?;
+        let result = evaluator.evaluate(&context);
+        assert!(result.applicable_policies.is_empty());
+
+        context.client_app_type = "exchangeActiveSync".into();
+        assert!(evaluator.evaluate(&context).controls.block);
+
+        context.client_app_type = "browser".into();
+        context.applications = vec!["MicrosoftAdminPortals".into()];
+        assert!(evaluator.evaluate(&context).controls.mfa);
+        Ok(())
+    }
+
+    #[test]
+    fn test_guests() -> Result<()> {
+        let evaluator = evaluator(vec![policy(
+            "guests_mfa",
+            json!({"users": {
+                "includeUsers": [],
+                "excludeUsers": [],
+                "includeGroups": [],
+                "excludeGroups": [],
+                "includeRoles": [],
+                "excludeRoles": [],
+                "includeGuestsOrExternalUsers": {
+                    "guestOrExternalUserTypes": "internalGuest,b2bCollaborationGuest",
+                    "externalTenants": {"membershipKind": "all"}
+                },
+                "excludeGuestsOrExternalUsers": null
+            }}),
+            mfa(),
+        )])?;
+        let mut context = context(&UNTRUSTED_IP_ADDRESS.to_string())?;
+        assert!(!evaluator.evaluate(&context).controls.mfa);
+        context.user.guest_or_external_user_type = Some("b2bCollaborationGuest".into());
+        assert!(evaluator.evaluate(&context).controls.mfa);
+        Ok(())
+    }
+
+    #[test]
+    fn test_guest_home_tenant() {
+        let mut guest = User::new("guest".to_owned(), "guest".to_owned());
+        guest.user_type = Some("Guest".into());
+        guest.user_principal_name = Some("alice_contoso.com#EXT#@fabrikam.onmicrosoft.com".into());
+        assert_eq!(
+            SignInUser::from(&guest).external_tenant_id.as_deref(),
+            Some("contoso.com")
+        );
+
+        guest.identities = Some(vec![ObjectIdentity {
+            sign_in_type: Some("federated".into()),
+            issuer: Some("https://sts.windows.net/72f988bf-86f1-41af-91ab-2d7cd011db47/".into()),
+            issuer_assigned_id: None,
+        }]);
+        assert_eq!(
+            SignInUser::from(&guest).external_tenant_id.as_deref(),
+            Some("72f988bf-86f1-41af-91ab-2d7cd011db47")
+        );
+
+        guest.user_type = Some("Member".into());
+        assert_eq!(SignInUser::from(&guest).external_tenant_id, None);
+    }
+
+    #[test]
+    fn test_privileged_user_findings() -> Result<()> {
+        let evaluator = evaluator(vec![policy(
+            "admins_mfa_outside_office",
+            json!({
+                "users": {
+                    "includeUsers": [],
+                    "excludeUsers": [],
+                    "includeGroups": [],
+                    "excludeGroups": [],
+                    "includeRoles": [GLOBAL_ADMINISTRATOR],
+                    "excludeRoles": [],
+                    "includeGuestsOrExternalUsers": null,
+                    "excludeGuestsOrExternalUsers": null
+                },
+                "locations": {"includeLocations": ["All"], "excludeLocations": ["AllTrusted"]}
+            }),
+            mfa(),
+        )])?;
+
+        let mut admin = User::new("admin".to_owned(), "admin".to_owned());
+        admin.is_privileged = Some(true);
+        admin.transitive_member_of = Some(vec![GroupOrRole::Role(DirectoryRole {
+            id: "role_object_id".to_owned(),
+            display_name: Some("Global Administrator".to_owned()),
+            role_template_id: GLOBAL_ADMINISTRATOR.to_owned(),
+            members: None,
+            is_privileged: Some(true),
+        })]);
+        let mut users = UsersMap::default();
+        let _ = users.inner.insert(admin.id.clone(), admin);
+
+        assert!(evaluator.user_findings(&users).inner.is_empty());
+
+        let mut user = User::new("user".to_owned(), "user".to_owned());
+        user.is_privileged = Some(true);
+        let mut users = UsersMap::default();
+        let _ = users.inner.insert(user.id.clone(), user);
+        let findings = evaluator.user_findings(&users);
+        assert!(findings
+            .inner
+            .iter()
+            .any(|finding| finding.finding == "privileged_user_no_mfa_from_untrusted_location"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_location_avoids_named_locations() -> Result<()> {
+        let named_locations: NamedLocations = serde_json::from_value(json!({"value": [
+            {
+                "@odata.type": "#microsoft.graph.ipNamedLocation",
+                "id": "office",
+                "isTrusted": true,
+                "ipRanges": [{"cidrAddress": "203.0.113.0/24"}]
+            },
+            {
+                "@odata.type": "#microsoft.graph.ipNamedLocation",
+                "id": "partner",
+                "isTrusted": false,
+                "ipRanges": [{"cidrAddress": "198.51.100.0/24"}]
+            },
+            {
+                "@odata.type": "#microsoft.graph.countryNamedLocation",
+                "id": "blocked_countries",
+                "countriesAndRegions": ["AQ"]
+            }
+        ]}))?;
+        let evaluator = ConditionalAccessEvaluator::new(
+            &serde_json::from_value(json!({"value": []}))?,
+            &named_locations,
+        );
+        assert_eq!(
+            evaluator.untrusted_location(),
+            Some(("198.51.100.10".parse::<IpAddr>()?, "BV".to_string()))
+        );
+        Ok(())
+    }
+}
@@ -2,21 +2,23 @@ use std::collections::HashSet;
 
 use data_ingester_splunk::splunk::ToHecEvents;
 
+use crate::conditional_access_evaluation::SignInUser;
 use crate::users::User;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
 use serde_with::skip_serializing_none;
+use serde_with::{serde_as, DefaultOnNull};
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ConditionalAccessPolicy {
     pub(crate) id: String,
-    display_name: Option<String>,
-    state: Option<String>,
-    conditions: ConditionalAccessPolicyConditions,
-    grant_controls: Option<serde_json::Value>,
-    session_controls: Option<serde_json::Value>,
+    pub(crate) display_name: Option<String>,
+    pub(crate) state: Option<String>,
+    pub(crate) conditions: ConditionalAccessPolicyConditions,
+    pub(crate) grant_controls: Option<serde_json::Value>,
+    pub(crate) session_controls: Option<serde_json::Value>,
 }
 
 impl ConditionalAccessPolicy {
@@ -106,20 +108,20 @@ impl ConditionalAccessPolicy {
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ConditionalAccessPolicyConditions {
-    applications: Value,
+    pub(crate) applications: Value,
     client_applications: Value,
-    client_app_types: Value,
-    devices: Value,
-    locations: Value,
-    platforms: Value,
+    pub(crate) client_app_types: Value,
+    pub(crate) devices: Value,
+    pub(crate) locations: Value,
+    pub(crate) platforms: Value,
     service_principal_risk_levels: Value,
-    sign_in_risk_levels: Value,
-    user_risk_levels: Value,
-    users: ConditionalAccessPolicyConditionsUsers,
+    pub(crate) sign_in_risk_levels: Value,
+    pub(crate) user_risk_levels: Value,
+    pub(crate) users: ConditionalAccessPolicyConditionsUsers,
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ConditionalAccessPolicyConditionsUsers {
     exclude_groups: HashSet<String>,
@@ -132,6 +134,86 @@ pub struct ConditionalAccessPolicyConditionsUsers {
     include_users: HashSet<String>,
 }
 
+impl ConditionalAccessPolicyConditionsUsers {
+    /// Excludes are checked first and win over includes
+    pub(crate) fn applies_to(&self, user: &SignInUser) -> bool {
+        let listed = |list: &HashSet<String>, ids: &HashSet<String>| {
+            list.contains("All") || ids.iter().any(|id| list.contains(id))
+        };
+        let users_listed = |list: &HashSet<String>| {
+            list.contains("All")
+                || list.contains(&user.id)
+                || (list.contains("GuestsOrExternalUsers")
+                    && user.guest_or_external_user_type.is_some())
+        };
+        let guests_listed = |guests: &Option<Value>| {
+            GuestsOrExternalUsers::from_value(guests).is_some_and(|guests| guests.matches(user))
+        };
+
+        if users_listed(&self.exclude_users)
+            || listed(&self.exclude_groups, &user.group_ids)
+            || listed(&self.exclude_roles, &user.role_ids)
+            || guests_listed(&self.exclude_guests_or_external_users)
+        {
+            return false;
+        }
+
+        users_listed(&self.include_users)
+            || listed(&self.include_groups, &user.group_ids)
+            || listed(&self.include_roles, &user.role_ids)
+            || guests_listed(&self.include_guests_or_external_users)
+    }
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/conditionalaccessguestsorexternalusers?view=graph-rest-1.0
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GuestsOrExternalUsers {
+    /// Comma separated, e.g. `internalGuest,b2bCollaborationGuest`
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    guest_or_external_user_types: String,
+    external_tenants: Option<ExternalTenants>,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ExternalTenants {
+    /// `all` or `enumerated`
+    membership_kind: Option<String>,
+    #[serde_as(deserialize_as = "DefaultOnNull")]
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+impl GuestsOrExternalUsers {
+    fn from_value(value: &Option<Value>) -> Option<Self> {
+        serde_json::from_value(value.as_ref()?.clone()).ok()
+    }
+
+    fn matches(&self, user: &SignInUser) -> bool {
+        let Some(user_type) = &user.guest_or_external_user_type else {
+            return false;
+        };
+        if !self
+            .guest_or_external_user_types
+            .split(',')
+            .any(|guest_type| guest_type.trim() == user_type)
+        {
+            return false;
+        }
+        match &self.external_tenants {
+            Some(tenants) if tenants.membership_kind.as_deref() == Some("enumerated") => user
+                .external_tenant_id
+                .as_ref()
+                .is_some_and(|tenant_id| tenants.members.contains(tenant_id)),
+            _ => true,
+        }
+    }
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -1,7 +1,9 @@
 pub mod admin_request_consent_policy;
+pub mod conditional_access_evaluation;
 pub mod conditional_access_policies;
 pub mod directory_roles;
 pub mod groups;
+pub mod membership;
 pub mod ms_graph;
 pub mod msgraph_data;
 pub mod roles;
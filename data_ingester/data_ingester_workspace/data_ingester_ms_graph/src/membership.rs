@@ -0,0 +1,364 @@
+//! Directory role membership, resolved through nested groups.
+//!
+//! [MembershipGraph] holds the active and PIM eligible directory role
+//! assignments of a tenant and the members of every group those roles
+//! are assigned to, so each user's roles can be traced back through
+//! the groups that grant them.
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::roles::RoleDefinitions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentState {
+    Active,
+    /// PIM eligible, the role must be activated before use
+    Eligible,
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/directoryobject?view=graph-rest-1.0
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryObject {
+    #[serde(rename = "@odata.type")]
+    odata_type: Option<String>,
+    id: String,
+    display_name: Option<String>,
+    is_assignable_to_role: Option<bool>,
+}
+
+impl DirectoryObject {
+    fn is_group(&self) -> bool {
+        self.odata_type.as_deref() == Some("#microsoft.graph.group")
+    }
+}
+
+// https://learn.microsoft.com/en-us/graph/api/resources/unifiedroleassignmentscheduleinstance?view=graph-rest-1.0
+// https://learn.microsoft.com/en-us/graph/api/resources/unifiedroleeligibilityscheduleinstance?view=graph-rest-1.0
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleScheduleInstance {
+    principal_id: String,
+    role_definition_id: String,
+    directory_scope_id: Option<String>,
+    end_date_time: Option<String>,
+    /// Present when requested with `$expand=principal`
+    principal: Option<DirectoryObject>,
+}
+
+#[derive(Debug, Clone)]
+struct RoleAssignment {
+    state: AssignmentState,
+    principal_id: String,
+    role_definition_id: String,
+    directory_scope_id: Option<String>,
+    end_date_time: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct GroupDetails {
+    display_name: Option<String>,
+    is_assignable_to_role: Option<bool>,
+}
+
+/// A group on the path from a user to a role
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipPathEntry {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub is_assignable_to_role: Option<bool>,
+}
+
+/// A directory role held by a user, directly or through groups
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDirectoryRole {
+    pub role_definition_id: String,
+    pub display_name: Option<String>,
+    pub is_privileged: bool,
+    pub assignment_state: AssignmentState,
+    /// `/` for the whole tenant
+    pub directory_scope_id: Option<String>,
+    pub end_date_time: Option<String>,
+    /// Groups granting the role, from the group the user is a direct
+    /// member of to the group the role is assigned to. Empty when the
+    /// role is assigned to the user.
+    pub path: Vec<MembershipPathEntry>,
+}
+
+/// Role assignments and the group memberships that lead to them
+#[derive(Debug, Default)]
+pub struct MembershipGraph {
+    assignments: Vec<RoleAssignment>,
+    groups: HashMap<String, GroupDetails>,
+    /// Groups each user or group is a direct member of, for groups whose
+    /// members have been added
+    member_of: HashMap<String, HashSet<String>>,
+    resolved_groups: HashSet<String>,
+    /// Some assignments or group members could not be read
+    incomplete: bool,
+}
+
+impl MembershipGraph {
+    /// A graph for a tenant whose role assignments could not be read
+    pub fn incomplete() -> Self {
+        Self {
+            incomplete: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_assignment(&mut self, state: AssignmentState, instance: RoleScheduleInstance) {
+        if let Some(principal) = instance.principal.filter(DirectoryObject::is_group) {
+            self.add_group(&principal);
+        }
+        self.assignments.push(RoleAssignment {
+            state,
+            principal_id: instance.principal_id,
+            role_definition_id: instance.role_definition_id,
+            directory_scope_id: instance.directory_scope_id,
+            end_date_time: instance.end_date_time,
+        });
+    }
+
+    /// Groups with roles assigned, or nested in such a group, whose
+    /// members have not been added yet
+    pub fn unresolved_groups(&self) -> Vec<String> {
+        let mut unresolved: Vec<String> = self
+            .groups
+            .keys()
+            .filter(|group_id| !self.resolved_groups.contains(*group_id))
+            .cloned()
+            .collect();
+        unresolved.sort();
+        unresolved
+    }
+
+    /// Add the direct members of `group_id`
+    pub fn add_group_members(&mut self, group_id: &str, members: Vec<DirectoryObject>) {
+        let _ = self.resolved_groups.insert(group_id.to_string());
+        let _ = self.groups.entry(group_id.to_string()).or_default();
+        for member in members {
+            if member.is_group() {
+                self.add_group(&member);
+            }
+            let _ = self
+                .member_of
+                .entry(member.id)
+                .or_default()
+                .insert(group_id.to_string());
+        }
+    }
+
+    /// Record that the members of `group_id` could not be read.
+    ///
+    /// Any user could be in the group, so the graph is no longer complete
+    pub fn add_failed_group(&mut self, group_id: &str) {
+        let _ = self.resolved_groups.insert(group_id.to_string());
+        self.incomplete = true;
+    }
+
+    /// Have every assignment and the members of every group with a role
+    /// been read
+    pub fn is_complete(&self) -> bool {
+        !self.incomplete
+    }
+
+    fn add_group(&mut self, group: &DirectoryObject) {
+        let details = self.groups.entry(group.id.clone()).or_default();
+        if group.display_name.is_some() {
+            details.display_name.clone_from(&group.display_name);
+        }
+        if group.is_assignable_to_role.is_some() {
+            details.is_assignable_to_role = group.is_assignable_to_role;
+        }
+    }
+
+    /// Every group `member_id` is in, directly or through nested groups,
+    /// with the shortest path to it
+    pub fn transitive_groups(&self, member_id: &str) -> HashMap<String, Vec<String>> {
+        let mut paths: HashMap<String, Vec<String>> = HashMap::new();
+        let mut queue = VecDeque::from([(member_id.to_string(), vec![])]);
+        while let Some((id, path)) = queue.pop_front() {
+            for group_id in self.member_of.get(&id).into_iter().flatten() {
+                if paths.contains_key(group_id) || group_id == member_id {
+                    continue;
+                }
+                let mut group_path = path.clone();
+                group_path.push(group_id.clone());
+                let _ = paths.insert(group_id.clone(), group_path.clone());
+                queue.push_back((group_id.clone(), group_path));
+            }
+        }
+        paths
+    }
+
+    /// The roles `user_id` holds directly or through groups
+    pub fn user_directory_roles(
+        &self,
+        user_id: &str,
+        role_definitions: &RoleDefinitions,
+    ) -> Vec<UserDirectoryRole> {
+        let groups = self.transitive_groups(user_id);
+        self.assignments
+            .iter()
+            .filter_map(|assignment| {
+                let path = if assignment.principal_id == user_id {
+                    vec![]
+                } else {
+                    groups.get(&assignment.principal_id)?.clone()
+                };
+                let role_definition = role_definitions.value.get(&assignment.role_definition_id);
+                Some(UserDirectoryRole {
+                    role_definition_id: assignment.role_definition_id.clone(),
+                    display_name: role_definition.and_then(|role| role.display_name.clone()),
+                    is_privileged: role_definition
+                        .and_then(|role| role.is_privileged)
+                        .unwrap_or(false),
+                    assignment_state: assignment.state,
+                    directory_scope_id: assignment.directory_scope_id.clone(),
+                    end_date_time: assignment.end_date_time.clone(),
+                    path: path
+                        .into_iter()
+                        .map(|group_id| {
+                            let details = self.groups.get(&group_id).cloned().unwrap_or_default();
+                            MembershipPathEntry {
+                                id: group_id,
+                                display_name: details.display_name,
+                                is_assignable_to_role: details.is_assignable_to_role,
+                            }
+                        })
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AssignmentState, DirectoryObject, MembershipGraph, RoleScheduleInstance};
+    use crate::roles::RoleDefinitions;
+    use anyhow::Result;
+    use serde_json::json;
+
+    const GLOBAL_ADMINISTRATOR: &str = "62e90394-69f5-4237-9190-012177145e10";
+    const GLOBAL_READER: &str = "f2ef992c-3afb-46b9-b7cf-a126ee74c451";
+
+    fn role_definitions() -> Result<RoleDefinitions> {
+        Ok(serde_json::from_value(json!({"value": [
+            {"id": GLOBAL_ADMINISTRATOR, "displayName": "Global Administrator", "isPrivileged": true},
+            {"id": GLOBAL_READER, "displayName": "Global Reader", "isPrivileged": false}
+        ]}))?)
+    }
+
+    fn instance(principal_id: &str, role: &str, group: bool) -> Result<RoleScheduleInstance> {
+        let principal = if group {
+            json!({"@odata.type": "#microsoft.graph.group", "id": principal_id, "displayName": "Tier 0", "isAssignableToRole": true})
+        } else {
+            json!({"@odata.type": "#microsoft.graph.user", "id": principal_id})
+        };
+        Ok(serde_json::from_value(json!({
+            "id": format!("{}_{}", principal_id, role),
+            "principalId": principal_id,
+            "roleDefinitionId": role,
+            "directoryScopeId": "/",
+            "principal": principal
+        }))?)
+    }
+
+    fn member(id: &str, group: bool) -> Result<DirectoryObject> {
+        let odata_type = if group {
+            "#microsoft.graph.group"
+        } else {
+            "#microsoft.graph.user"
+        };
+        Ok(serde_json::from_value(
+            json!({"@odata.type": odata_type, "id": id, "displayName": id}),
+        )?)
+    }
+
+    /// user1 -> nested -> tier0 (eligible Global Administrator)
+    /// user1 (active Global Reader)
+    fn graph() -> Result<MembershipGraph> {
+        let mut graph = MembershipGraph::default();
+        graph.add_assignment(
+            AssignmentState::Eligible,
+            instance("tier0", GLOBAL_ADMINISTRATOR, true)?,
+        );
+        graph.add_assignment(
+            AssignmentState::Active,
+            instance("user1", GLOBAL_READER, false)?,
+        );
+        assert_eq!(graph.unresolved_groups(), vec!["tier0"]);
+
+        graph.add_group_members("tier0", vec![member("nested", true)?]);
+        assert_eq!(graph.unresolved_groups(), vec!["nested"]);
+        graph.add_group_members(
+            "nested",
+            vec![member("user1", false)?, member("tier0", true)?],
+        );
+        assert!(graph.unresolved_groups().is_empty());
+        Ok(graph)
+    }
+
+    #[test]
+    fn test_transitive_groups_with_cycle() -> Result<()> {
+        let graph = graph()?;
+        let groups = graph.transitive_groups("user1");
+        assert_eq!(groups.get("nested"), Some(&vec!["nested".to_string()]));
+        assert_eq!(
+            groups.get("tier0"),
+            Some(&vec!["nested".to_string(), "tier0".to_string()])
+        );
+        assert_eq!(groups.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_directory_roles() -> Result<()> {
+        let graph = graph()?;
+        let mut roles = graph.user_directory_roles("user1", &role_definitions()?);
+        roles.sort_by(|a, b| a.role_definition_id.cmp(&b.role_definition_id));
+        assert_eq!(roles.len(), 2);
+
+        let admin = &roles[0];
+        assert_eq!(admin.role_definition_id, GLOBAL_ADMINISTRATOR);
+        assert_eq!(admin.assignment_state, AssignmentState::Eligible);
+        assert!(admin.is_privileged);
+        let path: Vec<&str> = admin.path.iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(path, vec!["nested", "tier0"]);
+        assert_eq!(admin.path[1].display_name.as_deref(), Some("Tier 0"));
+        assert_eq!(admin.path[1].is_assignable_to_role, Some(true));
+
+        let reader = &roles[1];
+        assert_eq!(reader.assignment_state, AssignmentState::Active);
+        assert!(!reader.is_privileged);
+        assert!(reader.path.is_empty());
+
+        assert!(graph
+            .user_directory_roles("user2", &role_definitions()?)
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_group_leaves_graph_incomplete() -> Result<()> {
+        let mut graph = MembershipGraph::default();
+        graph.add_assignment(
+            AssignmentState::Active,
+            instance("tier0", GLOBAL_ADMINISTRATOR, true)?,
+        );
+        assert!(graph.is_complete());
+        graph.add_failed_group("tier0");
+        assert!(graph.unresolved_groups().is_empty());
+        assert!(!graph.is_complete());
+        Ok(())
+    }
+}
@@ -2,21 +2,24 @@ use crate::admin_request_consent_policy::AdminRequestConsentPolicy;
 
 use crate::conditional_access_policies::ConditionalAccessPolicies;
 use crate::groups::Groups;
+use crate::membership::{AssignmentState, DirectoryObject, MembershipGraph};
 use data_ingester_supporting::dns::resolve_txt_record;
 use data_ingester_supporting::keyvault::Secrets;
+use data_ingester_supporting::metrics::METRICS;
 use graph_oauth::ClientSecretCredential;
 use graph_rs_sdk::GraphClient;
 use graph_rs_sdk::GraphClientConfiguration;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::msgraph_data::load_m365_toml;
 use crate::roles::RoleDefinitions;
 use crate::users::Users;
 use crate::users::UsersMap;
 use anyhow::{Context, Result};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::set_ssphp_run;
 use data_ingester_splunk::splunk::try_collect_send;
 use data_ingester_splunk::splunk::ToHecEvents;
-use data_ingester_splunk::splunk::{set_ssphp_run, Splunk};
 use futures::StreamExt;
 use graph_http::api_impl::RequestComponents;
 use graph_http::api_impl::RequestHandler;
@@ -108,6 +111,7 @@ impl MsGraph {
         let mut collection = Vec::default();
         while let Some(result) = stream.next().await {
             let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
             let body = response.into_body()?;
 
             match body {
@@ -142,6 +146,61 @@ impl MsGraph {
         Ok(RoleEligibilityScheduleInstance { inner: result })
     }
 
+    /// Active and PIM eligible directory role assignments, with the
+    /// members of every group they are assigned to, following nested
+    /// groups.
+    /// MSGraph Permission: RoleManagement.Read.Directory, GroupMember.Read.All
+    pub async fn get_role_membership_graph(&self) -> Result<MembershipGraph> {
+        let mut graph = MembershipGraph::default();
+        for (state, url) in [
+            (
+                AssignmentState::Active,
+                "/roleManagement/directory/roleAssignmentScheduleInstances?$expand=principal",
+            ),
+            (
+                AssignmentState::Eligible,
+                "/roleManagement/directory/roleEligibilityScheduleInstances?$expand=principal",
+            ),
+        ] {
+            for instance in self.get_url(url).await? {
+                graph.add_assignment(state, serde_json::from_value(instance)?);
+            }
+        }
+
+        loop {
+            let unresolved = graph.unresolved_groups();
+            if unresolved.is_empty() {
+                break;
+            }
+            for group_id in unresolved {
+                // A group that cannot be read, or has a member that
+                // cannot be parsed, marks the graph incomplete so the
+                // rest of the graph is still resolved
+                let members = self
+                    .get_url(&format!(
+                        "/groups/{}/members?$select=id,displayName,isAssignableToRole",
+                        group_id
+                    ))
+                    .await
+                    .and_then(|members| {
+                        members
+                            .into_iter()
+                            .map(serde_json::from_value)
+                            .collect::<Result<Vec<DirectoryObject>, _>>()
+                            .context("Parsing group members")
+                    });
+                match members {
+                    Ok(members) => graph.add_group_members(&group_id, members),
+                    Err(err) => {
+                        warn!(name="MS Graph", operation="Get group members", group_id=group_id, error=?err);
+                        graph.add_failed_group(&group_id);
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+
     /// M365 V2 1.1.17
     pub async fn list_legacy_policies(&self) -> Result<LegacyPolicies> {
         let result = self.get_url("/legacy/policies").await?;
@@ -168,6 +227,7 @@ impl MsGraph {
         let mut caps = ConditionalAccessPolicies::new();
         while let Some(result) = stream.next().await {
             let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
 
             let body = response.into_body();
 
@@ -189,6 +249,7 @@ impl MsGraph {
         let mut collection = NamedLocations::default();
         while let Some(result) = stream.next().await {
             let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
 
             let body = response.into_body();
 
@@ -208,6 +269,7 @@ impl MsGraph {
         let mut roles = RoleDefinitions::new();
         while let Some(result) = stream.next().await {
             let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
 
             let body = response.into_body()?;
 
@@ -227,7 +289,9 @@ impl MsGraph {
         let mut groups = Groups::default();
 
         while let Some(result) = stream.next().await {
-            let body = result?.into_body()?;
+            let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
+            let body = response.into_body()?;
             groups.inner.extend(body.inner);
         }
 
@@ -246,6 +310,7 @@ impl MsGraph {
                 "displayName",
                 "givenName",
                 "id",
+                "identities",
                 "mail",
                 "onPremisesSamAccountName",
                 "onPremisesSyncEnabled",
@@ -266,6 +331,7 @@ impl MsGraph {
 
         while let Some(result) = stream.next().await {
             let response = result?;
+            METRICS.http_status("ms_graph", response.status().as_u16());
 
             let mut users = response.into_body()?;
 
@@ -302,6 +368,7 @@ impl MsGraph {
             .get_admin_consent_request_policy()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json::<AdminRequestConsentPolicy>().await?;
         Ok(body)
     }
@@ -313,12 +380,14 @@ impl MsGraph {
             .get_authentication_methods_policy()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
 
     pub async fn get_domains(&self) -> Result<Domains> {
         let response = self.client.domains().list_domain().send().await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let mut body: Domains = response.json().await?;
         for domain in body.inner.iter_mut() {
             if let Ok(txt) = resolve_txt_record(&domain.id).await {
@@ -340,6 +409,7 @@ impl MsGraph {
             .get_authorization_policy()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
@@ -352,6 +422,7 @@ impl MsGraph {
             .list_permission_grant_policies()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
@@ -367,6 +438,7 @@ impl MsGraph {
             .get_identity_security_defaults_enforcement_policy()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
@@ -379,6 +451,7 @@ impl MsGraph {
             .list_token_lifetime_policies()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
@@ -394,6 +467,7 @@ impl MsGraph {
             .list_definitions()
             .send()
             .await?;
+        METRICS.http_status("ms_graph", response.status().as_u16());
         let body = response.json().await?;
         Ok(body)
     }
@@ -455,7 +529,7 @@ impl ToHecEvents for &DeviceRegistrationPolicy {
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NamedLocations {
     #[serde(rename = "value")]
-    inner: Vec<serde_json::Value>,
+    pub(crate) inner: Vec<serde_json::Value>,
 }
 
 impl ToHecEvents for &NamedLocations {
@@ -778,7 +852,7 @@ pub struct Group {
     // pub service_provisioning_errors: Vec<Value>,
 }
 
-pub async fn m365(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn m365(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     let ssphp_run_key = "m365";
     set_ssphp_run(ssphp_run_key)?;
 
@@ -3,7 +3,8 @@ use std::sync::Arc;
 
 use crate::ms_graph::MsGraph;
 use anyhow::Result;
-use data_ingester_splunk::splunk::{to_hec_events, Splunk};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::to_hec_events;
 use serde::Deserialize;
 use tracing::{error, info};
 
@@ -23,7 +24,7 @@ impl MsGraphData {
     pub async fn process_sources(
         &self,
         ms_graph: &MsGraph,
-        splunk: &Arc<Splunk>,
+        splunk: &Arc<dyn HecSink>,
         ssphp_run_key: &str,
     ) -> Result<()> {
         for ms_graph_sources in self.0.values() {
@@ -45,7 +46,7 @@ impl MsGraphData {
         source_name: &str,
         ms_graph_source: &MsGraphSource,
         ms_graph: &MsGraph,
-        splunk: &Splunk,
+        splunk: &dyn HecSink,
         ssphp_run_key: &str,
     ) -> Result<()> {
         let log_name = &format!("{}: {:?}", source_name, ms_graph_source);
@@ -145,7 +146,7 @@ mod live_tests {
 
     use crate::{ms_graph::MsGraph, msgraph_data::load_m365_toml};
     use anyhow::{Context, Result};
-    use data_ingester_splunk::splunk::Splunk;
+    use data_ingester_splunk::{sink::HecSink, splunk::Splunk};
     use data_ingester_supporting::keyvault::get_keyvault_secrets;
 
     #[tokio::test]
@@ -169,7 +170,7 @@ mod live_tests {
         )
         .await?;
 
-        let splunk = Arc::new(Splunk::new(
+        let splunk: Arc<dyn HecSink> = Arc::new(Splunk::new(
             secrets.splunk_host.as_ref().context("No value")?,
             secrets.splunk_token.as_ref().context("No value")?,
             true,
@@ -4,6 +4,7 @@ use crate::directory_roles::DirectoryRole;
 use crate::directory_roles::DirectoryRoles;
 use crate::groups::Group;
 use crate::groups::Groups;
+use crate::membership::{MembershipGraph, UserDirectoryRole};
 use crate::roles::RoleDefinitions as EntraRoleDefinitions;
 use anyhow::Context;
 use anyhow::Result;
@@ -24,8 +25,8 @@ use std::ops::Deref;
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct User<'a> {
-    is_privileged: Option<bool>,
-    account_enabled: Option<bool>,
+    pub(crate) is_privileged: Option<bool>,
+    pub(crate) account_enabled: Option<bool>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     assigned_plans: Vec<AssignedPlan>,
     // business_phones: Option<Vec<String>>,
@@ -42,15 +43,25 @@ pub struct User<'a> {
     //preferred_language: Option<String>,
     surname: Option<String>,
     pub(crate) transitive_member_of: Option<Vec<GroupOrRole>>,
-    user_principal_name: Option<String>,
+    pub(crate) user_principal_name: Option<String>,
     // Requires scope: AuditLog.Read.All
     sign_in_activity: Option<Value>,
-    user_type: Option<String>,
+    pub(crate) user_type: Option<String>,
+    /// Identities the user signs in with, only used to find a guest's home tenant
+    #[serde(skip_serializing)]
+    pub(crate) identities: Option<Vec<ObjectIdentity>>,
 
     // Custom attributes
     pub azure_roles: Option<UserAzureRoles>,
     #[serde(skip_deserializing)]
     conditional_access_policies: Option<Vec<UserConditionalAccessPolicy<'a>>>,
+    /// Active and eligible directory roles, with the groups granting them
+    #[serde(skip_deserializing)]
+    pub(crate) directory_roles: Option<Vec<UserDirectoryRole>>,
+    /// Set when some role assignments or group members could not be read,
+    /// so `directory_roles` and `is_privileged` may be missing roles
+    #[serde(skip_deserializing)]
+    pub(crate) directory_roles_unresolved: Option<bool>,
 }
 
 /// Used to represent an AAD users roles in Azure (Cloud) subscriptions
@@ -69,6 +80,15 @@ pub struct UserAzureRole {
     pub role_name: String,
 }
 
+// https://learn.microsoft.com/en-us/graph/api/resources/objectidentity?view=graph-rest-1.0
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectIdentity {
+    pub(crate) sign_in_type: Option<String>,
+    pub(crate) issuer: Option<String>,
+    pub(crate) issuer_assigned_id: Option<String>,
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -115,9 +135,12 @@ impl User<'_> {
             azure_roles: None,
             conditional_access_policies: None,
             description: None,
+            directory_roles: None,
+            directory_roles_unresolved: None,
             display_name: Some(display_name),
             given_name: None,
             id,
+            identities: None,
             is_privileged: None,
             mail: None,
             on_premises_sam_account_name: None,
@@ -194,7 +217,13 @@ impl User<'_> {
             .map(|roles| !roles.privileged_roles.is_empty())
             .unwrap_or(false);
 
-        self.is_privileged = Some(is_privileged || privileged_azure_roles);
+        let privileged_directory_roles = self
+            .directory_roles
+            .as_ref()
+            .is_some_and(|roles| roles.iter().any(|role| role.is_privileged));
+
+        self.is_privileged =
+            Some(is_privileged || privileged_azure_roles || privileged_directory_roles);
     }
 
     pub fn assigned_plans_remove_deleted(&mut self) {
@@ -220,6 +249,21 @@ impl<'a> UsersMap<'a> {
         }
     }
 
+    /// Attach the roles each user holds in `graph`, including eligible
+    /// roles and roles granted through nested groups. When `graph` is
+    /// incomplete every user is marked unresolved, as any of them could
+    /// hold the roles that could not be read
+    pub fn add_directory_roles(
+        &mut self,
+        graph: &MembershipGraph,
+        role_definitions: &EntraRoleDefinitions,
+    ) {
+        for (_, user) in self.inner.iter_mut() {
+            user.directory_roles = Some(graph.user_directory_roles(&user.id, role_definitions));
+            user.directory_roles_unresolved = (!graph.is_complete()).then_some(true);
+        }
+    }
+
     pub fn set_is_privileged(&mut self, role_definitions: &EntraRoleDefinitions) {
         for (_, user) in self.inner.iter_mut() {
             user.set_is_privileged(role_definitions);
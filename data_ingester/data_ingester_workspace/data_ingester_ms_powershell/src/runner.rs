@@ -4,8 +4,8 @@ use tracing::info;
 
 use data_ingester_splunk::splunk::set_ssphp_run;
 
+use data_ingester_splunk::sink::HecSink;
 use data_ingester_splunk::splunk::try_collect_send;
-use data_ingester_splunk::splunk::Splunk;
 use data_ingester_supporting::keyvault::Secrets;
 
 use crate::powershell::run_powershell_exchange_login_test;
@@ -34,7 +34,7 @@ use crate::powershell::run_powershell_get_spoof_intelligence_insight;
 use crate::powershell::run_powershell_get_transport_rule;
 use crate::powershell::run_powershell_get_user_vip;
 
-pub async fn powershell(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn powershell(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("powershell")?;
 
     info!("Starting M365 Powershell collection");
@@ -1,6 +1,7 @@
 use crate::Qualys;
 use anyhow::{Context, Result};
-use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, Splunk};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send};
 use data_ingester_splunk_search::search_client::SplunkApiClient;
 use data_ingester_supporting::keyvault::Secrets;
 use serde::Deserialize;
@@ -13,7 +14,7 @@ struct Cve {
     cve: String,
 }
 
-pub async fn qualys_qvs(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn qualys_qvs(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("qualys")?;
 
     let mut search_client = SplunkApiClient::new_from_secrets(secrets.clone())?.set_app("DCAP");
@@ -2,6 +2,7 @@ pub mod entrypoint;
 mod limits;
 mod qvs;
 use anyhow::{Context, Result};
+use data_ingester_supporting::metrics::METRICS;
 use limits::QualysLimits;
 use qvs::Qvs;
 use reqwest::{
@@ -97,6 +98,7 @@ impl Qualys {
                     }
                 };
 
+                METRICS.http_status("qualys", response.status().as_u16());
                 self.limits = QualysLimits::from_headers(response.headers());
                 info!("Qualys limits: {:?}", self.limits);
                 self.limits.wait().await;
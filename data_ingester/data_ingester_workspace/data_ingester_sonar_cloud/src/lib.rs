@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
-use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, Splunk, ToHecEvents};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, try_collect_send, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
 use reqwest::{
     header::{HeaderMap, HeaderValue},
@@ -10,7 +11,7 @@ use serde_json::Value;
 use std::sync::Arc;
 use tracing::info;
 
-pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn entrypoint(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("sonar_cloud")?;
     let sonar_api_key = secrets
         .sonar_api_key
@@ -0,0 +1,357 @@
+use crate::splunk::{HecEvent, HecFields};
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use std::{io::Write, iter::Peekable};
+use tracing::{error, warn};
+
+/// Appended to the `event` of a [HecEvent] truncated to fit in a batch
+pub const TRUNCATED_MARKER: &str = "...[TRUNCATED]";
+
+/// Smallest serialized event [split_batch] will truncate a single event to
+const MIN_TRUNCATED_BYTES: usize = 1024;
+
+/// Configuration for batching events sent to Splunk HEC
+///
+/// Read from the environment by [BatchConfig::from_env]:
+/// SPLUNK_HEC_MAX_BATCH_BYTES: maximum uncompressed size of a HEC request body
+/// SPLUNK_HEC_MAX_BATCH_EVENTS: maximum number of events in a HEC request
+/// SPLUNK_HEC_GZIP: `true` to gzip request bodies with `Content-Encoding: gzip`
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_bytes: usize,
+    pub max_events: usize,
+    pub gzip: bool,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 950,
+            max_events: 100,
+            gzip: false,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Build a [BatchConfig] from environment variables, unset
+    /// variables keep their [Default] values.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Ok(max_bytes) = std::env::var("SPLUNK_HEC_MAX_BATCH_BYTES") {
+            config.max_bytes = max_bytes
+                .parse()
+                .context("Parsing SPLUNK_HEC_MAX_BATCH_BYTES as usize")?;
+        }
+
+        if let Ok(max_events) = std::env::var("SPLUNK_HEC_MAX_BATCH_EVENTS") {
+            config.max_events = max_events
+                .parse()
+                .context("Parsing SPLUNK_HEC_MAX_BATCH_EVENTS as usize")?;
+        }
+
+        if let Ok(gzip) = std::env::var("SPLUNK_HEC_GZIP") {
+            config.gzip = gzip.parse().context("Parsing SPLUNK_HEC_GZIP as bool")?;
+        }
+
+        if config.max_bytes == 0 || config.max_events == 0 {
+            anyhow::bail!(
+                "SPLUNK_HEC_MAX_BATCH_BYTES and SPLUNK_HEC_MAX_BATCH_EVENTS must be greater than 0"
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+/// Serialize events for [batch_events].
+///
+/// Events too large to fit in a batch on their own are truncated
+/// with a [TRUNCATED_MARKER].
+pub(crate) fn serialize_events<'a>(
+    events: impl IntoIterator<Item = &'a HecEvent> + 'a,
+    config: &'a BatchConfig,
+) -> impl Iterator<Item = (String, HecEvent)> + 'a {
+    events.into_iter().filter_map(|event| {
+        let json = match serde_json::to_string(event) {
+            Ok(json) => json,
+            Err(err) => {
+                error!(name="SplunkHec", operation="Serialize HecEvent", error=?err);
+                return None;
+            }
+        };
+        // Each event is followed by a newline in the batch
+        if json.len() < config.max_bytes {
+            return Some((json, event.clone()));
+        }
+        match truncate_event(event.clone(), config.max_bytes - 1) {
+            Ok(truncated) => Some(truncated),
+            Err(err) => {
+                error!(name="SplunkHec", operation="Truncate HecEvent", error=?err);
+                None
+            }
+        }
+    })
+}
+
+/// Truncate the `event` of a [HecEvent] so its serialized form fits in
+/// `max_bytes`.
+///
+/// The original size of the `event` is recorded in
+/// [HecFields::truncated_from_bytes].
+pub(crate) fn truncate_event(mut event: HecEvent, max_bytes: usize) -> Result<(String, HecEvent)> {
+    let original = std::mem::take(&mut event.event);
+    let original_len = match event
+        .fields
+        .as_ref()
+        .and_then(|fields| fields.truncated_from_bytes)
+    {
+        Some(truncated_from_bytes) => truncated_from_bytes,
+        None => original.len(),
+    };
+    match event.fields {
+        Some(ref mut fields) => fields.truncated_from_bytes = Some(original_len),
+        None => {
+            event.fields = Some(HecFields {
+                truncated_from_bytes: Some(original_len),
+                ..Default::default()
+            })
+        }
+    }
+
+    let original = original.strip_suffix(TRUNCATED_MARKER).unwrap_or(&original);
+    let mut keep = original.len();
+    loop {
+        while !original.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        event.event = format!("{}{}", &original[..keep], TRUNCATED_MARKER);
+        let json = serde_json::to_string(&event).context("Serializing truncated HecEvent")?;
+        if json.len() <= max_bytes || keep == 0 {
+            warn!(
+                name = "SplunkHec",
+                operation = "Truncate HecEvent",
+                source = event.source,
+                sourcetype = event.sourcetype,
+                original_bytes = original_len,
+                truncated_bytes = json.len()
+            );
+            return Ok((json, event));
+        }
+        // Escaping means each byte removed from `event` shrinks the
+        // JSON by at least one byte
+        keep = keep.saturating_sub(json.len() - max_bytes);
+    }
+}
+
+/// Batch serialized events for Splunk HEC
+///
+/// Take events from the iterator until the batch would be larger than
+/// [BatchConfig::max_bytes] or contain more than [BatchConfig::max_events].
+pub(crate) fn batch_events<I>(
+    it: &mut Peekable<I>,
+    config: &BatchConfig,
+) -> Option<(String, Vec<HecEvent>)>
+where
+    I: Iterator<Item = (String, HecEvent)>,
+{
+    let mut lines = String::with_capacity(config.max_bytes);
+    let mut events = Vec::new();
+    while events.len() < config.max_events {
+        match it.peek() {
+            None => break,
+            Some((json, _))
+                if !events.is_empty() && lines.len() + json.len() >= config.max_bytes =>
+            {
+                break
+            }
+            Some(_) => {}
+        }
+        let Some((json, event)) = it.next() else {
+            break;
+        };
+        lines.push_str(json.as_str());
+        lines.push('\n');
+        events.push(event);
+    }
+
+    if events.is_empty() {
+        None
+    } else {
+        Some((lines, events))
+    }
+}
+
+/// Split a batch rejected by HEC as too large.
+///
+/// Batches of more than one event are split in half. A single event
+/// is truncated to half its size, or dropped once it can't be made
+/// any smaller.
+pub(crate) fn split_batch(lines: &str, mut events: Vec<HecEvent>) -> Vec<(String, Vec<HecEvent>)> {
+    if events.len() > 1 {
+        let second = events.split_off(events.len() / 2);
+        return [events, second]
+            .into_iter()
+            .map(|events| {
+                let lines = events
+                    .iter()
+                    .filter_map(|event| serde_json::to_string(event).ok())
+                    .map(|json| json + "\n")
+                    .collect();
+                (lines, events)
+            })
+            .collect();
+    }
+
+    let Some(event) = events.pop() else {
+        return vec![];
+    };
+    let max_bytes = lines.len() / 2;
+    if max_bytes < MIN_TRUNCATED_BYTES {
+        error!(
+            name = "SplunkHec",
+            operation = "Split HecBatch",
+            source = event.source,
+            sourcetype = event.sourcetype,
+            bytes = lines.len(),
+            "Dropping event rejected by HEC as too large"
+        );
+        return vec![];
+    }
+    match truncate_event(event, max_bytes) {
+        Ok((json, event)) => vec![(format!("{}\n", json), vec![event])],
+        Err(err) => {
+            error!(name="SplunkHec", operation="Split HecBatch", error=?err);
+            vec![]
+        }
+    }
+}
+
+/// Encode a batch as a HEC request body, gzipped if enabled
+pub(crate) fn encode_body(lines: &str, gzip: bool) -> Result<Vec<u8>> {
+    if !gzip {
+        return Ok(lines.as_bytes().to_vec());
+    }
+    let mut encoder = GzEncoder::new(Vec::with_capacity(lines.len() / 4), Compression::default());
+    encoder
+        .write_all(lines.as_bytes())
+        .context("Gzipping HEC batch")?;
+    encoder.finish().context("Finishing gzipped HEC batch")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        batch_events, encode_body, serialize_events, split_batch, BatchConfig, TRUNCATED_MARKER,
+    };
+    use crate::splunk::HecEvent;
+    use flate2::read::GzDecoder;
+    use std::{collections::HashMap, io::Read};
+
+    fn event(size: usize) -> HecEvent {
+        let data = HashMap::from([("data", "x".repeat(size))]);
+        HecEvent::new_with_ssphp_run(&data, "test_source", "test_sourcetype", 1).unwrap()
+    }
+
+    fn batches(events: &[HecEvent], config: &BatchConfig) -> Vec<(String, Vec<HecEvent>)> {
+        let mut serialized = serialize_events(events.iter(), config).peekable();
+        std::iter::from_fn(|| batch_events(&mut serialized, config)).collect()
+    }
+
+    #[test]
+    fn test_batches_are_no_larger_than_max_bytes() {
+        let config = BatchConfig {
+            max_bytes: 4096,
+            ..Default::default()
+        };
+        let events = vec![event(1000); 10];
+        let batches = batches(&events, &config);
+        assert!(batches.len() > 1);
+        for (lines, _) in batches.iter() {
+            assert!(lines.len() <= config.max_bytes);
+        }
+        assert_eq!(
+            batches
+                .iter()
+                .map(|(_, events)| events.len())
+                .sum::<usize>(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_batches_are_no_larger_than_max_events() {
+        let config = BatchConfig {
+            max_events: 3,
+            ..Default::default()
+        };
+        let events = vec![event(10); 10];
+        let batches = batches(&events, &config);
+        assert_eq!(
+            batches
+                .iter()
+                .map(|(_, events)| events.len())
+                .collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+    }
+
+    #[test]
+    fn test_oversized_event_is_truncated_with_marker() {
+        let config = BatchConfig {
+            max_bytes: 4096,
+            ..Default::default()
+        };
+        let events = vec![event(10_000)];
+        let batches = batches(&events, &config);
+        assert_eq!(batches.len(), 1);
+        let (lines, events) = &batches[0];
+        assert!(lines.len() <= config.max_bytes);
+        assert!(events[0].event.ends_with(TRUNCATED_MARKER));
+        let truncated_from_bytes = events[0]
+            .fields
+            .as_ref()
+            .and_then(|fields| fields.truncated_from_bytes)
+            .unwrap();
+        assert!(truncated_from_bytes > 10_000);
+    }
+
+    #[test]
+    fn test_split_batch_halves_events() {
+        let events = vec![event(10); 5];
+        let (lines, events) = batches(&events, &BatchConfig::default()).remove(0);
+        let split = split_batch(&lines, events);
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].1.len(), 2);
+        assert_eq!(split[1].1.len(), 3);
+        assert_eq!(split[0].0.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_split_batch_truncates_single_event() {
+        let events = vec![event(10_000)];
+        let (lines, events) = batches(&events, &BatchConfig::default()).remove(0);
+        let split = split_batch(&lines, events);
+        assert_eq!(split.len(), 1);
+        assert!(split[0].0.len() <= lines.len() / 2 + 1);
+        assert!(split[0].1[0].event.ends_with(TRUNCATED_MARKER));
+
+        let (lines, events) = batches(&[event(10)], &BatchConfig::default()).remove(0);
+        assert!(split_batch(&lines, events).is_empty());
+    }
+
+    #[test]
+    fn test_encode_body_gzip() {
+        let lines = "{\"event\":\"foo\"}\n".repeat(100);
+        assert_eq!(encode_body(&lines, false).unwrap(), lines.as_bytes());
+
+        let body = encode_body(&lines, true).unwrap();
+        assert!(body.len() < lines.len());
+        let mut decoded = String::new();
+        let _ = GzDecoder::new(body.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, lines);
+    }
+}
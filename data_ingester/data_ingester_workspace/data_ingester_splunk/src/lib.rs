@@ -1,6 +1,16 @@
 #![feature(hash_extract_if)]
+mod batch;
+pub mod manifest;
+pub mod redaction;
+mod resend;
+pub mod routing;
+pub mod sink;
 pub mod splunk;
+mod spool;
 mod tasks;
 mod thread;
 mod tracing;
+pub use batch::BatchConfig;
+pub use resend::{DeadLetterConfig, ResendConfig, ResendCounts};
+pub use spool::{SpoolConfig, SpoolDepth};
 pub use tracing::start_splunk_tracing;
@@ -0,0 +1,308 @@
+use crate::sink::{HecSink, ShutdownFuture, SinkFuture};
+use crate::splunk::{get_ssphp_run, HecEvent};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+/// Sourcetype of [RunManifest] events
+pub const MANIFEST_SOURCETYPE: &str = "ssphp:manifest:json";
+
+/// The result of a single named
+/// [try_collect_send](crate::splunk::try_collect_send) step
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ManifestStep {
+    pub name: String,
+    pub status: StepStatus,
+    pub error: Option<String>,
+    pub event_count: usize,
+    pub duration_ms: u64,
+    /// SSPHP_RUN of the events produced by this step
+    pub ssphp_run: Option<u64>,
+    /// Parts of the step that failed without failing the step, e.g.
+    /// regions a regional check failed in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Ok,
+    Failed,
+}
+
+impl ManifestStep {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: StepStatus::Ok,
+            error: None,
+            event_count: 0,
+            duration_ms: 0,
+            ssphp_run: None,
+            errors: vec![],
+        }
+    }
+
+    /// Mark this step as failed with `error`
+    pub fn failed<E: Into<String>>(&mut self, error: E) {
+        self.status = StepStatus::Failed;
+        self.error = Some(error.into());
+    }
+}
+
+/// Overall status of a collection run
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The entrypoint finished and every step succeeded
+    Complete,
+    /// The entrypoint finished but one or more steps failed
+    Partial,
+    /// The entrypoint failed, timed out or was cancelled
+    Failed,
+}
+
+/// Final event sent for each collection run listing every step.
+///
+/// Lets Splunk tell "no findings" apart from "collection broken" for a
+/// given `SSPHP_RUN`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunManifest {
+    pub collector: String,
+    pub ssphp_run: u64,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    pub start: u64,
+    pub finish: u64,
+    pub steps_total: usize,
+    pub steps_failed: usize,
+    pub events_total: usize,
+    pub steps: Vec<ManifestStep>,
+}
+
+/// A [HecSink] that records the [ManifestStep]s of a collection run.
+///
+/// Events are passed through to the wrapped sink tagged with the
+/// collector name. Call
+/// [ManifestSink::finish] when the entrypoint returns to send the
+/// [RunManifest].
+pub struct ManifestSink {
+    inner: Arc<dyn HecSink>,
+    collector: String,
+    start: u64,
+    steps: Mutex<Vec<ManifestStep>>,
+}
+
+impl ManifestSink {
+    pub fn new(collector: &str, inner: Arc<dyn HecSink>) -> Self {
+        Self {
+            inner,
+            collector: collector.to_string(),
+            start: now(),
+            steps: Mutex::new(vec![]),
+        }
+    }
+
+    /// Build the [RunManifest] from the recorded steps.
+    ///
+    /// error: The error returned by the entrypoint, if any
+    pub fn manifest(&self, error: Option<String>) -> Result<RunManifest> {
+        let steps = self
+            .steps
+            .lock()
+            .map_err(|_| anyhow!("Manifest steps lock poisoned"))?
+            .clone();
+        let steps_failed = steps
+            .iter()
+            .filter(|step| step.status == StepStatus::Failed)
+            .count();
+        let status = match (&error, steps_failed) {
+            (Some(_), _) => RunStatus::Failed,
+            (None, 0) => RunStatus::Complete,
+            (None, _) => RunStatus::Partial,
+        };
+        let ssphp_run = steps
+            .iter()
+            .filter_map(|step| step.ssphp_run)
+            .max()
+            .unwrap_or_else(|| get_ssphp_run(&self.collector));
+        Ok(RunManifest {
+            collector: self.collector.to_string(),
+            ssphp_run,
+            status,
+            error,
+            start: self.start,
+            finish: now(),
+            steps_total: steps.len(),
+            steps_failed,
+            events_total: steps.iter().map(|step| step.event_count).sum(),
+            steps,
+        })
+    }
+
+    /// Send the [RunManifest] to the wrapped sink
+    pub async fn finish(&self, error: Option<String>) -> Result<RunManifest> {
+        let manifest = self.manifest(error)?;
+        let event = HecEvent::new_with_ssphp_run(
+            &manifest,
+            &self.collector,
+            MANIFEST_SOURCETYPE,
+            manifest.ssphp_run,
+        )
+        .context("Building manifest HecEvent")?;
+        self.inner
+            .send_collector_batch(&self.collector, vec![event])
+            .await
+            .context("Sending run manifest")?;
+        info!(
+            name = "Manifest",
+            collector = manifest.collector,
+            status = ?manifest.status,
+            steps_total = manifest.steps_total,
+            steps_failed = manifest.steps_failed,
+            events_total = manifest.events_total,
+        );
+        Ok(manifest)
+    }
+}
+
+impl HecSink for ManifestSink {
+    fn name(&self) -> &str {
+        "manifest"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        self.inner.send_collector_batch(&self.collector, events)
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        self.inner.send_collector_batch(collector, events)
+    }
+
+    fn record_step(&self, step: ManifestStep) {
+        if let Ok(mut steps) = self.steps.lock() {
+            steps.push(step);
+        }
+    }
+
+    fn shutdown(&self, deadline: Duration) -> ShutdownFuture<'_> {
+        self.inner.shutdown(deadline)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ManifestSink, RunStatus, StepStatus, MANIFEST_SOURCETYPE};
+    use crate::sink::{HecSink, SinkFuture};
+    use crate::splunk::{try_collect_send, HecEvent, ToHecEvents};
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct CollectingSink {
+        events: Mutex<Vec<HecEvent>>,
+    }
+
+    impl HecSink for CollectingSink {
+        fn name(&self) -> &str {
+            "collecting"
+        }
+
+        fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+            self.events.lock().unwrap().extend(events);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[derive(Debug)]
+    struct Things(Vec<HashMap<&'static str, u32>>);
+
+    impl Things {
+        fn new(count: u32) -> Self {
+            Self((0..count).map(|i| HashMap::from([("thing", i)])).collect())
+        }
+    }
+
+    impl ToHecEvents for &Things {
+        type Item = HashMap<&'static str, u32>;
+
+        fn source(&self) -> &str {
+            "things"
+        }
+
+        fn sourcetype(&self) -> &str {
+            "ssphp:things"
+        }
+
+        fn collection<'i>(&'i self) -> Box<dyn Iterator<Item = &'i Self::Item> + 'i> {
+            Box::new(self.0.iter())
+        }
+
+        fn ssphp_run_key(&self) -> &str {
+            "default"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manifest_records_try_collect_send_steps() {
+        let inner = Arc::new(CollectingSink::default());
+        let manifest_sink = ManifestSink::new("test_collector", inner.clone());
+
+        let _ = try_collect_send("good step", async { Ok(Things::new(3)) }, &manifest_sink).await;
+        let _ = try_collect_send(
+            "bad step",
+            async { Err::<Things, _>(anyhow!("broken")) },
+            &manifest_sink,
+        )
+        .await;
+
+        let manifest = manifest_sink.finish(None).await.unwrap();
+        assert_eq!(manifest.status, RunStatus::Partial);
+        assert_eq!(manifest.steps_total, 2);
+        assert_eq!(manifest.steps_failed, 1);
+        assert_eq!(manifest.events_total, 3);
+        assert_eq!(manifest.steps[0].name, "good step");
+        assert_eq!(manifest.steps[0].status, StepStatus::Ok);
+        assert_eq!(manifest.steps[1].status, StepStatus::Failed);
+        assert!(manifest.steps[1].error.as_ref().unwrap().contains("broken"));
+
+        let events = inner.events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[3].sourcetype, MANIFEST_SOURCETYPE);
+        assert_eq!(events[3].source, "test_collector");
+    }
+
+    #[tokio::test]
+    async fn test_manifest_status() {
+        let inner = Arc::new(CollectingSink::default());
+        let manifest_sink = ManifestSink::new("test_collector", inner);
+        assert_eq!(
+            manifest_sink.manifest(None).unwrap().status,
+            RunStatus::Complete
+        );
+        assert_eq!(
+            manifest_sink
+                .manifest(Some("timed_out".to_string()))
+                .unwrap()
+                .status,
+            RunStatus::Failed
+        );
+    }
+}
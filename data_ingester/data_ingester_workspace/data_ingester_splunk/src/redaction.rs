@@ -0,0 +1,460 @@
+use crate::routing::wildcard_match;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::sync::{Arc, RwLock};
+use tracing::error;
+
+/// Replacement value for masked fields
+pub const MASK: &str = "[REDACTED]";
+
+static REDACTOR: RwLock<Option<Arc<Redactor>>> = RwLock::new(None);
+
+/// Set the [Redactor] applied to every [HecEvent](crate::splunk::HecEvent)
+/// as it is built, `None` turns redaction off
+pub fn set_redactor(redactor: Option<Redactor>) {
+    match REDACTOR.write() {
+        Ok(mut current) => *current = redactor.map(Arc::new),
+        Err(err) => {
+            error!(name="Redaction", operation="Set redactor", error=?err);
+        }
+    }
+}
+
+pub(crate) fn redactor() -> Option<Arc<Redactor>> {
+    REDACTOR.read().ok().and_then(|redactor| redactor.clone())
+}
+
+/// What to do with a field matched by a [RedactionRule]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionAction {
+    /// Remove the field from the event
+    Drop,
+    /// Replace the value with [MASK]
+    Mask,
+    /// Replace the value with the hex HMAC-SHA256 of the value, so
+    /// events can still be correlated without the original value
+    Hmac,
+}
+
+/// A single redaction rule.
+///
+/// `field` is either a JSON pointer, e.g. `/mail` or
+/// `/contacts/0/phone`, or a dotted glob where `*` matches any run of
+/// characters in one key and `**` matches any number of keys, e.g.
+/// `**.mobilePhone` or `contacts.*.phone`. Array elements are matched
+/// by their index.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionRule {
+    /// Pattern for the sourcetypes the rule applies to, `*` matches
+    /// any run of characters. The rule applies to every sourcetype
+    /// when unset
+    pub sourcetype: Option<String>,
+    pub field: String,
+    pub action: RedactionAction,
+}
+
+/// Fields to redact from events before they leave the process
+///
+/// ```toml
+/// [[rules]]
+/// sourcetype = "ssphp:ms_graph:*"
+/// field = "/mail"
+/// action = "hmac"
+///
+/// [[rules]]
+/// field = "**.mobilePhone"
+/// action = "drop"
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionConfig {
+    #[serde(default)]
+    pub rules: Vec<RedactionRule>,
+}
+
+impl RedactionConfig {
+    /// Load redaction rules from the file named by
+    /// SSPHP_REDACTION_FILE, returns `None` if it is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("SSPHP_REDACTION_FILE") else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading redaction file: {}", path))?;
+        Ok(Some(Self::from_toml(&contents)?))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        toml::from_str(contents).context("Parsing redaction TOML")
+    }
+}
+
+#[derive(Debug)]
+enum FieldPath {
+    /// Unescaped JSON pointer tokens
+    Pointer(Vec<String>),
+    /// Dotted glob segments
+    Glob(Vec<String>),
+}
+
+impl FieldPath {
+    fn parse(field: &str) -> Result<Self> {
+        if field.is_empty() {
+            anyhow::bail!("Redaction field must not be empty");
+        }
+        if let Some(pointer) = field.strip_prefix('/') {
+            return Ok(Self::Pointer(
+                pointer
+                    .split('/')
+                    .map(|token| token.replace("~1", "/").replace("~0", "~"))
+                    .collect(),
+            ));
+        }
+        Ok(Self::Glob(field.split('.').map(str::to_string).collect()))
+    }
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    sourcetype: Option<String>,
+    path: FieldPath,
+    action: RedactionAction,
+}
+
+impl CompiledRule {
+    fn applies_to(&self, sourcetype: &str) -> bool {
+        self.sourcetype
+            .as_ref()
+            .is_none_or(|pattern| wildcard_match(pattern, sourcetype))
+    }
+}
+
+/// Applies a [RedactionConfig] to events
+#[derive(Debug)]
+pub struct Redactor {
+    rules: Vec<CompiledRule>,
+    hmac_key: Option<Vec<u8>>,
+}
+
+impl Redactor {
+    /// hmac_key: Key for [RedactionAction::Hmac] rules, usually the
+    /// `redaction-hmac-key` secret
+    pub fn new(config: RedactionConfig, hmac_key: Option<&str>) -> Result<Self> {
+        let rules = config
+            .rules
+            .into_iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    path: FieldPath::parse(&rule.field)
+                        .with_context(|| format!("Parsing redaction field '{}'", rule.field))?,
+                    sourcetype: rule.sourcetype,
+                    action: rule.action,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let hmac_key = hmac_key
+            .filter(|key| !key.is_empty())
+            .map(|key| key.as_bytes().to_vec());
+        if hmac_key.is_none()
+            && rules
+                .iter()
+                .any(|rule| rule.action == RedactionAction::Hmac)
+        {
+            anyhow::bail!("Redaction rules use hmac but no HMAC key is set");
+        }
+        Ok(Self { rules, hmac_key })
+    }
+
+    /// Build a [Redactor] from [RedactionConfig::from_env], returns
+    /// `None` if SSPHP_REDACTION_FILE is not set.
+    pub fn from_env(hmac_key: Option<&str>) -> Result<Option<Self>> {
+        RedactionConfig::from_env()?
+            .map(|config| Self::new(config, hmac_key))
+            .transpose()
+    }
+
+    /// Do any rules apply to events of `sourcetype`
+    pub fn applies_to(&self, sourcetype: &str) -> bool {
+        self.rules.iter().any(|rule| rule.applies_to(sourcetype))
+    }
+
+    /// Redact a serialized event in place
+    pub fn redact(&self, sourcetype: &str, event: &mut Value) {
+        for rule in self.rules.iter().filter(|rule| rule.applies_to(sourcetype)) {
+            match &rule.path {
+                FieldPath::Pointer(tokens) => self.redact_pointer(event, tokens, rule.action),
+                FieldPath::Glob(segments) => self.redact_glob(event, segments, rule.action),
+            }
+        }
+    }
+
+    /// Serialize an event to JSON, redacting any fields the rules for
+    /// `sourcetype` match
+    pub fn redact_to_string<T: Serialize>(&self, sourcetype: &str, event: &T) -> Result<String> {
+        let mut value = serde_json::to_value(event).context("Serializing event to redact")?;
+        self.redact(sourcetype, &mut value);
+        serde_json::to_string(&value).context("Serializing redacted event")
+    }
+
+    fn redact_pointer(&self, value: &mut Value, tokens: &[String], action: RedactionAction) {
+        let Some((last, parents)) = tokens.split_last() else {
+            return;
+        };
+        let mut parent = value;
+        for token in parents {
+            let Some(child) = child_mut(parent, token) else {
+                return;
+            };
+            parent = child;
+        }
+        self.redact_child(parent, last, action);
+    }
+
+    fn redact_glob(&self, value: &mut Value, segments: &[String], action: RedactionAction) {
+        let Some((segment, rest)) = segments.split_first() else {
+            return;
+        };
+        if segment == "**" {
+            self.redact_glob(value, rest, action);
+            for child in children_mut(value) {
+                self.redact_glob(child, segments, action);
+            }
+            return;
+        }
+        let keys: Vec<String> = match value {
+            Value::Object(map) => map
+                .keys()
+                .filter(|key| wildcard_match(segment, key))
+                .cloned()
+                .collect(),
+            Value::Array(array) => (0..array.len())
+                .map(|i| i.to_string())
+                .filter(|i| wildcard_match(segment, i))
+                .collect(),
+            _ => return,
+        };
+        // Remove array elements from the end so indexes stay valid
+        for key in keys.iter().rev() {
+            if rest.is_empty() {
+                self.redact_child(value, key, action);
+            } else if let Some(child) = child_mut(value, key) {
+                self.redact_glob(child, rest, action);
+            }
+        }
+    }
+
+    /// Apply `action` to the field `key` of `parent`
+    fn redact_child(&self, parent: &mut Value, key: &str, action: RedactionAction) {
+        if action == RedactionAction::Drop {
+            match parent {
+                Value::Object(map) => {
+                    let _ = map.remove(key);
+                }
+                Value::Array(array) => {
+                    if let Ok(i) = key.parse::<usize>() {
+                        if i < array.len() {
+                            let _ = array.remove(i);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        let Some(child) = child_mut(parent, key) else {
+            return;
+        };
+        match (action, &child) {
+            (_, Value::Null) => {}
+            (RedactionAction::Mask, _) => *child = Value::String(MASK.to_string()),
+            (RedactionAction::Hmac, Value::String(s)) => *child = Value::String(self.hmac(s)),
+            (RedactionAction::Hmac, other) => *child = Value::String(self.hmac(&other.to_string())),
+            (RedactionAction::Drop, _) => {}
+        }
+    }
+
+    fn hmac(&self, value: &str) -> String {
+        let Some(key) = &self.hmac_key else {
+            return MASK.to_string();
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(key) else {
+            return MASK.to_string();
+        };
+        mac.update(value.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn child_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Object(map) => map.get_mut(key),
+        Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get_mut(i)),
+        _ => None,
+    }
+}
+
+fn children_mut(value: &mut Value) -> Box<dyn Iterator<Item = &mut Value> + '_> {
+    match value {
+        Value::Object(map) => Box::new(map.values_mut()),
+        Value::Array(array) => Box::new(array.iter_mut()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{set_redactor, RedactionConfig, Redactor, MASK};
+    use crate::splunk::HecEvent;
+    use serde_json::{json, Value};
+
+    const KEY: &str = "test_key";
+
+    fn redactor(rules: &str) -> Redactor {
+        Redactor::new(RedactionConfig::from_toml(rules).unwrap(), Some(KEY)).unwrap()
+    }
+
+    fn user() -> Value {
+        json!({
+            "displayName": "Jane Doe",
+            "mail": "jane@example.com",
+            "mobilePhone": "07700 900000",
+            "manager": {"mail": "boss@example.com", "mobilePhone": null},
+            "contacts": [
+                {"phone": "01632 960000", "type": "home"},
+                {"phone": "01632 960001", "type": "work"}
+            ],
+            "path/with~": "value"
+        })
+    }
+
+    #[test]
+    fn test_redact_json_pointer() {
+        let redactor = redactor(
+            r#"
+[[rules]]
+field = "/mail"
+action = "hmac"
+[[rules]]
+field = "/displayName"
+action = "mask"
+[[rules]]
+field = "/contacts/0"
+action = "drop"
+[[rules]]
+field = "/path~1with~0"
+action = "drop"
+[[rules]]
+field = "/missing/field"
+action = "drop"
+"#,
+        );
+        let mut event = user();
+        redactor.redact("ssphp:users", &mut event);
+
+        assert_eq!(event["displayName"], MASK);
+        let mail = event["mail"].as_str().unwrap();
+        assert_eq!(mail.len(), 64);
+        assert_ne!(mail, "jane@example.com");
+        assert_eq!(event["contacts"].as_array().unwrap().len(), 1);
+        assert_eq!(event["contacts"][0]["type"], "work");
+        assert!(event.get("path/with~").is_none());
+        assert_eq!(event["manager"]["mail"], "boss@example.com");
+    }
+
+    #[test]
+    fn test_redact_glob() {
+        let redactor = redactor(
+            r#"
+[[rules]]
+field = "**.mail"
+action = "mask"
+[[rules]]
+field = "contacts.*.phone"
+action = "drop"
+[[rules]]
+field = "mobile*"
+action = "hmac"
+"#,
+        );
+        let mut event = user();
+        redactor.redact("ssphp:users", &mut event);
+
+        assert_eq!(event["mail"], MASK);
+        assert_eq!(event["manager"]["mail"], MASK);
+        assert!(event["contacts"][0].get("phone").is_none());
+        assert!(event["contacts"][1].get("phone").is_none());
+        assert_eq!(event["contacts"][1]["type"], "work");
+        assert_eq!(event["mobilePhone"].as_str().unwrap().len(), 64);
+        assert!(event["manager"]["mobilePhone"].is_null());
+    }
+
+    #[test]
+    fn test_redact_hmac_is_keyed_and_stable() {
+        let rules = r#"
+[[rules]]
+field = "/mail"
+action = "hmac"
+"#;
+        let mut first = user();
+        let mut second = user();
+        redactor(rules).redact("ssphp:users", &mut first);
+        redactor(rules).redact("ssphp:users", &mut second);
+        assert_eq!(first["mail"], second["mail"]);
+
+        let other_key =
+            Redactor::new(RedactionConfig::from_toml(rules).unwrap(), Some("other")).unwrap();
+        let mut third = user();
+        other_key.redact("ssphp:users", &mut third);
+        assert_ne!(first["mail"], third["mail"]);
+
+        assert!(Redactor::new(RedactionConfig::from_toml(rules).unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn test_redact_per_sourcetype() {
+        let redactor = redactor(
+            r#"
+[[rules]]
+sourcetype = "ssphp:ms_graph:*"
+field = "/mail"
+action = "drop"
+"#,
+        );
+        assert!(redactor.applies_to("ssphp:ms_graph:users"));
+        assert!(!redactor.applies_to("ssphp:github:members"));
+
+        let mut github = user();
+        redactor.redact("ssphp:github:members", &mut github);
+        assert_eq!(github, user());
+
+        let mut ms_graph = user();
+        redactor.redact("ssphp:ms_graph:users", &mut ms_graph);
+        assert!(ms_graph.get("mail").is_none());
+    }
+
+    #[test]
+    fn test_hec_event_is_redacted() {
+        set_redactor(Some(redactor(
+            r#"
+[[rules]]
+sourcetype = "ssphp:redaction:test"
+field = "/mail"
+action = "mask"
+"#,
+        )));
+        let redacted =
+            HecEvent::new_with_ssphp_run(&user(), "test", "ssphp:redaction:test", 1).unwrap();
+        let event: Value = serde_json::from_str(&redacted.event).unwrap();
+        assert_eq!(event["mail"], MASK);
+        assert_eq!(event["SSPHP_RUN"], 1);
+
+        let untouched = HecEvent::new_with_ssphp_run(&user(), "test", "ssphp:other", 1).unwrap();
+        let event: Value = serde_json::from_str(&untouched.event).unwrap();
+        assert_eq!(event["mail"], "jane@example.com");
+    }
+}
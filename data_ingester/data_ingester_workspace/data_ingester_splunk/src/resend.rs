@@ -0,0 +1,241 @@
+use crate::splunk::HecEvent;
+use crate::spool::{Spool, SpoolConfig};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Configuration for resending events Splunk fails to acknowledge
+///
+/// Read from the environment by [ResendConfig::from_env]:
+/// SPLUNK_HEC_MAX_RESENDS: number of times a batch is resent before it is dead lettered
+/// SPLUNK_HEC_RESEND_BACKOFF_SECS: delay before the second resend of a batch, doubled for each resend after
+/// SPLUNK_HEC_DEAD_LETTER: `file:<directory>` or `sourcetype:<sourcetype>`, see [DeadLetterConfig]. Events are dropped when unset.
+#[derive(Debug, Clone)]
+pub struct ResendConfig {
+    pub max_resends: i32,
+    pub backoff: Duration,
+    pub dead_letter: Option<DeadLetterConfig>,
+}
+
+impl Default for ResendConfig {
+    fn default() -> Self {
+        Self {
+            max_resends: 5,
+            backoff: Duration::from_secs(30),
+            dead_letter: None,
+        }
+    }
+}
+
+impl ResendConfig {
+    /// Build a [ResendConfig] from environment variables, unset
+    /// variables keep their [Default] values.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Ok(max_resends) = std::env::var("SPLUNK_HEC_MAX_RESENDS") {
+            config.max_resends = max_resends
+                .parse()
+                .context("Parsing SPLUNK_HEC_MAX_RESENDS as i32")?;
+        }
+
+        if let Ok(backoff) = std::env::var("SPLUNK_HEC_RESEND_BACKOFF_SECS") {
+            config.backoff = Duration::from_secs(
+                backoff
+                    .parse()
+                    .context("Parsing SPLUNK_HEC_RESEND_BACKOFF_SECS as u64")?,
+            );
+        }
+
+        if let Ok(dead_letter) = std::env::var("SPLUNK_HEC_DEAD_LETTER") {
+            config.dead_letter = Some(
+                dead_letter
+                    .parse()
+                    .context("Parsing SPLUNK_HEC_DEAD_LETTER")?,
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Delay before resending a batch that has already been resent
+    /// `resend_count` times.
+    ///
+    /// The first resend has already waited for the ack timeout so is
+    /// not delayed.
+    pub(crate) fn backoff(&self, resend_count: i32) -> Duration {
+        let Ok(exponent) = u32::try_from(resend_count.saturating_sub(1)) else {
+            return Duration::ZERO;
+        };
+        self.backoff.saturating_mul(2_u32.saturating_pow(exponent))
+    }
+}
+
+/// Where events that exceed [ResendConfig::max_resends] are sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeadLetterConfig {
+    /// Write events to segment files in a directory. Segments use the
+    /// same format as the [SpoolConfig] spool so can be moved into a
+    /// client's spool directory, see [SpoolConfig::for_destination],
+    /// to be replayed.
+    File(PathBuf),
+    /// Send events to Splunk once more with their sourcetype rewritten.
+    /// The original sourcetype is kept in [crate::splunk::HecFields::original_sourcetype]
+    Sourcetype(String),
+}
+
+impl FromStr for DeadLetterConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("file", directory)) if !directory.is_empty() => {
+                Ok(Self::File(PathBuf::from(directory)))
+            }
+            Some(("sourcetype", sourcetype)) if !sourcetype.is_empty() => {
+                Ok(Self::Sourcetype(sourcetype.to_string()))
+            }
+            _ => anyhow::bail!(
+                "Unknown dead letter destination '{}', expected file:<directory> or sourcetype:<sourcetype>",
+                s
+            ),
+        }
+    }
+}
+
+/// A [DeadLetterConfig] ready to receive events
+#[derive(Debug)]
+pub(crate) enum DeadLetter {
+    File(Arc<Spool>),
+    Sourcetype(String),
+    Drop,
+}
+
+impl DeadLetter {
+    pub(crate) fn new(config: Option<DeadLetterConfig>) -> Result<Self> {
+        let dead_letter = match config {
+            Some(DeadLetterConfig::File(directory)) => Self::File(Arc::new(
+                Spool::new(SpoolConfig::new(directory)).context("Building dead letter spool")?,
+            )),
+            Some(DeadLetterConfig::Sourcetype(sourcetype)) => Self::Sourcetype(sourcetype),
+            None => Self::Drop,
+        };
+        Ok(dead_letter)
+    }
+}
+
+/// How the `AckTask` resends and dead letters batches
+#[derive(Debug)]
+pub(crate) struct ResendPolicy {
+    pub(crate) config: ResendConfig,
+    pub(crate) dead_letter: DeadLetter,
+    pub(crate) counters: Arc<ResendCounters>,
+}
+
+impl ResendPolicy {
+    pub(crate) fn new(config: ResendConfig) -> Result<Self> {
+        let dead_letter = DeadLetter::new(config.dead_letter.clone())?;
+        Ok(Self {
+            config,
+            dead_letter,
+            counters: Arc::new(ResendCounters::default()),
+        })
+    }
+}
+
+/// Running totals of events resent and dead lettered by the `AckTask`
+#[derive(Debug, Default)]
+pub(crate) struct ResendCounters {
+    resent: AtomicU64,
+    dead_lettered: AtomicU64,
+    dropped: AtomicU64,
+    unacknowledged: AtomicUsize,
+}
+
+impl ResendCounters {
+    pub(crate) fn resent(&self, events: &[HecEvent]) {
+        let _ = self
+            .resent
+            .fetch_add(events.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dead_lettered(&self, events: &[HecEvent]) {
+        let _ = self
+            .dead_lettered
+            .fetch_add(events.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self, events: &[HecEvent]) {
+        let _ = self
+            .dropped
+            .fetch_add(events.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Set the number of events waiting to be acknowledged or resent
+    pub(crate) fn unacknowledged(&self, events: usize) {
+        self.unacknowledged.store(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn counts(&self) -> ResendCounts {
+        ResendCounts {
+            resent: self.resent.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            unacknowledged: self.unacknowledged.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Number of events resent, dead lettered or dropped after failing
+/// to be acknowledged
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResendCounts {
+    pub resent: u64,
+    pub dead_lettered: u64,
+    pub dropped: u64,
+    /// Events currently waiting to be acknowledged or resent
+    pub unacknowledged: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeadLetterConfig, ResendConfig};
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn test_dead_letter_config_from_str() {
+        assert_eq!(
+            "file:/tmp/dead_letter".parse::<DeadLetterConfig>().unwrap(),
+            DeadLetterConfig::File(PathBuf::from("/tmp/dead_letter"))
+        );
+        assert_eq!(
+            "sourcetype:ssphp:dead_letter"
+                .parse::<DeadLetterConfig>()
+                .unwrap(),
+            DeadLetterConfig::Sourcetype("ssphp:dead_letter".to_string())
+        );
+        assert!("index:foo".parse::<DeadLetterConfig>().is_err());
+        assert!("file:".parse::<DeadLetterConfig>().is_err());
+    }
+
+    #[test]
+    fn test_resend_backoff_is_exponential() {
+        let config = ResendConfig {
+            backoff: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(config.backoff(0), Duration::ZERO);
+        assert_eq!(config.backoff(1), Duration::from_secs(10));
+        assert_eq!(config.backoff(2), Duration::from_secs(20));
+        assert_eq!(config.backoff(3), Duration::from_secs(40));
+        assert!(config.backoff(i32::MAX) > config.backoff(3));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::manifest::ManifestStep;
+use crate::sink::{HecSink, ShutdownFuture, SinkFuture};
+use crate::splunk::{HecEvent, ShutdownReport};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, warn};
+
+/// Declarative routing of events to named destinations
+///
+/// Rules are checked in order and the first matching rule decides
+/// where an event goes. Events matching no rule are sent to the
+/// `default` destinations, or dropped if there are none.
+///
+/// ```toml
+/// default = ["splunk"]
+///
+/// [destinations]
+/// splunk = "splunk"
+/// ian = "ian_splunk"
+/// archive = "ndjson:/var/lib/ssphp/archive"
+///
+/// [[rules]]
+/// collector = "azure_dev_ops"
+/// destinations = ["splunk", "ian"]
+///
+/// [[rules]]
+/// sourcetype = "ssphp:aws:*"
+/// destinations = ["ian"]
+/// set_index = "ssphp_aws"
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// Destination names to sink specs, see
+    /// [sink_from_secrets](crate::sink::sink_from_secrets)
+    pub destinations: BTreeMap<String, String>,
+    /// Destinations for events no rule matches
+    #[serde(default)]
+    pub default: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+}
+
+/// A single routing rule.
+///
+/// `source`, `sourcetype` and `collector` are patterns where `*`
+/// matches any run of characters. A rule matches when every pattern it
+/// sets matches the event.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRule {
+    pub source: Option<String>,
+    pub sourcetype: Option<String>,
+    /// Route name of the collector that produced the event, e.g.
+    /// `azure_dev_ops`. Only known for events sent through a
+    /// [ManifestSink](crate::manifest::ManifestSink)
+    pub collector: Option<String>,
+    pub destinations: Vec<String>,
+    /// Replace the index of matching events
+    pub set_index: Option<String>,
+    /// Replace the sourcetype of matching events
+    pub set_sourcetype: Option<String>,
+}
+
+impl RoutingConfig {
+    /// Load the routing table from the file named by
+    /// SSPHP_ROUTING_FILE, returns `None` if it is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("SSPHP_ROUTING_FILE") else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading routing file: {}", path))?;
+        Ok(Some(Self::from_toml(&contents)?))
+    }
+
+    /// Parse and validate a routing table
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        let config: RoutingConfig = toml::from_str(contents).context("Parsing routing TOML")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for name in self.default.iter() {
+            self.check_destination(name)
+                .context("Checking default destinations")?;
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.destinations.is_empty() {
+                anyhow::bail!("Routing rule {} has no destinations", i);
+            }
+            for name in rule.destinations.iter() {
+                self.check_destination(name)
+                    .with_context(|| format!("Checking routing rule {}", i))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_destination(&self, name: &str) -> Result<()> {
+        if !self.destinations.contains_key(name) {
+            anyhow::bail!("Unknown routing destination: '{}'", name);
+        }
+        Ok(())
+    }
+}
+
+impl RouteRule {
+    fn matches(&self, collector: Option<&str>, event: &HecEvent) -> bool {
+        let collector_matches = match (&self.collector, collector) {
+            (None, _) => true,
+            (Some(pattern), Some(collector)) => wildcard_match(pattern, collector),
+            (Some(_), None) => false,
+        };
+        collector_matches
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|pattern| wildcard_match(pattern, &event.source))
+            && self
+                .sourcetype
+                .as_ref()
+                .is_none_or(|pattern| wildcard_match(pattern, &event.sourcetype))
+    }
+
+    fn rewrite(&self, event: &mut HecEvent) {
+        if let Some(index) = &self.set_index {
+            event.index = Some(index.to_string());
+        }
+        if let Some(sourcetype) = &self.set_sourcetype {
+            event.sourcetype = sourcetype.to_string();
+        }
+    }
+}
+
+/// Match `value` against `pattern` where `*` matches any run of
+/// characters, including none
+pub(crate) fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` in the pattern
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(found) => rest = &rest[found + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// A [HecSink] sending events to destinations picked by a [RoutingConfig]
+pub struct RoutingSink {
+    config: RoutingConfig,
+    /// Destinations in the same order as [RoutingConfig::destinations]
+    destinations: Vec<(String, Arc<dyn HecSink>)>,
+}
+
+impl RoutingSink {
+    /// Build a sink for each destination with `build_sink`, which is
+    /// passed the destination's sink spec
+    pub fn new<F>(config: RoutingConfig, build_sink: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Arc<dyn HecSink>>,
+    {
+        config.validate()?;
+        let destinations = config
+            .destinations
+            .iter()
+            .map(|(name, spec)| {
+                build_sink(spec)
+                    .with_context(|| format!("Building routing destination '{}'", name))
+                    .map(|sink| (name.to_string(), sink))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            config,
+            destinations,
+        })
+    }
+
+    fn destination_index(&self, name: &str) -> Option<usize> {
+        self.destinations
+            .iter()
+            .position(|(destination, _)| destination == name)
+    }
+
+    /// Split events into a batch for each destination, applying the
+    /// rewrites of the matching rule
+    pub(crate) fn route(
+        &self,
+        collector: Option<&str>,
+        events: Vec<HecEvent>,
+    ) -> Vec<Vec<HecEvent>> {
+        let mut batches = vec![vec![]; self.destinations.len()];
+        let mut unrouted = 0;
+        for mut event in events {
+            let destinations = match self
+                .config
+                .rules
+                .iter()
+                .find(|rule| rule.matches(collector, &event))
+            {
+                Some(rule) => {
+                    rule.rewrite(&mut event);
+                    &rule.destinations
+                }
+                None => &self.config.default,
+            };
+            if destinations.is_empty() {
+                unrouted += 1;
+                continue;
+            }
+            for name in destinations {
+                if let Some(i) = self.destination_index(name) {
+                    batches[i].push(event.clone());
+                }
+            }
+        }
+        if unrouted > 0 {
+            warn!(
+                name = "HecSink",
+                operation = "Route events",
+                collector = collector,
+                events = unrouted,
+                "Dropping events that match no routing rule"
+            );
+        }
+        batches
+    }
+
+    async fn send(&self, collector: Option<&str>, events: Vec<HecEvent>) -> Result<()> {
+        let mut failed = vec![];
+        for ((name, sink), batch) in self.destinations.iter().zip(self.route(collector, events)) {
+            if batch.is_empty() {
+                continue;
+            }
+            if let Err(err) = sink.send_batch(batch).await {
+                error!(name="HecSink", operation="Routing send_batch", destination=name, sink=sink.name(), error=?err);
+                failed.push(format!("{}: {:?}", name, err));
+            }
+        }
+        if !failed.is_empty() {
+            return Err(anyhow!(failed.join("\n")));
+        }
+        Ok(())
+    }
+}
+
+impl HecSink for RoutingSink {
+    fn name(&self) -> &str {
+        "routing"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(self.send(None, events))
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        Box::pin(self.send(Some(collector), events))
+    }
+
+    fn record_step(&self, step: ManifestStep) {
+        for (_, sink) in self.destinations.iter() {
+            sink.record_step(step.clone());
+        }
+    }
+
+    /// Shutdown each destination in turn within the one `deadline`
+    fn shutdown(&self, deadline: Duration) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let mut report = ShutdownReport::default();
+            for (_, sink) in self.destinations.iter() {
+                report.merge(
+                    sink.shutdown(deadline.saturating_sub(started.elapsed()))
+                        .await,
+                );
+            }
+            report
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{wildcard_match, RoutingConfig, RoutingSink};
+    use crate::manifest::ManifestSink;
+    use crate::sink::{HecSink, SinkFuture};
+    use crate::splunk::HecEvent;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    const CONFIG: &str = r#"
+default = ["splunk"]
+
+[destinations]
+splunk = "splunk"
+ian = "ian_splunk"
+
+[[rules]]
+collector = "azure_dev_ops"
+destinations = ["splunk", "ian"]
+
+[[rules]]
+sourcetype = "ssphp:aws:*"
+destinations = ["ian"]
+set_index = "ssphp_aws"
+set_sourcetype = "ssphp:aws:routed"
+"#;
+
+    #[derive(Default)]
+    struct CollectingSink {
+        events: Mutex<Vec<HecEvent>>,
+    }
+
+    impl HecSink for CollectingSink {
+        fn name(&self) -> &str {
+            "collecting"
+        }
+
+        fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+            self.events.lock().unwrap().extend(events);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn event(source: &str, sourcetype: &str) -> HecEvent {
+        let data = HashMap::from([("foo", "bar")]);
+        HecEvent::new_with_ssphp_run(&data, source, sourcetype, 1).unwrap()
+    }
+
+    fn routing_sink() -> (RoutingSink, HashMap<String, Arc<CollectingSink>>) {
+        let sinks = HashMap::from([
+            ("splunk".to_string(), Arc::new(CollectingSink::default())),
+            (
+                "ian_splunk".to_string(),
+                Arc::new(CollectingSink::default()),
+            ),
+        ]);
+        let config = RoutingConfig::from_toml(CONFIG).unwrap();
+        let routing = RoutingSink::new(config, |spec| {
+            Ok(sinks.get(spec).unwrap().clone() as Arc<dyn HecSink>)
+        })
+        .unwrap();
+        (routing, sinks)
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("ssphp:aws:*", "ssphp:aws:iam"));
+        assert!(wildcard_match("ssphp:aws:*", "ssphp:aws:"));
+        assert!(!wildcard_match("ssphp:aws:*", "ssphp:azure:iam"));
+        assert!(wildcard_match("*:iam", "ssphp:aws:iam"));
+        assert!(wildcard_match("ssphp:*:iam", "ssphp:aws:iam"));
+        assert!(!wildcard_match("ssphp:*:iam", "ssphp:aws:s3"));
+        assert!(wildcard_match("a*a", "aa"));
+        assert!(!wildcard_match("aa*aa", "aaa"));
+        assert!(wildcard_match("exact", "exact"));
+        assert!(!wildcard_match("exact", "exactly"));
+        assert!(wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn test_routing_config_rejects_unknown_destinations() {
+        assert!(RoutingConfig::from_toml(CONFIG).is_ok());
+        let unknown_default = r#"
+default = ["missing"]
+[destinations]
+splunk = "splunk"
+"#;
+        assert!(RoutingConfig::from_toml(unknown_default).is_err());
+        let unknown_rule = r#"
+[destinations]
+splunk = "splunk"
+[[rules]]
+source = "foo"
+destinations = ["missing"]
+"#;
+        assert!(RoutingConfig::from_toml(unknown_rule).is_err());
+        let no_destinations = r#"
+[destinations]
+splunk = "splunk"
+[[rules]]
+source = "foo"
+destinations = []
+"#;
+        assert!(RoutingConfig::from_toml(no_destinations).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_routing_sink_applies_first_matching_rule() {
+        let (routing, sinks) = routing_sink();
+        routing
+            .send_batch(vec![
+                event("aws", "ssphp:aws:iam"),
+                event("github", "ssphp:github:repo"),
+            ])
+            .await
+            .unwrap();
+
+        let splunk = sinks["splunk"].events.lock().unwrap();
+        assert_eq!(splunk.len(), 1);
+        assert_eq!(splunk[0].source, "github");
+        assert_eq!(splunk[0].index, None);
+
+        let ian = sinks["ian_splunk"].events.lock().unwrap();
+        assert_eq!(ian.len(), 1);
+        assert_eq!(ian[0].index.as_deref(), Some("ssphp_aws"));
+        assert_eq!(ian[0].sourcetype, "ssphp:aws:routed");
+        let json = serde_json::to_value(&ian[0]).unwrap();
+        assert_eq!(json["index"], "ssphp_aws");
+    }
+
+    #[tokio::test]
+    async fn test_routing_sink_routes_by_collector_through_manifest_sink() {
+        let (routing, sinks) = routing_sink();
+        let routing = Arc::new(routing);
+        routing
+            .send_batch(vec![event("ado", "ssphp:ado:json")])
+            .await
+            .unwrap();
+        assert_eq!(sinks["ian_splunk"].events.lock().unwrap().len(), 0);
+
+        let manifest = ManifestSink::new("azure_dev_ops", routing);
+        manifest
+            .send_batch(vec![event("ado", "ssphp:ado:json")])
+            .await
+            .unwrap();
+        assert_eq!(sinks["splunk"].events.lock().unwrap().len(), 2);
+        assert_eq!(sinks["ian_splunk"].events.lock().unwrap().len(), 1);
+    }
+}
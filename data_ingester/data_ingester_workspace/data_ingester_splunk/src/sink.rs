@@ -0,0 +1,510 @@
+use crate::manifest::ManifestStep;
+use crate::splunk::{HecEvent, ShutdownReport, Splunk};
+use anyhow::{anyhow, Context, Result};
+use data_ingester_supporting::keyvault::Secrets;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs,
+    future::Future,
+    io::Write,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::error;
+use uuid::Uuid;
+
+/// Future returned by [HecSink::send_batch]
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Future returned by [HecSink::shutdown]
+pub type ShutdownFuture<'a> = Pin<Box<dyn Future<Output = ShutdownReport> + Send + 'a>>;
+
+/// A destination for [HecEvent]s.
+///
+/// Collectors send their events to a [HecSink] rather than directly
+/// to Splunk so the same data can be written to local files, stdout
+/// or several HEC endpoints.
+pub trait HecSink: Send + Sync {
+    /// Name of the sink used for logging
+    fn name(&self) -> &str;
+
+    /// Send a batch of events to the sink
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_>;
+
+    /// Send a batch of events produced by `collector`.
+    ///
+    /// Only a [RoutingSink](crate::routing::RoutingSink) uses the
+    /// collector, other sinks send the batch as normal
+    fn send_collector_batch<'a>(
+        &'a self,
+        _collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        self.send_batch(events)
+    }
+
+    /// Record the result of a [try_collect_send](crate::splunk::try_collect_send) step.
+    ///
+    /// Ignored unless the sink is a [ManifestSink](crate::manifest::ManifestSink)
+    fn record_step(&self, _step: ManifestStep) {}
+
+    /// Flush any buffered events before the process exits, waiting at
+    /// most `deadline`.
+    ///
+    /// Sinks that write events as they are sent have nothing to flush
+    fn shutdown(&self, _deadline: Duration) -> ShutdownFuture<'_> {
+        Box::pin(async { ShutdownReport::default() })
+    }
+}
+
+/// Lets a shared `Arc<dyn HecSink>` be passed wherever a `&dyn HecSink` is expected
+impl<T: HecSink + ?Sized> HecSink for Arc<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        (**self).send_batch(events)
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        (**self).send_collector_batch(collector, events)
+    }
+
+    fn record_step(&self, step: ManifestStep) {
+        (**self).record_step(step)
+    }
+
+    fn shutdown(&self, deadline: Duration) -> ShutdownFuture<'_> {
+        (**self).shutdown(deadline)
+    }
+}
+
+impl HecSink for Splunk {
+    fn name(&self) -> &str {
+        "splunk_hec"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(Splunk::send_batch(self, events))
+    }
+
+    fn shutdown(&self, deadline: Duration) -> ShutdownFuture<'_> {
+        Box::pin(Splunk::shutdown(self, deadline))
+    }
+}
+
+/// Send every batch to all of the wrapped sinks.
+///
+/// A failing sink does not stop the batch being sent to the remaining
+/// sinks. An error is returned if any of the sinks failed.
+pub struct FanOutSink {
+    sinks: Vec<Arc<dyn HecSink>>,
+}
+
+impl FanOutSink {
+    pub fn new(sinks: Vec<Arc<dyn HecSink>>) -> Self {
+        Self { sinks }
+    }
+
+    async fn send(&self, collector: Option<&str>, events: Vec<HecEvent>) -> Result<()> {
+        let mut failed = vec![];
+        for sink in self.sinks.iter() {
+            let result = match collector {
+                Some(collector) => sink.send_collector_batch(collector, events.clone()).await,
+                None => sink.send_batch(events.clone()).await,
+            };
+            if let Err(err) = result {
+                error!(name="HecSink", operation="FanOut send_batch", sink=sink.name(), error=?err);
+                failed.push(format!("{}: {:?}", sink.name(), err));
+            }
+        }
+        if !failed.is_empty() {
+            return Err(anyhow!(failed.join("\n")));
+        }
+        Ok(())
+    }
+}
+
+impl HecSink for FanOutSink {
+    fn name(&self) -> &str {
+        "fan_out"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(self.send(None, events))
+    }
+
+    fn send_collector_batch<'a>(
+        &'a self,
+        collector: &'a str,
+        events: Vec<HecEvent>,
+    ) -> SinkFuture<'a> {
+        Box::pin(self.send(Some(collector), events))
+    }
+
+    /// Record the step on every sink, so a [ManifestSink](crate::manifest::ManifestSink)
+    /// behind the fan out still sees it
+    fn record_step(&self, step: ManifestStep) {
+        for sink in self.sinks.iter() {
+            sink.record_step(step.clone());
+        }
+    }
+
+    /// Shutdown each sink in turn within the one `deadline`
+    fn shutdown(&self, deadline: Duration) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let mut report = ShutdownReport::default();
+            for sink in self.sinks.iter() {
+                report.merge(
+                    sink.shutdown(deadline.saturating_sub(started.elapsed()))
+                        .await,
+                );
+            }
+            report
+        })
+    }
+}
+
+/// Append events as newline delimited JSON to local files.
+///
+/// Events are written to one file per sourcetype in `directory`. Each
+/// file is opened once and kept open for the life of the sink.
+pub struct NdjsonFileSink {
+    directory: PathBuf,
+    files: Mutex<HashMap<PathBuf, tokio::fs::File>>,
+}
+
+impl NdjsonFileSink {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Creating NDJSON sink directory: {}", directory.display()))?;
+        Ok(Self {
+            directory,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn write(&self, events: &[HecEvent]) -> Result<()> {
+        let mut by_path: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+        for event in events {
+            let path = self
+                .directory
+                .join(file_name(&event.sourcetype))
+                .with_extension("ndjson");
+            write_event(by_path.entry(path).or_default(), event)?;
+        }
+
+        let mut files = self.files.lock().await;
+        for (path, buffer) in by_path {
+            let file = match files.entry(path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let file = tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(entry.key())
+                        .await
+                        .with_context(|| {
+                            format!("Opening NDJSON sink file: {}", entry.key().display())
+                        })?;
+                    entry.insert(file)
+                }
+            };
+            file.write_all(&buffer)
+                .await
+                .context("Writing NDJSON sink file")?;
+            file.flush().await.context("Flushing NDJSON sink file")?;
+        }
+        Ok(())
+    }
+}
+
+impl HecSink for NdjsonFileSink {
+    fn name(&self) -> &str {
+        "ndjson"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(async move { self.write(&events).await })
+    }
+}
+
+/// A stand in for an S3 / Blob style object store.
+///
+/// Each batch is written as a single NDJSON object under
+/// `{root}/{sourcetype}/{millis}-{uuid}.ndjson`, mirroring the key
+/// layout used when uploading to a bucket or container.
+pub struct ObjectStoreSink {
+    root: PathBuf,
+}
+
+impl ObjectStoreSink {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)
+            .with_context(|| format!("Creating object store sink root: {}", root.display()))?;
+        Ok(Self { root })
+    }
+
+    async fn put(&self, events: &[HecEvent]) -> Result<()> {
+        let Some(first) = events.first() else {
+            return Ok(());
+        };
+        let prefix = self.root.join(file_name(&first.sourcetype));
+        tokio::fs::create_dir_all(&prefix)
+            .await
+            .with_context(|| format!("Creating object store prefix: {}", prefix.display()))?;
+
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let key = prefix
+            .join(format!("{:020}-{}", millis, Uuid::new_v4()))
+            .with_extension("ndjson");
+
+        let mut object = vec![];
+        for event in events {
+            write_event(&mut object, event)?;
+        }
+        tokio::fs::write(&key, object)
+            .await
+            .with_context(|| format!("Writing object store object: {}", key.display()))?;
+        Ok(())
+    }
+}
+
+impl HecSink for ObjectStoreSink {
+    fn name(&self) -> &str {
+        "object_store"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(async move { self.put(&events).await })
+    }
+}
+
+/// Print events as newline delimited JSON to stdout
+#[derive(Default)]
+pub struct StdoutSink {}
+
+impl HecSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    fn send_batch(&self, events: Vec<HecEvent>) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let mut buffer = vec![];
+            for event in events.iter() {
+                write_event(&mut buffer, event)?;
+            }
+            let mut stdout = tokio::io::stdout();
+            stdout
+                .write_all(&buffer)
+                .await
+                .context("Writing to stdout")?;
+            stdout.flush().await.context("Flushing stdout")?;
+            Ok(())
+        })
+    }
+}
+
+/// Build a sink from a sink specification.
+///
+/// stdout: [StdoutSink]
+/// ndjson:<directory>: [NdjsonFileSink]
+/// object_store:<directory>: [ObjectStoreSink]
+/// hec:<token>@<host>: an additional [Splunk] HEC endpoint
+pub fn sink_from_spec(spec: &str) -> Result<Arc<dyn HecSink>> {
+    let (kind, target) = spec.split_once(':').unwrap_or((spec, ""));
+    let sink: Arc<dyn HecSink> = match kind {
+        "stdout" => Arc::new(StdoutSink::default()),
+        "ndjson" => Arc::new(NdjsonFileSink::new(non_empty(spec, target)?)?),
+        "object_store" => Arc::new(ObjectStoreSink::new(non_empty(spec, target)?)?),
+        "hec" => {
+            let (token, host) = non_empty(spec, target)?
+                .split_once('@')
+                .with_context(|| format!("Sink spec '{spec}' should be hec:<token>@<host>"))?;
+            Arc::new(Splunk::new(host, token, false).context("Building HEC sink")?)
+        }
+        _ => anyhow::bail!("Unknown sink spec: '{spec}'"),
+    };
+    Ok(sink)
+}
+
+/// Build a sink from a sink specification, including the Splunk HEC
+/// endpoints held in [Secrets].
+///
+/// splunk: [Splunk] using `splunk_host` and `splunk_token`
+/// ian_splunk: [Splunk] using `ian_splunk_host` and `ian_splunk_token`
+/// Any other spec is built by [sink_from_spec]
+pub fn sink_from_secrets(spec: &str, secrets: &Secrets) -> Result<Arc<dyn HecSink>> {
+    let (host, token) = match spec {
+        "splunk" => (&secrets.splunk_host, &secrets.splunk_token),
+        "ian_splunk" => (&secrets.ian_splunk_host, &secrets.ian_splunk_token),
+        _ => return sink_from_spec(spec),
+    };
+    Ok(Arc::new(
+        Splunk::new(
+            host.as_ref()
+                .with_context(|| format!("Expect {spec}_host secret for the {spec} sink"))?,
+            token
+                .as_ref()
+                .with_context(|| format!("Expect {spec}_token secret for the {spec} sink"))?,
+            false,
+        )
+        .context("Create Splunk Client")?,
+    ))
+}
+
+/// Additional sinks from the comma separated SSPHP_SINKS environment
+/// variable, see [sink_from_spec]
+pub fn sinks_from_env() -> Result<Vec<Arc<dyn HecSink>>> {
+    let Ok(specs) = std::env::var("SSPHP_SINKS") else {
+        return Ok(vec![]);
+    };
+    specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| sink_from_spec(spec).with_context(|| format!("Building sink '{spec}'")))
+        .collect()
+}
+
+fn non_empty<'a>(spec: &str, target: &'a str) -> Result<&'a str> {
+    if target.is_empty() {
+        anyhow::bail!("Sink spec '{spec}' is missing a target");
+    }
+    Ok(target)
+}
+
+/// Make a sourcetype safe to use as a file name
+fn file_name(sourcetype: &str) -> PathBuf {
+    sourcetype
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .into()
+}
+
+fn write_event<W: Write>(writer: &mut W, event: &HecEvent) -> Result<()> {
+    serde_json::to_writer(&mut *writer, event).context("Serializing HecEvent")?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        sink_from_spec, FanOutSink, HecSink, NdjsonFileSink, ObjectStoreSink, StdoutSink,
+    };
+    use crate::manifest::{ManifestSink, ManifestStep};
+    use crate::splunk::HecEvent;
+    use crate::spool::test::spool_dir;
+    use anyhow::anyhow;
+    use std::{collections::HashMap, fs, sync::Arc};
+
+    fn events(count: usize) -> Vec<HecEvent> {
+        (0..count)
+            .map(|i| {
+                let mut data = HashMap::new();
+                let _ = data.insert("count", i);
+                HecEvent::new_with_ssphp_run(&data, "sink_source", "ssphp:sink:json", 1)
+                    .expect("Building events should not fail")
+            })
+            .collect()
+    }
+
+    struct FailingSink {}
+
+    impl HecSink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn send_batch(&self, _events: Vec<HecEvent>) -> super::SinkFuture<'_> {
+            Box::pin(async { Err(anyhow!("always fails")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_file_sink_appends_per_sourcetype() {
+        let dir = spool_dir();
+        let sink = NdjsonFileSink::new(&dir).unwrap();
+        sink.send_batch(events(2)).await.unwrap();
+        sink.send_batch(events(1)).await.unwrap();
+
+        let contents = fs::read_to_string(dir.join("ssphp_sink_json.ndjson")).unwrap();
+        let lines: Vec<HecEvent> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].source, "sink_source");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_object_store_sink_writes_one_object_per_batch() {
+        let dir = spool_dir();
+        let sink = ObjectStoreSink::new(&dir).unwrap();
+        sink.send_batch(events(2)).await.unwrap();
+        sink.send_batch(events(2)).await.unwrap();
+        sink.send_batch(vec![]).await.unwrap();
+
+        let objects = fs::read_dir(dir.join("ssphp_sink_json")).unwrap().count();
+        assert_eq!(objects, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_sink_sends_to_all_sinks_and_reports_failure() {
+        let dir_a = spool_dir();
+        let dir_b = spool_dir();
+        let sink = FanOutSink::new(vec![
+            Arc::new(NdjsonFileSink::new(&dir_a).unwrap()),
+            Arc::new(FailingSink {}),
+            Arc::new(NdjsonFileSink::new(&dir_b).unwrap()),
+        ]);
+        let result = sink.send_batch(events(1)).await;
+        assert!(result.is_err());
+        assert!(dir_a.join("ssphp_sink_json.ndjson").exists());
+        assert!(dir_b.join("ssphp_sink_json.ndjson").exists());
+        fs::remove_dir_all(dir_a).unwrap();
+        fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_sink_records_steps_on_every_sink() {
+        let manifest = Arc::new(ManifestSink::new("fan_out", Arc::new(StdoutSink::default())));
+        let sink = FanOutSink::new(vec![manifest.clone(), Arc::new(FailingSink {})]);
+        sink.record_step(ManifestStep::new("step"));
+        assert_eq!(manifest.manifest(None).unwrap().steps_total, 1);
+    }
+
+    #[test]
+    fn test_sink_from_spec() {
+        assert_eq!(sink_from_spec("stdout").unwrap().name(), "stdout");
+        let dir = spool_dir();
+        let spec = format!("ndjson:{}", dir.display());
+        assert_eq!(sink_from_spec(&spec).unwrap().name(), "ndjson");
+        assert!(sink_from_spec("ndjson").is_err());
+        assert!(sink_from_spec("hec:no_host").is_err());
+        assert!(sink_from_spec("kafka:topic").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -9,15 +9,30 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     future::Future,
-    sync::{LazyLock, RwLock},
-    time::{SystemTime, UNIX_EPOCH},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::mpsc::{channel, Sender, WeakSender},
+    task::JoinHandle,
+    time::MissedTickBehavior,
 };
-use tokio::sync::mpsc::{channel, Sender};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::batch::BatchConfig;
+use crate::manifest::ManifestStep;
+use crate::redaction::redactor;
+use crate::resend::{ResendConfig, ResendCounts};
+use crate::sink::HecSink;
+use crate::spool::{Spool, SpoolConfig, SpoolDepth};
 use crate::tasks::AckTask;
 use crate::tasks::SendingTask;
+use data_ingester_supporting::metrics::{EventStage, METRICS};
 
 // Legacy, just used for tests and logs
 static SSPHP_RUN: RwLock<u64> = RwLock::new(0_u64);
@@ -33,14 +48,24 @@ pub struct HecEvent {
     pub source: String,
     pub sourcetype: String,
     pub host: String,
+    /// Splunk index to write the event to, the HEC token's default
+    /// index is used when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
     pub event: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fields: Option<HecFields>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct HecFields {
     pub resend_count: i32,
+    /// Size of `event` before it was truncated to fit in a HEC batch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated_from_bytes: Option<usize>,
+    /// Sourcetype of the event before it was dead lettered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_sourcetype: Option<String>,
 }
 
 impl HecEvent {
@@ -56,7 +81,8 @@ impl HecEvent {
             source: source.to_string(),
             sourcetype: sourcetype.to_string(),
             host: hostname,
-            event: serde_json::to_string(&ssphp_event)?,
+            index: None,
+            event: ssphp_event.to_json(sourcetype)?,
             fields: None,
         })
     }
@@ -75,7 +101,8 @@ impl HecEvent {
             source: source.to_string(),
             sourcetype: sourcetype.to_string(),
             host: hostname,
-            event: serde_json::to_string(&ssphp_event)?,
+            index: None,
+            event: ssphp_event.to_json(sourcetype)?,
             fields: None,
         })
     }
@@ -84,7 +111,10 @@ impl HecEvent {
         if let Some(ref mut fields) = self.fields {
             fields.resend_count += 1;
         } else {
-            self.fields = Some(HecFields { resend_count: 1 })
+            self.fields = Some(HecFields {
+                resend_count: 1,
+                ..Default::default()
+            })
         };
     }
 }
@@ -130,6 +160,18 @@ struct SsphpEvent<T: Serialize> {
     event: T,
 }
 
+impl<T: Serialize> SsphpEvent<T> {
+    /// Serialize the event, applying any redaction rules for `sourcetype`
+    fn to_json(&self, sourcetype: &str) -> Result<String> {
+        match redactor() {
+            Some(redactor) if redactor.applies_to(sourcetype) => {
+                redactor.redact_to_string(sourcetype, self)
+            }
+            _ => Ok(serde_json::to_string(self)?),
+        }
+    }
+}
+
 pub fn set_ssphp_run(ssphp_run_key: &str) -> Result<()> {
     let start = SystemTime::now();
     let since_the_epoch = start.duration_since(UNIX_EPOCH)?.as_secs();
@@ -218,14 +260,54 @@ pub trait ToHecEvents {
         ssphp_run
     }
     fn ssphp_run_key(&self) -> &str;
+    /// Errors for parts of the collection that failed without failing
+    /// it, recorded on the [ManifestStep] by [try_collect_send]
+    fn errors(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 pub struct Splunk {
-    #[allow(unused)]
     pub(crate) sending_task: SendingTask,
-    #[allow(unused)]
     pub(crate) ack_task: AckTask,
     pub(crate) send_tx: Sender<HecEvent>,
+    pub(crate) spool: Option<Arc<Spool>>,
+    /// Retries spooled batches that failed to send
+    pub(crate) spool_retry: Option<JoinHandle<()>>,
+    /// Set by [Splunk::shutdown] to stop accepting events
+    pub(crate) shutting_down: AtomicBool,
+}
+
+/// Events a [Splunk] client could not deliver before the deadline
+/// passed to [Splunk::shutdown]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ShutdownReport {
+    /// Events still queued to be sent
+    pub queued: usize,
+    /// Events taken from the queue but not sent
+    pub in_flight: usize,
+    /// Events sent but not acknowledged by Splunk
+    pub unacknowledged: usize,
+    /// Spool segments left to be replayed by the next client
+    pub spooled_segments: usize,
+    /// The deadline passed before the queues were drained
+    pub timed_out: bool,
+}
+
+impl ShutdownReport {
+    /// Total number of events that were not delivered
+    pub fn undelivered(&self) -> usize {
+        self.queued + self.in_flight + self.unacknowledged
+    }
+
+    /// Add the report of another client to this one
+    pub fn merge(&mut self, other: ShutdownReport) {
+        self.queued += other.queued;
+        self.in_flight += other.in_flight;
+        self.unacknowledged += other.unacknowledged;
+        self.spooled_segments += other.spooled_segments;
+        self.timed_out |= other.timed_out;
+    }
 }
 
 unsafe impl Send for Splunk {}
@@ -237,9 +319,74 @@ pub(crate) struct Message {
 }
 
 impl Splunk {
+    /// Create a new Splunk client.
+    ///
+    /// Enables the on-disk spool if SPLUNK_SPOOL_DIR is set, see [SpoolConfig::from_env]
     pub fn new(host: &str, token: &str, hec_acknowledgment: bool) -> Result<Self> {
+        let spool_config = SpoolConfig::from_env().context("Reading Splunk spool config")?;
+        Self::new_with_spool(host, token, hec_acknowledgment, spool_config)
+    }
+
+    /// Whether to ask Splunk to acknowledge indexed events, read from
+    /// SPLUNK_HEC_ACKNOWLEDGMENT and off when unset.
+    ///
+    /// With acknowledgment on, batches Splunk fails to index are resent
+    /// and then dead lettered, see [ResendConfig::from_env]. The HEC
+    /// token must have indexer acknowledgment enabled.
+    pub fn hec_acknowledgment_from_env() -> Result<bool> {
+        match std::env::var("SPLUNK_HEC_ACKNOWLEDGMENT") {
+            Ok(hec_acknowledgment) => hec_acknowledgment
+                .parse()
+                .context("Parsing SPLUNK_HEC_ACKNOWLEDGMENT as bool"),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Create a new Splunk client with an optional on-disk spool.
+    ///
+    /// Each `host` and `token` is spooled to its own subdirectory, see
+    /// [SpoolConfig::for_destination]. Any segments left in it by a
+    /// previous client are replayed in the background, and batches
+    /// that fail to send are retried every [SpoolConfig::retry_interval].
+    ///
+    /// Batching is configured by [BatchConfig::from_env] and resending
+    /// by [ResendConfig::from_env]
+    pub fn new_with_spool(
+        host: &str,
+        token: &str,
+        hec_acknowledgment: bool,
+        spool_config: Option<SpoolConfig>,
+    ) -> Result<Self> {
+        let batch_config = BatchConfig::from_env().context("Reading Splunk batch config")?;
+        let resend_config = ResendConfig::from_env().context("Reading Splunk resend config")?;
+        Self::new_with_config(
+            host,
+            token,
+            hec_acknowledgment,
+            spool_config,
+            batch_config,
+            resend_config,
+        )
+    }
+
+    /// Create a new Splunk client with an optional on-disk spool, the
+    /// given [BatchConfig] and [ResendConfig]
+    pub fn new_with_config(
+        host: &str,
+        token: &str,
+        hec_acknowledgment: bool,
+        spool_config: Option<SpoolConfig>,
+        batch_config: BatchConfig,
+        resend_config: ResendConfig,
+    ) -> Result<Self> {
         let url = format!("https://{}", host);
 
+        let spool = spool_config
+            .map(|config| Spool::new(config.for_destination(host, token)))
+            .transpose()
+            .context("Building Splunk Spool")?
+            .map(Arc::new);
+
         let client = Self::new_request_client(token, hec_acknowledgment)
             .context("Building Reqwest Client")?;
 
@@ -252,19 +399,178 @@ impl Splunk {
             ack_tx.clone(),
             url.clone(),
             hec_acknowledgment,
+            spool.clone(),
+            batch_config,
         )
         .context("Building Splunk Sending Task")?;
 
-        let ack_task = AckTask::new(client.clone(), send_tx.clone(), ack_rx, url.clone(), None)
-            .context("Building Splunk Ack Task")?;
+        let ack_task = AckTask::new(
+            client.clone(),
+            send_tx.clone(),
+            ack_rx,
+            url.clone(),
+            None,
+            spool.clone(),
+            resend_config,
+        )
+        .context("Building Splunk Ack Task")?;
+
+        let spool_retry = match spool.as_ref() {
+            Some(spool) => {
+                Self::replay_spool(spool.clone(), send_tx.clone());
+                Some(Self::spawn_spool_retry(spool.clone(), send_tx.downgrade()))
+            }
+            None => None,
+        };
 
         Ok(Self {
             sending_task,
             ack_task,
             send_tx,
+            spool,
+            spool_retry,
+            shutting_down: AtomicBool::new(false),
         })
     }
 
+    /// Queue events left in the spool by a previous run.
+    ///
+    /// The spool is read on the blocking pool so a full spool doesn't
+    /// hold up the client or the runtime.
+    fn replay_spool(spool: Arc<Spool>, send_tx: Sender<HecEvent>) {
+        drop(tokio::spawn(async move {
+            let replay_spool = spool.clone();
+            let segments = match tokio::task::spawn_blocking(move || replay_spool.replay()).await {
+                Ok(Ok(segments)) => segments,
+                Ok(Err(err)) => {
+                    error!(name="SplunkSpool", operation="Read spool", error=?err);
+                    return;
+                }
+                Err(err) => {
+                    error!(name="SplunkSpool", operation="Read spool", error=?err);
+                    return;
+                }
+            };
+            Self::queue_segments(&spool, &send_tx, segments).await;
+        }));
+    }
+
+    /// Every [SpoolConfig::retry_interval] queue the batches that failed
+    /// to send again. Exits once the client is dropped.
+    fn spawn_spool_retry(spool: Arc<Spool>, send_tx: WeakSender<HecEvent>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(spool.retry_interval());
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately
+            let _ = interval.tick().await;
+            loop {
+                let _ = interval.tick().await;
+                let Some(send_tx) = send_tx.upgrade() else {
+                    return;
+                };
+                let failed_spool = spool.clone();
+                let segments = match tokio::task::spawn_blocking(move || failed_spool.take_failed())
+                    .await
+                {
+                    Ok(segments) => segments,
+                    Err(err) => {
+                        error!(name="SplunkSpool", operation="Retry failed segments", error=?err);
+                        continue;
+                    }
+                };
+                Self::queue_segments(&spool, &send_tx, segments).await;
+            }
+        })
+    }
+
+    /// Queue the events of spooled segments to be sent.
+    ///
+    /// Segments are removed once their events are queued as the
+    /// `SendingTask` spools them again.
+    async fn queue_segments(
+        spool: &Spool,
+        send_tx: &Sender<HecEvent>,
+        segments: Vec<(PathBuf, Vec<HecEvent>)>,
+    ) {
+        for (segment, events) in segments {
+            for event in events {
+                if let Err(err) = send_tx.send(event).await {
+                    error!(name="SplunkSpool", operation="Queue spooled events", error=?err);
+                    return;
+                }
+            }
+            spool.remove(&segment);
+        }
+    }
+
+    /// Number of batches and bytes waiting in the spool, `None` if the spool is disabled
+    pub fn spool_depth(&self) -> Option<SpoolDepth> {
+        self.spool.as_ref().map(|spool| spool.depth())
+    }
+
+    /// Stop accepting events and wait up to `deadline` for the queued
+    /// events to be sent and acknowledged.
+    ///
+    /// Returns a [ShutdownReport] of the events that could not be
+    /// delivered. Undelivered batches in the spool are replayed by the
+    /// next client.
+    pub async fn shutdown(&self, deadline: Duration) -> ShutdownReport {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        info!(name="Splunk", operation="Shutdown", deadline=?deadline, queued=self.queued(), "Draining HEC queues");
+
+        // Batches still failing are replayed by the next client
+        if let Some(spool_retry) = self.spool_retry.as_ref() {
+            spool_retry.abort();
+        }
+
+        self.sending_task.shutdown();
+        let mut sending = self.sending_task.take_join();
+        let mut acking = self.ack_task.take_join();
+        let drained = tokio::time::timeout(deadline, async {
+            for join in [sending.as_mut(), acking.as_mut()].into_iter().flatten() {
+                match join.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        error!(name="Splunk", operation="Shutdown", error=?err, "HEC task failed")
+                    }
+                    Err(err) => {
+                        error!(name="Splunk", operation="Shutdown", error=?err, "HEC task panicked")
+                    }
+                }
+            }
+        })
+        .await
+        .is_ok();
+
+        let report = ShutdownReport {
+            queued: self.queued(),
+            in_flight: self.sending_task.in_flight(),
+            unacknowledged: self.ack_task.counters.counts().unacknowledged,
+            spooled_segments: self.spool_depth().map(|depth| depth.segments).unwrap_or(0),
+            timed_out: !drained,
+        };
+        for join in [sending, acking].into_iter().flatten() {
+            join.abort();
+        }
+
+        if report.undelivered() > 0 || report.timed_out {
+            error!(name="Splunk", operation="Shutdown", report=?report, undelivered=report.undelivered(), "HEC queues not drained");
+        } else {
+            info!(name="Splunk", operation="Shutdown", report=?report, "HEC queues drained");
+        }
+        report
+    }
+
+    /// Number of events waiting in the queue for the `SendingTask`
+    fn queued(&self) -> usize {
+        self.send_tx.max_capacity() - self.send_tx.capacity()
+    }
+
+    /// Number of events resent, dead lettered or dropped after failing to be acknowledged
+    pub fn resend_counts(&self) -> ResendCounts {
+        self.ack_task.counters.counts()
+    }
+
     /// Create a Request Client for Splunk
     pub(crate) fn new_request_client(token: &str, hec_acknowledgment: bool) -> Result<Client> {
         let accept_invalid_certs = std::env::var_os("ACCEPT_INVALID_CERTS").is_some();
@@ -292,14 +598,24 @@ impl Splunk {
     }
 
     pub async fn send_batch(&self, events: impl IntoIterator<Item = HecEvent>) -> Result<()> {
+        if self.shutting_down.load(Ordering::Relaxed) {
+            anyhow::bail!("Splunk client is shutting down");
+        }
         for event in events {
             match self.send_tx.reserve().await {
                 Ok(permit) => {
+                    METRICS.events(
+                        EventStage::Queued,
+                        [(event.source.as_str(), event.sourcetype.as_str())],
+                    );
                     permit.send(event);
                 }
+                // Not logged as the error would be sent back to
+                // Splunk by the tracing layer
                 Err(err) => {
-                    error!(operation="SplunkHec", operation="Reserve HecBatch on self.send_tx failed", error=?err);
-                    panic!("FAIED to reserve space for Splunk Batch on send_tx channel");
+                    return Err(
+                        anyhow!(err).context("Reserving space on the Splunk send_tx channel")
+                    )
                 }
             }
         }
@@ -307,26 +623,35 @@ impl Splunk {
     }
 }
 
-/// Run a future to completion and send the results to Splunk.
+/// Run a future to completion and send the results to a [HecSink].
 ///
 /// Logs the start / end / error messages of the collection to Splunk.
+/// The outcome is recorded as a [ManifestStep] on the sink.
 /// TODO Fix this so logging is not excluded.
 pub async fn try_collect_send<T>(
     name: &str,
     future: impl Future<Output = Result<T>>,
-    splunk: &Splunk,
+    splunk: &dyn HecSink,
 ) -> Result<T>
 where
     for<'a> &'a T: ToHecEvents + Debug,
 {
     info!("Getting {}", &name);
+    let started = Instant::now();
+    let mut step = ManifestStep::new(name);
     let result = future.await;
     match &result {
         Ok(ref result) => {
+            step.ssphp_run = Some(result.get_ssphp_run());
+            step.errors = result.errors();
             let hec_events = match result.to_hec_events() {
-                Ok(hec_events) => hec_events,
+                Ok(hec_events) => {
+                    step.event_count = hec_events.len();
+                    hec_events
+                }
                 Err(e) => {
                     warn!("Failed converting to HecEvents: {e}");
+                    step.failed(format!("Failed converting to HecEvents: {e:?}"));
                     vec![HecEvent::new(
                         &Message {
                             event: format!("Failed converting to HecEvents: {e:?}"),
@@ -343,13 +668,17 @@ where
                 }
                 Err(e) => {
                     warn!("Failed Sending to Splunk: {e}");
+                    step.failed(format!("Failed Sending to Splunk: {e:?}"));
                 }
             };
         }
         Err(err) => {
-            warn!("Failed to get {name}: {err:?}")
+            warn!("Failed to get {name}: {err:?}");
+            step.failed(format!("{err:?}"));
         }
     };
+    step.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    splunk.record_step(step);
     result
 }
 #[cfg(test)]
@@ -0,0 +1,541 @@
+use crate::splunk::HecEvent;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+const SEGMENT_EXTENSION: &str = "ndjson";
+const TMP_EXTENSION: &str = "tmp";
+
+/// Configuration for the on-disk HEC spool
+///
+/// Read from the environment by [SpoolConfig::from_env]:
+/// SPLUNK_SPOOL_DIR: directory to write segment files to. The spool is disabled when unset.
+/// SPLUNK_SPOOL_MAX_BYTES: total size of all segments before the oldest are dropped
+/// SPLUNK_SPOOL_MAX_AGE_SECS: segments older than this are dropped instead of replayed
+/// SPLUNK_SPOOL_RETRY_SECS: how often batches that failed to send are retried
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    pub max_bytes: u64,
+    pub max_age: Duration,
+    pub retry_interval: Duration,
+}
+
+impl SpoolConfig {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            max_bytes: 1024 * 1024 * 1024,
+            max_age: Duration::from_secs(60 * 60 * 24),
+            retry_interval: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// The spool for one HEC endpoint and token, in its own
+    /// subdirectory so clients sending to different destinations never
+    /// replay each other's segments
+    pub fn for_destination(&self, host: &str, token: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(host.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(token.as_bytes());
+        let digest = hex::encode(hasher.finalize());
+        let host: String = host
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        Self {
+            directory: self
+                .directory
+                .join(format!("{}-{}", host, &digest[..16])),
+            ..self.clone()
+        }
+    }
+
+    /// Build a [SpoolConfig] from environment variables, returns
+    /// `None` if SPLUNK_SPOOL_DIR is not set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(directory) = std::env::var_os("SPLUNK_SPOOL_DIR") else {
+            return Ok(None);
+        };
+        let mut config = Self::new(directory);
+
+        if let Ok(max_bytes) = std::env::var("SPLUNK_SPOOL_MAX_BYTES") {
+            config.max_bytes = max_bytes
+                .parse()
+                .context("Parsing SPLUNK_SPOOL_MAX_BYTES as u64")?;
+        }
+
+        if let Ok(max_age) = std::env::var("SPLUNK_SPOOL_MAX_AGE_SECS") {
+            config.max_age = Duration::from_secs(
+                max_age
+                    .parse()
+                    .context("Parsing SPLUNK_SPOOL_MAX_AGE_SECS as u64")?,
+            );
+        }
+
+        if let Ok(retry_interval) = std::env::var("SPLUNK_SPOOL_RETRY_SECS") {
+            config.retry_interval = Duration::from_secs(
+                retry_interval
+                    .parse()
+                    .context("Parsing SPLUNK_SPOOL_RETRY_SECS as u64")?,
+            );
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// The current size of the spool
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SpoolDepth {
+    pub segments: usize,
+    pub bytes: u64,
+}
+
+/// A write-ahead spool of HEC batches.
+///
+/// Each batch is written to its own segment file before it is sent
+/// to Splunk and removed once Splunk has accepted (or acknowledged)
+/// it. Segments of batches that failed to send are retried every
+/// [SpoolConfig::retry_interval], and segments left on disk are
+/// replayed the next time a [crate::splunk::Splunk] client is created.
+#[derive(Debug)]
+pub(crate) struct Spool {
+    config: SpoolConfig,
+    /// Running total of the size of all segments
+    bytes: AtomicU64,
+    /// Segments of batches that failed to send, waiting to be retried
+    failed: Mutex<Vec<PathBuf>>,
+}
+
+impl Spool {
+    pub(crate) fn new(config: SpoolConfig) -> Result<Self> {
+        fs::create_dir_all(&config.directory).with_context(|| {
+            format!(
+                "Creating Splunk spool directory: {}",
+                config.directory.display()
+            )
+        })?;
+        let spool = Self {
+            config,
+            bytes: AtomicU64::new(0),
+            failed: Mutex::new(vec![]),
+        };
+        let bytes = spool
+            .segments()?
+            .iter()
+            .map(|(_path, size)| size)
+            .sum();
+        spool.bytes.store(bytes, Ordering::Relaxed);
+        Ok(spool)
+    }
+
+    /// How often segments of batches that failed to send are retried
+    pub(crate) fn retry_interval(&self) -> Duration {
+        self.config.retry_interval
+    }
+
+    /// Persist a batch of events as a new segment on the blocking thread
+    /// pool, so writing and syncing the segment doesn't stall the async
+    /// runtime
+    pub(crate) async fn persist_async(self: &Arc<Self>, events: &[HecEvent]) -> Result<PathBuf> {
+        let segment = serialize_segment(events)?;
+        let spool = self.clone();
+        tokio::task::spawn_blocking(move || spool.write_segment(&segment))
+            .await
+            .context("Joining spool persist task")?
+    }
+
+    /// Persist a batch of events as a new segment
+    #[cfg(test)]
+    pub(crate) fn persist(&self, events: &[HecEvent]) -> Result<PathBuf> {
+        self.write_segment(&serialize_segment(events)?)
+    }
+
+    fn write_segment(&self, segment: &[u8]) -> Result<PathBuf> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let name = format!("{:020}-{}", millis, Uuid::new_v4());
+        let tmp_path = self
+            .config
+            .directory
+            .join(&name)
+            .with_extension(TMP_EXTENSION);
+        let path = self
+            .config
+            .directory
+            .join(&name)
+            .with_extension(SEGMENT_EXTENSION);
+
+        let mut file = fs::File::create(&tmp_path)
+            .with_context(|| format!("Creating spool segment: {}", tmp_path.display()))?;
+        file.write_all(segment)
+            .context("Writing spool segment")?;
+        file.sync_all().context("Syncing spool segment")?;
+        fs::rename(&tmp_path, &path).context("Renaming spool segment")?;
+
+        let size = segment.len() as u64;
+        if self.bytes.fetch_add(size, Ordering::Relaxed) + size > self.config.max_bytes {
+            self.enforce_max_bytes();
+        }
+
+        Ok(path)
+    }
+
+    /// Remove a segment once its events have been delivered
+    pub(crate) fn remove(&self, segment: &Path) {
+        let size = fs::metadata(segment).map(|metadata| metadata.len());
+        match fs::remove_file(segment) {
+            Ok(()) => {
+                let size = size.unwrap_or(0);
+                let _ = self
+                    .bytes
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                        Some(bytes.saturating_sub(size))
+                    });
+            }
+            // Segment may already have been evicted by the size limit
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                error!(name="SplunkSpool", operation="Remove segment", segment=?segment, error=?err);
+            }
+        }
+    }
+
+    /// Keep the segment of a batch that failed to send so it is
+    /// retried by [Spool::take_failed]
+    pub(crate) fn retain_failed(&self, segment: PathBuf) {
+        match self.failed.lock() {
+            Ok(mut failed) => failed.push(segment),
+            Err(err) => {
+                error!(name="SplunkSpool", operation="Retain failed segment", segment=?segment, error=?err);
+            }
+        }
+    }
+
+    /// Read the segments of batches that failed to send since the last
+    /// call, oldest first.
+    ///
+    /// Segments evicted by the size limit are skipped. Returned
+    /// segments are left on disk until the caller [Spool::remove]s them.
+    pub(crate) fn take_failed(&self) -> Vec<(PathBuf, Vec<HecEvent>)> {
+        let mut failed = match self.failed.lock() {
+            Ok(mut failed) => std::mem::take(&mut *failed),
+            Err(err) => {
+                error!(name="SplunkSpool", operation="Retry failed segments", error=?err);
+                return vec![];
+            }
+        };
+        failed.sort();
+        let mut segments = vec![];
+        for path in failed {
+            match read_segment(&path) {
+                Ok(events) => segments.push((path, events)),
+                Err(err)
+                    if err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::NotFound) => {}
+                Err(err) => {
+                    error!(name="SplunkSpool", operation="Retry failed segments", segment=?path, error=?err, "Dropping unreadable segment");
+                    self.remove(&path);
+                }
+            }
+        }
+        if !segments.is_empty() {
+            info!(
+                name = "SplunkSpool",
+                operation = "Retry failed segments",
+                segments = segments.len()
+            );
+        }
+        segments
+    }
+
+    /// Read all segments still on disk, oldest first.
+    ///
+    /// Segments past `max_age` and segments that can't be parsed are
+    /// dropped. Returned segments are left on disk until the caller
+    /// [Spool::remove]s them.
+    pub(crate) fn replay(&self) -> Result<Vec<(PathBuf, Vec<HecEvent>)>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let mut segments = vec![];
+        for (path, _size) in self.segments()? {
+            let expired = segment_created(&path)
+                .map(|created| now.saturating_sub(created) > self.config.max_age)
+                .unwrap_or(true);
+            if expired {
+                warn!(name="SplunkSpool", operation="Replay", segment=?path, "Dropping expired segment");
+                self.remove(&path);
+                continue;
+            }
+
+            match read_segment(&path) {
+                Ok(events) => segments.push((path, events)),
+                Err(err) => {
+                    error!(name="SplunkSpool", operation="Replay", segment=?path, error=?err, "Dropping unreadable segment");
+                    self.remove(&path);
+                }
+            }
+        }
+        info!(
+            name = "SplunkSpool",
+            operation = "Replay",
+            segments = segments.len()
+        );
+        Ok(segments)
+    }
+
+    /// Number of segments and their total size
+    pub(crate) fn depth(&self) -> SpoolDepth {
+        match self.segments() {
+            Ok(segments) => SpoolDepth {
+                segments: segments.len(),
+                bytes: segments.iter().map(|(_path, size)| size).sum(),
+            },
+            Err(err) => {
+                error!(name="SplunkSpool", operation="Depth", error=?err);
+                SpoolDepth::default()
+            }
+        }
+    }
+
+    /// All completed segments and their sizes, oldest first
+    fn segments(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut segments = vec![];
+        for entry in fs::read_dir(&self.config.directory).context("Reading spool directory")? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            segments.push((path, entry.metadata()?.len()));
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Drop the oldest segments until the spool is under `max_bytes`
+    fn enforce_max_bytes(&self) {
+        let segments = match self.segments() {
+            Ok(segments) => segments,
+            Err(err) => {
+                error!(name="SplunkSpool", operation="Enforce max bytes", error=?err);
+                return;
+            }
+        };
+        let mut total: u64 = segments.iter().map(|(_path, size)| size).sum();
+        let mut dropped = 0;
+        for (path, size) in segments.iter() {
+            if total <= self.config.max_bytes {
+                break;
+            }
+            self.remove(path);
+            total -= size;
+            dropped += 1;
+        }
+        if dropped > 0 {
+            error!(
+                name = "SplunkSpool",
+                operation = "Enforce max bytes",
+                dropped_segments = dropped,
+                max_bytes = self.config.max_bytes,
+                "Spool full, oldest segments dropped"
+            );
+        }
+    }
+}
+
+/// Events as newline delimited JSON, the format of a segment
+fn serialize_segment(events: &[HecEvent]) -> Result<Vec<u8>> {
+    let mut segment = vec![];
+    for event in events {
+        serde_json::to_writer(&mut segment, event).context("Serializing HecEvent to spool")?;
+        segment.push(b'\n');
+    }
+    Ok(segment)
+}
+
+/// Segment creation time from the millisecond prefix of its file name
+fn segment_created(path: &Path) -> Option<Duration> {
+    path.file_stem()?
+        .to_str()?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+        .map(Duration::from_millis)
+}
+
+fn read_segment(path: &Path) -> Result<Vec<HecEvent>> {
+    let file = fs::File::open(path)?;
+    let mut events = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line).context("Deserializing spooled HecEvent")?);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Spool, SpoolConfig};
+    use crate::splunk::HecEvent;
+    use std::{
+        collections::HashMap, fs, path::PathBuf, sync::atomic::Ordering, time::Duration,
+    };
+    use uuid::Uuid;
+
+    pub(crate) fn spool_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("ssphp_spool_test_{}", Uuid::new_v4()))
+    }
+
+    fn events(count: usize) -> Vec<HecEvent> {
+        (0..count)
+            .map(|i| {
+                let mut data = HashMap::new();
+                let _ = data.insert("count", i);
+                HecEvent::new_with_ssphp_run(&data, "spool_source", "spool_sourcetype", 1)
+                    .expect("Building events should not fail")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_spool_persist_and_replay() {
+        let dir = spool_dir();
+        let spool = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        let segment = spool.persist(&events(3)).unwrap();
+        assert!(segment.exists());
+
+        let replayed = spool.replay().unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].0, segment);
+        assert_eq!(replayed[0].1.len(), 3);
+        assert_eq!(replayed[0].1[0].source, "spool_source");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_remove() {
+        let dir = spool_dir();
+        let spool = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        let segment = spool.persist(&events(1)).unwrap();
+        spool.remove(&segment);
+        assert!(!segment.exists());
+        assert_eq!(spool.depth().segments, 0);
+        // Removing twice shouldn't fail
+        spool.remove(&segment);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_depth() {
+        let dir = spool_dir();
+        let spool = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        let _ = spool.persist(&events(1)).unwrap();
+        let _ = spool.persist(&events(2)).unwrap();
+        let depth = spool.depth();
+        assert_eq!(depth.segments, 2);
+        assert!(depth.bytes > 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_max_bytes_drops_oldest() {
+        let dir = spool_dir();
+        let mut config = SpoolConfig::new(&dir);
+        config.max_bytes = 1;
+        let spool = Spool::new(config).unwrap();
+        let _ = spool.persist(&events(1)).unwrap();
+        let _ = spool.persist(&events(1)).unwrap();
+        assert_eq!(spool.depth().segments, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_config_for_destination() {
+        let config = SpoolConfig::new("/spool");
+        let a = config.for_destination("splunk.example.com:8088", "token_a");
+        let b = config.for_destination("splunk.example.com:8088", "token_b");
+        assert_ne!(a.directory, b.directory);
+        assert_eq!(a.directory.parent(), Some(config.directory.as_path()));
+        assert!(a
+            .directory
+            .to_string_lossy()
+            .contains("splunk_example_com_8088-"));
+        assert!(!a.directory.to_string_lossy().contains("token_a"));
+        assert_eq!(
+            a.directory,
+            config
+                .for_destination("splunk.example.com:8088", "token_a")
+                .directory
+        );
+    }
+
+    #[test]
+    fn test_spool_take_failed() {
+        let dir = spool_dir();
+        let spool = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        let failed = spool.persist(&events(2)).unwrap();
+        let evicted = spool.persist(&events(1)).unwrap();
+        spool.retain_failed(failed.clone());
+        spool.retain_failed(evicted.clone());
+        spool.remove(&evicted);
+
+        let retried = spool.take_failed();
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].0, failed);
+        assert_eq!(retried[0].1.len(), 2);
+        // Segments are only retried once until they fail again
+        assert!(spool.take_failed().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_bytes_tracks_segments() {
+        let dir = spool_dir();
+        let spool = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        let segment = spool.persist(&events(1)).unwrap();
+        assert_eq!(
+            spool.bytes.load(Ordering::Relaxed),
+            fs::metadata(&segment).unwrap().len()
+        );
+        // A new spool counts the segments already on disk
+        let reopened = Spool::new(SpoolConfig::new(&dir)).unwrap();
+        assert_eq!(
+            reopened.bytes.load(Ordering::Relaxed),
+            spool.bytes.load(Ordering::Relaxed)
+        );
+        spool.remove(&segment);
+        assert_eq!(spool.bytes.load(Ordering::Relaxed), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_max_age_drops_on_replay() {
+        let dir = spool_dir();
+        let mut config = SpoolConfig::new(&dir);
+        config.max_age = Duration::from_secs(0);
+        let spool = Spool::new(config).unwrap();
+        let _ = spool.persist(&events(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(spool.replay().unwrap().is_empty());
+        assert_eq!(spool.depth().segments, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,24 +1,68 @@
+use crate::batch::{batch_events, encode_body, serialize_events, split_batch, BatchConfig};
+use crate::resend::{DeadLetter, ResendConfig, ResendCounters, ResendPolicy};
 use crate::splunk::{HecEvent, HecFields};
+use crate::spool::Spool;
 use anyhow::{Context, Result};
+use data_ingester_supporting::metrics::{EventStage, METRICS};
 use itertools::Itertools;
-use reqwest::{Client, Response, StatusCode};
+use reqwest::{header::CONTENT_ENCODING, Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 use tokio::{
-    sync::mpsc::{
-        error::TryRecvError::{Disconnected, Empty},
-        Receiver, Sender,
+    sync::{
+        mpsc::{
+            error::TryRecvError::{Disconnected, Empty},
+            Receiver, Sender,
+        },
+        watch,
     },
     task::JoinHandle,
     time::{sleep, Duration, Instant},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[derive(Debug, Clone)]
 pub(crate) struct HecBatch {
     ack_id: u32,
     batch: Vec<HecEvent>,
     sent_time: tokio::time::Instant,
+    /// Spool segment holding this batch until it is acknowledged
+    segment: Option<PathBuf>,
+}
+
+impl HecBatch {
+    /// The most times any event in this batch has been resent
+    fn resend_count(&self) -> i32 {
+        self.batch
+            .iter()
+            .filter_map(|event| event.fields.as_ref().map(|fields| fields.resend_count))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Events for the `SendingTask` to send and the signal to stop receiving them
+struct Intake {
+    send_rx: Receiver<HecEvent>,
+    /// Set to `true` to close `send_rx` and exit once it is drained
+    shutdown: watch::Receiver<bool>,
+    /// Number of events received from `send_rx` but not yet sent
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// A batch waiting for its [ResendConfig::backoff] before being resent
+#[derive(Debug)]
+struct PendingResend {
+    due: Instant,
+    batch: HecBatch,
 }
 
 /// Data recieved from Splunk after sending an event via HEC with
@@ -49,16 +93,39 @@ pub(crate) struct HecAckQueryResponseParsed {
     pub(crate) acks: HashMap<u32, bool>,
 }
 
+/// Splunk HEC rejected a batch with `413 Payload Too Large`
+#[derive(Debug)]
+pub(crate) struct PayloadTooLarge;
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Splunk HEC rejected the batch as too large")
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
 /// An async task to send messages to Splunk.
 /// Listens on a channel for `HecEvent`s, serializes them, sends them
 /// to Splunk HEC over HTTP, and forwards their details to the
 /// `AckTask` for indexer acknowledgement
 ///
+/// If a [Spool] is configured each batch is persisted before sending
+/// and only removed once Splunk has accepted it. Batches that fail to
+/// send are left in the spool to be retried.
+///
+/// Batches are limited by [BatchConfig] and split in half when Splunk
+/// responds with `413 Payload Too Large`.
+///
+/// [SendingTask::shutdown] stops the task receiving new events, it
+/// exits once the events already queued have been sent.
+///
 /// https://docs.splunk.com/Documentation/Splunk/9.3.2/Data/AboutHECIDXAck
 ///
 pub(crate) struct SendingTask {
-    #[allow(unused)]
-    join: JoinHandle<Result<()>>,
+    join: Mutex<Option<JoinHandle<Result<()>>>>,
+    shutdown: watch::Sender<bool>,
+    in_flight: Arc<AtomicUsize>,
 }
 
 impl SendingTask {
@@ -68,19 +135,65 @@ impl SendingTask {
         ack_tx: Sender<HecBatch>,
         url: String,
         hec_acknowledgment: bool,
+        spool: Option<Arc<Spool>>,
+        batch_config: BatchConfig,
     ) -> Result<Self> {
         let url = format!("{}/services/collector", &url);
-        let join = Self::spawn_task(splunk, send_rx, ack_tx, url, hec_acknowledgment)
-            .context("Starting Splunk Sending Task")?;
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let intake = Intake {
+            send_rx,
+            shutdown: shutdown_rx,
+            in_flight: in_flight.clone(),
+        };
+        let join = Self::spawn_task(
+            splunk,
+            intake,
+            ack_tx,
+            url,
+            hec_acknowledgment,
+            spool,
+            batch_config,
+        )
+        .context("Starting Splunk Sending Task")?;
+
+        Ok(SendingTask {
+            join: Mutex::new(Some(join)),
+            shutdown,
+            in_flight,
+        })
+    }
+
+    /// Stop receiving new events, the task exits once the queued
+    /// events have been sent.
+    pub(crate) fn shutdown(&self) {
+        let _ = self.shutdown.send_replace(true);
+    }
+
+    /// Number of events received but not yet sent
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
 
-        Ok(SendingTask { join })
+    /// Take the task's [JoinHandle] to wait for it to exit
+    pub(crate) fn take_join(&self) -> Option<JoinHandle<Result<()>>> {
+        self.join.lock().ok().and_then(|mut join| join.take())
     }
 
     // Send a batch to Splunk HEC
-    async fn send_batch_to_splunk(splunk: &Client, batch: &str, url: &str) -> Result<Response> {
+    async fn send_batch_to_splunk(
+        splunk: &Client,
+        body: &[u8],
+        url: &str,
+        gzip: bool,
+    ) -> Result<Response> {
         let mut retry_count = 0;
         let response: Response = loop {
-            let response = splunk.post(url).body(batch.to_string()).send().await;
+            let mut request = splunk.post(url).body(body.to_vec());
+            if gzip {
+                request = request.header(CONTENT_ENCODING, "gzip");
+            }
+            let response = request.send().await;
 
             let response = match response {
                 Ok(response) => response,
@@ -131,6 +244,9 @@ impl SendingTask {
             // 16	        400	                Bad Request     	Query string authorization is not enabled
             if status.is_success() {
                 break response;
+            } else if status == StatusCode::PAYLOAD_TOO_LARGE {
+                log_failed(status);
+                return Err(PayloadTooLarge.into());
             } else if status.is_client_error() {
                 log_failed(status);
                 anyhow::bail!("Client error response when sending to Splunk. Should not happen");
@@ -159,10 +275,11 @@ impl SendingTask {
     ///  Send a batch to Splunk and extract a HecAckResponse for Hec Acknowledgement
     async fn send_batch_to_splunk_with_ack(
         splunk: &Client,
-        batch: &str,
+        body: &[u8],
         url: &str,
+        gzip: bool,
     ) -> Result<HecAckResponse> {
-        let response = Self::send_batch_to_splunk(splunk, batch, url).await?;
+        let response = Self::send_batch_to_splunk(splunk, body, url, gzip).await?;
 
         let response_ack = match response.json::<HecAckResponse>().await {
             Ok(response_ack) => response_ack,
@@ -177,41 +294,98 @@ impl SendingTask {
 
     async fn sending_task(
         splunk: Client,
-        mut send_rx: Receiver<HecEvent>,
+        mut intake: Intake,
         ack_tx: Sender<HecBatch>,
         sending_url: String,
         hec_acknowledgment: bool,
+        spool: Option<Arc<Spool>>,
+        batch_config: BatchConfig,
     ) -> Result<()> {
-        let mut buffer = Vec::with_capacity(100);
+        let mut buffer = Vec::with_capacity(batch_config.max_events);
+        let mut closing = false;
         loop {
             // Get messages from channel
-            let received_count = send_rx.recv_many(&mut buffer, 100).await;
+            let received_count = tokio::select! {
+                received_count = intake.send_rx.recv_many(&mut buffer, batch_config.max_events) => received_count,
+                // Also closes the channel if the owner of the task is dropped
+                _ = intake.shutdown.wait_for(|shutdown| *shutdown), if !closing => {
+                    info!(name="SplunkHec", operation="Shutdown", queued=intake.send_rx.len(), "Draining HEC queue");
+                    intake.send_rx.close();
+                    closing = true;
+                    continue;
+                }
+            };
 
             // Break if channel is closed
             if received_count == 0 {
                 break;
             }
+            intake.in_flight.store(buffer.len(), Ordering::Relaxed);
 
             // Batch events for sending
-            let batches: Vec<(String, Vec<HecEvent>)> =
-                buffer.iter().batching(batch_events).collect();
+            let mut batches: Vec<(String, Vec<HecEvent>)> =
+                serialize_events(buffer.iter(), &batch_config)
+                    .peekable()
+                    .batching(|it| batch_events(it, &batch_config))
+                    .collect();
+
+            // Batches are popped from the end, so batches split after a
+            // 413 response are sent next
+            batches.reverse();
+
+            while let Some(batch) = batches.pop() {
+                let body = match encode_body(&batch.0, batch_config.gzip) {
+                    Ok(body) => body,
+                    Err(err) => {
+                        error!(name="SplunkHec", operation="Encode Hec payload", error=?err);
+                        continue;
+                    }
+                };
+
+                // Write ahead to the spool so the batch survives a restart
+                let segment = match spool.as_ref() {
+                    Some(spool) => match spool.persist_async(&batch.1).await {
+                        Ok(segment) => Some(segment),
+                        Err(err) => {
+                            error!(name="SplunkHec", operation="Persist batch to spool", error=?err);
+                            None
+                        }
+                    },
+                    None => None,
+                };
 
-            for batch in batches.into_iter() {
                 // Send batch and receive the Ack code for the batch
                 if hec_acknowledgment {
-                    let response_ack = Self::send_batch_to_splunk_with_ack(
+                    let response_ack = match Self::send_batch_to_splunk_with_ack(
                         &splunk,
-                        &batch.0,
+                        &body,
                         sending_url.as_str(),
+                        batch_config.gzip,
                     )
                     .await
-                    .context("sending batch to Splunk")?;
+                    .context("sending batch to Splunk")
+                    {
+                        Ok(response_ack) => response_ack,
+                        Err(err) if err.is::<PayloadTooLarge>() => {
+                            Self::rebatch(batch, &mut batches, spool.as_deref(), segment);
+                            continue;
+                        }
+                        Err(err) if segment.is_some() => {
+                            error!(name="SplunkHec", operation="Send Hec payload", segment=?segment, error=?err, "batch retained in spool");
+                            Self::retain_failed(spool.as_deref(), segment);
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                    METRICS.events(EventStage::Sent, source_sourcetypes(&batch.1));
 
                     // Build a batch to enable resending
                     let hec_batch = HecBatch {
                         ack_id: response_ack.ack_id,
                         batch: batch.1,
                         sent_time: tokio::time::Instant::now(),
+                        segment,
                     };
 
                     // Send batch to Ack Task
@@ -224,31 +398,86 @@ impl SendingTask {
                         }
                     }
                 } else {
-                    let _ = Self::send_batch_to_splunk(&splunk, &batch.0, sending_url.as_str())
-                        .await
-                        .context("sending batch to Splunk")?;
+                    match Self::send_batch_to_splunk(
+                        &splunk,
+                        &body,
+                        sending_url.as_str(),
+                        batch_config.gzip,
+                    )
+                    .await
+                    .context("sending batch to Splunk")
+                    {
+                        Ok(_) => {
+                            METRICS.events(EventStage::Sent, source_sourcetypes(&batch.1));
+                            if let (Some(spool), Some(segment)) = (spool.as_ref(), segment) {
+                                spool.remove(&segment);
+                            }
+                        }
+                        Err(err) if err.is::<PayloadTooLarge>() => {
+                            Self::rebatch(batch, &mut batches, spool.as_deref(), segment);
+                        }
+                        Err(err) if segment.is_some() => {
+                            error!(name="SplunkHec", operation="Send Hec payload", segment=?segment, error=?err, "batch retained in spool");
+                            Self::retain_failed(spool.as_deref(), segment);
+                        }
+                        Err(err) => return Err(err),
+                    }
                 }
             }
 
             buffer.clear();
+            intake.in_flight.store(0, Ordering::Relaxed);
         }
         Ok(())
     }
 
+    /// Keep the spool segment of a batch that failed to send so it is
+    /// retried, see [crate::splunk::Splunk::new_with_config]
+    fn retain_failed(spool: Option<&Spool>, segment: Option<PathBuf>) {
+        if let (Some(spool), Some(segment)) = (spool, segment) {
+            spool.retain_failed(segment);
+        }
+    }
+
+    /// Split a batch rejected as too large and queue the parts to be
+    /// sent next. The parts are spooled again when they are sent.
+    fn rebatch(
+        batch: (String, Vec<HecEvent>),
+        batches: &mut Vec<(String, Vec<HecEvent>)>,
+        spool: Option<&Spool>,
+        segment: Option<PathBuf>,
+    ) {
+        if let (Some(spool), Some(segment)) = (spool, segment) {
+            spool.remove(&segment);
+        }
+        let split = split_batch(&batch.0, batch.1);
+        info!(
+            name = "SplunkHec",
+            operation = "Rebatch Hec payload",
+            bytes = batch.0.len(),
+            batches = split.len(),
+        );
+        batches.extend(split.into_iter().rev());
+    }
+
     /// Spawn a new tokio task to send events to Splunk
     fn spawn_task(
         splunk: Client,
-        send_rx: Receiver<HecEvent>,
+        intake: Intake,
         ack_tx: Sender<HecBatch>,
         url: String,
         hec_acknowledgment: bool,
+        spool: Option<Arc<Spool>>,
+        batch_config: BatchConfig,
     ) -> Result<JoinHandle<Result<()>>> {
         let join_handle = tokio::spawn(Self::sending_task(
             splunk,
-            send_rx,
+            intake,
             ack_tx,
             url,
             hec_acknowledgment,
+            spool,
+            batch_config,
         ));
         Ok(join_handle)
     }
@@ -258,11 +487,21 @@ impl SendingTask {
 ///
 /// If a message has failed to Ack after 5mins then send the 'failed'
 /// events back to the `SendingTask` for retransmission.
+///
+/// Batches are resent at most [ResendConfig::max_resends] times, with
+/// an exponential [ResendConfig::backoff] between resends, before being
+/// sent to the [DeadLetter].
+///
+/// Acknowledged and resent batches have their [Spool] segment removed.
+///
+/// Once the `SendingTask` exits the task stops resending and exits
+/// when every outstanding batch is acknowledged or has timed out.
+/// Batches that are not acknowledged are left in the [Spool].
 pub(crate) struct AckTask {
-    #[allow(unused)]
-    join: JoinHandle<Result<()>>,
+    join: Mutex<Option<JoinHandle<Result<()>>>>,
     #[allow(unused)]
     timeout: Duration,
+    pub(crate) counters: Arc<ResendCounters>,
 }
 
 impl AckTask {
@@ -272,6 +511,8 @@ impl AckTask {
         ack_rx: Receiver<HecBatch>,
         url: String,
         timeout: Option<Duration>,
+        spool: Option<Arc<Spool>>,
+        resend_config: ResendConfig,
     ) -> Result<Self> {
         let url = format!("{}/services/collector/ack", &url);
         let timeout = if let Some(timeout) = timeout {
@@ -279,9 +520,20 @@ impl AckTask {
         } else {
             Duration::from_secs(60 * 5)
         };
-        let join = Self::spawn_task(splunk, ack_rx, send_tx, url, timeout)
+        let policy = ResendPolicy::new(resend_config).context("Building resend policy")?;
+        let counters = policy.counters.clone();
+        let join = Self::spawn_task(splunk, ack_rx, send_tx, url, timeout, spool, policy)
             .context("Starting Splunk Ack Task")?;
-        Ok(Self { join, timeout })
+        Ok(Self {
+            join: Mutex::new(Some(join)),
+            timeout,
+            counters,
+        })
+    }
+
+    /// Take the task's [JoinHandle] to wait for it to exit
+    pub(crate) fn take_join(&self) -> Option<JoinHandle<Result<()>>> {
+        self.join.lock().ok().and_then(|mut join| join.take())
     }
 
     fn spawn_task(
@@ -290,8 +542,12 @@ impl AckTask {
         send_tx: Sender<HecEvent>,
         url: String,
         timeout: Duration,
+        spool: Option<Arc<Spool>>,
+        policy: ResendPolicy,
     ) -> Result<JoinHandle<Result<()>>> {
-        let join_handle = tokio::spawn(Self::ack_task(splunk, ack_rx, send_tx, url, timeout));
+        let join_handle = tokio::spawn(Self::ack_task(
+            splunk, ack_rx, send_tx, url, timeout, spool, policy,
+        ));
         Ok(join_handle)
     }
 
@@ -331,10 +587,14 @@ impl AckTask {
         send_tx: Sender<HecEvent>,
         ack_url: String,
         timeout: Duration,
+        spool: Option<Arc<Spool>>,
+        policy: ResendPolicy,
     ) -> Result<()> {
         let mut to_be_acked: HashMap<u32, HecBatch> = HashMap::with_capacity(3200);
+        let mut pending_resends: Vec<PendingResend> = vec![];
         let mut last_ack_time = Instant::now();
         let pause_between_acks = Duration::from_secs(1);
+        let mut draining = false;
         'main: loop {
             // Get messages from channel
 
@@ -346,17 +606,46 @@ impl AckTask {
                     }
                     Err(err) => match err {
                         Empty => break 'recv,
-                        Disconnected => break 'main,
+                        // The `SendingTask` has exited so nothing can be resent
+                        Disconnected => {
+                            draining = true;
+                            break 'recv;
+                        }
                     },
                 };
             }
 
+            if !draining {
+                Self::send_due_resends(&mut pending_resends, &send_tx, spool.as_deref(), &policy)
+                    .await;
+            }
+
+            policy.counters.unacknowledged(
+                to_be_acked
+                    .values()
+                    .chain(pending_resends.iter().map(|pending| &pending.batch))
+                    .map(|batch| batch.batch.len())
+                    .sum(),
+            );
+
             // If there is nothing to ACK, sleep and poll channel again
             if to_be_acked.is_empty() {
+                if draining {
+                    break 'main;
+                }
                 sleep(Duration::from_millis(20)).await;
                 continue;
             }
 
+            // Stop draining once the remaining batches have timed out
+            if draining
+                && to_be_acked
+                    .values()
+                    .all(|batch| batch.sent_time.elapsed() > timeout)
+            {
+                break 'main;
+            }
+
             // Only ack once per pause_between_acks interval
             if Instant::now() - last_ack_time < pause_between_acks {
                 sleep(Duration::from_millis(20)).await;
@@ -387,22 +676,46 @@ impl AckTask {
                     }
                 })
                 .for_each(|(ack_id, state)| {
-                    if *state && to_be_acked.remove(&ack_id).is_none() {
-                        error!(name="Splunk", operation="HecAck", ack_id=?ack_id, "ack_id not found in known acks");
+                    if !*state {
+                        return;
+                    }
+                    match to_be_acked.remove(&ack_id) {
+                        Some(batch) => {
+                            METRICS.events(EventStage::Acked, source_sourcetypes(&batch.batch));
+                            if let (Some(spool), Some(segment)) = (spool.as_ref(), batch.segment) {
+                                spool.remove(&segment);
+                            }
+                        }
+                        None => {
+                            error!(name="Splunk", operation="HecAck", ack_id=?ack_id, "ack_id not found in known acks");
+                        }
                     }
                 });
-            if !to_be_acked.is_empty() {
-                let _ = Self::resend_events(&mut to_be_acked, send_tx.clone(), &timeout).await;
+            if !to_be_acked.is_empty() && !draining {
+                Self::resend_events(
+                    &mut to_be_acked,
+                    &mut pending_resends,
+                    &send_tx,
+                    &timeout,
+                    spool.as_deref(),
+                    &policy,
+                )
+                .await;
             }
         }
         Ok(())
     }
 
+    /// Take batches that have failed to be acknowledged within `timeout`
+    /// and either schedule them to be resent or dead letter them.
     async fn resend_events(
         to_be_acked: &mut HashMap<u32, HecBatch>,
-        send_tx: Sender<HecEvent>,
+        pending_resends: &mut Vec<PendingResend>,
+        send_tx: &Sender<HecEvent>,
         timeout: &Duration,
-    ) -> Result<()> {
+        spool: Option<&Spool>,
+        policy: &ResendPolicy,
+    ) {
         let now = Instant::now();
         let pre_resend_to_be_acked_len = to_be_acked.len();
 
@@ -424,12 +737,51 @@ impl AckTask {
         }
 
         for batch in resend_batches.into_iter() {
+            let resend_count = batch.resend_count();
+            if resend_count >= policy.config.max_resends {
+                Self::dead_letter(batch, send_tx, spool, policy).await;
+            } else {
+                pending_resends.push(PendingResend {
+                    due: now + policy.config.backoff(resend_count),
+                    batch,
+                });
+            }
+        }
+    }
+
+    /// Send batches whose backoff has passed back to the `SendingTask`
+    async fn send_due_resends(
+        pending_resends: &mut Vec<PendingResend>,
+        send_tx: &Sender<HecEvent>,
+        spool: Option<&Spool>,
+        policy: &ResendPolicy,
+    ) {
+        if pending_resends.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let (due, waiting): (Vec<PendingResend>, Vec<PendingResend>) =
+            std::mem::take(pending_resends)
+                .into_iter()
+                .partition(|pending| pending.due <= now);
+        *pending_resends = waiting;
+
+        for PendingResend { batch, .. } in due.into_iter() {
+            // The resent events are spooled again by the `SendingTask`
+            if let (Some(spool), Some(segment)) = (spool, batch.segment.as_ref()) {
+                spool.remove(segment);
+            }
+            METRICS.events(EventStage::Resent, source_sourcetypes(&batch.batch));
+            policy.counters.resent(&batch.batch);
             for mut event in batch.batch.into_iter() {
                 event.fields = if let Some(mut fields) = event.fields {
                     fields.resend_count += 1;
                     Some(fields)
                 } else {
-                    Some(HecFields { resend_count: 1 })
+                    Some(HecFields {
+                        resend_count: 1,
+                        ..Default::default()
+                    })
                 };
 
                 let send_result = send_tx.send(event).await;
@@ -441,61 +793,106 @@ impl AckTask {
                 };
             }
         }
-
-        Ok(())
     }
-}
 
-/// Batch events for Splunk HEC
-///
-/// Split an iterator into batches of events based on their JSON serialized total size.
-/// Each batch should be no larger than (1024 * 950) bytes
-///
-pub(crate) fn batch_events<'a, I>(it: &mut I) -> Option<(String, Vec<HecEvent>)>
-where
-    I: Iterator<Item = &'a HecEvent>,
-{
-    const MAX: usize = 1024 * 950;
-
-    let mut lines = String::with_capacity(MAX);
-    let mut events = Vec::new();
-    let mut size: usize = 0;
-    while size < MAX {
-        match it.next() {
-            None => {
-                break;
-            }
-            Some(x) => {
-                let json = match serde_json::to_string(&x) {
-                    Ok(json) => json,
-                    Err(err) => {
-                        error!("Failed to serialize Item for Splunk:  {err}");
-                        continue;
+    /// Send a batch that has exceeded [ResendConfig::max_resends] to the [DeadLetter]
+    async fn dead_letter(
+        batch: HecBatch,
+        send_tx: &Sender<HecEvent>,
+        spool: Option<&Spool>,
+        policy: &ResendPolicy,
+    ) {
+        if let (Some(spool), Some(segment)) = (spool, batch.segment.as_ref()) {
+            spool.remove(segment);
+        }
+        match &policy.dead_letter {
+            DeadLetter::File(dead_letter) => match dead_letter.persist_async(&batch.batch).await {
+                Ok(segment) => {
+                    warn!(name="SplunkHec", operation="Dead letter HecBatch", segment=?segment, events=batch.batch.len());
+                    METRICS.events(EventStage::DeadLettered, source_sourcetypes(&batch.batch));
+                    policy.counters.dead_lettered(&batch.batch);
+                }
+                Err(err) => {
+                    error!(name="SplunkHec", operation="Dead letter HecBatch", events=batch.batch.len(), error=?err, "dropping batch");
+                    METRICS.events(EventStage::Dropped, source_sourcetypes(&batch.batch));
+                    policy.counters.dropped(&batch.batch);
+                }
+            },
+            DeadLetter::Sourcetype(sourcetype) => {
+                // Events that fail to be acknowledged after being dead
+                // lettered are dropped
+                let (dead_letter, drop): (Vec<HecEvent>, Vec<HecEvent>) =
+                    batch.batch.into_iter().partition(|event| {
+                        event
+                            .fields
+                            .as_ref()
+                            .is_none_or(|fields| fields.original_sourcetype.is_none())
+                    });
+                if !drop.is_empty() {
+                    error!(
+                        name = "SplunkHec",
+                        operation = "Dead letter HecBatch",
+                        events = drop.len(),
+                        "dropping dead lettered events"
+                    );
+                    METRICS.events(EventStage::Dropped, source_sourcetypes(&drop));
+                    policy.counters.dropped(&drop);
+                }
+                if dead_letter.is_empty() {
+                    return;
+                }
+                warn!(
+                    name = "SplunkHec",
+                    operation = "Dead letter HecBatch",
+                    sourcetype = sourcetype,
+                    events = dead_letter.len()
+                );
+                METRICS.events(EventStage::DeadLettered, source_sourcetypes(&dead_letter));
+                policy.counters.dead_lettered(&dead_letter);
+                for mut event in dead_letter.into_iter() {
+                    let original_sourcetype =
+                        std::mem::replace(&mut event.sourcetype, sourcetype.to_string());
+                    event
+                        .fields
+                        .get_or_insert_with(HecFields::default)
+                        .original_sourcetype = Some(original_sourcetype);
+                    if let Err(err) = send_tx.send(event).await {
+                        error!(name="SplunkHec", operation="Transmitting HecEvent to SendingTask", error=?err);
                     }
-                };
-                size += json.len();
-                lines.push_str(json.as_str());
-                lines.push('\n');
-                events.push(x.clone());
+                }
+            }
+            DeadLetter::Drop => {
+                error!(
+                    name = "SplunkHec",
+                    operation = "Dead letter HecBatch",
+                    events = batch.batch.len(),
+                    "no dead letter configured, dropping batch"
+                );
+                METRICS.events(EventStage::Dropped, source_sourcetypes(&batch.batch));
+                policy.counters.dropped(&batch.batch);
             }
         }
     }
+}
 
-    if lines.is_empty() {
-        None
-    } else {
-        Some((lines, events))
-    }
+/// (source, sourcetype) of each event for [METRICS]
+fn source_sourcetypes(events: &[HecEvent]) -> impl Iterator<Item = (&str, &str)> {
+    events
+        .iter()
+        .map(|event| (event.source.as_str(), event.sourcetype.as_str()))
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
+        batch::BatchConfig,
+        resend::{DeadLetterConfig, ResendConfig},
         splunk::{HecEvent, Splunk},
+        spool::{test::spool_dir, Spool, SpoolConfig},
         tasks::{AckTask, HecAckQueryResponse, HecAckResponse, HecBatch, SendingTask},
     };
     use mockito::{Matcher::Any, Server, ServerGuard};
-    use std::collections::HashMap;
+    use std::{collections::HashMap, sync::Arc};
     use tokio::{
         sync::mpsc::{channel, error::TryRecvError, Receiver, Sender},
         time::{sleep, Duration, Instant},
@@ -530,9 +927,16 @@ mod test {
         let hec_acknowledgment = true;
         let client = Splunk::new_request_client("mock_token", hec_acknowledgment)
             .expect("Splunk Client to build sucessfully");
-        let sending_task =
-            SendingTask::new(client, send_rx, ack_tx.clone(), url, hec_acknowledgment)
-                .expect("Spawning SendingTask shouldn't fail");
+        let sending_task = SendingTask::new(
+            client,
+            send_rx,
+            ack_tx.clone(),
+            url,
+            hec_acknowledgment,
+            None,
+            BatchConfig::default(),
+        )
+        .expect("Spawning SendingTask shouldn't fail");
 
         (sending_task, send_tx, ack_rx, mock_server, tracing_guard)
     }
@@ -666,9 +1070,16 @@ mod test {
         let client = Splunk::new_request_client("mock_token", hec_acknowledgment)
             .expect("Splunk Client to build sucessfully");
 
-        let _sending_task =
-            SendingTask::new(client, send_rx, ack_tx.clone(), url, hec_acknowledgment)
-                .expect("Spawning SendingTask shouldn't fail");
+        let _sending_task = SendingTask::new(
+            client,
+            send_rx,
+            ack_tx.clone(),
+            url,
+            hec_acknowledgment,
+            None,
+            BatchConfig::default(),
+        )
+        .expect("Spawning SendingTask shouldn't fail");
 
         let _mock = mock_server
             .mock("POST", "/services/collector")
@@ -720,6 +1131,206 @@ mod test {
         assert_eq!(ack_message.batch[0].sourcetype, fake_event.sourcetype);
     }
 
+    #[tokio::test]
+    async fn test_sending_task_removes_spool_segment_after_send() {
+        let subscriber = tracing_subscriber::FmtSubscriber::new();
+        let _tracing_guard = tracing::subscriber::set_default(subscriber);
+
+        let mut mock_server = Server::new_async().await;
+        let url = format!("http://{}", mock_server.host_with_port());
+        let (send_tx, send_rx) = channel::<HecEvent>(1000);
+        let (ack_tx, _ack_rx) = channel(1000);
+        let hec_acknowledgment = false;
+        let client = Splunk::new_request_client("mock_token", hec_acknowledgment)
+            .expect("Splunk Client to build sucessfully");
+
+        let spool_dir = spool_dir();
+        let spool = Arc::new(Spool::new(SpoolConfig::new(&spool_dir)).expect("Spool to build"));
+
+        let _sending_task = SendingTask::new(
+            client,
+            send_rx,
+            ack_tx,
+            url,
+            hec_acknowledgment,
+            Some(spool.clone()),
+            BatchConfig::default(),
+        )
+        .expect("Spawning SendingTask shouldn't fail");
+
+        let mock = mock_server
+            .mock("POST", "/services/collector")
+            .with_status(200)
+            .with_body(mock_response_body())
+            .create();
+
+        send_hec_event(send_tx).await;
+
+        mock.assert();
+        assert_eq!(spool.depth().segments, 0);
+        std::fs::remove_dir_all(spool_dir).expect("Removing spool dir");
+    }
+
+    #[tokio::test]
+    async fn test_sending_task_retains_spool_segment_on_failure() {
+        let subscriber = tracing_subscriber::FmtSubscriber::new();
+        let _tracing_guard = tracing::subscriber::set_default(subscriber);
+
+        let mut mock_server = Server::new_async().await;
+        let url = format!("http://{}", mock_server.host_with_port());
+        let (send_tx, send_rx) = channel::<HecEvent>(1000);
+        let (ack_tx, _ack_rx) = channel(1000);
+        let hec_acknowledgment = false;
+        let client = Splunk::new_request_client("mock_token", hec_acknowledgment)
+            .expect("Splunk Client to build sucessfully");
+
+        let spool_dir = spool_dir();
+        let spool = Arc::new(Spool::new(SpoolConfig::new(&spool_dir)).expect("Spool to build"));
+
+        let _sending_task = SendingTask::new(
+            client,
+            send_rx,
+            ack_tx,
+            url,
+            hec_acknowledgment,
+            Some(spool.clone()),
+            BatchConfig::default(),
+        )
+        .expect("Spawning SendingTask shouldn't fail");
+
+        let _mock = mock_server
+            .mock("POST", "/services/collector")
+            .with_status(400)
+            .create();
+
+        send_hec_event(send_tx).await;
+
+        assert_eq!(spool.depth().segments, 1);
+        assert_eq!(spool.take_failed().len(), 1);
+        std::fs::remove_dir_all(spool_dir).expect("Removing spool dir");
+    }
+
+    #[tokio::test]
+    async fn test_sending_task_splits_batch_on_payload_too_large() {
+        let (_sending_task, send_tx, mut ack_rx, mut mock_server, _tracing_guard) =
+            setup_send_task().await;
+
+        let too_large = mock_server
+            .mock("POST", "/services/collector")
+            .match_request(|request| {
+                request
+                    .utf8_lossy_body()
+                    .map(|body| body.lines().count() > 1)
+                    .unwrap_or(false)
+            })
+            .with_status(413)
+            .expect(1)
+            .create();
+        let accepted = mock_server
+            .mock("POST", "/services/collector")
+            .match_request(|request| {
+                request
+                    .utf8_lossy_body()
+                    .map(|body| body.lines().count() == 1)
+                    .unwrap_or(false)
+            })
+            .with_status(200)
+            .with_body(mock_response_body())
+            .expect(2)
+            .create();
+
+        for _ in 0..2 {
+            send_tx
+                .send(fake_event())
+                .await
+                .expect("Sending on channel shouldn't fail");
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        too_large.assert();
+        accepted.assert();
+        for _ in 0..2 {
+            let ack_message = ack_rx.recv().await.expect("To receive message");
+            assert_eq!(ack_message.batch.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sending_task_drains_queue_on_shutdown() {
+        let (sending_task, send_tx, _ack_rx, mut mock_server, _tracing_guard) =
+            setup_send_task().await;
+
+        let mock = mock_server
+            .mock("POST", "/services/collector")
+            .with_status(200)
+            .with_body(mock_response_body())
+            .expect_at_least(1)
+            .create();
+
+        for _ in 0..3 {
+            send_tx
+                .send(fake_event())
+                .await
+                .expect("Sending on channel shouldn't fail");
+        }
+        sending_task.shutdown();
+
+        let join = sending_task.take_join().expect("Join handle should exist");
+        tokio::time::timeout(Duration::from_secs(5), join)
+            .await
+            .expect("SendingTask should exit after draining")
+            .expect("SendingTask shouldn't panic")
+            .expect("SendingTask shouldn't fail");
+
+        mock.assert();
+        assert_eq!(sending_task.in_flight(), 0);
+        assert!(send_tx.send(fake_event()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sending_task_gzips_body() {
+        let subscriber = tracing_subscriber::FmtSubscriber::new();
+        let _tracing_guard = tracing::subscriber::set_default(subscriber);
+
+        let mut mock_server = Server::new_async().await;
+        let url = format!("http://{}", mock_server.host_with_port());
+        let (send_tx, send_rx) = channel::<HecEvent>(1000);
+        let (ack_tx, _ack_rx) = channel(1000);
+        let client = Splunk::new_request_client("mock_token", false)
+            .expect("Splunk Client to build sucessfully");
+
+        let _sending_task = SendingTask::new(
+            client,
+            send_rx,
+            ack_tx,
+            url,
+            false,
+            None,
+            BatchConfig {
+                gzip: true,
+                ..Default::default()
+            },
+        )
+        .expect("Spawning SendingTask shouldn't fail");
+
+        let mock = mock_server
+            .mock("POST", "/services/collector")
+            .match_header("content-encoding", "gzip")
+            .match_request(|request| {
+                request
+                    .body()
+                    .map(|body| body.starts_with(&[0x1f, 0x8b]))
+                    .unwrap_or(false)
+            })
+            .with_status(200)
+            .with_body(mock_response_body())
+            .create();
+
+        send_hec_event(send_tx).await;
+
+        mock.assert();
+    }
+
     async fn setup_ack_task(
         timeout: Option<Duration>,
     ) -> (
@@ -728,6 +1339,19 @@ mod test {
         Receiver<HecEvent>,
         ServerGuard,
         DefaultGuard,
+    ) {
+        setup_ack_task_with_resend_config(timeout, ResendConfig::default()).await
+    }
+
+    async fn setup_ack_task_with_resend_config(
+        timeout: Option<Duration>,
+        resend_config: ResendConfig,
+    ) -> (
+        AckTask,
+        Sender<HecBatch>,
+        Receiver<HecEvent>,
+        ServerGuard,
+        DefaultGuard,
     ) {
         // Start tracing
         let subscriber = tracing_subscriber::FmtSubscriber::new();
@@ -742,7 +1366,7 @@ mod test {
         let (ack_tx, ack_rx) = channel(1000);
         let client =
             Splunk::new_request_client("mock_token", true).expect("Splunk client to build");
-        let ack_task = AckTask::new(client, send_tx, ack_rx, url, timeout)
+        let ack_task = AckTask::new(client, send_tx, ack_rx, url, timeout, None, resend_config)
             .expect("Spawning SendingTask shouldn't fail");
 
         (ack_task, ack_tx, send_rx, mock_server, tracing_guard)
@@ -770,6 +1394,7 @@ mod test {
             ack_id: 0,
             batch: vec![fake_event()],
             sent_time: Instant::now(),
+            segment: None,
         }
     }
 
@@ -881,4 +1506,96 @@ mod test {
             1
         );
     }
+
+    #[tokio::test]
+    async fn test_ack_task_exits_once_acked_after_sending_task_exits() {
+        let (ack_task, ack_tx, _send_rx, mut mock_server, _tracing_guard) =
+            setup_ack_task(None).await;
+
+        let _mock = mock_server
+            .mock("POST", "/services/collector/ack")
+            .with_status(200)
+            .with_body(ack_response_body_success())
+            .create();
+
+        ack_tx
+            .send(hec_batch())
+            .await
+            .expect("Sending on channel shouldn't fail");
+        drop(ack_tx);
+
+        let join = ack_task.take_join().expect("Join handle should exist");
+        tokio::time::timeout(Duration::from_secs(5), join)
+            .await
+            .expect("AckTask should exit after draining")
+            .expect("AckTask shouldn't panic")
+            .expect("AckTask shouldn't fail");
+        assert_eq!(ack_task.counters.counts().unacknowledged, 0);
+    }
+
+    async fn dead_letter_ack_task(
+        dead_letter: Option<DeadLetterConfig>,
+    ) -> (AckTask, Receiver<HecEvent>) {
+        let resend_config = ResendConfig {
+            max_resends: 0,
+            dead_letter,
+            ..Default::default()
+        };
+        let (ack_task, ack_tx, send_rx, mut mock_server, _tracing_guard) =
+            setup_ack_task_with_resend_config(Some(Duration::from_nanos(1)), resend_config).await;
+
+        let _mock = mock_server
+            .mock("POST", "/services/collector/ack")
+            .with_status(200)
+            .with_body(ack_response_body_failure())
+            .create();
+
+        send_hec_batch(ack_tx).await;
+
+        (ack_task, send_rx)
+    }
+
+    #[tokio::test]
+    async fn test_ack_task_drops_events_after_max_resends() {
+        let (ack_task, mut send_rx) = dead_letter_ack_task(None).await;
+
+        assert!(send_rx.try_recv().is_err());
+        let counts = ack_task.counters.counts();
+        assert_eq!(counts.resent, 0);
+        assert_eq!(counts.dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ack_task_dead_letters_events_to_file() {
+        let dead_letter_dir = spool_dir();
+        let (ack_task, mut send_rx) =
+            dead_letter_ack_task(Some(DeadLetterConfig::File(dead_letter_dir.clone()))).await;
+
+        assert!(send_rx.try_recv().is_err());
+        assert_eq!(ack_task.counters.counts().dead_lettered, 1);
+        let dead_letter = Spool::new(SpoolConfig::new(&dead_letter_dir)).expect("Spool to build");
+        assert_eq!(dead_letter.depth().segments, 1);
+        std::fs::remove_dir_all(dead_letter_dir).expect("Removing dead letter dir");
+    }
+
+    #[tokio::test]
+    async fn test_ack_task_dead_letters_events_to_sourcetype() {
+        let (ack_task, mut send_rx) = dead_letter_ack_task(Some(DeadLetterConfig::Sourcetype(
+            "ssphp:dead_letter".to_string(),
+        )))
+        .await;
+
+        let event = send_rx
+            .try_recv()
+            .expect("Dead lettered event should exist");
+        assert_eq!(event.sourcetype, "ssphp:dead_letter");
+        assert_eq!(
+            event
+                .fields
+                .expect("fields should exist on dead lettered events")
+                .original_sourcetype,
+            Some(fake_event().sourcetype)
+        );
+        assert_eq!(ack_task.counters.counts().dead_lettered, 1);
+    }
 }
@@ -0,0 +1,46 @@
+//! IP address ranges in CIDR notation.
+use std::net::IpAddr;
+
+/// Whether `cidr`, such as `192.0.2.0/24`, contains `address`.
+///
+/// A network without a prefix length is a single address, and an
+/// IPv4 network never contains an IPv6 address or the other way round
+pub fn cidr_contains(cidr: &str, address: IpAddr) -> bool {
+    let (network, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+    let Ok(network) = network.trim().parse::<IpAddr>() else {
+        return false;
+    };
+    match (network, address) {
+        (IpAddr::V4(network), IpAddr::V4(address)) => {
+            let prefix = prefix.parse::<u32>().unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(address)) => {
+            let prefix = prefix.parse::<u32>().unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(network) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::cidr_contains;
+    use anyhow::Result;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_cidr_contains() -> Result<()> {
+        let address: IpAddr = "192.0.2.10".parse()?;
+        assert!(cidr_contains("192.0.2.0/24", address));
+        assert!(!cidr_contains("192.0.3.0/24", address));
+        assert!(cidr_contains("0.0.0.0/0", address));
+        assert!(cidr_contains("192.0.2.10", address));
+        assert!(!cidr_contains("not an address/24", address));
+        assert!(cidr_contains("2001:db8::/32", "2001:db8::1".parse()?));
+        assert!(!cidr_contains("2001:db8::/32", address));
+        Ok(())
+    }
+}
@@ -1,12 +1,15 @@
 use crate::dev_ops_pats::{azure_dev_ops_pats, AdoDevOpsPat};
+use crate::secret_provider::{get_secrets, SecretProvider, REQUIRED_SECRETS};
 use anyhow::{Context, Result};
+use azure_core::error::ErrorKind;
+use azure_core::StatusCode;
 use azure_identity::DefaultAzureCredential;
 use azure_identity::TokenCredentialOptions;
 use azure_security_keyvault::{KeyvaultClient, SecretClient};
 use base64::prelude::*;
+use futures::future::BoxFuture;
 use futures::StreamExt;
 use std::sync::Arc;
-use tokio::task::JoinHandle;
 use tracing::{info, warn};
 
 pub struct Secrets {
@@ -35,6 +38,8 @@ pub struct Secrets {
     pub mssql_db: Option<String>,
     pub mssql_username: Option<String>,
     pub mssql_password: Option<String>,
+    /// Key for HMAC redaction rules
+    pub redaction_hmac_key: Option<String>,
     pub ado_pats: Vec<AdoDevOpsPat>,
 }
 
@@ -52,7 +57,7 @@ pub struct GitHubApp {
 impl GitHubApp {
     /// Create a new Github App secret.
     /// 'private_key' should be a base64 encoded DER RSA key
-    fn new(app_id: String, private_key: String) -> Result<Self> {
+    pub(crate) fn new(app_id: String, private_key: String) -> Result<Self> {
         Ok(Self {
             app_id: app_id.parse().context("Parse app ID as u64")?,
             private_key: BASE64_STANDARD
@@ -62,118 +67,85 @@ impl GitHubApp {
     }
 }
 
-/// Spawn a future getting a secret to be await'd later
-/// Speeds up secrets collection
-fn get_secret(client: &SecretClient, name: &str) -> JoinHandle<Option<String>> {
-    let client_ = (*client).clone();
-    let name_ = name.to_string();
-    tokio::spawn(async move {
-        info!("KeyVault: getting '{}'", &name_);
-        match client_.get(&name_).await {
-            Ok(secret) => Some(secret.value.to_string()),
-            Err(err) => {
-                warn!("Keyvault: Error getting '{}': {:?}", &name_, err);
-                None
-            }
-        }
-    })
+/// Secrets stored in an Azure KeyVault
+pub struct KeyVaultSecretProvider {
+    client: SecretClient,
 }
 
-/// Get all the secrets from KeyVault
-pub async fn get_keyvault_secrets(keyvault_name: &str) -> Result<Secrets> {
-    info!("Getting Default Azure Credentials");
-    let credential = Arc::new(
-        DefaultAzureCredential::create(TokenCredentialOptions::default())
-            .context("Unable to build default Azure Credentials")?,
-    );
-
-    info!("KeyVault Secret Client created");
-    let keyvault_url = format!("https://{keyvault_name}.vault.azure.net");
-    let client = KeyvaultClient::new(&keyvault_url, credential.clone())
-        .context("Creating key vault client")?
-        .secret_client();
+impl KeyVaultSecretProvider {
+    /// Create a KeyVault client using the default Azure credentials
+    pub fn new(keyvault_name: &str) -> Result<Self> {
+        info!("Getting Default Azure Credentials");
+        let credential = Arc::new(
+            DefaultAzureCredential::create(TokenCredentialOptions::default())
+                .context("Unable to build default Azure Credentials")?,
+        );
 
-    let splunk_host = get_secret(&client, "splunk-host");
-    let splunk_token = get_secret(&client, "splunk-token");
-    let ian_splunk_host = get_secret(&client, "ian-splunk-host");
-    let ian_splunk_token = get_secret(&client, "ian-splunk-token");
-    let splunk_acs_token = get_secret(&client, "splunk-acs-token");
-    let splunk_search_token = get_secret(&client, "splunk-search-token");
-    let splunk_search_url = get_secret(&client, "splunk-search-url");
-    let splunk_cloud_stack = get_secret(&client, "splunk-cloud-stack");
-    let azure_client_id = get_secret(&client, "ad-client-id");
-    let azure_client_secret = get_secret(&client, "ad-client-secret");
-    // Secret is automatically created when generating a certificate in KeyVault
-    let azure_client_certificate = get_secret(&client, "ad-client-certificate");
-    let azure_client_organization = get_secret(&client, "ad-client-organization");
-    let azure_tenant_id = get_secret(&client, "ad-tenant-id");
-    let aws_access_key_id = get_secret(&client, "aws-access-key-id");
-    let aws_secret_access_key = get_secret(&client, "aws-secret-access-key");
-    let github_private_key_1 = get_secret(&client, "github-private-key-1");
-    let github_app_id_1 = get_secret(&client, "github-app-id-1");
-    let qualys_username = get_secret(&client, "qualys-username");
-    let qualys_password = get_secret(&client, "qualys-password");
-    let sonar_api_key = get_secret(&client, "sonar-api-key");
-    let sonar_orgs = get_secret(&client, "sonar-orgs");
-    let mssql_host = get_secret(&client, "mssql-host");
-    let mssql_db = get_secret(&client, "mssql-db");
-    let mssql_port = get_secret(&client, "mssql-port");
-    let mssql_username = get_secret(&client, "mssql-username");
-    let mssql_password = get_secret(&client, "mssql-password");
+        info!("KeyVault Secret Client created");
+        let keyvault_url = format!("https://{keyvault_name}.vault.azure.net");
+        let client = KeyvaultClient::new(&keyvault_url, credential)
+            .context("Creating key vault client")?
+            .secret_client();
+        Ok(Self { client })
+    }
+}
 
-    let github_app = if let (Some(github_app_id_1), Some(github_private_key_1)) =
-        (github_app_id_1.await?, github_private_key_1.await?)
-    {
-        Some(
-            GitHubApp::new(github_app_id_1, github_private_key_1)
-                .context("Building Github App Credentials")?,
-        )
-    } else {
-        None
-    };
+impl SecretProvider for KeyVaultSecretProvider {
+    fn name(&self) -> &str {
+        "keyvault"
+    }
 
-    let secrets = client
-        .list_secrets()
-        .into_stream()
-        .filter_map(|result| async move {
-            match result {
-                Ok(result) => Some(result.value),
-                Err(_) => None,
+    fn get_secret<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            info!("KeyVault: getting '{}'", name);
+            match self.client.get(name).await {
+                Ok(secret) => Ok(Some(secret.value.to_string())),
+                // Only a secret that doesn't exist is not configured, any
+                // other error, e.g. auth or network, is logged or fails
+                // a required secret
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::HttpResponse {
+                            status: StatusCode::NotFound,
+                            ..
+                        }
+                    ) =>
+                {
+                    info!("KeyVault: '{}' not found", name);
+                    Ok(None)
+                }
+                Err(err) if REQUIRED_SECRETS.contains(&name) => {
+                    Err(anyhow::Error::new(err)
+                        .context(format!("Getting '{}' from KeyVault", name)))
+                }
+                Err(err) => {
+                    warn!(name="KeyVault", operation="Get secret", secret=name, error=?err);
+                    Ok(None)
+                }
             }
         })
-        .concat()
-        .await;
+    }
 
-    let ado_pats = azure_dev_ops_pats(&client, &secrets).await;
+    fn ado_pats(&self) -> BoxFuture<'_, Result<Vec<AdoDevOpsPat>>> {
+        Box::pin(async move {
+            let mut secrets = vec![];
+            let mut pages = self.client.list_secrets().into_stream();
+            while let Some(page) = pages.next().await {
+                match page {
+                    Ok(page) => secrets.extend(page.value),
+                    Err(err) => {
+                        warn!(name="KeyVault", operation="List secrets", error=?err);
+                    }
+                }
+            }
 
-    Ok(Secrets {
-        ian_splunk_host: ian_splunk_host.await?,
-        ian_splunk_token: ian_splunk_token.await?,
-        splunk_host: splunk_host.await?,
-        splunk_token: splunk_token.await?,
-        splunk_acs_token: splunk_acs_token.await?,
-        splunk_search_token: splunk_search_token.await?,
-        splunk_search_url: splunk_search_url.await?,
-        splunk_cloud_stack: splunk_cloud_stack.await?,
-        azure_client_id: azure_client_id.await?,
-        azure_client_secret: azure_client_secret.await?,
-        azure_client_certificate: azure_client_certificate.await?,
-        azure_client_organization: azure_client_organization.await?,
-        azure_tenant_id: azure_tenant_id.await?,
-        aws_access_key_id: aws_access_key_id.await?,
-        aws_secret_access_key: aws_secret_access_key.await?,
-        github_app,
-        qualys_username: qualys_username.await?,
-        qualys_password: qualys_password.await?,
-        sonar_api_key: sonar_api_key.await?,
-        sonar_orgs: sonar_orgs
-            .await?
-            .map(|s| s.split(",").map(|s| s.to_string()).collect()),
-        mssql_host: mssql_host.await?,
-        mssql_port: mssql_port.await?,
-        mssql_db: mssql_db.await?,
-        mssql_username: mssql_username.await?,
-        mssql_password: mssql_password.await?,
-        ado_pats,
-    })
+            Ok(azure_dev_ops_pats(&self.client, &secrets).await)
+        })
+    }
+}
+
+/// Get all the secrets from KeyVault
+pub async fn get_keyvault_secrets(keyvault_name: &str) -> Result<Secrets> {
+    get_secrets(&KeyVaultSecretProvider::new(keyvault_name)?).await
 }
@@ -1,4 +1,8 @@
+pub mod cidr;
 pub mod dev_ops_pats;
 pub mod dns;
 pub mod keyvault;
+pub mod metrics;
+pub mod secret_provider;
+pub mod state_store;
 mod secret_identifier;
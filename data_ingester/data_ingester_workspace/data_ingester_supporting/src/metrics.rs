@@ -0,0 +1,274 @@
+//! Prometheus metrics for the data ingester.
+//!
+//! Metrics are registered in a process wide [Registry] and rendered in
+//! the Prometheus text format by [gather] for the `/metrics` route.
+//!
+//! If the metrics cannot be registered the error is logged and
+//! recording metrics does nothing, rather than failing collection.
+use anyhow::{anyhow, Context, Result};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use tracing::error;
+
+/// All ingester metrics
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| match Registered::new() {
+    Ok(registered) => Metrics(Some(registered)),
+    Err(err) => {
+        error!(name="Metrics", operation="Register metrics", error=?err);
+        Metrics(None)
+    }
+});
+
+/// Stage of a HEC event in the Splunk client
+#[derive(Debug, Clone, Copy)]
+pub enum EventStage {
+    /// Queued on the Splunk client's channel
+    Queued,
+    /// Accepted by Splunk HEC
+    Sent,
+    /// Acknowledged as indexed by Splunk HEC
+    Acked,
+    /// Sent again after failing to be acknowledged
+    Resent,
+    /// Sent to the dead letter destination after too many resends
+    DeadLettered,
+    /// Dropped after too many resends
+    Dropped,
+}
+
+/// Counters, histograms and gauges exposed on `/metrics`
+///
+/// `None` when the metrics could not be registered
+pub struct Metrics(Option<Registered>);
+
+/// Metrics registered in [Registered::registry]
+struct Registered {
+    registry: Registry,
+    events_queued: IntCounterVec,
+    events_sent: IntCounterVec,
+    events_acked: IntCounterVec,
+    events_resent: IntCounterVec,
+    events_dead_lettered: IntCounterVec,
+    events_dropped: IntCounterVec,
+    collector_runs: IntCounterVec,
+    collector_duration: HistogramVec,
+    upstream_http_responses: IntCounterVec,
+    memory_bytes: IntGaugeVec,
+}
+
+impl Registered {
+    fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("ssphp".to_string()), None)
+            .context("Creating metrics registry")?;
+
+        let events = |name: &str, help: &str| -> Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help), &["source", "sourcetype"])
+                .with_context(|| format!("Creating {}", name))?;
+            registry
+                .register(Box::new(counter.clone()))
+                .with_context(|| format!("Registering {}", name))?;
+            Ok(counter)
+        };
+        let events_queued = events(
+            "hec_events_queued_total",
+            "HEC events queued for sending to Splunk",
+        )?;
+        let events_sent = events("hec_events_sent_total", "HEC events accepted by Splunk")?;
+        let events_acked = events(
+            "hec_events_acked_total",
+            "HEC events acknowledged as indexed by Splunk",
+        )?;
+        let events_resent = events(
+            "hec_events_resent_total",
+            "HEC events resent after not being acknowledged",
+        )?;
+        let events_dead_lettered = events(
+            "hec_events_dead_lettered_total",
+            "HEC events sent to the dead letter destination after too many resends",
+        )?;
+        let events_dropped = events(
+            "hec_events_dropped_total",
+            "HEC events dropped after too many resends",
+        )?;
+
+        let collector_runs = IntCounterVec::new(
+            Opts::new("collector_runs_total", "Collector runs by outcome"),
+            &["collector", "outcome"],
+        )
+        .context("Creating collector_runs")?;
+        registry
+            .register(Box::new(collector_runs.clone()))
+            .context("Registering collector_runs")?;
+
+        let collector_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "collector_duration_seconds",
+                "Time taken by collector runs by outcome",
+            )
+            .buckets(vec![
+                1.0, 10.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0,
+            ]),
+            &["collector", "outcome"],
+        )
+        .context("Creating collector_duration")?;
+        registry
+            .register(Box::new(collector_duration.clone()))
+            .context("Registering collector_duration")?;
+
+        let upstream_http_responses = IntCounterVec::new(
+            Opts::new(
+                "upstream_http_responses_total",
+                "HTTP responses from upstream APIs by status code",
+            ),
+            &["api", "status"],
+        )
+        .context("Creating upstream_http_responses")?;
+        registry
+            .register(Box::new(upstream_http_responses.clone()))
+            .context("Registering upstream_http_responses")?;
+
+        let memory_bytes = IntGaugeVec::new(
+            Opts::new("memory_bytes", "Memory used by the ingester process"),
+            &["type"],
+        )
+        .context("Creating memory_bytes")?;
+        registry
+            .register(Box::new(memory_bytes.clone()))
+            .context("Registering memory_bytes")?;
+
+        Ok(Self {
+            registry,
+            events_queued,
+            events_sent,
+            events_acked,
+            events_resent,
+            events_dead_lettered,
+            events_dropped,
+            collector_runs,
+            collector_duration,
+            upstream_http_responses,
+            memory_bytes,
+        })
+    }
+}
+
+impl Metrics {
+    /// Count events at a stage, `events` yields (source, sourcetype) pairs
+    pub fn events<'a>(
+        &self,
+        stage: EventStage,
+        events: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) {
+        let Some(metrics) = &self.0 else {
+            return;
+        };
+        let counter = match stage {
+            EventStage::Queued => &metrics.events_queued,
+            EventStage::Sent => &metrics.events_sent,
+            EventStage::Acked => &metrics.events_acked,
+            EventStage::Resent => &metrics.events_resent,
+            EventStage::DeadLettered => &metrics.events_dead_lettered,
+            EventStage::Dropped => &metrics.events_dropped,
+        };
+        let mut counts: HashMap<(&str, &str), u64> = HashMap::new();
+        for event in events {
+            *counts.entry(event).or_default() += 1;
+        }
+        for ((source, sourcetype), count) in counts {
+            counter
+                .with_label_values(&[source, sourcetype])
+                .inc_by(count);
+        }
+    }
+
+    /// Record a finished collector run
+    pub fn collector_run(&self, collector: &str, outcome: &str, duration_secs: f64) {
+        let Some(metrics) = &self.0 else {
+            return;
+        };
+        metrics
+            .collector_runs
+            .with_label_values(&[collector, outcome])
+            .inc();
+        metrics
+            .collector_duration
+            .with_label_values(&[collector, outcome])
+            .observe(duration_secs);
+    }
+
+    /// Record the status code of a response from an upstream API
+    pub fn http_status(&self, api: &str, status: u16) {
+        let Some(metrics) = &self.0 else {
+            return;
+        };
+        metrics
+            .upstream_http_responses
+            .with_label_values(&[api, &status.to_string()])
+            .inc();
+    }
+
+    /// Record the process memory usage
+    pub fn memory(&self, physical_bytes: usize, virtual_bytes: usize) {
+        let Some(metrics) = &self.0 else {
+            return;
+        };
+        metrics
+            .memory_bytes
+            .with_label_values(&["physical"])
+            .set(i64::try_from(physical_bytes).unwrap_or(i64::MAX));
+        metrics
+            .memory_bytes
+            .with_label_values(&["virtual"])
+            .set(i64::try_from(virtual_bytes).unwrap_or(i64::MAX));
+    }
+}
+
+/// Render all metrics in the Prometheus text exposition format
+pub fn gather() -> Result<String> {
+    let metrics = METRICS
+        .0
+        .as_ref()
+        .ok_or_else(|| anyhow!("Metrics are not registered"))?;
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .context("Encoding metrics")?;
+    String::from_utf8(buffer).context("Metrics should be UTF8")
+}
+
+/// Content type of [gather]
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+#[cfg(test)]
+mod test {
+    use super::{gather, EventStage, METRICS};
+
+    #[test]
+    fn test_gather_metrics() {
+        METRICS.events(
+            EventStage::Queued,
+            [
+                ("test_source", "test:sourcetype"),
+                ("test_source", "test:sourcetype"),
+            ],
+        );
+        METRICS.collector_run("test_collector", "success", 12.0);
+        METRICS.http_status("github", 200);
+        METRICS.memory(1024, 2048);
+
+        let metrics = gather().unwrap();
+        assert!(metrics.contains(
+            r#"ssphp_hec_events_queued_total{source="test_source",sourcetype="test:sourcetype"} 2"#
+        ));
+        assert!(metrics.contains(
+            r#"ssphp_collector_runs_total{collector="test_collector",outcome="success"} 1"#
+        ));
+        assert!(
+            metrics.contains(r#"ssphp_upstream_http_responses_total{api="github",status="200"} 1"#)
+        );
+        assert!(metrics.contains(r#"ssphp_memory_bytes{type="physical"} 1024"#));
+    }
+}
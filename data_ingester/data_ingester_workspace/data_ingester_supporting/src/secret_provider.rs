@@ -0,0 +1,534 @@
+//! Sources of [Secrets].
+//!
+//! Secrets are looked up by their KeyVault secret names, e.g.
+//! `splunk-host`, `ad-client-id`, `github-app-id-1`, regardless of
+//! which [SecretProvider] they come from.
+use crate::dev_ops_pats::AdoDevOpsPat;
+use crate::keyvault::{GitHubApp, Secrets};
+use anyhow::{Context, Result};
+use futures::future::{join_all, BoxFuture};
+use std::{collections::HashMap, path::Path};
+use tracing::info;
+
+/// Names of all secrets that make up [Secrets]
+pub const SECRET_NAMES: [&str; 27] = [
+    "splunk-host",
+    "splunk-token",
+    "ian-splunk-host",
+    "ian-splunk-token",
+    "splunk-acs-token",
+    "splunk-search-token",
+    "splunk-search-url",
+    "splunk-cloud-stack",
+    "ad-client-id",
+    "ad-client-secret",
+    "ad-client-certificate",
+    "ad-client-organization",
+    "ad-tenant-id",
+    "aws-access-key-id",
+    "aws-secret-access-key",
+    "github-app-id-1",
+    "github-private-key-1",
+    "qualys-username",
+    "qualys-password",
+    "sonar-api-key",
+    "sonar-orgs",
+    "mssql-host",
+    "mssql-port",
+    "mssql-db",
+    "mssql-username",
+    "mssql-password",
+    "redaction-hmac-key",
+];
+
+/// Secrets every run needs to send its results, a provider that fails
+/// to read one of these stops startup. Failures reading any other
+/// secret are logged and the secret left unset, so only the collectors
+/// needing it are skipped.
+pub const REQUIRED_SECRETS: [&str; 2] = ["splunk-host", "splunk-token"];
+
+/// A source of secret values
+pub trait SecretProvider: Send + Sync {
+    /// Name of the provider, used for logging and
+    /// [ChainedSecretProvider::with_precedence]
+    fn name(&self) -> &str;
+
+    /// Get a single secret by its KeyVault secret name.
+    ///
+    /// Returns `Ok(None)` if the provider does not have the secret, and
+    /// an error if the provider could not be asked, e.g. auth or network
+    /// failures.
+    fn get_secret<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+
+    /// Azure DevOps PATs held by this provider
+    fn ado_pats(&self) -> BoxFuture<'_, Result<Vec<AdoDevOpsPat>>> {
+        Box::pin(async { Ok(vec![]) })
+    }
+}
+
+/// Build [Secrets] from a [SecretProvider]
+pub async fn get_secrets(provider: &dyn SecretProvider) -> Result<Secrets> {
+    info!("Getting secrets from '{}'", provider.name());
+    let values = join_all(SECRET_NAMES.iter().map(|name| async move {
+        let value = provider
+            .get_secret(name)
+            .await
+            .with_context(|| format!("Getting secret '{}' from '{}'", name, provider.name()))?;
+        anyhow::Ok(value.map(|value| (name.to_string(), value)))
+    }))
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>>>()?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let ado_pats = provider
+        .ado_pats()
+        .await
+        .context("Getting Azure DevOps PATs")?;
+
+    secrets_from_values(&values, ado_pats)
+}
+
+/// Build [Secrets] from a map of KeyVault secret names to values
+fn secrets_from_values(
+    values: &HashMap<String, String>,
+    ado_pats: Vec<AdoDevOpsPat>,
+) -> Result<Secrets> {
+    let get = |name: &str| values.get(name).cloned();
+
+    let github_app = if let (Some(app_id), Some(private_key)) =
+        (get("github-app-id-1"), get("github-private-key-1"))
+    {
+        Some(GitHubApp::new(app_id, private_key).context("Building Github App Credentials")?)
+    } else {
+        None
+    };
+
+    Ok(Secrets {
+        splunk_host: get("splunk-host"),
+        splunk_token: get("splunk-token"),
+        ian_splunk_host: get("ian-splunk-host"),
+        ian_splunk_token: get("ian-splunk-token"),
+        splunk_acs_token: get("splunk-acs-token"),
+        splunk_search_token: get("splunk-search-token"),
+        splunk_search_url: get("splunk-search-url"),
+        splunk_cloud_stack: get("splunk-cloud-stack"),
+        azure_client_id: get("ad-client-id"),
+        azure_client_secret: get("ad-client-secret"),
+        azure_client_certificate: get("ad-client-certificate"),
+        azure_client_organization: get("ad-client-organization"),
+        azure_tenant_id: get("ad-tenant-id"),
+        aws_access_key_id: get("aws-access-key-id"),
+        aws_secret_access_key: get("aws-secret-access-key"),
+        github_app,
+        qualys_username: get("qualys-username"),
+        qualys_password: get("qualys-password"),
+        sonar_api_key: get("sonar-api-key"),
+        sonar_orgs: get("sonar-orgs").map(|s| s.split(",").map(|s| s.to_string()).collect()),
+        mssql_host: get("mssql-host"),
+        mssql_port: get("mssql-port"),
+        mssql_db: get("mssql-db"),
+        mssql_username: get("mssql-username"),
+        mssql_password: get("mssql-password"),
+        redaction_hmac_key: get("redaction-hmac-key"),
+        ado_pats,
+    })
+}
+
+/// Route names of all collectors
+pub const COLLECTORS: [&str; 12] = [
+    "aws",
+    "azure",
+    "azure_dev_ops",
+    "azure_resource_graph",
+    "financial_business_partners",
+    "github",
+    "github_custom_properties",
+    "m365",
+    "powershell",
+    "qualys_qvs",
+    "sonar_cloud",
+    "threagile",
+];
+
+/// Secrets each collector needs before it can run.
+///
+/// `collector` is either the route name, e.g. `azure_resource_graph`,
+/// or the display name, e.g. `Azure Resource Graph`.
+///
+/// `aws_access_keys` is whether the AWS collector reads access keys
+/// from the secrets rather than the AWS chain or web identity, see
+/// `data_ingester_aws::aws::aws_access_key_secrets_required`.
+pub fn required_secrets(collector: &str, aws_access_keys: bool) -> &'static [&'static str] {
+    const AZURE_APP: &[&str] = &["ad-client-id", "ad-client-secret", "ad-tenant-id"];
+    const SPLUNK_SEARCH: &[&str] = &["splunk-search-token", "splunk-search-url"];
+    match collector.to_lowercase().replace(' ', "_").as_str() {
+        "aws" if aws_access_keys => &["aws-access-key-id", "aws-secret-access-key"],
+        "aws" => &[],
+        "azure" | "azure_resource_graph" | "m365" => AZURE_APP,
+        "financial_business_partners" => &[
+            "mssql-host",
+            "mssql-port",
+            "mssql-db",
+            "mssql-username",
+            "mssql-password",
+        ],
+        "github" => &["github-app-id-1", "github-private-key-1"],
+        "github_custom_properties" => &[
+            "github-app-id-1",
+            "github-private-key-1",
+            "splunk-search-token",
+            "splunk-search-url",
+        ],
+        "powershell" => &[
+            "ad-client-id",
+            "ad-client-certificate",
+            "ad-client-organization",
+        ],
+        "qualys_qvs" => &[
+            "qualys-username",
+            "qualys-password",
+            "splunk-search-token",
+            "splunk-search-url",
+        ],
+        "sonar_cloud" => &["sonar-api-key", "sonar-orgs"],
+        "threagile" => SPLUNK_SEARCH,
+        _ => &[],
+    }
+}
+
+impl Secrets {
+    /// Is the secret with this KeyVault secret name set
+    pub fn is_set(&self, name: &str) -> bool {
+        match name {
+            "splunk-host" => self.splunk_host.is_some(),
+            "splunk-token" => self.splunk_token.is_some(),
+            "ian-splunk-host" => self.ian_splunk_host.is_some(),
+            "ian-splunk-token" => self.ian_splunk_token.is_some(),
+            "splunk-acs-token" => self.splunk_acs_token.is_some(),
+            "splunk-search-token" => self.splunk_search_token.is_some(),
+            "splunk-search-url" => self.splunk_search_url.is_some(),
+            "splunk-cloud-stack" => self.splunk_cloud_stack.is_some(),
+            "ad-client-id" => self.azure_client_id.is_some(),
+            "ad-client-secret" => self.azure_client_secret.is_some(),
+            "ad-client-certificate" => self.azure_client_certificate.is_some(),
+            "ad-client-organization" => self.azure_client_organization.is_some(),
+            "ad-tenant-id" => self.azure_tenant_id.is_some(),
+            "aws-access-key-id" => self.aws_access_key_id.is_some(),
+            "aws-secret-access-key" => self.aws_secret_access_key.is_some(),
+            "github-app-id-1" | "github-private-key-1" => self.github_app.is_some(),
+            "qualys-username" => self.qualys_username.is_some(),
+            "qualys-password" => self.qualys_password.is_some(),
+            "sonar-api-key" => self.sonar_api_key.is_some(),
+            "sonar-orgs" => self.sonar_orgs.is_some(),
+            "mssql-host" => self.mssql_host.is_some(),
+            "mssql-port" => self.mssql_port.is_some(),
+            "mssql-db" => self.mssql_db.is_some(),
+            "mssql-username" => self.mssql_username.is_some(),
+            "mssql-password" => self.mssql_password.is_some(),
+            "redaction-hmac-key" => self.redaction_hmac_key.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Required secrets for `collector` that are not set, see [required_secrets]
+    pub fn missing_for(&self, collector: &str, aws_access_keys: bool) -> Vec<&'static str> {
+        required_secrets(collector, aws_access_keys)
+            .iter()
+            .filter(|name| !self.is_set(name))
+            .copied()
+            .collect()
+    }
+}
+
+/// Read secrets from environment variables.
+///
+/// `splunk-host` is read from `SSPHP_SECRET_SPLUNK_HOST`
+pub struct EnvSecretProvider {
+    prefix: String,
+}
+
+impl EnvSecretProvider {
+    pub fn new() -> Self {
+        Self {
+            prefix: "SSPHP_SECRET_".to_string(),
+        }
+    }
+
+    fn env_var(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name.to_uppercase().replace('-', "_"))
+    }
+}
+
+impl Default for EnvSecretProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecretProvider for EnvSecretProvider {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn get_secret<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move { Ok(std::env::var(self.env_var(name)).ok()) })
+    }
+}
+
+/// Read secrets from a TOML or JSON file of KeyVault secret names to values
+///
+/// ```toml
+/// splunk-host = "splunk.example.com"
+/// splunk-token = "00000000-0000-0000-0000-000000000000"
+/// ```
+pub struct FileSecretProvider {
+    values: HashMap<String, String>,
+}
+
+impl FileSecretProvider {
+    pub fn new(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+
+    /// Load secrets from a file, files ending `.json` are parsed as
+    /// JSON, everything else as TOML
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading secrets file: {}", path.display()))?;
+        let values = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents).context("Parsing JSON secrets file")?,
+            _ => toml::from_str(&contents).context("Parsing TOML secrets file")?,
+        };
+        Ok(Self::new(values))
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn get_secret<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.values.get(name).cloned()) })
+    }
+}
+
+/// Look up secrets from several providers.
+///
+/// By default providers are tried in order and the first value found
+/// is used. The order can be changed for individual secrets with
+/// [ChainedSecretProvider::with_precedence]. A provider that fails
+/// fails the lookup, rather than the secret looking unset.
+pub struct ChainedSecretProvider {
+    providers: Vec<Box<dyn SecretProvider>>,
+    precedence: HashMap<String, Vec<String>>,
+}
+
+impl ChainedSecretProvider {
+    pub fn new(providers: Vec<Box<dyn SecretProvider>>) -> Self {
+        Self {
+            providers,
+            precedence: HashMap::new(),
+        }
+    }
+
+    /// Only look up `secret` from the named providers, in the order given
+    pub fn with_precedence(mut self, secret: &str, providers: &[&str]) -> Self {
+        let _ = self.precedence.insert(
+            secret.to_string(),
+            providers.iter().map(|name| name.to_string()).collect(),
+        );
+        self
+    }
+
+    fn providers_for(&self, secret: &str) -> Vec<&dyn SecretProvider> {
+        match self.precedence.get(secret) {
+            Some(order) => order
+                .iter()
+                .filter_map(|name| {
+                    self.providers
+                        .iter()
+                        .find(|provider| provider.name() == name)
+                        .map(|provider| provider.as_ref())
+                })
+                .collect(),
+            None => self
+                .providers
+                .iter()
+                .map(|provider| provider.as_ref())
+                .collect(),
+        }
+    }
+}
+
+impl SecretProvider for ChainedSecretProvider {
+    fn name(&self) -> &str {
+        "chained"
+    }
+
+    fn get_secret<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            for provider in self.providers_for(name) {
+                if let Some(value) = provider
+                    .get_secret(name)
+                    .await
+                    .with_context(|| format!("Getting '{}' from '{}'", name, provider.name()))?
+                {
+                    return Ok(Some(value));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn ado_pats(&self) -> BoxFuture<'_, Result<Vec<AdoDevOpsPat>>> {
+        Box::pin(async move {
+            let mut ado_pats = vec![];
+            for provider in self.providers_for("azure-dev-ops") {
+                ado_pats.extend(provider.ado_pats().await?);
+            }
+            Ok(ado_pats)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_secrets, ChainedSecretProvider, FileSecretProvider, SecretProvider};
+    use std::collections::HashMap;
+
+    fn file_provider(values: &[(&str, &str)]) -> FileSecretProvider {
+        FileSecretProvider::new(
+            values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>(),
+        )
+    }
+
+    struct NamedProvider(&'static str, FileSecretProvider);
+
+    impl SecretProvider for NamedProvider {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn get_secret<'a>(
+            &'a self,
+            name: &'a str,
+        ) -> futures::future::BoxFuture<'a, anyhow::Result<Option<String>>> {
+            self.1.get_secret(name)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_secrets_from_file_provider() {
+        let provider = file_provider(&[
+            ("splunk-host", "splunk.example.com"),
+            ("sonar-orgs", "org1,org2"),
+        ]);
+        let secrets = get_secrets(&provider).await.unwrap();
+        assert_eq!(secrets.splunk_host.as_deref(), Some("splunk.example.com"));
+        assert_eq!(
+            secrets.sonar_orgs,
+            Some(vec!["org1".to_string(), "org2".to_string()])
+        );
+        assert!(secrets.splunk_token.is_none());
+        assert!(secrets.ado_pats.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_secrets_with_invalid_github_app_id() {
+        let provider = file_provider(&[
+            ("github-app-id-1", "not a number"),
+            ("github-private-key-1", "AAAA"),
+        ]);
+        assert!(get_secrets(&provider).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_for_collector() {
+        let provider = file_provider(&[("aws-access-key-id", "key")]);
+        let secrets = get_secrets(&provider).await.unwrap();
+        assert_eq!(
+            secrets.missing_for("AWS", true),
+            vec!["aws-secret-access-key"]
+        );
+        assert!(secrets.missing_for("AWS", false).is_empty());
+        assert_eq!(
+            secrets.missing_for("Azure Resource Graph", true),
+            vec!["ad-client-id", "ad-client-secret", "ad-tenant-id"]
+        );
+        assert!(secrets.missing_for("azure_dev_ops", true).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chained_provider_uses_first_value_found() {
+        let provider = ChainedSecretProvider::new(vec![
+            Box::new(NamedProvider("a", file_provider(&[("splunk-host", "a")]))),
+            Box::new(NamedProvider(
+                "b",
+                file_provider(&[("splunk-host", "b"), ("splunk-token", "b")]),
+            )),
+        ]);
+        let secrets = get_secrets(&provider).await.unwrap();
+        assert_eq!(secrets.splunk_host.as_deref(), Some("a"));
+        assert_eq!(secrets.splunk_token.as_deref(), Some("b"));
+    }
+
+    struct FailingProvider;
+
+    impl SecretProvider for FailingProvider {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn get_secret<'a>(
+            &'a self,
+            _name: &'a str,
+        ) -> futures::future::BoxFuture<'a, anyhow::Result<Option<String>>> {
+            Box::pin(async { Err(anyhow::anyhow!("unauthorized")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chained_provider_fails_when_a_provider_fails() {
+        let provider = ChainedSecretProvider::new(vec![
+            Box::new(NamedProvider("a", file_provider(&[]))),
+            Box::new(FailingProvider),
+        ]);
+        assert!(provider.get_secret("splunk-host").await.is_err());
+        assert!(get_secrets(&provider).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chained_provider_per_key_precedence() {
+        let provider = ChainedSecretProvider::new(vec![
+            Box::new(NamedProvider(
+                "a",
+                file_provider(&[("splunk-host", "a"), ("splunk-token", "a")]),
+            )),
+            Box::new(NamedProvider(
+                "b",
+                file_provider(&[("splunk-host", "b"), ("splunk-token", "b")]),
+            )),
+        ])
+        .with_precedence("splunk-token", &["b", "a"]);
+        let secrets = get_secrets(&provider).await.unwrap();
+        assert_eq!(secrets.splunk_host.as_deref(), Some("a"));
+        assert_eq!(secrets.splunk_token.as_deref(), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn test_chained_provider_precedence_excludes_unlisted_providers() {
+        let provider = ChainedSecretProvider::new(vec![Box::new(NamedProvider(
+            "a",
+            file_provider(&[("splunk-token", "a")]),
+        ))])
+        .with_precedence("splunk-token", &["b"]);
+        assert!(provider.get_secret("splunk-token").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,267 @@
+//! Storage for state kept between collector runs.
+//!
+//! Values are opaque bytes stored under `/` separated keys, e.g.
+//! `aws/inventory/111111111111.json`.
+use anyhow::{bail, Context, Result};
+use azure_core::auth::TokenCredential;
+use azure_identity::{DefaultAzureCredential, TokenCredentialOptions};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Version of the Blob service REST API
+const BLOB_API_VERSION: &str = "2021-08-06";
+
+/// A place to keep state between runs
+pub trait StateStore: Send + Sync {
+    /// Name of the store, used for logging
+    fn name(&self) -> &str;
+
+    /// Read the value stored under `key`.
+    ///
+    /// Returns `Ok(None)` if nothing has been stored yet.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Replace the value stored under `key`.
+    ///
+    /// Stores that support conditional writes fail if another run has
+    /// changed `key` since this store read it with [StateStore::get]
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Build the [StateStore] configured in the environment, `None` if no
+/// store is configured.
+///
+/// SSPHP_STATE_DIR: directory for a [FileStateStore]
+/// SSPHP_STATE_BLOB_ACCOUNT: storage account for an [AzureBlobStateStore]
+/// SSPHP_STATE_BLOB_CONTAINER: container for an [AzureBlobStateStore]
+pub fn state_store_from_env() -> Result<Option<Box<dyn StateStore>>> {
+    if let Some(directory) = std::env::var_os("SSPHP_STATE_DIR") {
+        return Ok(Some(Box::new(FileStateStore::new(directory))));
+    }
+    match (
+        std::env::var("SSPHP_STATE_BLOB_ACCOUNT"),
+        std::env::var("SSPHP_STATE_BLOB_CONTAINER"),
+    ) {
+        (Ok(account), Ok(container)) => Ok(Some(Box::new(AzureBlobStateStore::new(
+            &account, &container,
+        )?))),
+        (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+            bail!("SSPHP_STATE_BLOB_ACCOUNT and SSPHP_STATE_BLOB_CONTAINER must be set together")
+        }
+        (Err(_), Err(_)) => Ok(None),
+    }
+}
+
+/// Keep state in files below a local directory
+pub struct FileStateStore {
+    directory: PathBuf,
+}
+
+impl FileStateStore {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            bail!("Invalid state key: {}", key);
+        }
+        Ok(self.directory.join(key))
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            match tokio::fs::read(&path).await {
+                Ok(value) => Ok(Some(value)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err).with_context(|| format!("Reading state: {}", path.display())),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .with_context(|| format!("Creating state directory: {}", parent.display()))?;
+            }
+            // Write then rename so a crash never leaves a partial file
+            let tmp_path = path.with_extension("tmp");
+            tokio::fs::write(&tmp_path, value)
+                .await
+                .with_context(|| format!("Writing state: {}", tmp_path.display()))?;
+            tokio::fs::rename(&tmp_path, &path)
+                .await
+                .with_context(|| format!("Renaming state: {}", path.display()))?;
+            Ok(())
+        })
+    }
+}
+
+/// Keep state as block blobs in an Azure Storage container, using the
+/// default Azure credential.
+///
+/// Writes are conditional on the ETag seen when the blob was read, so
+/// two runs updating the same key can't silently overwrite each other
+pub struct AzureBlobStateStore {
+    container_url: String,
+    credential: Arc<dyn TokenCredential>,
+    client: reqwest::Client,
+    /// ETag of each blob read or written, `None` if it did not exist
+    etags: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl AzureBlobStateStore {
+    pub fn new(account: &str, container: &str) -> Result<Self> {
+        let credential = DefaultAzureCredential::create(TokenCredentialOptions::default())
+            .context("Unable to build default Azure Credentials")?;
+        Ok(Self {
+            container_url: format!("https://{}.blob.core.windows.net/{}", account, container),
+            credential: Arc::new(credential),
+            client: reqwest::Client::new(),
+            etags: Mutex::default(),
+        })
+    }
+
+    async fn request(&self, method: reqwest::Method, key: &str) -> Result<reqwest::RequestBuilder> {
+        let token = self
+            .credential
+            .get_token(&["https://storage.azure.com/.default"])
+            .await
+            .context("Getting Azure Storage token")?;
+        Ok(self
+            .client
+            .request(method, format!("{}/{}", self.container_url, key))
+            .bearer_auth(token.token.secret())
+            .header("x-ms-version", BLOB_API_VERSION))
+    }
+
+    fn etag(response: &reqwest::Response) -> Option<String> {
+        response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_string())
+    }
+}
+
+impl StateStore for AzureBlobStateStore {
+    fn name(&self) -> &str {
+        "azure_blob"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let response = self
+                .request(reqwest::Method::GET, key)
+                .await?
+                .send()
+                .await
+                .with_context(|| format!("Getting state blob: {}", key))?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                let _ = self.etags.lock().await.insert(key.to_string(), None);
+                return Ok(None);
+            }
+            let response = response
+                .error_for_status()
+                .with_context(|| format!("Getting state blob: {}", key))?;
+            let etag = Self::etag(&response);
+            let value = response.bytes().await?.to_vec();
+            let _ = self.etags.lock().await.insert(key.to_string(), etag);
+            Ok(Some(value))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut etags = self.etags.lock().await;
+            let mut request = self
+                .request(reqwest::Method::PUT, key)
+                .await?
+                .header("x-ms-blob-type", "BlockBlob");
+            request = match etags.get(key) {
+                Some(Some(etag)) => request.header(reqwest::header::IF_MATCH, etag),
+                Some(None) => request.header(reqwest::header::IF_NONE_MATCH, "*"),
+                None => request,
+            };
+            let response = request
+                .body(value)
+                .send()
+                .await
+                .with_context(|| format!("Putting state blob: {}", key))?;
+            if matches!(
+                response.status(),
+                reqwest::StatusCode::PRECONDITION_FAILED | reqwest::StatusCode::CONFLICT
+            ) {
+                bail!(
+                    "State blob {} was changed by another run since it was read",
+                    key
+                );
+            }
+            let response = response
+                .error_for_status()
+                .with_context(|| format!("Putting state blob: {}", key))?;
+            let _ = etags.insert(key.to_string(), Self::etag(&response));
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileStateStore, StateStore};
+    use anyhow::Result;
+
+    fn store() -> FileStateStore {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let directory = std::env::temp_dir().join(format!(
+            "ssphp_state_store_{}_{}",
+            std::process::id(),
+            nanos
+        ));
+        FileStateStore::new(directory)
+    }
+
+    #[tokio::test]
+    async fn test_file_state_store_round_trip() -> Result<()> {
+        let store = store();
+        assert_eq!(store.get("aws/inventory/1.json").await?, None);
+
+        store.put("aws/inventory/1.json", b"first".to_vec()).await?;
+        store
+            .put("aws/inventory/1.json", b"second".to_vec())
+            .await?;
+        assert_eq!(
+            store.get("aws/inventory/1.json").await?,
+            Some(b"second".to_vec())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_state_store_rejects_escaping_keys() {
+        let store = store();
+        assert!(store.get("../outside").await.is_err());
+        assert!(store.put("aws//inventory", vec![]).await.is_err());
+    }
+}
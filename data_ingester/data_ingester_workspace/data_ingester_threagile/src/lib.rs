@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
-use data_ingester_splunk::splunk::{set_ssphp_run, Splunk, ToHecEvents};
+use data_ingester_splunk::sink::HecSink;
+use data_ingester_splunk::splunk::{set_ssphp_run, ToHecEvents};
 use data_ingester_supporting::keyvault::Secrets;
 use std::collections::HashMap;
 use std::fs::{self};
@@ -37,7 +38,7 @@ fn extract_threagile() -> Result<PathBuf> {
     Ok(threagile_path)
 }
 
-pub async fn threagile(secrets: Arc<Secrets>, splunk: Arc<Splunk>) -> Result<()> {
+pub async fn threagile(secrets: Arc<Secrets>, splunk: Arc<dyn HecSink>) -> Result<()> {
     set_ssphp_run("threagile")?;
     info!("Extracting Threagile bins");
     let threagile_path = extract_threagile()?;